@@ -0,0 +1,53 @@
+// Lines bookmarked with `M`, to go back and forth between with `gb` and `gB`
+// or to pick from the list `gm` shows. A bookmark stays on its line as lines
+// are added or removed above it, and goes away with its line.
+
+use std::collections::BTreeSet;
+
+#[derive(Default)]
+pub struct Bookmarks {
+  rows: BTreeSet<usize>,
+}
+
+impl Bookmarks {
+  pub fn is_empty(&self) -> bool {
+    self.rows.is_empty()
+  }
+
+  // Bookmarks a line, or takes its bookmark away, returning whether it has
+  // one now.
+  pub fn toggle(&mut self, row: usize) -> bool {
+    self.rows.remove(&row) || self.rows.insert(row)
+  }
+
+  pub fn set(&mut self, row: usize) {
+    self.rows.insert(row);
+  }
+
+  // The bookmarked lines, in order
+  pub fn rows(&self) -> Vec<usize> {
+    self.rows.iter().copied().collect()
+  }
+
+  // The first bookmark after a line, or the last one before it
+  pub fn next(&self, row: usize, forward: bool) -> Option<usize> {
+    if forward {
+      self.rows.range(row + 1..).next().copied()
+    } else {
+      self.rows.range(..row).next_back().copied()
+    }
+  }
+
+  // Follows an edit that replaced `removed` lines at `start` with `added`
+  // others.
+  pub fn adjust(&mut self, start: usize, removed: usize, added: usize) {
+    let rows = std::mem::take(&mut self.rows);
+    for row in rows {
+      if row >= start + removed {
+        self.rows.insert(row + added - removed);
+      } else if row < start + added {
+        self.rows.insert(row);
+      }
+    }
+  }
+}
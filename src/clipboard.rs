@@ -0,0 +1,164 @@
+// The stack of cut and copied text.
+//
+// Each clip is either whole lines or text from within lines, and is pasted
+// back the same way, all at once. At most `limit` lines are kept in memory.
+// When the stack grows past that, the older clips are moved to a spill file,
+// and they are read back, newest first and no more than fit in memory, once
+// the clips in memory have all been pasted. The spill file is only readable
+// by the user and has no name, so nothing is left behind when the editor
+// exits.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+  // whole lines, pasted above the cursor
+  Lines,
+  // text pasted at the cursor, with a line break between each of its lines
+  Text,
+  // a column of text, each line pasted at the cursor's column on the lines
+  // from the cursor down
+  Block,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Clip {
+  pub kind: Kind,
+  pub lines: Vec<String>,
+}
+
+impl Clip {
+  pub fn lines(lines: Vec<String>) -> Self {
+    Clip{kind: Kind::Lines, lines}
+  }
+
+  pub fn text(text: &str) -> Self {
+    Clip{kind: Kind::Text, lines: text.split('\n').map(String::from).collect()}
+  }
+
+  pub fn block(lines: Vec<String>) -> Self {
+    Clip{kind: Kind::Block, lines}
+  }
+}
+
+pub struct Clipboard {
+  clips: Vec<Clip>,
+  limit: usize,
+  // number of lines in the clips in memory
+  held: usize,
+  spill: Option<File>,
+  // where each clip in the spill file starts and its number of lines, oldest
+  // first
+  spilled: Vec<(u64, usize)>,
+}
+
+// In the spill file, each clip is a line with its kind and the length in
+// bytes of each of its lines, like `L12 0 7` or `T5`, followed by its lines,
+// each ended by a newline. The lengths keep lines that contain a newline
+// themselves whole.
+fn write_clip(file: &mut File, clip: &Clip) -> io::Result<()> {
+  let kind = match clip.kind {
+    Kind::Lines => 'L',
+    Kind::Text => 'T',
+    Kind::Block => 'B',
+  };
+  let lens: Vec<String> = clip.lines.iter().map(|line| line.len().to_string()).collect();
+  writeln!(file, "{}{}", kind, lens.join(" "))?;
+  for line in &clip.lines {
+    writeln!(file, "{}", line)?;
+  }
+  Ok(())
+}
+
+fn read_clips(mut text: &str) -> io::Result<Vec<Clip>> {
+  let bad = || io::Error::new(io::ErrorKind::InvalidData, "corrupt clipboard spill file");
+  let mut clips = Vec::new();
+  while let Some((header, rest)) = text.split_once('\n') {
+    let kind = match header.chars().next() {
+      Some('L') => Kind::Lines,
+      Some('T') => Kind::Text,
+      Some('B') => Kind::Block,
+      _ => return Err(bad()),
+    };
+    let mut clip_lines = Vec::new();
+    text = rest;
+    for len in header[1..].split(' ').filter(|len| !len.is_empty()) {
+      let len: usize = len.parse().map_err(|_| bad())?;
+      let line = text.get(..len).filter(|_| text[len..].starts_with('\n')).ok_or_else(bad)?;
+      clip_lines.push(String::from(line));
+      text = &text[len + 1..];
+    }
+    clips.push(Clip{kind, lines: clip_lines});
+  }
+  Ok(clips)
+}
+
+impl Clipboard {
+  pub fn new(limit: usize) -> Self {
+    Clipboard{clips: Vec::new(), limit: limit.max(2), held: 0, spill: None, spilled: Vec::new()}
+  }
+
+  pub fn push(&mut self, clip: Clip) -> io::Result<()> {
+    self.held += clip.lines.len();
+    self.clips.push(clip);
+    if self.held > self.limit && self.clips.len() > 1 {
+      // keep the newest clip, and as many others as fit in half the limit
+      let mut keep = self.clips.len() - 1;
+      let mut kept = self.clips[keep].lines.len();
+      while keep > 0 && kept + self.clips[keep - 1].lines.len() <= self.limit / 2 {
+        keep -= 1;
+        kept += self.clips[keep].lines.len();
+      }
+      let old: Vec<Clip> = self.clips.drain(..keep).collect();
+      if self.spill.is_none() {
+        self.spill = Some(tempfile::tempfile()?);
+      }
+      let file = self.spill.as_mut().unwrap();
+      let mut offset = file.seek(SeekFrom::End(0))?;
+      for clip in &old {
+        write_clip(file, clip)?;
+        self.spilled.push((offset, clip.lines.len()));
+        offset = file.stream_position()?;
+      }
+      self.held = kept;
+    }
+    Ok(())
+  }
+
+  pub fn pop(&mut self) -> io::Result<Option<Clip>> {
+    if self.clips.is_empty() && !self.spilled.is_empty() {
+      self.unspill()?;
+    }
+    let clip = self.clips.pop();
+    if let Some(ref clip) = clip {
+      self.held -= clip.lines.len();
+    }
+    Ok(clip)
+  }
+
+  // Moves the newest spilled clips back into memory, up to half a limit of
+  // lines, but at least one clip.
+  fn unspill(&mut self) -> io::Result<()> {
+    let file = match self.spill.as_mut() {
+      Some(file) => file,
+      None => return Ok(()),
+    };
+    let spilled = &mut self.spilled;
+    let mut keep = spilled.len();
+    let mut kept = 0;
+    while keep > 0 && (kept == 0 || kept + spilled[keep - 1].1 <= self.limit / 2) {
+      keep -= 1;
+      kept += spilled[keep].1;
+    }
+    let offset = spilled[keep].0;
+    let mut text = String::new();
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_to_string(&mut text)?;
+    self.clips = read_clips(&text)?;
+    file.set_len(offset)?;
+    spilled.truncate(keep);
+    self.held = kept;
+    Ok(())
+  }
+}
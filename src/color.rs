@@ -0,0 +1,125 @@
+// Colors for the terminal, in as many as it can show. A color with more
+// detail than the terminal supports is drawn as the nearest one it has, so
+// that the same colors work everywhere from 16 colors up to 24-bit ones.
+
+use std::env;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Depth {
+  // the 16 colors of the terminal's own palette
+  Basic,
+  // xterm's 256 colors
+  Indexed,
+  // 24-bit colors
+  Rgb,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Color {
+  // one of the 16 palette colors, the bright ones from 8
+  Basic(u8),
+  Indexed(u8),
+  Rgb(u8, u8, u8),
+}
+
+// The default xterm palette, for finding the nearest of the 16 colors
+const BASIC: [(u8, u8, u8); 16] = [
+  (0, 0, 0), (205, 0, 0), (0, 205, 0), (205, 205, 0),
+  (0, 0, 238), (205, 0, 205), (0, 205, 205), (229, 229, 229),
+  (127, 127, 127), (255, 0, 0), (0, 255, 0), (255, 255, 0),
+  (92, 92, 255), (255, 0, 255), (0, 255, 255), (255, 255, 255),
+];
+
+// The levels of each component in the 6x6x6 cube of the 256 colors
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+// Finds how many colors the terminal shows from `$TERM` and `$COLORTERM`.
+pub fn depth() -> Depth {
+  detect(env::var("TERM").ok().as_deref(), env::var("COLORTERM").ok().as_deref())
+}
+
+pub fn detect(term: Option<&str>, colorterm: Option<&str>) -> Depth {
+  let term = term.unwrap_or("");
+  if matches!(colorterm, Some("truecolor") | Some("24bit")) || term.ends_with("-direct") {
+    Depth::Rgb
+  } else if term.contains("256color") {
+    Depth::Indexed
+  } else {
+    Depth::Basic
+  }
+}
+
+// The escape sequence setting the foreground color.
+pub fn fg(color: Color, depth: Depth) -> String {
+  match reduce(color, depth) {
+    Color::Basic(n) if n < 8 => format!("\x1b[{}m", 30 + n),
+    Color::Basic(n) => format!("\x1b[{}m", 90 + n % 8),
+    Color::Indexed(n) => format!("\x1b[38;5;{}m", n),
+    Color::Rgb(r, g, b) => format!("\x1b[38;2;{};{};{}m", r, g, b),
+  }
+}
+
+// The escape sequence setting the background color. A dark shade that is
+// nearest to black in the 16 colors gets the default background instead,
+// since black is the background of many terminals and hides the text on the
+// others.
+pub fn bg(color: Color, depth: Depth) -> String {
+  match reduce(color, depth) {
+    Color::Basic(0) if rgb(color) != BASIC[0] => String::from("\x1b[49m"),
+    Color::Basic(n) if n < 8 => format!("\x1b[{}m", 40 + n),
+    Color::Basic(n) => format!("\x1b[{}m", 100 + n % 8),
+    Color::Indexed(n) => format!("\x1b[48;5;{}m", n),
+    Color::Rgb(r, g, b) => format!("\x1b[48;2;{};{};{}m", r, g, b),
+  }
+}
+
+// Turns a color into the nearest one the terminal shows.
+pub fn reduce(color: Color, depth: Depth) -> Color {
+  match (color, depth) {
+    (Color::Basic(n), _) => Color::Basic(n % 16),
+    (Color::Indexed(n), _) if n < 16 => Color::Basic(n),
+    (Color::Indexed(_), Depth::Indexed) | (Color::Indexed(_), Depth::Rgb) => color,
+    (Color::Rgb(..), Depth::Rgb) => color,
+    (Color::Rgb(r, g, b), Depth::Indexed) => Color::Indexed(nearest_indexed((r, g, b))),
+    (_, Depth::Basic) => Color::Basic(nearest_basic(rgb(color))),
+  }
+}
+
+fn rgb(color: Color) -> (u8, u8, u8) {
+  match color {
+    Color::Basic(n) => BASIC[n as usize % 16],
+    Color::Indexed(n) if n < 16 => BASIC[n as usize],
+    Color::Indexed(n) if n < 232 => {
+      let n = n as usize - 16;
+      (CUBE[n / 36], CUBE[n / 6 % 6], CUBE[n % 6])
+    }
+    Color::Indexed(n) => {
+      let level = 8 + 10 * (n - 232);
+      (level, level, level)
+    }
+    Color::Rgb(r, g, b) => (r, g, b),
+  }
+}
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+  let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2) as u32;
+  d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+fn nearest_basic(color: (u8, u8, u8)) -> u8 {
+  (0..16).min_by_key(|&n| distance(color, BASIC[n as usize])).unwrap()
+}
+
+// Picks the closer of the nearest color in the cube and the nearest gray.
+fn nearest_indexed(color: (u8, u8, u8)) -> u8 {
+  let level = |x: u8| (0..6).min_by_key(|&i| (CUBE[i] as i32 - x as i32).abs()).unwrap();
+  let (r, g, b) = (level(color.0), level(color.1), level(color.2));
+  let cube = (16 + 36 * r + 6 * g + b) as u8;
+  let average = (color.0 as u32 + color.1 as u32 + color.2 as u32) / 3;
+  let gray = 232 + (average.saturating_sub(3) / 10).min(23) as u8;
+  if distance(color, rgb(Color::Indexed(gray))) < distance(color, rgb(Color::Indexed(cube))) {
+    gray
+  } else {
+    cube
+  }
+}
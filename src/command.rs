@@ -0,0 +1,295 @@
+// Commands typed after `:` on the bottom line, like `:w` or `:e notes.txt`.
+// Some work on a range of lines given before them, as in `:3,10d`.
+
+use std::ops::Range;
+
+use crate::timeline::{self, Step};
+use crate::transform::Order;
+
+// A line given by number, counted from 1, as `.` for the cursor's line or `$`
+// for the last one, and moved by an offset like `+5`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Line {
+  Number(usize),
+  Current,
+  Last,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Address {
+  pub line: Line,
+  pub offset: isize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineRange {
+  pub start: Address,
+  pub end: Address,
+}
+
+impl Address {
+  fn new(line: Line) -> Self {
+    Address{line, offset: 0}
+  }
+
+  // The row of the line, counted from 0
+  fn row(&self, row: usize, len: usize) -> Result<usize, String> {
+    let line = match self.line {
+      Line::Number(n) => n as isize,
+      Line::Current => row as isize + 1,
+      Line::Last => len as isize,
+    }.saturating_add(self.offset);
+    if line < 1 || line as usize > len {
+      return Err(format!("No line {}", line));
+    }
+    Ok(line as usize - 1)
+  }
+}
+
+impl LineRange {
+  pub fn current() -> Self {
+    LineRange{start: Address::new(Line::Current), end: Address::new(Line::Current)}
+  }
+
+  pub fn all() -> Self {
+    LineRange{start: Address::new(Line::Number(1)), end: Address::new(Line::Last)}
+  }
+
+  // The rows of the lines, for the cursor on `row` of `len` lines
+  pub fn rows(&self, row: usize, len: usize) -> Result<Range<usize>, String> {
+    let (start, end) = (self.start.row(row, len)?, self.end.row(row, len)?);
+    if start > end {
+      return Err(String::from("Backwards range"));
+    }
+    Ok(start..end + 1)
+  }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+  Write,
+  Quit,
+  // quits even with unsaved changes
+  ForceQuit,
+  WriteQuit,
+  // saves the file under a new name, which it keeps
+  WriteAs(String),
+  Edit(String),
+  // reads the file again, dropping unsaved changes
+  Reload,
+  // takes the text left in the swap file of the current file
+  Recover,
+  NextBuffer,
+  PrevBuffer,
+  // a line number, counted from 1
+  Goto(usize),
+  // converts the line endings to CRLF, or back to LF
+  Crlf(bool),
+  // compares the buffer with the file on disk
+  Diff,
+  // stages and commits the file with the message
+  Commit(String),
+  // pipes the lines through a shell command
+  Filter(LineRange, String),
+  // deletes the lines into the clipboard
+  Delete(LineRange),
+  // copies the lines into the clipboard
+  Yank(LineRange),
+  // replaces the text with other text in the lines, all of it in each line
+  // or only the first
+  Substitute(LineRange, String, String, bool),
+  Sort(LineRange, Order),
+  Reverse(LineRange),
+  // drops repeated lines
+  Uniq(LineRange),
+  // comments the lines out, or back in
+  Comment(LineRange),
+  // runs a shell in a panel below the text
+  Terminal,
+  // shows a markdown file rendered beside its text, or stops
+  Preview,
+  // shows the files of the current directory as a tree beside the text, or
+  // stops
+  Tree,
+  // shows separated values lined up in columns, or stops
+  Csv,
+  // brings back the text as it was a number of changes or a time before, or
+  // after
+  Earlier(Step),
+  Later(Step),
+  // attaches a note to the current line, or shows the one it has
+  Note(String),
+  // removes the note of the current line
+  Unnote,
+  // shows a manual page in a buffer that can't be edited
+  Man(String),
+  // runs the build with the arguments, or a shell command, showing its
+  // output in a buffer
+  Make(String),
+  Run(String),
+  // goes to the next place named in the output, or the previous one
+  Location(bool),
+}
+
+// Numbers too big for any buffer are kept as the biggest offset, which no
+// line has.
+fn parse_number(text: &str) -> (Option<usize>, &str) {
+  let end = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+  let n = match &text[..end] {
+    "" => None,
+    digits => Some(digits.parse().map_or(isize::MAX as usize, |n: usize| n.min(isize::MAX as usize))),
+  };
+  (n, &text[end..])
+}
+
+// Parses an address at the start of the text, returning it and the rest.
+fn parse_address(text: &str) -> Option<(Address, &str)> {
+  let (line, mut rest) = match text.chars().next() {
+    Some('.') => (Some(Line::Current), &text[1..]),
+    Some('$') => (Some(Line::Last), &text[1..]),
+    _ => match parse_number(text) {
+      (Some(n), rest) => (Some(Line::Number(n)), rest),
+      (None, rest) => (None, rest),
+    },
+  };
+  let mut offset: isize = 0;
+  while let Some(sign @ ('+' | '-')) = rest.chars().next() {
+    let (n, after) = parse_number(&rest[1..]);
+    let n = n.unwrap_or(1) as isize;
+    offset = if sign == '+' { offset.saturating_add(n) } else { offset.saturating_sub(n) };
+    rest = after;
+  }
+  if line.is_none() && rest.len() == text.len() {
+    return None;
+  }
+  Some((Address{line: line.unwrap_or(Line::Current), offset}, rest))
+}
+
+// Parses the range at the start of the text, if there is one, returning it
+// and the rest.
+fn parse_range(text: &str) -> (Option<LineRange>, &str) {
+  if let Some(rest) = text.strip_prefix('%') {
+    return (Some(LineRange::all()), rest);
+  }
+  let (start, rest) = match parse_address(text) {
+    Some(address) => address,
+    None => return (None, text),
+  };
+  match rest.strip_prefix(',').and_then(parse_address) {
+    Some((end, rest)) => (Some(LineRange{start, end}), rest),
+    None => (Some(LineRange{start, end: start}), rest),
+  }
+}
+
+// Splits `/old/new/flags` at the delimiter it starts with, which a backslash
+// lets the text contain.
+fn parse_substitution(text: &str) -> Result<(String, String, String), String> {
+  let mut chars = text.chars();
+  let delim = match chars.next() {
+    Some(c) if !c.is_alphanumeric() && !c.is_whitespace() && c != '\\' => c,
+    _ => return Err(String::from("Usage: s/old/new/")),
+  };
+  let mut parts = vec![String::new()];
+  while let Some(c) = chars.next() {
+    match c {
+      '\\' => match chars.next() {
+        Some(c) if c == delim => parts.last_mut().unwrap().push(c),
+        Some(c) => parts.last_mut().unwrap().extend(['\\', c]),
+        None => parts.last_mut().unwrap().push('\\'),
+      },
+      c if c == delim && parts.len() < 3 => parts.push(String::new()),
+      c => parts.last_mut().unwrap().push(c),
+    }
+  }
+  parts.resize(3, String::new());
+  let flags = parts.pop().unwrap();
+  let new = parts.pop().unwrap();
+  let old = parts.pop().unwrap();
+  if old.is_empty() {
+    return Err(String::from("No text to replace"));
+  }
+  Ok((old, new, flags))
+}
+
+pub fn parse(text: &str) -> Result<Command, String> {
+  let text = text.trim();
+  if let Ok(line) = text.parse() {
+    return Ok(Command::Goto(line));
+  }
+  let (range, text) = parse_range(text);
+  if let Some(command) = text.strip_prefix('!') {
+    return match command.trim() {
+      "" => Err(String::from("No shell command")),
+      command => Ok(Command::Filter(range.unwrap_or_else(LineRange::all), String::from(command))),
+    };
+  }
+  if let Some(rest) = text.strip_prefix('s').filter(|rest| !rest.starts_with(char::is_alphanumeric)) {
+    let (old, new, flags) = parse_substitution(rest)?;
+    let global = match flags.trim() {
+      "" => false,
+      "g" => true,
+      flags => return Err(format!("Unknown flags: {}", flags)),
+    };
+    return Ok(Command::Substitute(range.unwrap_or_else(LineRange::current), old, new, global));
+  }
+  let (name, arg) = match text.find(char::is_whitespace) {
+    Some(i) => (&text[..i], text[i..].trim_start()),
+    None => (text, ""),
+  };
+  // Commands on lines work on the current line or the whole file by default.
+  let line = range.unwrap_or_else(LineRange::current);
+  let whole = range.unwrap_or_else(LineRange::all);
+  let command = match (name, arg) {
+    ("d", "") => Some(Command::Delete(line)),
+    ("y", "") => Some(Command::Yank(line)),
+    ("sort", "") => Some(Command::Sort(whole, Order::Text)),
+    ("sort", "i") => Some(Command::Sort(whole, Order::IgnoreCase)),
+    ("sort", "n") => Some(Command::Sort(whole, Order::Numeric)),
+    ("sort", _) => return Err(String::from("Usage: sort [i|n]")),
+    ("reverse", "") => Some(Command::Reverse(whole)),
+    ("uniq", "") => Some(Command::Uniq(whole)),
+    ("comment", "") => Some(Command::Comment(line)),
+    _ => None,
+  };
+  match (command, range) {
+    (Some(command), _) => return Ok(command),
+    (None, Some(_)) if text.is_empty() => return Err(String::from("No command after the range")),
+    (None, Some(_)) => return Err(format!("Not a command with a range: {}", text)),
+    (None, None) => (),
+  }
+  let command = match (name, arg) {
+    ("w", "") => Command::Write,
+    ("q", "") => Command::Quit,
+    ("q!", "") => Command::ForceQuit,
+    ("w", path) => Command::WriteAs(String::from(path)),
+    ("wq", "") | ("x", "") => Command::WriteQuit,
+    ("bn", "") => Command::NextBuffer,
+    ("bp", "") => Command::PrevBuffer,
+    ("crlf", "") => Command::Crlf(true),
+    ("lf", "") => Command::Crlf(false),
+    ("diff", "") => Command::Diff,
+    ("term", "") => Command::Terminal,
+    ("preview", "") => Command::Preview,
+    ("tree", "") => Command::Tree,
+    ("csv", "") => Command::Csv,
+    ("note", text) => Command::Note(String::from(text)),
+    ("unnote", "") => Command::Unnote,
+    ("earlier", step) => Command::Earlier(timeline::parse_step(step)?),
+    ("later", step) => Command::Later(timeline::parse_step(step)?),
+    ("man", "") => return Err(String::from("No manual page")),
+    ("man", topic) => Command::Man(String::from(topic)),
+    ("make", args) => Command::Make(String::from(args)),
+    ("run", "") => return Err(String::from("No shell command")),
+    ("run", command) => Command::Run(String::from(command)),
+    ("cn", "") => Command::Location(true),
+    ("cp", "") => Command::Location(false),
+    ("commit", "") => return Err(String::from("No commit message")),
+    ("commit", message) => Command::Commit(String::from(message)),
+    ("e!", "") => Command::Reload,
+    ("recover", "") => Command::Recover,
+    ("e", "") => return Err(String::from("No file name")),
+    ("e", path) => Command::Edit(String::from(path)),
+    _ => return Err(format!("Not a command: {}", text)),
+  };
+  Ok(command)
+}
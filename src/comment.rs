@@ -0,0 +1,37 @@
+// Commenting lines out and back in with the line comment of the file type.
+//
+// Lines are uncommented when all of them that aren't blank are comments, and
+// otherwise commented, with the comment lined up at the least indentation of
+// the lines. Blank lines are left alone. Only spaces and tabs count as
+// indentation, so a comment is never put inside a multibyte character.
+
+fn indent(line: &str) -> usize {
+  line.len() - line.trim_start_matches([' ', '\t']).len()
+}
+
+fn is_commented(line: &str, leader: &str) -> bool {
+  line[indent(line)..].starts_with(leader)
+}
+
+// Toggles the comments on the lines, returning whether they are now
+// commented.
+pub fn toggle(lines: &mut [String], leader: &str) -> bool {
+  let text: Vec<&String> = lines.iter().filter(|line| !line.trim().is_empty()).collect();
+  let column = text.iter().map(|line| indent(line)).min();
+  if !text.is_empty() && text.iter().all(|line| is_commented(line, leader)) {
+    for line in lines.iter_mut().filter(|line| is_commented(line, leader)) {
+      let start = indent(line);
+      let mut end = start + leader.len();
+      if line[end..].starts_with(' ') {
+        end += 1;
+      }
+      line.replace_range(start..end, "");
+    }
+    return false;
+  }
+  let column = column.unwrap_or(0);
+  for line in lines.iter_mut().filter(|line| !line.trim().is_empty()) {
+    line.insert_str(column, &format!("{} ", leader));
+  }
+  true
+}
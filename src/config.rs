@@ -0,0 +1,347 @@
+// User configuration, read from `~/.config/red/config.toml`, and project
+// configuration, read from the nearest `.red.toml` above the edited file.
+//
+// The files are a small subset of TOML: one `key = value` setting per line,
+// where a value is `true`, `false`, an integer, or a double-quoted string.
+// Everything has a default, so the files are optional.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{RwLock, RwLockReadGuard};
+use std::time::SystemTime;
+
+use crate::keymap::{self, Keymap};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ControlChars {
+  // `^A`, two cells wide
+  Caret,
+  // `␁` from the Unicode control pictures block, one cell wide
+  Symbol,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bell {
+  // flash the screen
+  Visual,
+  // ring the terminal bell
+  Audible,
+  None,
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+  pub control_chars: ControlChars,
+  // whether DEL is shown like the other control characters, as `^?`
+  pub caret_del: bool,
+  // soft wrap long lines instead of scrolling horizontally
+  pub wrap: bool,
+  // shade the row of the cursor
+  pub cursor_line: bool,
+  // milliseconds to wait for the next key of a sequence, or 0 to wait as long
+  // as it takes
+  pub key_timeout: usize,
+  // seconds without a key before the editor does its idle work, like
+  // writing swap files, or 0 never to
+  pub idle_time: usize,
+  // shown at the start of each continuation row of a wrapped line
+  pub wrap_marker: String,
+  // feedback given for operations that have no effect
+  pub bell: Bell,
+  // megabytes from which a file's lines are left in it until they are shown,
+  // or 0 always to read the whole file
+  pub large_file: usize,
+  // number of saved versions of each file kept in the local history
+  pub history_size: usize,
+  // keep the previous version of a saved file as `<file>~`
+  pub backup: bool,
+  // remove trailing whitespace and blank lines at the end when saving
+  pub strip_whitespace: bool,
+  // strftime(3) formats for inserting the date and time
+  pub date_format: String,
+  pub time_format: String,
+  // maximum line width when reflowing paragraphs
+  pub text_width: usize,
+  // number of clipboard lines kept in memory before older ones go to disk
+  pub clipboard_size: usize,
+  // let the cursor move past the end of the line
+  pub virtual_edit: bool,
+  // start new lines with the indentation of the line they were broken from,
+  // and one more level after `{` or `:` with `smart_indent`
+  pub auto_indent: bool,
+  pub smart_indent: bool,
+  // number of spaces in a level of indentation
+  pub shift_width: usize,
+  // show the folds in a column left of the text
+  pub fold_column: bool,
+  // highlight comments, strings, numbers and keywords in code
+  pub syntax: bool,
+  // words highlighted and listed as reminders
+  pub todo_markers: Vec<String>,
+  // the shell command `:make` runs, with its arguments after
+  pub make_program: String,
+  // actions bound to keys in normal, visual and insert mode, and to the
+  // text objects typed after an operator
+  pub normal_keys: Keymap,
+  pub visual_keys: Keymap,
+  pub insert_keys: Keymap,
+  pub object_keys: Keymap,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Config{
+      control_chars: ControlChars::Caret,
+      caret_del: true,
+      wrap: false,
+      cursor_line: false,
+      key_timeout: 1000,
+      idle_time: 4,
+      wrap_marker: String::from("\u{21AA} "),
+      bell: Bell::Visual,
+      large_file: 64,
+      history_size: 10,
+      backup: false,
+      strip_whitespace: false,
+      date_format: String::from("%Y-%m-%d"),
+      time_format: String::from("%H:%M"),
+      text_width: 79,
+      clipboard_size: 10000,
+      virtual_edit: false,
+      auto_indent: true,
+      smart_indent: false,
+      shift_width: 4,
+      todo_markers: vec![String::from("TODO"), String::from("FIXME"), String::from("XXX")],
+      make_program: String::from("make"),
+      fold_column: false,
+      syntax: true,
+      normal_keys: keymap::normal_defaults(),
+      visual_keys: keymap::visual_defaults(),
+      insert_keys: keymap::insert_defaults(),
+      object_keys: keymap::object_defaults(),
+    }
+  }
+}
+
+enum Value {
+  Bool(bool),
+  Int(usize),
+  Str(String),
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+  match text {
+    "true" => Ok(Value::Bool(true)),
+    "false" => Ok(Value::Bool(false)),
+    _ if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') =>
+      Ok(Value::Str(text[1..text.len() - 1].to_string())),
+    _ => text.parse().map(Value::Int).map_err(|_| format!("invalid value `{}`", text)),
+  }
+}
+
+impl Config {
+  fn set(&mut self, key: &str, value: Value) -> Result<(), String> {
+    match (key, value) {
+      ("control_chars", Value::Str(ref s)) if s == "caret" =>
+        self.control_chars = ControlChars::Caret,
+      ("control_chars", Value::Str(ref s)) if s == "symbol" =>
+        self.control_chars = ControlChars::Symbol,
+      ("caret_del", Value::Bool(b)) => self.caret_del = b,
+      ("wrap", Value::Bool(b)) => self.wrap = b,
+      ("cursor_line", Value::Bool(b)) => self.cursor_line = b,
+      ("key_timeout", Value::Int(n)) => self.key_timeout = n,
+      ("idle_time", Value::Int(n)) => self.idle_time = n,
+      ("operators", Value::Bool(b)) => keymap::use_operators(&mut self.normal_keys, b),
+      ("wrap_marker", Value::Str(s)) => self.wrap_marker = s,
+      ("bell", Value::Str(ref s)) if s == "visual" => self.bell = Bell::Visual,
+      ("bell", Value::Str(ref s)) if s == "audible" => self.bell = Bell::Audible,
+      ("bell", Value::Str(ref s)) if s == "none" => self.bell = Bell::None,
+      ("large_file", Value::Int(n)) => self.large_file = n,
+      ("history_size", Value::Int(n)) => self.history_size = n,
+      ("backup", Value::Bool(b)) => self.backup = b,
+      ("strip_whitespace", Value::Bool(b)) => self.strip_whitespace = b,
+      ("date_format", Value::Str(s)) => self.date_format = s,
+      ("time_format", Value::Str(s)) => self.time_format = s,
+      ("text_width", Value::Int(n)) if n > 0 => self.text_width = n,
+      ("clipboard_size", Value::Int(n)) => self.clipboard_size = n,
+      ("virtual_edit", Value::Bool(b)) => self.virtual_edit = b,
+      ("auto_indent", Value::Bool(b)) => self.auto_indent = b,
+      ("smart_indent", Value::Bool(b)) => self.smart_indent = b,
+      ("shift_width", Value::Int(n)) if n > 0 => self.shift_width = n,
+      ("todo_markers", Value::Str(s)) =>
+        self.todo_markers = s.split_whitespace().map(String::from).collect(),
+      ("fold_column", Value::Bool(b)) => self.fold_column = b,
+      ("syntax", Value::Bool(b)) => self.syntax = b,
+      ("make_program", Value::Str(s)) if !s.trim().is_empty() => self.make_program = s,
+      (_, Value::Str(ref s)) if key.starts_with("normal.") =>
+        keymap::bind(&mut self.normal_keys, &key["normal.".len()..], s)?,
+      (_, Value::Str(ref s)) if key.starts_with("visual.") =>
+        keymap::bind(&mut self.visual_keys, &key["visual.".len()..], s)?,
+      (_, Value::Str(ref s)) if key.starts_with("insert.") =>
+        keymap::bind(&mut self.insert_keys, &key["insert.".len()..], s)?,
+      (_, Value::Str(ref s)) if key.starts_with("object.") =>
+        keymap::bind(&mut self.object_keys, &key["object.".len()..], s)?,
+      _ => return Err(format!("invalid setting for `{}`", key)),
+    }
+    Ok(())
+  }
+
+  // Applies the settings in `text` over the current ones.
+  pub fn merge(&mut self, text: &str) -> Result<(), String> {
+    for (i, line) in text.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let err = |msg: String| format!("line {}: {}", i + 1, msg);
+      let mut parts = line.splitn(2, '=');
+      let key = parts.next().unwrap_or("").trim();
+      let value = match parts.next() {
+        Some(value) => parse_value(value.trim()).map_err(err)?,
+        None => return Err(err(format!("expected `{} = <value>`", key))),
+      };
+      self.set(key, value).map_err(err)?;
+    }
+    Ok(())
+  }
+}
+
+pub fn parse(text: &str) -> Result<Config, String> {
+  let mut cfg = Config::default();
+  cfg.merge(text)?;
+  Ok(cfg)
+}
+
+// Tests keep their state, swap files and history in a directory of their
+// own rather than the user's, left for the system to clean up.
+#[cfg(test)]
+lazy_static! {
+  static ref TEST_CONFIG_HOME: tempfile::TempDir = tempfile::tempdir().unwrap();
+}
+
+#[cfg(test)]
+pub fn config_dir() -> Option<PathBuf> {
+  Some(TEST_CONFIG_HOME.path().join("red"))
+}
+
+#[cfg(not(test))]
+pub fn config_dir() -> Option<PathBuf> {
+  env::var_os("XDG_CONFIG_HOME")
+    .map(PathBuf::from)
+    .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    .map(|dir| dir.join("red"))
+}
+
+fn file_error(path: &Path, msg: String) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), msg))
+}
+
+fn user_config_path() -> Option<PathBuf> {
+  config_dir().map(|dir| dir.join("config.toml"))
+}
+
+pub fn load() -> io::Result<Config> {
+  let path = match user_config_path() {
+    Some(path) => path,
+    None => return Ok(Config::default()),
+  };
+  match fs::read_to_string(&path) {
+    Ok(text) => parse(&text).map_err(|msg| file_error(&path, msg)),
+    Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+    Err(err) => Err(err),
+  }
+}
+
+pub const PROJECT_FILE: &str = ".red.toml";
+
+// Looks for a project config in the directory of `file` and its parents.
+pub fn find_project_config(file: &Path) -> Option<PathBuf> {
+  let file = env::current_dir().ok()?.join(file);
+  file.ancestors()
+    .skip(1)
+    .map(|dir| dir.join(PROJECT_FILE))
+    .find(|path| path.is_file())
+}
+
+// 64-bit FNV-1a, used to notice when a trusted project config has changed.
+fn fingerprint(text: &str) -> u64 {
+  text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+    (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+  })
+}
+
+// Project configs are only applied once the user has trusted their current
+// contents, since they come with whatever repository was checked out. The
+// trusted ones are listed in `~/.config/red/trusted`.
+fn trust_entry(path: &Path, text: &str) -> String {
+  format!("{:016x} {}", fingerprint(text), path.display())
+}
+
+fn is_trusted(entry: &str, trusted: &Path) -> bool {
+  fs::read_to_string(trusted).is_ok_and(|list| list.lines().any(|line| line == entry))
+}
+
+fn trust(entry: &str, trusted: &Path) -> io::Result<()> {
+  if let Some(dir) = trusted.parent() {
+    fs::create_dir_all(dir)?;
+  }
+  let mut file = fs::OpenOptions::new().append(true).create(true).open(trusted)?;
+  writeln!(file, "{}", entry)
+}
+
+// Merges the project config for `file` over `cfg`, calling `ask` to confirm
+// a config that has not been trusted yet. Returns the path of a project
+// config left out because it wasn't trusted.
+pub fn load_project<F>(cfg: &mut Config, file: &Path, ask: F) -> io::Result<Option<PathBuf>>
+where
+  F: FnOnce(&Path) -> io::Result<bool>,
+{
+  let (path, dir) = match (find_project_config(file), config_dir()) {
+    (Some(path), Some(dir)) => (path, dir),
+    _ => return Ok(None),
+  };
+  let text = fs::read_to_string(&path)?;
+  let trusted = dir.join("trusted");
+  let entry = trust_entry(&path, &text);
+  if !is_trusted(&entry, &trusted) {
+    if !ask(&path)? {
+      return Ok(Some(path));
+    }
+    trust(&entry, &trusted)?;
+  }
+  cfg.merge(&text).map_err(|msg| file_error(&path, msg))?;
+  Ok(None)
+}
+
+// Modification times of the user and project configs for `file`, compared
+// to notice when they change while editing.
+pub fn stamps(file: &Path) -> Vec<Option<SystemTime>> {
+  user_config_path().into_iter()
+    .chain(find_project_config(file))
+    .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+    .collect()
+}
+
+// Reads the user and project configs for `file` again. A project config that
+// has not been trusted in its current form is skipped, since there is no way
+// to ask while editing, and its path returned.
+pub fn reload(file: &Path) -> io::Result<(Config, Option<PathBuf>)> {
+  let mut cfg = load()?;
+  let untrusted = load_project(&mut cfg, file, |_| Ok(false))?;
+  Ok((cfg, untrusted))
+}
+
+lazy_static! {
+  static ref CONFIG: RwLock<Config> = RwLock::new(Config::default());
+}
+
+pub fn get() -> RwLockReadGuard<'static, Config> {
+  CONFIG.read().unwrap()
+}
+
+pub fn set(cfg: Config) {
+  *CONFIG.write().unwrap() = cfg;
+}
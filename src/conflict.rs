@@ -0,0 +1,98 @@
+// Merge conflicts left in a file by git and other version control tools:
+//
+//   <<<<<<< ours
+//   our lines
+//   ||||||| base (only with the diff3 conflict style)
+//   original lines
+//   =======
+//   their lines
+//   >>>>>>> theirs
+
+use crate::lines::Lines;
+
+const START: &str = "<<<<<<<";
+const BASE: &str = "|||||||";
+const SEPARATOR: &str = "=======";
+const END: &str = ">>>>>>>";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Part {
+  Marker,
+  Ours,
+  Base,
+  Theirs,
+}
+
+// Line numbers of the markers of one conflict
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Conflict {
+  pub start: usize,
+  pub base: Option<usize>,
+  pub separator: usize,
+  pub end: usize,
+}
+
+impl Conflict {
+  pub fn part(&self, row: usize) -> Option<Part> {
+    if row == self.start || Some(row) == self.base || row == self.separator || row == self.end {
+      Some(Part::Marker)
+    } else if row < self.start || row > self.end {
+      None
+    } else if row > self.separator {
+      Some(Part::Theirs)
+    } else if self.base.is_some_and(|base| row > base) {
+      Some(Part::Base)
+    } else {
+      Some(Part::Ours)
+    }
+  }
+}
+
+fn parse_at(start: usize, buf: &Lines) -> Option<Conflict> {
+  let mut base = None;
+  let mut separator = None;
+  for (row, line) in buf.iter().enumerate().skip(start + 1) {
+    if line.starts_with(START) {
+      return None;
+    } else if line.starts_with(BASE) && base.is_none() && separator.is_none() {
+      base = Some(row);
+    } else if line.starts_with(SEPARATOR) && separator.is_none() {
+      separator = Some(row);
+    } else if line.starts_with(END) {
+      return separator.map(|separator| Conflict{start, base, separator, end: row});
+    }
+  }
+  None
+}
+
+pub fn find_all(buf: &Lines) -> Vec<Conflict> {
+  let mut conflicts = Vec::new();
+  let mut row = 0;
+  while row < buf.len() {
+    if buf[row].starts_with(START) {
+      if let Some(conflict) = parse_at(row, buf) {
+        conflicts.push(conflict);
+        row = conflict.end;
+      }
+    }
+    row += 1;
+  }
+  conflicts
+}
+
+pub fn find_at(row: usize, buf: &Lines) -> Option<Conflict> {
+  find_all(buf).into_iter().find(|c| c.start <= row && row <= c.end)
+}
+
+// Replaces the conflict with the chosen sides, dropping the markers and the
+// base section.
+pub fn resolve(conflict: &Conflict, ours: bool, theirs: bool, buf: &mut Lines) {
+  let mut lines = Vec::new();
+  if ours {
+    lines.extend(buf.slice(conflict.start + 1..conflict.base.unwrap_or(conflict.separator)));
+  }
+  if theirs {
+    lines.extend(buf.slice(conflict.separator + 1..conflict.end));
+  }
+  buf.splice(conflict.start..conflict.end + 1, lines);
+}
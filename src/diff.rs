@@ -0,0 +1,186 @@
+// Line-oriented diff between two buffers, using Myers' algorithm.
+
+use std::ops::Range;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Edit {
+  // a line present in both, at its index in the old and the new buffer
+  Same(usize, usize),
+  // a line of the old buffer removed from the new one
+  Delete(usize),
+  // a line of the new buffer missing from the old one
+  Insert(usize),
+}
+
+// Returns the shortest edit script turning `a` into `b`. The linear space
+// version of the algorithm finds the middle of the edits, then the edits on
+// either side of it, so that only two rows of diagonals are kept rather than
+// one for every edit.
+pub fn diff_lines(a: &[String], b: &[String]) -> Vec<Edit> {
+  let max = (a.len() + b.len()).div_ceil(2) + 1;
+  let (mut forward, mut backward) = (Diagonals::new(max), Diagonals::new(max));
+  let mut edits = Vec::new();
+  diff_ranges(a, 0..a.len(), b, 0..b.len(), &mut forward, &mut backward, &mut edits);
+  // within each change, lines removed come before lines added
+  for change in edits.split_mut(|edit| matches!(edit, Edit::Same(..))) {
+    change.sort_by_key(|edit| matches!(edit, Edit::Insert(_)));
+  }
+  edits
+}
+
+// The furthest x reached on each diagonal k = x - y, which can be negative
+struct Diagonals {
+  v: Vec<usize>,
+  offset: isize,
+}
+
+impl Diagonals {
+  fn new(max: usize) -> Self {
+    Diagonals{v: vec![0; 2 * max + 1], offset: max as isize}
+  }
+}
+
+impl std::ops::Index<isize> for Diagonals {
+  type Output = usize;
+
+  fn index(&self, k: isize) -> &usize {
+    &self.v[(k + self.offset) as usize]
+  }
+}
+
+impl std::ops::IndexMut<isize> for Diagonals {
+  fn index_mut(&mut self, k: isize) -> &mut usize {
+    &mut self.v[(k + self.offset) as usize]
+  }
+}
+
+fn common_prefix(a: &[String], b: &[String]) -> usize {
+  a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+fn common_suffix(a: &[String], b: &[String]) -> usize {
+  a.iter().rev().zip(b.iter().rev()).take_while(|(x, y)| x == y).count()
+}
+
+fn diff_ranges(
+  a: &[String],
+  mut xs: Range<usize>,
+  b: &[String],
+  mut ys: Range<usize>,
+  forward: &mut Diagonals,
+  backward: &mut Diagonals,
+  edits: &mut Vec<Edit>,
+) {
+  let prefix = common_prefix(&a[xs.clone()], &b[ys.clone()]);
+  edits.extend((0..prefix).map(|i| Edit::Same(xs.start + i, ys.start + i)));
+  xs.start += prefix;
+  ys.start += prefix;
+  let suffix = common_suffix(&a[xs.clone()], &b[ys.clone()]);
+  xs.end -= suffix;
+  ys.end -= suffix;
+  if xs.is_empty() || ys.is_empty() {
+    edits.extend(xs.clone().map(Edit::Delete));
+    edits.extend(ys.clone().map(Edit::Insert));
+  } else {
+    let (x, y) = middle_snake(a, xs.clone(), b, ys.clone(), forward, backward);
+    diff_ranges(a, xs.start..x, b, ys.start..y, forward, backward, edits);
+    diff_ranges(a, x..xs.end, b, y..ys.end, forward, backward, edits);
+  }
+  edits.extend((0..suffix).map(|i| Edit::Same(xs.end + i, ys.end + i)));
+}
+
+// Finds where the shortest edit script between two ranges, which differ at
+// their first and last lines, crosses its middle, by following it from both
+// ends at once until they meet.
+fn middle_snake(
+  a: &[String],
+  xs: Range<usize>,
+  b: &[String],
+  ys: Range<usize>,
+  forward: &mut Diagonals,
+  backward: &mut Diagonals,
+) -> (usize, usize) {
+  let (n, m) = (xs.len(), ys.len());
+  let delta = n as isize - m as isize;
+  let odd = delta & 1 == 1;
+  forward[1] = 0;
+  backward[1] = 0;
+  for d in 0..=((n + m).div_ceil(2) as isize) {
+    for k in (-d..=d).rev().step_by(2) {
+      let mut x = if k == -d || (k != d && forward[k - 1] < forward[k + 1]) {
+        forward[k + 1]
+      } else {
+        forward[k - 1] + 1
+      };
+      let y = (x as isize - k) as usize;
+      let start = (x, y);
+      if x < n && y < m {
+        x += common_prefix(&a[xs.start + x..xs.end], &b[ys.start + y..ys.end]);
+      }
+      forward[k] = x;
+      if odd && (k - delta).abs() < d && forward[k] + backward[delta - k] >= n {
+        return (xs.start + start.0, ys.start + start.1);
+      }
+    }
+    for k in (-d..=d).rev().step_by(2) {
+      let mut x = if k == -d || (k != d && backward[k - 1] < backward[k + 1]) {
+        backward[k + 1]
+      } else {
+        backward[k - 1] + 1
+      };
+      let mut y = (x as isize - k) as usize;
+      if x < n && y < m {
+        let same = common_suffix(&a[xs.start..xs.end - x], &b[ys.start..ys.end - y]);
+        x += same;
+        y += same;
+      }
+      backward[k] = x;
+      if !odd && (k - delta).abs() <= d && backward[k] + forward[delta - k] >= n {
+        return (xs.end - x, ys.end - y);
+      }
+    }
+  }
+  unreachable!("the two ends of an edit script always meet")
+}
+
+// Lines the two buffers up for showing side by side. Removed and added lines
+// between the same unchanged lines are paired off as changed lines.
+pub fn side_by_side(edits: &[Edit]) -> Vec<(Option<usize>, Option<usize>)> {
+  fn pair_off(
+    deleted: &mut Vec<usize>,
+    inserted: &mut Vec<usize>,
+    rows: &mut Vec<(Option<usize>, Option<usize>)>,
+  ) {
+    for i in 0..deleted.len().max(inserted.len()) {
+      rows.push((deleted.get(i).cloned(), inserted.get(i).cloned()));
+    }
+    deleted.clear();
+    inserted.clear();
+  }
+  let mut rows = Vec::new();
+  let (mut deleted, mut inserted) = (Vec::new(), Vec::new());
+  for edit in edits {
+    match *edit {
+      Edit::Same(a, b) => {
+        pair_off(&mut deleted, &mut inserted, &mut rows);
+        rows.push((Some(a), Some(b)));
+      }
+      Edit::Delete(a) => deleted.push(a),
+      Edit::Insert(b) => inserted.push(b),
+    }
+  }
+  pair_off(&mut deleted, &mut inserted, &mut rows);
+  rows
+}
+
+// Returns the ranges of characters that differ between two changed lines,
+// after skipping their common prefix and suffix.
+pub fn changed_chars(a: &str, b: &str) -> (Range<usize>, Range<usize>) {
+  let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+  let prefix = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+  let suffix = a[prefix..].iter().rev()
+    .zip(b[prefix..].iter().rev())
+    .take_while(|(x, y)| x == y)
+    .count();
+  (prefix..a.len() - suffix, prefix..b.len() - suffix)
+}
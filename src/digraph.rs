@@ -0,0 +1,46 @@
+// RFC 1345 digraphs for characters that are commonly missing from keyboards.
+// A digraph is typed as a base character followed by a mark, and may be typed
+// in either order.
+
+const ACCENTS: &[(char, &str, &str)] = &[
+  // mark, base letters, accented letters
+  ('\'', "aeiouyAEIOUYcCnNsSzZ", "áéíóúýÁÉÍÓÚÝćĆńŃśŚźŹ"),
+  ('!', "aeiouAEIOU", "àèìòùÀÈÌÒÙ"),
+  ('>', "aeiouAEIOU", "âêîôûÂÊÎÔÛ"),
+  ('?', "anoANO", "ãñõÃÑÕ"),
+  (':', "aeiouyAEIOUY", "äëïöüÿÄËÏÖÜŸ"),
+  (',', "cCsS", "çÇşŞ"),
+  ('<', "cCeEnNrRsSzZ", "čČěĚňŇřŘšŠžŽ"),
+  ('/', "oOdDlL", "øØđĐłŁ"),
+  ('a', "aA", "åÅ"),
+];
+
+const SYMBOLS: &[(&str, char)] = &[
+  ("ae", 'æ'), ("AE", 'Æ'), ("oe", 'œ'), ("OE", 'Œ'), ("ss", 'ß'),
+  ("!I", '¡'), ("?I", '¿'), ("<<", '«'), (">>", '»'), ("SE", '§'),
+  ("Co", '©'), ("Rg", '®'), ("DG", '°'), ("+-", '±'), ("My", 'µ'),
+  ("*X", '×'), ("-:", '÷'), ("Eu", '€'), ("Pd", '£'), ("Ye", '¥'),
+  ("Ct", '¢'), ("NS", '\u{a0}'), ("..", '…'), ("-N", '–'), ("-M", '—'),
+  ("'6", '‘'), ("'9", '’'), ("\"6", '“'), ("\"9", '”'), ("->", '→'),
+  ("<-", '←'), ("-!", '↑'), ("-v", '↓'), ("OK", '✓'), ("XX", '✗'),
+  ("a*", 'α'), ("b*", 'β'), ("g*", 'γ'), ("d*", 'δ'), ("e*", 'ε'),
+  ("l*", 'λ'), ("m*", 'μ'), ("p*", 'π'), ("s*", 'σ'), ("w*", 'ω'),
+];
+
+fn lookup_ordered(a: char, b: char) -> Option<char> {
+  for &(mark, bases, accented) in ACCENTS {
+    if b == mark {
+      if let Some(i) = bases.chars().position(|c| c == a) {
+        return accented.chars().nth(i);
+      }
+    }
+  }
+  SYMBOLS.iter().find(|&&(digraph, _)| {
+    let mut chars = digraph.chars();
+    chars.next() == Some(a) && chars.next() == Some(b)
+  }).map(|&(_, c)| c)
+}
+
+pub fn lookup(a: char, b: char) -> Option<char> {
+  lookup_ordered(a, b).or_else(|| lookup_ordered(b, a))
+}
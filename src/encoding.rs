@@ -0,0 +1,164 @@
+// Files in encodings other than UTF-8 are converted to it when they are read,
+// and back to their own encoding when they are saved.
+
+use std::convert::TryFrom;
+use std::io;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+  Utf8,
+  Utf16Le,
+  Utf16Be,
+  Latin1,
+  Windows1252,
+}
+
+const NAMES: &[(&str, Encoding)] = &[
+  ("utf-8", Encoding::Utf8),
+  ("utf-16le", Encoding::Utf16Le),
+  ("utf-16be", Encoding::Utf16Be),
+  ("latin-1", Encoding::Latin1),
+  ("windows-1252", Encoding::Windows1252),
+];
+
+// The characters of Windows-1252 that differ from Latin-1, for the bytes
+// 0x80 to 0x9f. The five bytes it leaves undefined keep their Latin-1
+// meaning.
+const WINDOWS_1252: [char; 32] = [
+  '\u{20ac}', '\u{81}', '\u{201a}', '\u{192}', '\u{201e}', '\u{2026}', '\u{2020}', '\u{2021}',
+  '\u{2c6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8d}', '\u{17d}', '\u{8f}',
+  '\u{90}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}', '\u{2022}', '\u{2013}', '\u{2014}',
+  '\u{2dc}', '\u{2122}', '\u{161}', '\u{203a}', '\u{153}', '\u{9d}', '\u{17e}', '\u{178}',
+];
+
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+
+impl Encoding {
+  // Accepts names like `latin1`, `ISO-8859-1` or `utf16le`.
+  pub fn by_name(name: &str) -> Option<Encoding> {
+    let name = name.to_ascii_lowercase().replace('_', "-");
+    let name = match name.as_str() {
+      "utf8" => "utf-8",
+      "utf16le" => "utf-16le",
+      "utf16be" => "utf-16be",
+      "latin1" | "iso-8859-1" | "iso8859-1" => "latin-1",
+      "cp1252" => "windows-1252",
+      name => name,
+    };
+    NAMES.iter().find(|(n, _)| *n == name).map(|&(_, encoding)| encoding)
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Encoding::Utf8 => "UTF-8",
+      Encoding::Utf16Le => "UTF-16LE",
+      Encoding::Utf16Be => "UTF-16BE",
+      Encoding::Latin1 => "Latin-1",
+      Encoding::Windows1252 => "Windows-1252",
+    }
+  }
+
+  fn bom(self) -> &'static [u8] {
+    match self {
+      Encoding::Utf8 => UTF8_BOM,
+      Encoding::Utf16Le => b"\xff\xfe",
+      Encoding::Utf16Be => b"\xfe\xff",
+      Encoding::Latin1 | Encoding::Windows1252 => b"",
+    }
+  }
+}
+
+// Guesses the encoding of a file and whether it starts with a byte order
+// mark. Text that is not valid UTF-8 is taken to be Latin-1, which any bytes
+// are.
+pub fn detect(bytes: &[u8]) -> (Encoding, bool) {
+  if bytes.starts_with(UTF8_BOM) {
+    (Encoding::Utf8, true)
+  } else if bytes.starts_with(Encoding::Utf16Le.bom()) {
+    (Encoding::Utf16Le, true)
+  } else if bytes.starts_with(Encoding::Utf16Be.bom()) {
+    (Encoding::Utf16Be, true)
+  } else if let Some(encoding) = detect_utf16(bytes) {
+    (encoding, false)
+  } else if std::str::from_utf8(bytes).is_ok() {
+    (Encoding::Utf8, false)
+  } else {
+    (Encoding::Latin1, false)
+  }
+}
+
+// UTF-16 without a byte order mark is told by its zero bytes: text that is
+// mostly ASCII has one in at least every other unit, always on the same side.
+fn detect_utf16(bytes: &[u8]) -> Option<Encoding> {
+  if bytes.len() < 2 || !bytes.len().is_multiple_of(2) {
+    return None;
+  }
+  let zeros = |side: usize| bytes.iter().skip(side).step_by(2).filter(|&&b| b == 0).count();
+  let (first, second) = (zeros(0), zeros(1));
+  let units = bytes.len() / 2;
+  let encoding = match (first, second) {
+    (0, n) if 2 * n >= units => Encoding::Utf16Le,
+    (n, 0) if 2 * n >= units => Encoding::Utf16Be,
+    _ => return None,
+  };
+  decode(encoding, bytes).ok().map(|_| encoding)
+}
+
+fn invalid(msg: String) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// Converts the bytes of a file to UTF-8, dropping its byte order mark.
+pub fn decode(encoding: Encoding, bytes: &[u8]) -> io::Result<String> {
+  let bytes = bytes.strip_prefix(encoding.bom()).unwrap_or(bytes);
+  match encoding {
+    Encoding::Utf8 => String::from_utf8(bytes.to_vec())
+      .map_err(|_| invalid(String::from("invalid UTF-8"))),
+    Encoding::Utf16Le | Encoding::Utf16Be => {
+      if !bytes.len().is_multiple_of(2) {
+        return Err(invalid(format!("odd number of bytes for {}", encoding.name())));
+      }
+      let units = bytes.chunks(2).map(|pair| match encoding {
+        Encoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+        _ => u16::from_be_bytes([pair[0], pair[1]]),
+      });
+      std::char::decode_utf16(units)
+        .collect::<Result<_, _>>()
+        .map_err(|_| invalid(format!("invalid {}", encoding.name())))
+    }
+    Encoding::Latin1 => Ok(bytes.iter().map(|&b| char::from(b)).collect()),
+    Encoding::Windows1252 => Ok(bytes.iter().map(|&b| match b {
+      0x80..=0x9f => WINDOWS_1252[usize::from(b - 0x80)],
+      _ => char::from(b),
+    }).collect()),
+  }
+}
+
+// Converts text back to the encoding of its file. Characters the encoding
+// has no bytes for are an error, rather than being lost.
+pub fn encode(encoding: Encoding, bom: bool, text: &str) -> io::Result<Vec<u8>> {
+  let mut bytes = Vec::new();
+  if bom {
+    bytes.extend_from_slice(encoding.bom());
+  }
+  let unencodable = |c: char| invalid(format!("{:?} cannot be saved as {}", c, encoding.name()));
+  match encoding {
+    Encoding::Utf8 => bytes.extend_from_slice(text.as_bytes()),
+    Encoding::Utf16Le => text.encode_utf16().for_each(|u| bytes.extend_from_slice(&u.to_le_bytes())),
+    Encoding::Utf16Be => text.encode_utf16().for_each(|u| bytes.extend_from_slice(&u.to_be_bytes())),
+    Encoding::Latin1 => for c in text.chars() {
+      bytes.push(u8::try_from(u32::from(c)).map_err(|_| unencodable(c))?);
+    },
+    Encoding::Windows1252 => for c in text.chars() {
+      let b = match WINDOWS_1252.iter().position(|&w| w == c) {
+        Some(i) => 0x80 + i as u8,
+        None => match u8::try_from(u32::from(c)) {
+          Ok(b) if !(0x80..=0x9f).contains(&b) => b,
+          _ => return Err(unencodable(c)),
+        },
+      };
+      bytes.push(b);
+    },
+  }
+  Ok(bytes)
+}
@@ -0,0 +1,200 @@
+// The editor run as an engine on a thread of its own, which frontends talk to
+// with messages: they send it keys, pastes and the size of the screen, and it
+// sends back the frames to show and what else the frontend has to do, like
+// suspending. The terminal is one frontend and `red --remote` another.
+//
+// An engine that panics tells its frontend so, rather than leaving it waiting
+// for frames that never come.
+
+use std::io::{self, Write};
+use std::mem;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use termion::event::Key;
+
+use crate::{Editor, Screen, VISUAL_BELL_DURATION};
+
+// How often the screen is drawn while a script plays, to show it is going
+const SCRIPT_DRAW_INTERVAL: Duration = Duration::from_millis(500);
+
+pub enum Request {
+  Key(Key),
+  Paste(String),
+  // keys played one after another, like those of `red --script`, with the
+  // screen drawn only once in a while until they are done
+  Script(Vec<Key>),
+  Resize(usize, usize),
+  Tick,
+  // draws the whole screen again, after the terminal was given back to it
+  Redraw,
+  // files to open and commands to run, as for `Editor::remote`, with what to
+  // do with the answer
+  Remote(Vec<String>, Box<dyn FnOnce(Result<String, String>) + Send>),
+  // stops the engine, leaving the files as they are
+  Stop,
+}
+
+pub enum Output {
+  // what to write to the terminal
+  Frame(Vec<u8>),
+  // the last key asked for the editor to be suspended, after which the
+  // frontend asks for a redraw
+  Suspend,
+  Quit,
+  Failed(io::Error),
+}
+
+// The terminal of the engine, whose output is sent to the frontend a frame
+// at a time
+struct Frames<F: Fn(Output) -> bool> {
+  output: Rc<F>,
+  bytes: Vec<u8>,
+}
+
+impl<F: Fn(Output) -> bool> Write for Frames<F> {
+  fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+    self.bytes.extend_from_slice(data);
+    Ok(data.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    if !self.bytes.is_empty() {
+      (self.output)(Output::Frame(mem::take(&mut self.bytes)));
+    }
+    Ok(())
+  }
+}
+
+struct Panicked<F: Fn(Output) -> bool>(Rc<F>);
+
+impl<F: Fn(Output) -> bool> Drop for Panicked<F> {
+  fn drop(&mut self) {
+    if thread::panicking() {
+      (self.0)(Output::Failed(io::Error::other("red stopped after an internal error")));
+    }
+  }
+}
+
+pub struct Engine {
+  requests: mpsc::Sender<Request>,
+  // the time each key took, returned when the engine stops
+  thread: thread::JoinHandle<Vec<Duration>>,
+}
+
+// Another frontend of a running engine
+#[derive(Clone)]
+pub struct Client {
+  requests: mpsc::Sender<Request>,
+}
+
+impl Client {
+  // Sends a request, returning false once the engine has stopped.
+  pub fn send(&self, request: Request) -> bool {
+    self.requests.send(request).is_ok()
+  }
+}
+
+impl Engine {
+  // Starts the editor on a thread of its own, drawing the screen for the
+  // first time and passing everything it outputs to `output`.
+  pub fn start<F>(editor: Editor, output: F) -> Engine
+  where
+    F: Fn(Output) -> bool + Send + 'static,
+  {
+    let (requests, rx) = mpsc::channel();
+    let thread = thread::spawn(move || run(editor, rx, output));
+    Engine{requests, thread}
+  }
+
+  pub fn send(&self, request: Request) -> bool {
+    self.requests.send(request).is_ok()
+  }
+
+  pub fn client(&self) -> Client {
+    Client{requests: self.requests.clone()}
+  }
+
+  // Stops the engine, if it is still running, and waits for it, returning
+  // the time each key took.
+  pub fn finish(self) -> Vec<Duration> {
+    let _ = self.requests.send(Request::Stop);
+    self.thread.join().unwrap_or_default()
+  }
+}
+
+fn run<F: Fn(Output) -> bool + 'static>(mut editor: Editor, requests: mpsc::Receiver<Request>, output: F) -> Vec<Duration> {
+  let output = Rc::new(output);
+  // dropped after the screen, whose last frame gives the terminal back its
+  // cursor and the rest
+  let _panicked = Panicked(output.clone());
+  let mut scr: Screen = Screen::new(Box::new(Frames{output: output.clone(), bytes: Vec::new()}));
+  let mut keys = Vec::new();
+  let mut res = editor.draw(&mut scr).map(|_| true);
+  while let Ok(true) = res {
+    let Ok(request) = requests.recv() else {
+      break;
+    };
+    let start = Instant::now();
+    let key = matches!(request, Request::Key(_));
+    res = handle(&mut editor, &mut scr, request);
+    if key {
+      keys.push(start.elapsed());
+    }
+    if editor.take_suspend() {
+      output(Output::Suspend);
+    }
+  }
+  drop(scr);
+  match res {
+    Ok(true) => true,
+    Ok(false) => output(Output::Quit),
+    Err(err) => output(Output::Failed(err)),
+  };
+  keys
+}
+
+// Handles a request, returning false once the editor quits.
+fn handle(editor: &mut Editor, scr: &mut Screen, request: Request) -> io::Result<bool> {
+  scr.end_flash(VISUAL_BELL_DURATION)?;
+  let redraw = match request {
+    Request::Key(key) => if editor.handle_key(key)? { true } else { return Ok(false) },
+    Request::Paste(text) => if editor.paste(&text)? { true } else { return Ok(false) },
+    Request::Script(keys) => {
+      let mut drawn = Instant::now();
+      for key in keys {
+        if !editor.handle_key(key)? || editor.has_quit() {
+          return Ok(false);
+        }
+        if drawn.elapsed() >= SCRIPT_DRAW_INTERVAL {
+          editor.draw(scr)?;
+          drawn = Instant::now();
+        }
+      }
+      true
+    }
+    Request::Resize(rows, cols) => {
+      editor.resize(rows, cols);
+      true
+    }
+    Request::Tick => editor.tick()?,
+    Request::Redraw => {
+      scr.forget();
+      true
+    }
+    Request::Remote(args, reply) => {
+      reply(editor.remote(&args));
+      true
+    }
+    Request::Stop => return Ok(false),
+  };
+  if editor.has_quit() {
+    return Ok(false);
+  }
+  if redraw {
+    editor.draw(scr)?;
+  }
+  Ok(true)
+}
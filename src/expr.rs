@@ -0,0 +1,163 @@
+// Expressions for inserting dynamic text: the current date and time, random
+// identifiers, or the result of simple arithmetic.
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, Read};
+use std::iter::Peekable;
+use std::mem;
+use std::os::raw::c_char;
+use std::ptr;
+use std::str::Chars;
+
+// Formats the current local time with a strftime(3) format.
+pub fn format_local_time(format: &str) -> String {
+  let format = match CString::new(format) {
+    Ok(format) => format,
+    Err(_) => return String::new(),
+  };
+  let mut out = [0u8; 256];
+  let len = unsafe {
+    let now = libc::time(ptr::null_mut());
+    let mut tm: libc::tm = mem::zeroed();
+    libc::localtime_r(&now, &mut tm);
+    libc::strftime(out.as_mut_ptr() as *mut c_char, out.len(), format.as_ptr(), &tm)
+  };
+  String::from_utf8_lossy(&out[..len]).into_owned()
+}
+
+fn random_bytes(n: usize) -> io::Result<Vec<u8>> {
+  let mut bytes = vec![0; n];
+  File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+  Ok(bytes)
+}
+
+// Formats 16 random bytes as a version 4 UUID.
+pub fn format_uuid(mut bytes: [u8; 16]) -> String {
+  bytes[6] = (bytes[6] & 0x0f) | 0x40;
+  bytes[8] = (bytes[8] & 0x3f) | 0x80;
+  let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+  format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+pub fn uuid() -> io::Result<String> {
+  let mut bytes = [0; 16];
+  bytes.copy_from_slice(&random_bytes(16)?);
+  Ok(format_uuid(bytes))
+}
+
+const TOKEN_CHARS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+const TOKEN_LEN: usize = 20;
+
+// A random string of letters and digits.
+pub fn token() -> io::Result<String> {
+  let mut token = String::new();
+  while token.len() < TOKEN_LEN {
+    // Bytes past the last whole multiple of the alphabet size are skipped so
+    // that every character is equally likely.
+    let limit = 256 - 256 % TOKEN_CHARS.len();
+    for b in random_bytes(TOKEN_LEN)? {
+      if (b as usize) < limit && token.len() < TOKEN_LEN {
+        token.push(TOKEN_CHARS[b as usize % TOKEN_CHARS.len()] as char);
+      }
+    }
+  }
+  Ok(token)
+}
+
+struct Parser<'a> {
+  chars: Peekable<Chars<'a>>,
+}
+
+impl<'a> Parser<'a> {
+  fn skip_spaces(&mut self) {
+    while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+      self.chars.next();
+    }
+  }
+
+  fn next_if(&mut self, c: char) -> bool {
+    self.skip_spaces();
+    self.chars.next_if_eq(&c).is_some()
+  }
+
+  fn expr(&mut self) -> Result<f64, String> {
+    let mut value = self.term()?;
+    loop {
+      if self.next_if('+') {
+        value += self.term()?;
+      } else if self.next_if('-') {
+        value -= self.term()?;
+      } else {
+        return Ok(value);
+      }
+    }
+  }
+
+  fn term(&mut self) -> Result<f64, String> {
+    let mut value = self.factor()?;
+    loop {
+      if self.next_if('*') {
+        value *= self.factor()?;
+      } else if self.next_if('/') {
+        value /= self.factor()?;
+      } else if self.next_if('%') {
+        value %= self.factor()?;
+      } else {
+        return Ok(value);
+      }
+    }
+  }
+
+  fn factor(&mut self) -> Result<f64, String> {
+    if self.next_if('-') {
+      return self.factor().map(|value| -value);
+    }
+    if self.next_if('+') {
+      return self.factor();
+    }
+    if self.next_if('(') {
+      let value = self.expr()?;
+      if !self.next_if(')') {
+        return Err(String::from("missing `)`"));
+      }
+      return Ok(value);
+    }
+    let mut number = String::new();
+    while let Some(c) = self.chars.next_if(|c| c.is_ascii_digit() || *c == '.') {
+      number.push(c);
+    }
+    number.parse().map_err(|_| match self.chars.peek() {
+      Some(c) => format!("unexpected `{}`", c),
+      None => String::from("unexpected end of expression"),
+    })
+  }
+}
+
+fn format_number(value: f64) -> String {
+  if value.fract() == 0.0 && value.abs() < 1e15 {
+    format!("{}", value as i64)
+  } else {
+    format!("{}", value)
+  }
+}
+
+// Evaluates `date`, `time`, `uuid`, `token`, or an arithmetic expression to
+// the text to insert. The date and time are formatted with the given formats.
+pub fn eval(text: &str, date_format: &str, time_format: &str) -> Result<String, String> {
+  match text.trim() {
+    "date" => return Ok(format_local_time(date_format)),
+    "time" => return Ok(format_local_time(time_format)),
+    "uuid" => return uuid().map_err(|err| err.to_string()),
+    "token" => return token().map_err(|err| err.to_string()),
+    _ => (),
+  }
+  let mut parser = Parser{chars: text.chars().peekable()};
+  let value = parser.expr()?;
+  parser.skip_spaces();
+  match parser.chars.peek() {
+    Some(c) => Err(format!("unexpected `{}`", c)),
+    None if value.is_finite() => Ok(format_number(value)),
+    None => Err(String::from("result is not a number")),
+  }
+}
@@ -0,0 +1,50 @@
+// Kinds of files, told apart by their names, and what the editor needs to
+// know about each, like how a line comment starts.
+
+use std::path::Path;
+
+use crate::syntax::{self, Language};
+
+pub struct FileType {
+  // extensions, or whole names for files like `Makefile`
+  pub names: &'static [&'static str],
+  pub comment: &'static str,
+  // how to highlight and find the structure of the code
+  pub syntax: Option<&'static Language>,
+}
+
+const FILE_TYPES: &[FileType] = &[
+  FileType{names: &["rs"], comment: "//", syntax: Some(&syntax::RUST)},
+  FileType{names: &["c", "h"], comment: "//", syntax: Some(&syntax::C)},
+  FileType{names: &["cc", "cpp", "cxx", "hh", "hpp"], comment: "//", syntax: Some(&syntax::C)},
+  FileType{names: &["go"], comment: "//", syntax: Some(&syntax::GO)},
+  FileType{names: &["java", "kt", "scala"], comment: "//", syntax: Some(&syntax::JAVA)},
+  FileType{names: &["js", "jsx", "mjs", "ts", "tsx"], comment: "//", syntax: Some(&syntax::JAVASCRIPT)},
+  FileType{names: &["swift"], comment: "//", syntax: None},
+  FileType{names: &["py"], comment: "#", syntax: Some(&syntax::PYTHON)},
+  FileType{names: &["rb"], comment: "#", syntax: Some(&syntax::RUBY)},
+  FileType{names: &["pl", "pm"], comment: "#", syntax: None},
+  FileType{names: &["sh", "bash", "zsh", ".bashrc", ".profile"], comment: "#", syntax: Some(&syntax::SHELL)},
+  FileType{names: &["Makefile", "makefile", "mk"], comment: "#", syntax: None},
+  FileType{names: &["Dockerfile"], comment: "#", syntax: None},
+  FileType{names: &["toml"], comment: "#", syntax: None},
+  FileType{names: &["yaml", "yml"], comment: "#", syntax: None},
+  FileType{names: &["conf", "cfg", ".gitignore"], comment: "#", syntax: None},
+  FileType{names: &["sql"], comment: "--", syntax: None},
+  FileType{names: &["lua"], comment: "--", syntax: Some(&syntax::LUA)},
+  FileType{names: &["hs"], comment: "--", syntax: None},
+  FileType{names: &["el", "lisp", "clj", "scm"], comment: ";;", syntax: None},
+  FileType{names: &["ini"], comment: ";", syntax: None},
+  FileType{names: &["tex", "sty"], comment: "%", syntax: None},
+  FileType{names: &["erl"], comment: "%", syntax: None},
+  FileType{names: &["vim", ".vimrc"], comment: "\"", syntax: None},
+];
+
+// Finds the type of a file from its whole name, or else its extension.
+pub fn detect(path: &str) -> Option<&'static FileType> {
+  let path = Path::new(path);
+  let name = path.file_name()?.to_str()?;
+  let ext = path.extension().and_then(|ext| ext.to_str());
+  FILE_TYPES.iter().find(|ft| ft.names.contains(&name))
+    .or_else(|| FILE_TYPES.iter().find(|ft| ext.is_some_and(|ext| ft.names.contains(&ext))))
+}
@@ -0,0 +1,170 @@
+// Files stored in an encoded form, like encrypted or compressed files, are
+// edited through external tools: the file is decoded into the buffer when it
+// is opened and encoded again when it is saved, so the plain text is never
+// written to disk.
+
+use std::io::{self, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+
+pub struct Filter {
+  extension: &'static str,
+  // given the path of the file, writes the plain text to stdout
+  decode: &'static [&'static str],
+  // reads the plain text from stdin and writes the encoded file to stdout,
+  // with the options the file was encoded with, or the default ones
+  encode: &'static [&'static str],
+  default: &'static [&'static str],
+  // finds the options a file was encoded with
+  options: Option<fn(&Path) -> Vec<String>>,
+}
+
+const FILTERS: &[Filter] = &[
+  Filter{
+    extension: "gpg",
+    decode: &["gpg", "--quiet", "--decrypt"],
+    encode: &["gpg", "--quiet", "--batch", "--yes"],
+    default: &["--encrypt", "--default-recipient-self"],
+    options: Some(gpg_options),
+  },
+  // only files encrypted with a passphrase, as `age` has no identity to
+  // decrypt others with unless it is named
+  Filter{extension: "age", decode: &["age", "--decrypt"], encode: &["age", "--encrypt", "--passphrase"], default: &[], options: None},
+  Filter{extension: "gz", decode: &["gzip", "-dc"], encode: &["gzip", "-c"], default: &[], options: None},
+  Filter{extension: "xz", decode: &["xz", "-dc"], encode: &["xz", "-c"], default: &[], options: None},
+  Filter{extension: "zst", decode: &["zstd", "-qdc"], encode: &["zstd", "-qc"], default: &[], options: None},
+];
+
+pub fn find(path: &Path) -> Option<&'static Filter> {
+  let extension = path.extension()?.to_str()?;
+  FILTERS.iter().find(|filter| filter.extension == extension)
+}
+
+fn command(args: &[&str]) -> Command {
+  let mut command = Command::new(args[0]);
+  command.args(&args[1..]);
+  command
+}
+
+fn command_error(args: &[&str], stderr: &[u8]) -> io::Error {
+  let msg = String::from_utf8_lossy(stderr);
+  let msg = msg.lines().last().unwrap_or("failed");
+  io::Error::other(format!("{}: {}", args[0], msg))
+}
+
+// Runs the decoder on a file. It keeps the terminal for stdin and stderr, so
+// that it can ask for a passphrase once the terminal is out of raw mode.
+pub fn decode(filter: &Filter, path: &Path) -> io::Result<Vec<u8>> {
+  let output = command(filter.decode)
+    .arg(path)
+    .stdin(Stdio::inherit())
+    .stderr(Stdio::inherit())
+    .output()?;
+  if !output.status.success() {
+    return Err(command_error(filter.decode, b""));
+  }
+  Ok(output.stdout)
+}
+
+// The options to encode a file with the next time it is saved, found when it
+// is opened. A file they can't be found for gets the default ones.
+pub fn options(filter: &Filter, path: &Path) -> Vec<String> {
+  filter.options.map_or_else(Vec::new, |options| options(path))
+}
+
+pub fn encode(filter: &Filter, options: &[String], text: &[u8]) -> io::Result<Vec<u8>> {
+  let mut args = filter.encode.to_vec();
+  match options {
+    [] => args.extend(filter.default),
+    _ => args.extend(options.iter().map(String::as_str)),
+  }
+  pipe(&args, text)
+}
+
+// Lists the packets of an encrypted file without decrypting it, so without a
+// passphrase.
+fn gpg_options(path: &Path) -> Vec<String> {
+  let output = Command::new("gpg")
+    .args(["--batch", "--list-only", "--list-packets"])
+    .arg(path)
+    .stdin(Stdio::null())
+    .stderr(Stdio::null())
+    .output();
+  match output {
+    Ok(ref output) if output.status.success() => gpg_options_from(&String::from_utf8_lossy(&output.stdout)),
+    _ => Vec::new(),
+  }
+}
+
+// Encrypts again to the keys a file was encrypted to, and with a passphrase
+// if it had one. Recipients hidden with `--throw-keyids` can't be found.
+pub fn gpg_options_from(packets: &str) -> Vec<String> {
+  let mut recipients = Vec::new();
+  let mut symmetric = false;
+  for line in packets.lines() {
+    if line.starts_with(":symkey enc packet:") {
+      symmetric = true;
+    } else if line.starts_with(":pubkey enc packet:") {
+      match line.split("keyid ").nth(1).and_then(|rest| rest.split_whitespace().next()) {
+        Some(keyid) if keyid.chars().any(|c| c != '0') && !recipients.iter().any(|r| r == keyid) =>
+          recipients.push(keyid.to_string()),
+        _ => (),
+      }
+    }
+  }
+  let mut options = Vec::new();
+  if !recipients.is_empty() {
+    options.push(String::from("--encrypt"));
+    for keyid in recipients {
+      options.push(String::from("--recipient"));
+      options.push(keyid);
+    }
+  }
+  if symmetric {
+    options.push(String::from("--symmetric"));
+  }
+  options
+}
+
+// Runs a shell command for what it prints, with what it writes to stderr
+// mixed in where it was written. A command that fails is no error here, as a
+// failed build still has messages to show.
+pub fn capture(command: &str) -> io::Result<(Vec<u8>, ExitStatus)> {
+  let output = Command::new("sh")
+    .arg("-c")
+    .arg(format!("exec 2>&1\n{}", command))
+    .stdin(Stdio::null())
+    .output()?;
+  Ok((output.stdout, output.status))
+}
+
+// Runs a command with `input` as its stdin and returns its stdout.
+pub fn pipe(args: &[&str], input: &[u8]) -> io::Result<Vec<u8>> {
+  let mut child = command(args)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()?;
+  // Written from another thread, so that a command that starts writing
+  // before it has read all of its input does not block on a full pipe.
+  let mut stdin = child.stdin.take().unwrap();
+  let input = input.to_vec();
+  let writer = thread::spawn(move || stdin.write_all(&input));
+  let output = child.wait_with_output()?;
+  let written = writer.join().unwrap_or(Ok(()));
+  if output.status.signal().is_some() {
+    return Err(io::Error::new(io::ErrorKind::Interrupted, format!("{}: stopped", args[0])));
+  }
+  if !output.status.success() {
+    return Err(command_error(args, &output.stderr));
+  }
+  // A command that succeeds without reading all of its input, like `head`,
+  // only wanted part of it.
+  match written {
+    Err(ref err) if err.kind() == io::ErrorKind::BrokenPipe => (),
+    written => written?,
+  }
+  Ok(output.stdout)
+}
@@ -0,0 +1,193 @@
+// Folds found from indentation. A line followed by more indented lines
+// starts a fold of them, up to the next line indented no more than itself,
+// without the blank lines at its end. Closing a fold hides its lines, leaving
+// the line that starts it.
+
+use std::ops::Range;
+
+use crate::lines::Lines;
+
+// The width of a line's indentation, with tabs to the next multiple of 8, or
+// none for a blank line
+fn indent(line: &str) -> Option<usize> {
+  if line.trim().is_empty() {
+    return None;
+  }
+  let mut width = 0;
+  for c in line.chars() {
+    match c {
+      ' ' => width += 1,
+      '\t' => width = (width / 8 + 1) * 8,
+      _ => break,
+    }
+  }
+  Some(width)
+}
+
+// The indentation of the next line below `row` that isn't blank
+fn next_indent(buf: &Lines, row: usize) -> Option<usize> {
+  buf.iter().skip(row + 1).find_map(|line| indent(line))
+}
+
+pub fn starts_fold(buf: &Lines, row: usize) -> bool {
+  match (buf.get(row).and_then(|line| indent(line)), next_indent(buf, row)) {
+    (Some(level), Some(next)) => next > level,
+    _ => false,
+  }
+}
+
+// The lines of the fold started by the line on `row`, if it starts one
+pub fn fold_from(buf: &Lines, row: usize) -> Option<Range<usize>> {
+  if !starts_fold(buf, row) {
+    return None;
+  }
+  let level = indent(&buf[row])?;
+  let mut end = row + 1;
+  let mut last = None;
+  while end < buf.len() {
+    match indent(&buf[end]) {
+      Some(n) if n <= level => break,
+      Some(_) => last = Some(end),
+      None => (),
+    }
+    end += 1;
+  }
+  last.map(|last| row + 1..last + 1)
+}
+
+// The innermost fold with the line on `row`, or started by it
+pub fn fold_at(buf: &Lines, row: usize) -> Option<Range<usize>> {
+  (0..=row.min(buf.len().saturating_sub(1))).rev()
+    .filter_map(|start| fold_from(buf, start))
+    .find(|fold| fold.end > row)
+}
+
+// Every fold in the lines, outer ones first
+pub fn all(buf: &Lines) -> Vec<Range<usize>> {
+  (0..buf.len()).filter_map(|row| fold_from(buf, row)).collect()
+}
+
+// How many folds each line is in, counting the one it starts, found in one
+// pass from the indentation of the lines that start the folds around it
+pub fn levels(buf: &Lines) -> Vec<usize> {
+  let mut next = vec![None; buf.len()];
+  for row in (0..buf.len().saturating_sub(1)).rev() {
+    next[row] = indent(&buf[row + 1]).or(next[row + 1]);
+  }
+  let mut levels = vec![0; buf.len()];
+  let mut starts: Vec<usize> = Vec::new();
+  for row in 0..buf.len() {
+    // a blank line is in the folds the next line is in
+    let level = indent(&buf[row]);
+    if let Some(n) = level.or(next[row]) {
+      while starts.last().is_some_and(|&start| start >= n) {
+        starts.pop();
+      }
+    } else {
+      starts.clear();
+    }
+    if let (Some(level), Some(next)) = (level, next[row]) {
+      if next > level {
+        starts.push(level);
+      }
+    }
+    levels[row] = starts.len();
+  }
+  levels
+}
+
+// The folds that are closed, whose lines are hidden. A fold inside another
+// stays closed when the outer one is opened.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Folds {
+  closed: Vec<Range<usize>>,
+}
+
+impl Folds {
+  pub fn new() -> Self {
+    Folds::default()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.closed.is_empty()
+  }
+
+  pub fn close(&mut self, fold: Range<usize>) {
+    if !self.closed.contains(&fold) {
+      self.closed.push(fold);
+      self.closed.sort_by_key(|fold| (fold.start, fold.end));
+    }
+  }
+
+  // Opens the closed fold started by the line on `row`, returning whether
+  // there was one.
+  pub fn open(&mut self, row: usize) -> bool {
+    let n = self.closed.len();
+    self.closed.retain(|fold| fold.start != row + 1);
+    self.closed.len() != n
+  }
+
+  // Opens the folds hiding the line on `row`, returning whether there were
+  // any.
+  pub fn reveal(&mut self, row: usize) -> bool {
+    let n = self.closed.len();
+    self.closed.retain(|fold| !fold.contains(&row));
+    self.closed.len() != n
+  }
+
+  pub fn clear(&mut self) {
+    self.closed.clear();
+  }
+
+  pub fn is_closed(&self, row: usize) -> bool {
+    self.closed.iter().any(|fold| fold.start == row + 1)
+  }
+
+  pub fn is_hidden(&self, row: usize) -> bool {
+    self.closed.iter().any(|fold| fold.contains(&row))
+  }
+
+  // The lines hidden by the closed fold started by the line on `row`
+  pub fn hidden_after(&self, row: usize) -> usize {
+    self.closed.iter().filter(|fold| fold.start == row + 1).map(|fold| fold.len()).max().unwrap_or(0)
+  }
+
+  // The first line of the closed folds, which starts each of them
+  pub fn starts(&self) -> Vec<usize> {
+    self.closed.iter().map(|fold| fold.start - 1).collect()
+  }
+
+  // How many of the lines are shown
+  pub fn shown(&self, rows: Range<usize>) -> usize {
+    rows.filter(|&row| !self.is_hidden(row)).count()
+  }
+
+  // The line `n` shown lines above the one on `row`, or the first line, and
+  // the line starting the fold that hides it, if it is hidden
+  pub fn up(&self, row: usize, n: usize) -> usize {
+    let mut row = row;
+    let mut left = n;
+    while left > 0 && row > 0 {
+      row -= 1;
+      if !self.is_hidden(row) {
+        left -= 1;
+      }
+    }
+    while row > 0 && self.is_hidden(row) {
+      row -= 1;
+    }
+    row
+  }
+
+  // Moves the folds with the lines added and removed, dropping those whose
+  // lines were replaced.
+  pub fn adjust(&mut self, start: usize, removed: usize, added: usize) {
+    let end = start + removed;
+    self.closed.retain(|fold| fold.end <= start || fold.start >= end);
+    for fold in self.closed.iter_mut() {
+      if fold.start >= end {
+        *fold = fold.start - removed + added..fold.end - removed + added;
+      }
+    }
+  }
+}
@@ -0,0 +1,50 @@
+// Fuzzy matching of names, for picking an open file by typing a few letters
+// of it. The letters typed have to appear in the name in order, but not
+// next to each other, and a lowercase letter matches either case.
+//
+// A match scores a point for each letter, and more for letters at the start
+// of a word or in the file name after the last `/`, and for runs of them, so
+// that `ma` finds `src/main.rs` before `src/format.rs`. Of the ways the
+// letters can be found in the name, the one that scores best counts.
+
+fn starts_word(prev: Option<char>, c: char) -> bool {
+  match prev {
+    None => true,
+    Some(prev) => !prev.is_alphanumeric() || (prev.is_lowercase() && c.is_uppercase()),
+  }
+}
+
+fn matches(q: char, c: char) -> bool {
+  if q.is_lowercase() { c.to_lowercase().eq(q.to_lowercase()) } else { q == c }
+}
+
+// How well the query matches the name, or none if it doesn't.
+pub fn score(query: &str, name: &str) -> Option<usize> {
+  if query.is_empty() {
+    return Some(0);
+  }
+  let name: Vec<char> = name.chars().collect();
+  let base = name.iter().rposition(|&c| c == '/').map_or(0, |i| i + 1);
+  let points = |j: usize| {
+    let word = starts_word(j.checked_sub(1).map(|k| name[k]), name[j]);
+    1 + if word { 3 } else { 0 } + if j >= base { 1 } else { 0 }
+  };
+  // the best scores of the letters so far, by where the last of them is
+  let mut scores: Vec<Option<usize>> = Vec::new();
+  for (i, q) in query.chars().enumerate() {
+    let mut next = vec![None; name.len()];
+    // the best score of the letters so far ending before the last position
+    let mut before = if i == 0 { Some(0) } else { None };
+    for j in 0..name.len() {
+      if i > 0 && j >= 2 {
+        before = before.max(scores[j - 2]);
+      }
+      if matches(q, name[j]) {
+        let run = if i > 0 && j > 0 { scores[j - 1].map(|score| score + 2) } else { None };
+        next[j] = before.max(run).map(|score| score + points(j));
+      }
+    }
+    scores = next;
+  }
+  scores.into_iter().max().flatten()
+}
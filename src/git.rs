@@ -0,0 +1,93 @@
+// Files in a git repository show which of their lines differ from the index
+// in a column left of the text, and can be committed from the editor.
+
+use std::io;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+use crate::diff;
+use crate::lines::Lines;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sign {
+  Added,
+  Modified,
+  // lines removed before this one
+  Deleted,
+}
+
+// Runs git in the directory of the file.
+fn git(path: &Path, args: &[&str]) -> io::Result<Output> {
+  let dir = match path.parent() {
+    Some(dir) if !dir.as_os_str().is_empty() => dir,
+    _ => Path::new("."),
+  };
+  Command::new("git").arg("-C").arg(dir).args(args).stdin(Stdio::null()).output()
+}
+
+fn check(out: Output) -> io::Result<Output> {
+  if out.status.success() {
+    return Ok(out);
+  }
+  let text = if out.stderr.is_empty() { &out.stdout } else { &out.stderr };
+  let msg = String::from_utf8_lossy(text).lines().next().unwrap_or("git failed").to_string();
+  Err(io::Error::other(msg))
+}
+
+// Returns the lines of the file as they are in the index, no lines for a file
+// git doesn't track yet, or None outside a repository.
+pub fn index_lines(path: &str) -> Option<Vec<String>> {
+  let path = Path::new(path);
+  let name = path.file_name()?.to_str()?;
+  let inside = git(path, &["rev-parse", "--is-inside-work-tree"]).ok()?;
+  if !inside.status.success() || inside.stdout.trim_ascii() != b"true" {
+    return None;
+  }
+  match git(path, &["show", &format!(":./{}", name)]) {
+    Ok(out) if out.status.success() =>
+      Some(String::from_utf8_lossy(&out.stdout).lines().map(String::from).collect()),
+    _ => Some(Vec::new()),
+  }
+}
+
+// Returns the sign of each line of the buffer. Lines deleted from the end are
+// shown on the last line.
+pub fn signs(index: &[String], buf: &Lines) -> Vec<Option<Sign>> {
+  let mut signs = vec![None; buf.len() + 1];
+  // the buffer line after the last one seen
+  let mut next = 0;
+  for row in diff::side_by_side(&diff::diff_lines(index, &buf.to_vec())) {
+    match row {
+      (Some(i), Some(j)) => {
+        if index[i] != buf[j] {
+          signs[j] = Some(Sign::Modified);
+        }
+        next = j + 1;
+      }
+      (None, Some(j)) => {
+        signs[j] = Some(Sign::Added);
+        next = j + 1;
+      }
+      (Some(_), None) => {
+        signs[next].get_or_insert(Sign::Deleted);
+      }
+      (None, None) => (),
+    }
+  }
+  let end = signs.pop().flatten();
+  if let (Some(last), Some(sign)) = (signs.last_mut(), end) {
+    last.get_or_insert(sign);
+  }
+  signs
+}
+
+// Stages the file and commits it alone, returning the first line of git's
+// summary of the commit.
+pub fn commit(path: &str, message: &str) -> io::Result<String> {
+  let path = Path::new(path);
+  let name = path.file_name().and_then(|name| name.to_str())
+    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No file name"))?;
+  check(git(path, &["add", "--", name])?)?;
+  let out = check(git(path, &["commit", "-m", message, "--", name])?)?;
+  Ok(String::from_utf8_lossy(&out.stdout).lines().next().unwrap_or("").to_string())
+}
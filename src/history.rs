@@ -0,0 +1,72 @@
+// Local history: a copy of each saved version of a file, kept under
+// `~/.config/red/history/` independently of any version control.
+//
+// Each file gets its own directory, named after its absolute path with `/`
+// replaced by `%`, holding one copy per save named by the time of the save in
+// milliseconds since the Unix epoch.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn history_dir(root: &Path, path: &Path) -> io::Result<PathBuf> {
+  let path = fs::canonicalize(path)?;
+  Ok(root.join(path.to_string_lossy().replace('/', "%")))
+}
+
+// Saved versions of a file, newest first, as (milliseconds, copy) pairs.
+pub fn versions(root: &Path, path: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+  let dir = history_dir(root, path)?;
+  let entries = match fs::read_dir(&dir) {
+    Ok(entries) => entries,
+    Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+    Err(err) => return Err(err),
+  };
+  let mut versions = Vec::new();
+  for entry in entries {
+    let entry = entry?;
+    if let Some(millis) = entry.file_name().to_str().and_then(|name| name.parse().ok()) {
+      versions.push((millis, entry.path()));
+    }
+  }
+  versions.sort_by(|a, b| b.cmp(a));
+  Ok(versions)
+}
+
+// Copies the just-saved file into its history, keeping at most `keep` copies.
+pub fn record(root: &Path, path: &Path, keep: usize) -> io::Result<()> {
+  if keep == 0 {
+    return Ok(());
+  }
+  let dir = history_dir(root, path)?;
+  fs::create_dir_all(&dir)?;
+  let millis = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |d| d.as_millis() as u64);
+  let newest = versions(root, path)?.first().map_or(0, |v| v.0);
+  fs::copy(path, dir.join(millis.max(newest + 1).to_string()))?;
+  for (_, old) in versions(root, path)?.iter().skip(keep) {
+    fs::remove_file(old)?;
+  }
+  Ok(())
+}
+
+// Formats a time in seconds since the Unix epoch as a UTC date and time.
+pub fn format_time(secs: u64) -> String {
+  // days to a civil date, from Howard Hinnant's date algorithms
+  let days = (secs / 86400) as i64 + 719_468;
+  let era = days.div_euclid(146_097);
+  let doe = days.rem_euclid(146_097);
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = doy - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+  let secs = secs % 86400;
+  format!(
+    "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+    year, month, day, secs / 3600, secs / 60 % 60, secs % 60,
+  )
+}
@@ -0,0 +1,64 @@
+// Where the cursor was before each jump, like a search or going to a line, so
+// that it can go back and forth between them like the pages of a browser.
+
+type Pos = (usize, usize);
+
+const LIMIT: usize = 100;
+
+pub struct JumpList {
+  positions: Vec<Pos>,
+  // the position gone back to, or the end of the list when not going back
+  index: usize,
+}
+
+impl JumpList {
+  pub fn new() -> Self {
+    JumpList{positions: Vec::new(), index: 0}
+  }
+
+  // A list of the positions, oldest first, as kept from another session
+  pub fn from(mut positions: Vec<Pos>) -> Self {
+    positions.drain(..positions.len().saturating_sub(LIMIT));
+    let index = positions.len();
+    JumpList{positions, index}
+  }
+
+  pub fn positions(&self) -> &[Pos] {
+    &self.positions
+  }
+
+  // Records a jump from `from`, forgetting the positions gone back from.
+  pub fn push(&mut self, from: Pos) {
+    if self.index < self.positions.len() {
+      self.positions.truncate(self.index + 1);
+    }
+    if self.positions.last() != Some(&from) {
+      self.positions.push(from);
+    }
+    if self.positions.len() > LIMIT {
+      self.positions.remove(0);
+    }
+    self.index = self.positions.len();
+  }
+
+  // Returns the position before `at`, which is kept to come forward to again.
+  pub fn back(&mut self, at: Pos) -> Option<Pos> {
+    if self.index == self.positions.len() {
+      self.push(at);
+      self.index = self.positions.len() - 1;
+    }
+    if self.index == 0 {
+      return None;
+    }
+    self.index -= 1;
+    Some(self.positions[self.index])
+  }
+
+  pub fn forward(&mut self) -> Option<Pos> {
+    if self.index + 1 >= self.positions.len() {
+      return None;
+    }
+    self.index += 1;
+    Some(self.positions[self.index])
+  }
+}
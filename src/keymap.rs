@@ -0,0 +1,547 @@
+// Keys are bound to named actions, so that the bindings can be changed in the
+// config with settings like `normal.Ctrl-s = "save"` or `insert.Tab = "none"`.
+// An action can also be bound to a sequence of keys, like `normal."g c"`.
+
+use std::collections::HashMap;
+
+use termion::event::Key;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+  // normal mode
+  MoveLeft,
+  MoveRight,
+  MoveUp,
+  MoveDown,
+  PrevBlank,
+  NextBlank,
+  PrevBlankLine,
+  NextBlankLine,
+  NextWord,
+  PrevWord,
+  WordEnd,
+  LineStart,
+  LineEnd,
+  NextConflict,
+  PrevConflict,
+  Insert,
+  DeleteLine,
+  CopyLine,
+  PasteLine,
+  CutLine,
+  DeleteChar,
+  // operators, which apply to the text a motion moves over
+  Cut,
+  Copy,
+  Change,
+  NextCell,
+  PrevCell,
+  Visual,
+  VisualBlock,
+  BlockInsert,
+  BlockAppend,
+  Transpose,
+  Indent,
+  Dedent,
+  MoveLineUp,
+  MoveLineDown,
+  GotoLine,
+  GotoFirstLine,
+  ToggleComment,
+  Reflow,
+  ToggleFold,
+  OpenFold,
+  CloseFold,
+  OpenAllFolds,
+  CloseAllFolds,
+  PrevFunction,
+  NextFunction,
+  BlockStart,
+  BlockEnd,
+  AroundFunction,
+  InnerFunction,
+  AroundBlock,
+  InnerBlock,
+  AroundArgument,
+  InnerArgument,
+  HexPanel,
+  Stats,
+  NextTodo,
+  PrevTodo,
+  TodoList,
+  ShowNote,
+  ToggleBookmark,
+  NextBookmark,
+  PrevBookmark,
+  BookmarkList,
+  Tree,
+  ScrollTop,
+  ScrollMiddle,
+  ScrollBottom,
+  JumpBack,
+  JumpForward,
+  SetMark,
+  GotoMark,
+  KeepOurs,
+  KeepTheirs,
+  KeepBoth,
+  Follow,
+  OpenLocation,
+  Command,
+  Search,
+  SearchNext,
+  SearchPrev,
+  NextBuffer,
+  PrevBuffer,
+  SwitchBuffer,
+  Save,
+  SaveAs,
+  Quit,
+  ForceQuit,
+  Suspend,
+  Filter,
+  // both modes
+  Delete,
+  Backspace,
+  // insert mode
+  Normal,
+  BreakLine,
+  Literal,
+  Digraph,
+  Dynamic,
+}
+
+const ACTION_NAMES: &[(&str, Action)] = &[
+  ("move_left", Action::MoveLeft),
+  ("move_right", Action::MoveRight),
+  ("move_up", Action::MoveUp),
+  ("move_down", Action::MoveDown),
+  ("prev_blank", Action::PrevBlank),
+  ("next_blank", Action::NextBlank),
+  ("prev_blank_line", Action::PrevBlankLine),
+  ("next_blank_line", Action::NextBlankLine),
+  ("next_word", Action::NextWord),
+  ("prev_word", Action::PrevWord),
+  ("word_end", Action::WordEnd),
+  ("line_start", Action::LineStart),
+  ("line_end", Action::LineEnd),
+  ("next_conflict", Action::NextConflict),
+  ("prev_conflict", Action::PrevConflict),
+  ("insert", Action::Insert),
+  ("delete_line", Action::DeleteLine),
+  ("copy_line", Action::CopyLine),
+  ("paste_line", Action::PasteLine),
+  ("cut_line", Action::CutLine),
+  ("delete_char", Action::DeleteChar),
+  ("cut", Action::Cut),
+  ("copy", Action::Copy),
+  ("change", Action::Change),
+  ("next_cell", Action::NextCell),
+  ("prev_cell", Action::PrevCell),
+  ("visual", Action::Visual),
+  ("visual_block", Action::VisualBlock),
+  ("block_insert", Action::BlockInsert),
+  ("block_append", Action::BlockAppend),
+  ("transpose", Action::Transpose),
+  ("indent", Action::Indent),
+  ("dedent", Action::Dedent),
+  ("move_line_up", Action::MoveLineUp),
+  ("move_line_down", Action::MoveLineDown),
+  ("goto_line", Action::GotoLine),
+  ("goto_first_line", Action::GotoFirstLine),
+  ("toggle_comment", Action::ToggleComment),
+  ("reflow", Action::Reflow),
+  ("toggle_fold", Action::ToggleFold),
+  ("open_fold", Action::OpenFold),
+  ("close_fold", Action::CloseFold),
+  ("open_all_folds", Action::OpenAllFolds),
+  ("close_all_folds", Action::CloseAllFolds),
+  ("prev_function", Action::PrevFunction),
+  ("next_function", Action::NextFunction),
+  ("block_start", Action::BlockStart),
+  ("block_end", Action::BlockEnd),
+  ("around_function", Action::AroundFunction),
+  ("inner_function", Action::InnerFunction),
+  ("around_block", Action::AroundBlock),
+  ("inner_block", Action::InnerBlock),
+  ("around_argument", Action::AroundArgument),
+  ("inner_argument", Action::InnerArgument),
+  ("hex_panel", Action::HexPanel),
+  ("stats", Action::Stats),
+  ("next_todo", Action::NextTodo),
+  ("prev_todo", Action::PrevTodo),
+  ("todo_list", Action::TodoList),
+  ("show_note", Action::ShowNote),
+  ("toggle_bookmark", Action::ToggleBookmark),
+  ("next_bookmark", Action::NextBookmark),
+  ("prev_bookmark", Action::PrevBookmark),
+  ("bookmark_list", Action::BookmarkList),
+  ("tree", Action::Tree),
+  ("scroll_top", Action::ScrollTop),
+  ("scroll_middle", Action::ScrollMiddle),
+  ("scroll_bottom", Action::ScrollBottom),
+  ("jump_back", Action::JumpBack),
+  ("jump_forward", Action::JumpForward),
+  ("set_mark", Action::SetMark),
+  ("goto_mark", Action::GotoMark),
+  ("keep_ours", Action::KeepOurs),
+  ("keep_theirs", Action::KeepTheirs),
+  ("keep_both", Action::KeepBoth),
+  ("follow", Action::Follow),
+  ("open_location", Action::OpenLocation),
+  ("command", Action::Command),
+  ("search", Action::Search),
+  ("search_next", Action::SearchNext),
+  ("search_prev", Action::SearchPrev),
+  ("next_buffer", Action::NextBuffer),
+  ("prev_buffer", Action::PrevBuffer),
+  ("switch_buffer", Action::SwitchBuffer),
+  ("save", Action::Save),
+  ("save_as", Action::SaveAs),
+  ("quit", Action::Quit),
+  ("force_quit", Action::ForceQuit),
+  ("suspend", Action::Suspend),
+  ("filter", Action::Filter),
+  ("delete", Action::Delete),
+  ("backspace", Action::Backspace),
+  ("normal", Action::Normal),
+  ("break_line", Action::BreakLine),
+  ("literal", Action::Literal),
+  ("digraph", Action::Digraph),
+  ("dynamic", Action::Dynamic),
+];
+
+const NORMAL_KEYS: &[(Key, Action)] = &[
+  (Key::Char('h'), Action::MoveLeft),
+  (Key::Char('l'), Action::MoveRight),
+  (Key::Char('k'), Action::MoveUp),
+  (Key::Char('j'), Action::MoveDown),
+  (Key::Char('H'), Action::PrevBlank),
+  (Key::Char('L'), Action::NextBlank),
+  (Key::Char('K'), Action::PrevBlankLine),
+  (Key::Char('J'), Action::NextBlankLine),
+  (Key::Char('w'), Action::NextWord),
+  (Key::Char('b'), Action::PrevWord),
+  (Key::Char('e'), Action::WordEnd),
+  (Key::Char('^'), Action::LineStart),
+  (Key::Home, Action::LineStart),
+  (Key::Char('$'), Action::LineEnd),
+  (Key::End, Action::LineEnd),
+  (Key::Char(']'), Action::NextConflict),
+  (Key::Char('['), Action::PrevConflict),
+  (Key::Char('('), Action::PrevFunction),
+  (Key::Char(')'), Action::NextFunction),
+  (Key::Char('{'), Action::BlockStart),
+  (Key::Char('}'), Action::BlockEnd),
+  (Key::Char('i'), Action::Insert),
+  (Key::Delete, Action::Delete),
+  (Key::Backspace, Action::Backspace),
+  (Key::Char('p'), Action::PasteLine),
+  (Key::Alt('l'), Action::NextCell),
+  (Key::Alt('h'), Action::PrevCell),
+  (Key::Char('v'), Action::Visual),
+  (Key::Ctrl('v'), Action::VisualBlock),
+  (Key::Char('t'), Action::Transpose),
+  (Key::Char('>'), Action::Indent),
+  (Key::Char('<'), Action::Dedent),
+  (Key::Alt('k'), Action::MoveLineUp),
+  (Key::Alt('K'), Action::MoveLineUp),
+  (Key::Alt('j'), Action::MoveLineDown),
+  (Key::Alt('J'), Action::MoveLineDown),
+  (Key::Char('G'), Action::GotoLine),
+  (Key::Ctrl('o'), Action::JumpBack),
+  // Ctrl-I is Tab in a terminal
+  (Key::Alt('o'), Action::JumpForward),
+  (Key::Char('m'), Action::SetMark),
+  (Key::Char('\''), Action::GotoMark),
+  (Key::Char('M'), Action::ToggleBookmark),
+  (Key::Char('O'), Action::KeepOurs),
+  (Key::Char('T'), Action::KeepTheirs),
+  (Key::Char('B'), Action::KeepBoth),
+  (Key::Char('F'), Action::Follow),
+  (Key::Char('\n'), Action::OpenLocation),
+  (Key::Char(':'), Action::Command),
+  (Key::Char('/'), Action::Search),
+  (Key::Char('n'), Action::SearchNext),
+  (Key::Char('N'), Action::SearchPrev),
+  (Key::Char('\t'), Action::NextBuffer),
+  (Key::BackTab, Action::PrevBuffer),
+  (Key::Ctrl('p'), Action::SwitchBuffer),
+  (Key::Char('s'), Action::Save),
+  (Key::Char('S'), Action::SaveAs),
+  (Key::Char('q'), Action::Quit),
+  (Key::Char('Q'), Action::ForceQuit),
+  (Key::Ctrl('z'), Action::Suspend),
+];
+
+// `d`, `y` and `c` are operators, doubled for whole lines like `dd`, or
+// followed by a motion like `dw`
+const OPERATOR_KEYS: &[(Key, Action)] = &[
+  (Key::Char('d'), Action::Cut),
+  (Key::Char('y'), Action::Copy),
+  (Key::Char('c'), Action::Change),
+  (Key::Char('x'), Action::DeleteChar),
+];
+
+// The single keys for lines of earlier versions, with `operators = false`
+const LINE_KEYS: &[(Key, Action)] = &[
+  (Key::Char('d'), Action::DeleteLine),
+  (Key::Char('c'), Action::CopyLine),
+  (Key::Char('x'), Action::CutLine),
+];
+
+const NORMAL_SEQUENCES: &[([Key; 2], Action)] = &[
+  ([Key::Char('g'), Key::Char('g')], Action::GotoFirstLine),
+  ([Key::Char('g'), Key::Char('c')], Action::ToggleComment),
+  ([Key::Char('g'), Key::Char('q')], Action::Reflow),
+  ([Key::Char('g'), Key::Char('8')], Action::HexPanel),
+  ([Key::Char('g'), Key::Ctrl('g')], Action::Stats),
+  ([Key::Char('g'), Key::Char('t')], Action::NextTodo),
+  ([Key::Char('g'), Key::Char('T')], Action::PrevTodo),
+  ([Key::Char('g'), Key::Char('l')], Action::TodoList),
+  ([Key::Char('g'), Key::Char('n')], Action::ShowNote),
+  ([Key::Char('g'), Key::Char('b')], Action::NextBookmark),
+  ([Key::Char('g'), Key::Char('B')], Action::PrevBookmark),
+  ([Key::Char('g'), Key::Char('m')], Action::BookmarkList),
+  ([Key::Char('g'), Key::Char('f')], Action::Tree),
+  ([Key::Char('z'), Key::Char('t')], Action::ScrollTop),
+  ([Key::Char('z'), Key::Char('z')], Action::ScrollMiddle),
+  ([Key::Char('z'), Key::Char('b')], Action::ScrollBottom),
+  ([Key::Char('z'), Key::Char('a')], Action::ToggleFold),
+  ([Key::Char('z'), Key::Char('o')], Action::OpenFold),
+  ([Key::Char('z'), Key::Char('c')], Action::CloseFold),
+  ([Key::Char('z'), Key::Char('R')], Action::OpenAllFolds),
+  ([Key::Char('z'), Key::Char('M')], Action::CloseAllFolds),
+];
+
+// After an operator, these keys pick the function, block or argument around
+// the cursor, with `a` for all of it and `i` for what is inside it
+const OBJECT_SEQUENCES: &[([Key; 2], Action)] = &[
+  ([Key::Char('a'), Key::Char('f')], Action::AroundFunction),
+  ([Key::Char('i'), Key::Char('f')], Action::InnerFunction),
+  ([Key::Char('a'), Key::Char('b')], Action::AroundBlock),
+  ([Key::Char('i'), Key::Char('b')], Action::InnerBlock),
+  ([Key::Char('a'), Key::Char('a')], Action::AroundArgument),
+  ([Key::Char('i'), Key::Char('a')], Action::InnerArgument),
+];
+
+// Visual mode moves the cursor with the motions of normal mode, and these
+// keys act on the selection.
+const VISUAL_KEYS: &[(Key, Action)] = &[
+  (Key::Char('d'), Action::Delete),
+  (Key::Char('x'), Action::Cut),
+  (Key::Char('c'), Action::Copy),
+  (Key::Char('>'), Action::Indent),
+  (Key::Char('<'), Action::Dedent),
+  (Key::Char('!'), Action::Filter),
+  (Key::Char('#'), Action::ToggleComment),
+  (Key::Char('I'), Action::BlockInsert),
+  (Key::Char('A'), Action::BlockAppend),
+  (Key::Esc, Action::Normal),
+  (Key::Char('v'), Action::Normal),
+  (Key::Ctrl('v'), Action::VisualBlock),
+];
+
+const INSERT_KEYS: &[(Key, Action)] = &[
+  (Key::Esc, Action::Normal),
+  (Key::Char('\n'), Action::BreakLine),
+  (Key::Delete, Action::Delete),
+  (Key::Backspace, Action::Backspace),
+  (Key::Ctrl('v'), Action::Literal),
+  (Key::Ctrl('k'), Action::Digraph),
+  (Key::Ctrl('r'), Action::Dynamic),
+];
+
+// The actions bound to sequences of keys, as a trie from the first key of
+// each sequence
+#[derive(Clone, Debug, Default)]
+pub struct Keymap {
+  action: Option<Action>,
+  next: HashMap<Key, Keymap>,
+}
+
+// What the keys typed so far are bound to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Lookup {
+  Unbound,
+  Action(Action),
+  // the start of longer sequences, and the action of the keys themselves,
+  // if they have one
+  Prefix(Option<Action>),
+}
+
+impl Keymap {
+  pub fn insert(&mut self, keys: &[Key], action: Action) {
+    let node = keys.iter().fold(self, |node, key| node.next.entry(*key).or_default());
+    node.action = Some(action);
+  }
+
+  // Unbinds the sequence, dropping the keys that start no other sequence.
+  pub fn remove(&mut self, keys: &[Key]) {
+    match keys.split_first() {
+      None => self.action = None,
+      Some((key, rest)) => if let Some(node) = self.next.get_mut(key) {
+        node.remove(rest);
+        if node.action.is_none() && node.next.is_empty() {
+          self.next.remove(key);
+        }
+      },
+    }
+  }
+
+  pub fn lookup(&self, keys: &[Key]) -> Lookup {
+    let mut node = self;
+    for key in keys {
+      match node.next.get(key) {
+        Some(next) => node = next,
+        None => return Lookup::Unbound,
+      }
+    }
+    match (node.action, node.next.is_empty()) {
+      (Some(action), true) => Lookup::Action(action),
+      (None, true) => Lookup::Unbound,
+      (action, false) => Lookup::Prefix(action),
+    }
+  }
+
+  // Makes the keys start the same sequences as another key.
+  pub fn copy_sequences(&mut self, keys: &[Key], from: Key) {
+    let next = self.next.get(&from).map_or_else(HashMap::new, |node| node.next.clone());
+    let node = keys.iter().fold(self, |node, key| node.next.entry(*key).or_default());
+    node.next.extend(next);
+  }
+
+  // The action bound to the key on its own
+  pub fn get(&self, key: &Key) -> Option<Action> {
+    self.next.get(key).and_then(|node| node.action)
+  }
+}
+
+pub fn normal_defaults() -> Keymap {
+  let mut keymap = Keymap::default();
+  for &(key, action) in NORMAL_KEYS {
+    keymap.insert(&[key], action);
+  }
+  for (keys, action) in NORMAL_SEQUENCES {
+    keymap.insert(keys, *action);
+  }
+  use_operators(&mut keymap, true);
+  keymap
+}
+
+// Binds the operator keys, or the single keys for lines in their place.
+pub fn use_operators(keymap: &mut Keymap, on: bool) {
+  for &(key, _) in OPERATOR_KEYS {
+    keymap.remove(&[key]);
+  }
+  for &(key, action) in if on { OPERATOR_KEYS } else { LINE_KEYS } {
+    keymap.insert(&[key], action);
+  }
+}
+
+pub fn visual_defaults() -> Keymap {
+  let mut keymap = Keymap::default();
+  for &(key, action) in VISUAL_KEYS {
+    keymap.insert(&[key], action);
+  }
+  keymap
+}
+
+pub fn object_defaults() -> Keymap {
+  let mut keymap = Keymap::default();
+  for (keys, action) in OBJECT_SEQUENCES {
+    keymap.insert(keys, *action);
+  }
+  keymap
+}
+
+pub fn insert_defaults() -> Keymap {
+  let mut keymap = Keymap::default();
+  for &(key, action) in INSERT_KEYS {
+    keymap.insert(&[key], action);
+  }
+  keymap
+}
+
+// Parses names like `x`, `Ctrl-s`, `Alt-j`, `Enter` or `F5`.
+pub fn parse_key(name: &str) -> Option<Key> {
+  let single = |text: &str| {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+      (Some(c), None) => Some(c),
+      _ => None,
+    }
+  };
+  let key = match name {
+    "Enter" => Key::Char('\n'),
+    "Tab" => Key::Char('\t'),
+    "Shift-Tab" => Key::BackTab,
+    "Space" => Key::Char(' '),
+    "Esc" => Key::Esc,
+    "Backspace" => Key::Backspace,
+    "Delete" => Key::Delete,
+    "Up" => Key::Up,
+    "Down" => Key::Down,
+    "Left" => Key::Left,
+    "Right" => Key::Right,
+    "Home" => Key::Home,
+    "End" => Key::End,
+    "PageUp" => Key::PageUp,
+    "PageDown" => Key::PageDown,
+    _ if name.starts_with("Ctrl-") => Key::Ctrl(single(&name[5..])?.to_ascii_lowercase()),
+    _ if name.starts_with("Alt-") => Key::Alt(single(&name[4..])?),
+    _ if name.len() > 1 && name.starts_with('F') => Key::F(name[1..].parse().ok()?),
+    _ => Key::Char(single(name)?),
+  };
+  Some(key)
+}
+
+// Parses a sequence of key names separated by spaces, like `g c`. A space on
+// its own is the space key.
+pub fn parse_keys(names: &str) -> Option<Vec<Key>> {
+  if names.trim().is_empty() {
+    return parse_key(names).map(|key| vec![key]);
+  }
+  names.split_whitespace().map(parse_key).collect()
+}
+
+// Parses the keys of a script, as for `red --script`: each character is its
+// key, with a newline for Enter, and a name in angle brackets, like `<Esc>`
+// or `<Ctrl-v>`, is the key it names. `<<>` is `<`.
+pub fn parse_script(text: &str) -> Result<Vec<Key>, String> {
+  let mut keys = Vec::new();
+  let mut chars = text.chars();
+  while let Some(c) = chars.next() {
+    keys.push(match c {
+      '<' => {
+        let name: String = chars.by_ref().take_while(|&c| c != '>').collect();
+        parse_key(&name).ok_or_else(|| format!("Unknown key <{}>", name))?
+      }
+      c => Key::Char(c),
+    });
+  }
+  Ok(keys)
+}
+
+// The actions that made a key wait for a second one before sequences could
+// be bound, which now make it start the same sequences as `g` or `z`
+const PREFIX_NAMES: &[(&str, char)] = &[("prefix_g", 'g'), ("prefix_z", 'z')];
+
+// Binds keys to the named action, or unbinds them for `none`.
+pub fn bind(keymap: &mut Keymap, keys: &str, action: &str) -> Result<(), String> {
+  let keys = keys.trim_matches('"');
+  let keys = parse_keys(keys).ok_or_else(|| format!("unknown key `{}`", keys))?;
+  if action == "none" {
+    keymap.remove(&keys);
+    return Ok(());
+  }
+  if let Some(&(_, c)) = PREFIX_NAMES.iter().find(|(name, _)| *name == action) {
+    keymap.copy_sequences(&keys, Key::Char(c));
+    return Ok(());
+  }
+  match ACTION_NAMES.iter().find(|(name, _)| *name == action) {
+    Some(&(_, action)) => keymap.insert(&keys, action),
+    None => return Err(format!("unknown action `{}`", action)),
+  };
+  Ok(())
+}
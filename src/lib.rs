@@ -0,0 +1,6977 @@
+#[cfg(test)]
+extern crate tempfile;
+extern crate termion;
+#[macro_use]
+extern crate lazy_static;
+extern crate libc;
+
+mod bookmarks;
+mod clipboard;
+pub mod color;
+mod command;
+mod comment;
+mod config;
+mod conflict;
+mod diff;
+mod digraph;
+mod encoding;
+pub mod engine;
+mod expr;
+mod filter;
+mod filetype;
+mod fold;
+mod fuzzy;
+mod git;
+mod history;
+mod jumps;
+mod keymap;
+mod lines;
+mod location;
+mod man;
+mod markdown;
+mod marks;
+mod notes;
+mod profile;
+mod pty;
+mod reflow;
+mod remote;
+pub mod screen;
+mod search;
+mod sha256;
+mod state;
+mod swap;
+mod syntax;
+mod table;
+mod timeline;
+mod todo;
+mod transform;
+mod tree;
+mod vt;
+#[cfg(test)]
+mod tests;
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, Write};
+use std::mem;
+use std::ops::Range;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{mpsc, Mutex, OnceLock, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use termion::{
+  raw::IntoRawMode,
+  input::{TermRead, TermReadEventsAndRaw},
+};
+
+use clipboard::{Clip, Clipboard};
+use color::Color;
+use command::Command;
+use config::{Bell, ControlChars};
+use conflict::{Conflict, Part};
+use encoding::Encoding;
+use engine::{Engine, Output, Request};
+use fold::Folds;
+use git::Sign;
+use bookmarks::Bookmarks;
+use jumps::JumpList;
+use lines::Lines;
+use marks::Marks;
+use notes::Notes;
+use keymap::{Action, Lookup};
+use location::Location;
+use profile::Profile;
+use syntax::{Object, Target};
+use table::Table;
+use timeline::{Step, Timeline};
+use tree::Tree;
+use screen::CursorShape;
+
+type Line = String;
+
+// The lines of a file. Each edit counts itself, which tells whether a file
+// has changed since it was saved, and notes the lines it added or removed, for
+// marks to follow.
+#[derive(Clone, Default)]
+struct Buffer {
+  lines: Lines,
+  generation: u64,
+  // the lines replaced since they were last taken, as the first of them and
+  // how many there were before and after
+  changes: Vec<(usize, usize, usize)>,
+}
+
+impl Buffer {
+  fn new() -> Self {
+    Buffer::default()
+  }
+
+  // Counts an edit that changed lines without adding or removing any.
+  fn touch(&mut self) {
+    self.generation += 1;
+  }
+
+  // Counts an edit that replaced `removed` lines at `start` with `added`
+  // others.
+  fn note(&mut self, start: usize, removed: usize, added: usize) {
+    self.touch();
+    if removed != added {
+      self.changes.push((start, removed, added));
+    }
+  }
+
+  // Replaces the lines with others, as when a file is read again, keeping
+  // marks on the lines at the start and end that are the same.
+  fn replace(&mut self, lines: impl Into<Lines>) {
+    let lines = lines.into();
+    // the lines of a large file aren't all read to compare them
+    let (start, removed, added) = match self.lines.is_lazy() || lines.is_lazy() {
+      true => (0, self.lines.len(), lines.len()),
+      false => marks::changed_lines(&self.lines, &lines),
+    };
+    self.lines = lines;
+    self.note(start, removed, added);
+  }
+
+  fn take_changes(&mut self) -> Vec<(usize, usize, usize)> {
+    mem::take(&mut self.changes)
+  }
+}
+
+impl std::ops::Deref for Buffer {
+  type Target = Lines;
+
+  fn deref(&self) -> &Lines {
+    &self.lines
+  }
+}
+
+impl std::ops::DerefMut for Buffer {
+  fn deref_mut(&mut self) -> &mut Lines {
+    &mut self.lines
+  }
+}
+
+impl From<Vec<Line>> for Buffer {
+  fn from(lines: Vec<Line>) -> Self {
+    Buffer::from(Lines::from(lines))
+  }
+}
+
+impl From<Lines> for Buffer {
+  fn from(lines: Lines) -> Self {
+    Buffer{lines, ..Buffer::default()}
+  }
+}
+
+impl std::iter::FromIterator<Line> for Buffer {
+  fn from_iter<I: IntoIterator<Item = Line>>(iter: I) -> Self {
+    Buffer::from(iter.into_iter().collect::<Vec<_>>())
+  }
+}
+
+impl PartialEq for Buffer {
+  fn eq(&self, other: &Buffer) -> bool {
+    self.lines == other.lines
+  }
+}
+
+impl PartialEq<Vec<Line>> for Buffer {
+  fn eq(&self, other: &Vec<Line>) -> bool {
+    self.lines == *other
+  }
+}
+
+impl PartialEq<Buffer> for Vec<Line> {
+  fn eq(&self, other: &Buffer) -> bool {
+    other.lines == *self
+  }
+}
+
+impl PartialEq<Buffer> for Vec<&str> {
+  fn eq(&self, other: &Buffer) -> bool {
+    other.lines == *self
+  }
+}
+
+impl std::fmt::Debug for Buffer {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    self.lines.fmt(f)
+  }
+}
+
+// The editor draws on any writer, such as the terminal or, for tests, a vector.
+pub type Screen = screen::Screen<Box<dyn Write>>;
+type Key = termion::event::Key;
+
+#[derive(Clone)]
+struct Cursor {
+  col: usize,
+  row: usize,
+  left: usize,
+  top: usize,
+  // the lines hidden in closed folds, which the view skips
+  folds: Folds,
+  // the columns separated values are lined up in, while shown as a table
+  table: Option<Table>,
+}
+
+impl Cursor {
+  fn new() -> Self {
+    Cursor{col: 0, row: 0, left: 0, top: 0, folds: Folds::new(), table: None}
+  }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Size {
+  rows: usize,
+  cols: usize,
+  // columns left of the text, for the gutter
+  left: usize,
+}
+
+impl Size {
+  fn new<T: Into<usize>>(rows: T, cols: T) -> Self {
+    Size{rows: rows.into(), cols: cols.into(), left: 0}
+  }
+}
+
+// Editor state kept from one key to the next, besides the buffer and cursor
+struct Session {
+  clip: Clipboard,
+  // whether the hex panel is shown
+  hex: bool,
+  // the last search, and where the cursor was when the current one started
+  search: String,
+  search_origin: (usize, usize),
+  // the shell started with `:term`, shown in a panel while it runs
+  shell: Option<pty::Shell>,
+  // the places named in the output of the last `:make` or `:run`, and the
+  // one last gone to
+  locations: Vec<Location>,
+  location: Option<usize>,
+  // whether markdown files are previewed beside their text, and the last
+  // preview rendered
+  preview: bool,
+  rendered: Option<Preview>,
+  // the files of the current directory, shown beside the text while it is
+  // open
+  tree: Option<Tree>,
+  // text being typed on the first line of a block, to repeat on the others
+  block_insert: Option<BlockInsert>,
+}
+
+// Where the text typed on the first line of a block goes on the others:
+// before the block, skipping lines short of it, or after it, padding them
+struct BlockInsert {
+  row: usize,
+  rows: Range<usize>,
+  col: usize,
+  append: bool,
+  // the first line as it was, and the length of the buffer, to tell what
+  // was typed
+  before: Line,
+  len: usize,
+}
+
+impl Session {
+  fn new() -> Self {
+    Session{
+      clip: Clipboard::new(config::get().clipboard_size),
+      hex: false,
+      search: String::new(),
+      search_origin: (0, 0),
+      shell: None,
+      locations: Vec::new(),
+      location: None,
+      preview: false,
+      rendered: None,
+      tree: None,
+      block_insert: None,
+    }
+  }
+}
+
+// The markdown preview of a file, rendered again when the file, its text, or
+// the width of the preview changes
+struct Preview {
+  file: usize,
+  generation: u64,
+  width: usize,
+  rows: Vec<markdown::Row>,
+}
+
+const NO_NAME: &str = "[No Name]";
+
+// What is shown with the lines of a file besides their text, found again
+// after each edit rather than on each draw
+#[derive(Default)]
+struct Annotations {
+  // the edit they were found at
+  generation: Option<u64>,
+  signs: Vec<Option<Sign>>,
+  conflicts: Vec<Conflict>,
+  // how many folds each line is in, for the fold column
+  fold_levels: Vec<usize>,
+  // the lines lexed for highlighting and for the structure of the code,
+  // kept from one edit to the next
+  syntax: syntax::Highlights,
+  // the lines with notes, and the bookmarked lines, in order
+  notes: Vec<usize>,
+  bookmarks: Vec<usize>,
+}
+
+impl Annotations {
+  // The sign of a line with a note or a bookmark, with the note shown over
+  // the bookmark
+  fn line_mark(&self, row: usize) -> Option<char> {
+    if self.notes.binary_search(&row).is_ok() {
+      Some(NOTE_SIGN)
+    } else if self.bookmarks.binary_search(&row).is_ok() {
+      Some(BOOKMARK_SIGN)
+    } else {
+      None
+    }
+  }
+}
+
+// The most lines, in the buffer and the index together, that a file has git
+// signs for
+const MAX_SIGNS_LINES: usize = 20_000;
+
+// A file open for editing, with its cursor kept while another file is shown
+// A file without a path, like text read from stdin, is given one when it is
+// first saved.
+struct OpenFile {
+  // empty until the file is named
+  path: String,
+  buf: Buffer,
+  cur: Cursor,
+  format: FileFormat,
+  // the edit the buffer was at, and its format, when it was last read or
+  // saved, which text from stdin has never been
+  saved: Option<u64>,
+  saved_format: FileFormat,
+  jumps: JumpList,
+  marks: Marks,
+  notes: Notes,
+  bookmarks: Bookmarks,
+  // the states of the buffer, for `:earlier` and `:later`
+  timeline: Timeline,
+  // the lines in the index, for a file in a git repository
+  git: Option<Vec<Line>>,
+  annotations: Annotations,
+  // how to encode an encrypted or compressed file again, as it was read
+  filter_options: Vec<String>,
+  // when the file on disk was modified as it was last read or saved, and a
+  // later change to it already warned about
+  stamp: Option<SystemTime>,
+  warned: Option<SystemTime>,
+  // the length of the file on disk as it was last read, saved or followed,
+  // where following it starts
+  length: u64,
+  // a warning about how the file was read, shown when it is first shown
+  warning: Option<String>,
+  // the name of a buffer holding the output of a command, which has no
+  // unsaved changes to lose
+  scratch: Option<String>,
+  // the lines of a buffer that can't be edited, put back after an edit
+  read_only: Option<Lines>,
+}
+
+impl OpenFile {
+  fn new(path: &str, buf: Buffer) -> Self {
+    OpenFile{
+      path: String::from(path),
+      saved: Some(buf.generation),
+      timeline: Timeline::new(&buf, buf.generation),
+      buf,
+      cur: Cursor::new(),
+      format: FileFormat::default(),
+      saved_format: FileFormat::default(),
+      jumps: JumpList::new(),
+      marks: Marks::new(),
+      notes: Notes::default(),
+      bookmarks: Bookmarks::default(),
+      git: None,
+      annotations: Annotations::default(),
+      filter_options: Vec::new(),
+      stamp: None,
+      warned: None,
+      length: 0,
+      warning: None,
+      scratch: None,
+      read_only: None,
+    }
+  }
+
+  fn modified(&self) -> bool {
+    self.scratch.is_none() && (self.saved != Some(self.buf.generation) || self.format != self.saved_format)
+  }
+
+  // The save steps only change the buffer once the file is written.
+  fn save(&mut self) -> io::Result<()> {
+    let steps = save_steps();
+    let stepped = if steps.is_empty() {
+      None
+    } else {
+      let mut buf = self.buf.clone();
+      for step in steps {
+        step(&mut buf);
+      }
+      Some(buf)
+    };
+    write_file_with_options(&self.path, stepped.as_ref().unwrap_or(&self.buf), self.format, &self.filter_options)?;
+    if let Some(buf) = stepped {
+      self.buf = buf;
+    }
+    self.cur.row = self.cur.row.min(self.buf.len().saturating_sub(1));
+    truncate_cursor_to_line(&mut self.cur, &self.buf);
+    self.saved = Some(self.buf.generation);
+    self.saved_format = self.format;
+    self.stamp = disk_stamp(&self.path);
+    self.length = fs::metadata(&self.path).map_or(0, |metadata| metadata.len());
+    Ok(())
+  }
+
+  // What goes with the file once it is saved; failing here leaves the file
+  // saved all the same.
+  fn save_side_files(&mut self) -> io::Result<()> {
+    // the notes, marks and jumps are saved for the lines they are on now
+    self.adjust_marks();
+    self.notes.save(&self.path)?;
+    remember_positions(self)?;
+    remove_swap(&self.path)
+  }
+
+  // Reads the lines of the file in the index again, as after committing it
+  // or saving it under another name.
+  fn read_index(&mut self) {
+    self.git = git::index_lines(&self.path);
+    self.annotations.generation = None;
+  }
+
+  // Finds the git signs and merge conflicts again after an edit. A file too
+  // long to diff on every edit has no signs.
+  fn update_annotations(&mut self) {
+    let generation = Some(self.buf.generation);
+    if self.annotations.generation == generation {
+      return;
+    }
+    // the lines of a large file are only read as they are shown
+    let whole = !self.buf.is_lazy();
+    let mut syntax = mem::take(&mut self.annotations.syntax);
+    syntax.update(&self.buf, filetype::detect(&self.path).filter(|_| whole));
+    self.annotations = Annotations{
+      generation,
+      signs: match self.git {
+        Some(ref index) if whole && index.len() + self.buf.len() <= MAX_SIGNS_LINES => git::signs(index, &self.buf),
+        _ => Vec::new(),
+      },
+      conflicts: if whole { conflict::find_all(&self.buf) } else { Vec::new() },
+      fold_levels: if whole && fold_column_width() > 0 { fold::levels(&self.buf) } else { Vec::new() },
+      syntax,
+      notes: self.notes.rows(),
+      bookmarks: self.bookmarks.rows(),
+    };
+  }
+
+  // Moves the marks and closed folds with the lines added and removed since
+  // this was last called.
+  fn adjust_marks(&mut self) {
+    for (start, removed, added) in self.buf.take_changes() {
+      self.marks.adjust(start, removed, added);
+      self.notes.adjust(start, removed, added);
+      self.bookmarks.adjust(start, removed, added);
+      self.cur.folds.adjust(start, removed, added);
+      self.annotations.syntax.adjust(start, removed, added);
+    }
+  }
+
+  // Whether another program has changed the file since it was read or saved
+  fn changed_on_disk(&self) -> bool {
+    !self.path.is_empty() && disk_stamp(&self.path) != self.stamp
+  }
+
+  // Reads the file again, keeping the cursor on the same line where it can.
+  fn reload(&mut self) -> io::Result<()> {
+    let file = open_file(&self.path, Some(self.format.encoding))?;
+    self.buf.replace(file.buf.lines);
+    self.saved = Some(self.buf.generation);
+    self.format = file.format;
+    self.saved_format = file.saved_format;
+    self.git = file.git;
+    self.annotations.generation = None;
+    self.filter_options = file.filter_options;
+    self.stamp = file.stamp;
+    self.length = file.length;
+    self.cur.row = self.cur.row.min(self.buf.len().saturating_sub(1));
+    truncate_cursor_to_line(&mut self.cur, &self.buf);
+    Ok(())
+  }
+}
+
+// The changes made to a buffer as it is saved, in order, as chosen in the
+// config
+fn save_steps() -> Vec<fn(&mut Buffer)> {
+  let mut steps: Vec<fn(&mut Buffer)> = Vec::new();
+  if config::get().strip_whitespace {
+    steps.push(|buf| {
+      transform::strip_trailing_whitespace(buf);
+      buf.touch();
+    });
+    steps.push(|buf| {
+      let len = buf.len();
+      transform::strip_final_blank_lines(buf);
+      let end = buf.len();
+      buf.note(end, len - end, 0);
+    });
+  }
+  steps
+}
+
+fn disk_stamp(path: &str) -> Option<SystemTime> {
+  fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// Quitting is refused while a file has unsaved changes.
+fn quit_unless_modified(files: &BufferList, fb: &mut Feedback) -> Mode {
+  match files.files.iter().find(|file| file.modified()) {
+    Some(file) => {
+      fb.bell = true;
+      let name = if file.path.is_empty() { NO_NAME } else { &file.path };
+      fb.message = Some(format!("{} has unsaved changes; Q or :q! quits anyway", name));
+      Mode::Normal
+    }
+    None => Mode::Quit,
+  }
+}
+
+// The open files, one of which is shown
+struct BufferList {
+  files: Vec<OpenFile>,
+  current: usize,
+  // the files that have been shown, the one shown last first
+  recent: Vec<usize>,
+}
+
+impl BufferList {
+  fn new(files: Vec<OpenFile>) -> Self {
+    BufferList{files, current: 0, recent: vec![0]}
+  }
+
+  fn current(&self) -> &OpenFile {
+    &self.files[self.current]
+  }
+
+  fn current_mut(&mut self) -> &mut OpenFile {
+    &mut self.files[self.current]
+  }
+
+  // Shows the file, opening it unless it is open already, under this or
+  // another name.
+  fn open(&mut self, path: &str) -> io::Result<()> {
+    self.current = match self.files.iter().position(|file| !file.path.is_empty() && same_file(&file.path, path)) {
+      Some(i) => i,
+      None => {
+        self.files.push(open_file(path, None)?);
+        self.files.len() - 1
+      }
+    };
+    Ok(())
+  }
+
+  // Shows the next or previous file, returning whether there was another.
+  fn cycle(&mut self, forward: bool) -> bool {
+    let n = self.files.len();
+    if n < 2 {
+      return false;
+    }
+    self.current = if forward { (self.current + 1) % n } else { (self.current + n - 1) % n };
+    true
+  }
+
+  // Moves the current file to the front of the recent ones.
+  fn mark_recent(&mut self) {
+    let current = self.current;
+    self.recent.retain(|&i| i != current);
+    self.recent.insert(0, current);
+  }
+
+  // The files whose names fuzzily match the query, best first, and the ones
+  // shown most recently first among those that match as well, or all of them
+  // by recency for an empty query
+  fn matching(&self, query: &str) -> Vec<usize> {
+    let unseen = (0..self.files.len()).filter(|i| !self.recent.contains(i));
+    let mut found: Vec<(usize, usize)> = self.recent.iter().copied().chain(unseen)
+      .filter_map(|i| fuzzy::score(query, buffer_name(&self.files[i])).map(|score| (score, i)))
+      .collect();
+    // a stable sort keeps the recent ones first
+    found.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+    found.into_iter().map(|(_, i)| i).collect()
+  }
+}
+
+fn buffer_name(file: &OpenFile) -> &str {
+  match file.scratch {
+    Some(ref name) => name,
+    None if file.path.is_empty() => NO_NAME,
+    None => &file.path,
+  }
+}
+
+// Feedback for the user collected while handling a key
+struct Feedback {
+  bell: bool,
+  // shown in the echo area
+  message: Option<String>,
+  // whether the terminal was lent to another program, which may have drawn
+  // over the screen
+  redraw: bool,
+}
+
+impl Feedback {
+  fn new() -> Self {
+    Feedback{bell: false, message: None, redraw: false}
+  }
+
+  // Rings the bell for an edit that could not be made.
+  fn edit(&mut self, res: BufResult) {
+    if res.is_err() {
+      self.bell = true;
+    }
+  }
+}
+
+// An edit or cursor move outside the buffer. These are refused, rather than
+// crashing the editor and leaving the terminal in raw mode.
+#[derive(Debug, PartialEq)]
+enum BufError {
+  BeforeStart,
+  PastEndOfBuffer,
+  PastEndOfLine,
+}
+
+type BufResult = Result<(), BufError>;
+
+fn get_screen_size() -> io::Result<Size> {
+  termion::terminal_size().map(|(cols, rows)| Size::new(rows, cols))
+}
+
+// file system functions
+
+// Expands a leading `~` and `$VAR` or `${VAR}` references in a path, looking
+// variables up with `var`. Unknown variables are left as they are.
+fn expand_path_with<F>(path: &str, var: F) -> String
+where
+  F: Fn(&str) -> Option<String>,
+{
+  let mut expanded = String::new();
+  let mut rest = path;
+  if rest == "~" || rest.starts_with("~/") {
+    if let Some(home) = var("HOME") {
+      expanded.push_str(&home);
+      rest = &rest[1..];
+    }
+  }
+  while let Some(i) = rest.find('$') {
+    expanded.push_str(&rest[..i]);
+    rest = &rest[i + 1..];
+    let (name, len) = if rest.starts_with('{') {
+      match rest.find('}') {
+        Some(end) => (&rest[1..end], end + 1),
+        None => ("", 0),
+      }
+    } else {
+      let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+      (&rest[..end], end)
+    };
+    match var(name).filter(|_| !name.is_empty()) {
+      Some(value) => expanded.push_str(&value),
+      None => {
+        expanded.push('$');
+        expanded.push_str(&rest[..len]);
+      }
+    }
+    rest = &rest[len..];
+  }
+  expanded.push_str(rest);
+  expanded
+}
+
+fn expand_path(path: &str) -> String {
+  expand_path_with(path, |name| env::var(name).ok())
+}
+
+// Expands the command line arguments that are paths, leaving options, line
+// numbers and the name after `--encoding` as they are.
+fn expand_path_args<F>(args: &[String], expand: F) -> Vec<String>
+where
+  F: Fn(&str) -> String,
+{
+  let mut expanded: Vec<String> = Vec::new();
+  for arg in args {
+    let after_encoding = expanded.last().map(String::as_str) == Some("--encoding");
+    if arg.starts_with("--") || arg.starts_with('+') || after_encoding {
+      expanded.push(arg.clone());
+    } else {
+      expanded.push(expand(arg));
+    }
+  }
+  expanded
+}
+
+fn read_file(path: &str) -> io::Result<Buffer> {
+  read_file_with_format(path, None).map(|(buf, _)| buf)
+}
+
+// Reads a file in the given encoding, or the one it seems to be in.
+fn read_file_with_format(path: &str, encoding: Option<Encoding>) -> io::Result<(Buffer, FileFormat)> {
+  let bytes = match filter::find(Path::new(path)) {
+    Some(filter) if Path::new(path).exists() => with_terminal_restored(|| filter::decode(filter, Path::new(path)))?,
+    _ if encoding.is_none_or(|encoding| encoding == Encoding::Utf8) && is_large_file(path) =>
+      match read_large_file(path)? {
+        Some(read) => return Ok(read),
+        None => fs::read(path)?,
+      },
+    _ => match fs::read(path) {
+      Ok(bytes) => bytes,
+      Err(ref err) if err.kind() == io::ErrorKind::NotFound =>
+        return Ok((Buffer::new(), FileFormat::default())),
+      Err(err) => return Err(err),
+    },
+  };
+  decode_text(path, &bytes, encoding)
+}
+
+fn is_large_file(path: &str) -> bool {
+  let size = config::get().large_file as u64;
+  size > 0 && fs::metadata(path).is_ok_and(|metadata| metadata.is_file() && metadata.len() >= size << 20)
+}
+
+// Reads where the lines of a large UTF-8 file are, leaving the lines in it
+// until they are needed, or nothing if it starts in another encoding.
+fn read_large_file(path: &str) -> io::Result<Option<(Buffer, FileFormat)>> {
+  use std::os::unix::fs::FileExt;
+  let file = fs::File::open(path)?;
+  let mut head = vec![0; 1 << 16];
+  let read = file.read_at(&mut head, 0)?;
+  head.truncate(read);
+  // the start may end part way through a character
+  let valid = match std::str::from_utf8(&head) {
+    Ok(_) => read,
+    Err(err) if err.error_len().is_none() => err.valid_up_to(),
+    Err(_) => return Ok(None),
+  };
+  if encoding::detect(&head[..valid]) != (Encoding::Utf8, false) {
+    return Ok(None);
+  }
+  let mut last = [0];
+  file.read_exact_at(&mut last, file.metadata()?.len() - 1)?;
+  let format = FileFormat{final_newline: last[0] == b'\n', ..FileFormat::detect(&head)};
+  let lines = Lines::read_from(file)?;
+  Ok(Some((Buffer::from(lines), format)))
+}
+
+fn decode_text(name: &str, bytes: &[u8], encoding: Option<Encoding>) -> io::Result<(Buffer, FileFormat)> {
+  let (encoding, bom) = match (encoding, encoding::detect(bytes)) {
+    (Some(encoding), (detected, bom)) => (encoding, bom && encoding == detected),
+    (None, detected) => detected,
+  };
+  let text = encoding::decode(encoding, bytes)
+    .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", name, err)))?;
+  let format = FileFormat{encoding, bom, ..FileFormat::detect(text.as_bytes())};
+  Ok((io::Cursor::new(text).lines().collect::<io::Result<_>>()?, format))
+}
+
+// New files start from a template in the config directory, if there is one,
+// named either after the whole file name (`main.rs`) or its extension (`rs`).
+fn template_path(path: &str, dir: &Path) -> Option<PathBuf> {
+  let path = Path::new(path);
+  let names = [path.file_name(), path.extension()];
+  names.iter()
+    .flatten()
+    .map(|name| dir.join(name))
+    .find(|template| template.is_file())
+}
+
+fn read_new_file(path: &str) -> io::Result<Buffer> {
+  let dir = match config::config_dir() {
+    Some(dir) => dir.join("templates"),
+    None => return Ok(Buffer::new()),
+  };
+  match template_path(path, &dir) {
+    Some(template) => read_file(&template.to_string_lossy()),
+    None => Ok(Buffer::new()),
+  }
+}
+
+// How a file was encoded and how its lines end, kept so that saving it
+// leaves them as they were.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct FileFormat {
+  encoding: Encoding,
+  // whether the file starts with a byte order mark
+  bom: bool,
+  crlf: bool,
+  // whether the last line ends with a newline too
+  final_newline: bool,
+}
+
+impl Default for FileFormat {
+  fn default() -> Self {
+    FileFormat{encoding: Encoding::Utf8, bom: false, crlf: false, final_newline: true}
+  }
+}
+
+impl FileFormat {
+  // Finds the line endings of decoded text. Files are taken to use the line
+  // ending of their first line.
+  fn detect(text: &[u8]) -> Self {
+    if text.is_empty() {
+      return FileFormat::default();
+    }
+    let crlf = match text.iter().position(|&b| b == b'\n') {
+      Some(i) => i > 0 && text[i - 1] == b'\r',
+      None => false,
+    };
+    FileFormat{crlf, final_newline: text.ends_with(b"\n"), ..FileFormat::default()}
+  }
+
+  fn line_endings(self) -> &'static str {
+    if self.crlf { "CRLF" } else { "LF" }
+  }
+}
+
+// The contents of the buffer as saved, with a newline after each line.
+fn buffer_text<'a>(lines: impl ExactSizeIterator<Item = &'a Line>, format: FileFormat) -> io::Result<Vec<u8>> {
+  let newline = if format.crlf { "\r\n" } else { "\n" };
+  let mut text = String::new();
+  let len = lines.len();
+  for (i, line) in lines.enumerate() {
+    text.push_str(line);
+    if format.final_newline || i + 1 < len {
+      text.push_str(newline);
+    }
+  }
+  encoding::encode(format.encoding, format.bom, &text)
+}
+
+// Creates a file to write the new contents to, next to the one it replaces,
+// so that it can be renamed over it. It has the mode of that file from the
+// start, so the text is never readable by more users than before.
+fn create_temp_file(path: &Path, mode: u32) -> io::Result<(PathBuf, fs::File)> {
+  use std::os::unix::fs::OpenOptionsExt;
+  let dir = path.parent().unwrap_or_else(|| Path::new(""));
+  let name = path.file_name().unwrap_or_default().to_string_lossy();
+  for i in 0.. {
+    let temp = dir.join(format!(".{}.red{}-{}", name, std::process::id(), i));
+    match fs::OpenOptions::new().write(true).create_new(true).mode(mode).open(&temp) {
+      Ok(file) => return Ok((temp, file)),
+      Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+      Err(err) => return Err(err),
+    }
+  }
+  unreachable!()
+}
+
+fn backup_file(target: &Path) -> io::Result<()> {
+  if config::get().backup {
+    let mut backup = target.to_path_buf().into_os_string();
+    backup.push("~");
+    fs::copy(target, backup)?;
+  }
+  Ok(())
+}
+
+// Overwrites the file itself, for when replacing it would lose its other
+// hard links or its owner.
+fn write_in_place(target: &Path, text: &[u8]) -> io::Result<()> {
+  backup_file(target)?;
+  let mut file = fs::OpenOptions::new().write(true).truncate(true).open(target)?;
+  file.write_all(text)?;
+  file.sync_all()
+}
+
+fn write_file(path: &str, buf: &Lines, format: FileFormat) -> io::Result<()> {
+  write_file_with_options(path, buf, format, &[])
+}
+
+// Saves are written to a temporary file which is then renamed over the
+// original, so that a crash part way through leaves the old file in place.
+// An encrypted or compressed file is encoded with `options`, or the default
+// ones without any.
+fn write_file_with_options(path: &str, buf: &Lines, format: FileFormat, options: &[String]) -> io::Result<()> {
+  use std::os::unix::fs::{fchown, MetadataExt, PermissionsExt};
+  let mut text = buffer_text(buf.iter(), format)?;
+  buf.check().map_err(|err| io::Error::new(err.kind(), format!("lines not saved as read: {}", err)))?;
+  if let Some(filter) = filter::find(Path::new(path)) {
+    text = with_terminal_restored(|| filter::encode(filter, options, &text))?;
+  }
+  // the lines of a large file not read yet would be read from what was
+  // written over them
+  let in_place = |target: &Path, text: &[u8]| match buf.is_lazy() {
+    true => Err(io::Error::other("a large file can't be saved in place")),
+    false => write_in_place(target, text),
+  };
+  // write through symlinks rather than replacing them
+  let target = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+  let original = fs::metadata(&target).ok();
+  if original.as_ref().is_some_and(|meta| meta.nlink() > 1) {
+    return in_place(&target, &text);
+  }
+  let mode = original.as_ref().map_or(0o666, |meta| meta.mode() & 0o7777);
+  let (temp, mut file) = create_temp_file(&target, mode)?;
+  if let Some(ref original) = original {
+    // only the owner, or root, can give the file away
+    if fchown(&file, Some(original.uid()), Some(original.gid())).is_err() {
+      drop(file);
+      let _ = fs::remove_file(&temp);
+      return in_place(&target, &text);
+    }
+  }
+  let written = (|| {
+    if original.is_some() {
+      // the umask may have taken bits off, and chown the set-id bits
+      file.set_permissions(fs::Permissions::from_mode(mode))?;
+    }
+    file.write_all(&text)?;
+    file.sync_all()?;
+    if original.is_some() {
+      backup_file(&target)?;
+    }
+    fs::rename(&temp, &target)
+  })();
+  if written.is_err() {
+    let _ = fs::remove_file(&temp);
+  }
+  written
+}
+
+// Whether anything about a file is kept in the config directory: its state,
+// swap file and history. Nothing is kept of an encrypted or compressed file,
+// whose text or lines would be kept there in the clear.
+fn keeps_side_files(path: &str) -> bool {
+  !path.is_empty() && filter::find(Path::new(path)).is_none()
+}
+
+fn state_root() -> Option<PathBuf> {
+  config::config_dir().map(|dir| dir.join("state"))
+}
+
+// Remembers the closed folds of a file for the next time it is opened, by
+// the lines that start them. A file not saved yet has no name to keep them
+// under.
+fn remember_folds(path: &str, folds: &Folds) -> io::Result<()> {
+  match state_root() {
+    Some(root) if keeps_side_files(path) && Path::new(path).exists() => {
+      let lines: Vec<String> = folds.starts().iter().map(|row| (row + 1).to_string()).collect();
+      state::update(&root, Path::new(path), "folds", &lines.join(" "))
+    }
+    _ => Ok(()),
+  }
+}
+
+// The marks named by a capital letter are kept in the state under this name,
+// with the file each is in.
+const GLOBAL_MARKS: &str = "marks";
+
+// Positions are kept as `line:column`, both counted from 1.
+fn format_position((row, col): (usize, usize)) -> String {
+  format!("{}:{}", row + 1, col + 1)
+}
+
+fn parse_position(text: &str) -> Option<(usize, usize)> {
+  let (row, col) = text.split_once(':')?;
+  let (row, col): (usize, usize) = (row.parse().ok()?, col.parse().ok()?);
+  Some((row.checked_sub(1)?, col.checked_sub(1)?))
+}
+
+// Remembers the marks, jumps, bookmarks and view of a file for the next time
+// it is opened, as they are on its lines now. A file not saved yet has no
+// name to keep them under.
+fn remember_positions(file: &OpenFile) -> io::Result<()> {
+  let root = match state_root() {
+    Some(root) if keeps_side_files(&file.path) && Path::new(&file.path).exists() => root,
+    _ => return Ok(()),
+  };
+  let marks = file.marks.all();
+  let local: Vec<String> = marks.iter()
+    .filter(|(name, _)| name.is_ascii_lowercase())
+    .map(|&(name, pos)| format!("{}:{}", name, format_position(pos)))
+    .collect();
+  let jumps: Vec<String> = file.jumps.positions().iter().map(|&pos| format_position(pos)).collect();
+  let cur = &file.cur;
+  let view = format!("{} {}", format_position((cur.row, cur.col)), format_position((cur.top, cur.left)));
+  let bookmarks: Vec<String> = file.bookmarks.rows().iter().map(|row| (row + 1).to_string()).collect();
+  let values = [("marks", local.join(" ")), ("jumps", jumps.join(" ")), ("view", view), ("bookmarks", bookmarks.join(" "))];
+  let values: Vec<(&str, &str)> = values.iter().map(|(key, value)| (*key, value.as_str())).collect();
+  state::update_all(&root, Path::new(&file.path), &values)?;
+  let path = fs::canonicalize(&file.path)?;
+  for (name, pos) in marks.into_iter().filter(|(name, _)| name.is_ascii_uppercase()) {
+    let value = format!("{} {}", format_position(pos), path.display());
+    state::update_named(&root, GLOBAL_MARKS, &name.to_string(), &value)?;
+  }
+  Ok(())
+}
+
+// The file and position of a mark named by a capital letter, as last
+// remembered
+fn remembered_global_mark(name: char) -> Option<(PathBuf, (usize, usize))> {
+  let state = state::load_named(&state_root()?, GLOBAL_MARKS);
+  let (pos, path) = state.get(&name.to_string())?.split_once(' ')?;
+  Some((PathBuf::from(path), parse_position(pos)?))
+}
+
+// Sets the marks, jumps and bookmarks remembered for a file that are still
+// on its lines, and puts the cursor and the view back where they were.
+fn restore_positions(file: &mut OpenFile) {
+  let root = match state_root() {
+    Some(root) => root,
+    None => return,
+  };
+  let path = Path::new(&file.path);
+  let state = state::load(&root, path);
+  let len = file.buf.len();
+  let on_lines = |pos: &(usize, usize)| pos.0 < len;
+  let marks = state.get("marks").map_or("", String::as_str).split_whitespace()
+    .filter_map(|mark| mark.split_once(':'))
+    .filter_map(|(name, pos)| Some((name.parse::<char>().ok()?, parse_position(pos)?)))
+    .filter(|(_, pos)| on_lines(pos));
+  for (name, pos) in marks {
+    file.marks.set(name, pos);
+  }
+  let jumps = state.get("jumps").map_or("", String::as_str).split_whitespace()
+    .filter_map(parse_position)
+    .filter(on_lines)
+    .collect();
+  file.jumps = JumpList::from(jumps);
+  let bookmarks = state.get("bookmarks").map_or("", String::as_str).split_whitespace()
+    .filter_map(|line| line.parse::<usize>().ok())
+    .filter(|&line| line > 0 && line <= len);
+  for line in bookmarks {
+    file.bookmarks.set(line - 1);
+  }
+  let view = state.get("view").and_then(|view| view.split_once(' '))
+    .and_then(|(cursor, top)| Some((parse_position(cursor)?, parse_position(top)?)))
+    .filter(|(cursor, top)| on_lines(cursor) && top.0 <= cursor.0);
+  if let Some(((row, col), (top, left))) = view {
+    let cur = &mut file.cur;
+    (cur.row, cur.col, cur.top, cur.left) = (row, col, top, left);
+    truncate_cursor_to_line(cur, &file.buf);
+  }
+  let canonical = fs::canonicalize(path).ok();
+  for name in 'A'..='Z' {
+    match remembered_global_mark(name) {
+      Some((path, pos)) if Some(&path) == canonical.as_ref() && on_lines(&pos) => file.marks.set(name, pos),
+      _ => (),
+    }
+  }
+}
+
+// Closes the folds remembered for a file that still start where they did.
+fn remembered_folds(path: &str, buf: &Buffer) -> Folds {
+  let mut folds = Folds::new();
+  let state = state_root().map(|root| state::load(&root, Path::new(path))).unwrap_or_default();
+  let lines = state.get("folds").map_or("", String::as_str).split_whitespace().filter_map(|n| n.parse().ok());
+  for line in lines {
+    let line: usize = line;
+    if let Some(fold) = fold::fold_from(buf, line.saturating_sub(1)) {
+      folds.close(fold);
+    }
+  }
+  folds
+}
+
+// Opens or closes folds, returning whether any were. The cursor goes to the
+// line starting a fold it closes.
+fn fold_lines(action: Action, cur: &mut Cursor, buf: &Buffer, size: &Size) -> bool {
+  let changed = match action {
+    Action::OpenFold => cur.folds.open(cur.row),
+    Action::OpenAllFolds if cur.folds.is_empty() => false,
+    Action::OpenAllFolds => {
+      cur.folds.clear();
+      true
+    }
+    Action::CloseAllFolds => {
+      let folds = fold::all(buf);
+      let any = !folds.is_empty();
+      for fold in folds {
+        cur.folds.close(fold);
+      }
+      any
+    }
+    Action::ToggleFold if cur.folds.open(cur.row) => true,
+    _ => match fold::fold_at(buf, cur.row) {
+      Some(fold) if !cur.folds.is_closed(fold.start - 1) => {
+        cur.row = fold.start - 1;
+        cur.folds.close(fold);
+        true
+      }
+      _ => false,
+    },
+  };
+  cur.row = cur.folds.up(cur.row, 0);
+  truncate_cursor_to_line(cur, buf);
+  align_cursor(cur, buf, size);
+  changed
+}
+
+// A cursor moved into a closed fold, as by a search, opens it.
+fn reveal_cursor(file: &mut OpenFile, size: &Size) {
+  let OpenFile{ref buf, ref mut cur, ..} = *file;
+  if cur.folds.reveal(cur.row) {
+    align_cursor(cur, buf, size);
+  }
+}
+
+fn swap_root() -> Option<PathBuf> {
+  config::config_dir().map(|dir| dir.join("swap"))
+}
+
+// Writes the unsaved text of a file to its swap file, or removes the swap
+// file of one with nothing unsaved.
+fn write_swap(file: &OpenFile) -> io::Result<()> {
+  match swap_root() {
+    // a large file would be read whole to write it
+    Some(root) if file.modified() && !file.buf.is_lazy() && keeps_side_files(&file.path) && Path::new(&file.path).exists() =>
+      swap::write(&root, Path::new(&file.path), &file.buf),
+    _ => remove_swap(&file.path),
+  }
+}
+
+fn remove_swap(path: &str) -> io::Result<()> {
+  match swap_root() {
+    Some(root) if !path.is_empty() => swap::remove(&root, Path::new(path)),
+    _ => Ok(()),
+  }
+}
+
+// The text left in the swap file of a file, unless it is the same as the
+// file's own
+fn swapped_lines(file: &OpenFile) -> io::Result<Option<Vec<Line>>> {
+  let root = match swap_root() {
+    Some(root) if !file.path.is_empty() => root,
+    _ => return Ok(None),
+  };
+  Ok(swap::read(&root, Path::new(&file.path))?.filter(|lines| file.buf.lines != *lines))
+}
+
+// Takes the text left in the swap file of a file by an editor that ended
+// without saving it, which leaves the file with unsaved changes.
+fn recover(file: &mut OpenFile, fb: &mut Feedback) -> io::Result<()> {
+  match swapped_lines(file)? {
+    Some(lines) => {
+      file.buf.replace(lines);
+      file.cur.row = file.cur.row.min(file.buf.len().saturating_sub(1));
+      truncate_cursor_to_line(&mut file.cur, &file.buf);
+      fb.message = Some(format!("Recovered {}; :w saves it", file.path));
+    }
+    None => {
+      fb.bell = true;
+      fb.message = Some(String::from("Nothing to recover"));
+    }
+  }
+  Ok(())
+}
+
+fn history_root() -> Option<PathBuf> {
+  config::config_dir().map(|dir| dir.join("history"))
+}
+
+// Copies a file just saved into the local history, unless it is large
+// enough that a copy on every save would cost more than it is worth.
+fn record_history(path: &str) -> io::Result<()> {
+  match history_root() {
+    Some(root) if keeps_side_files(path) && !is_large_file(path) => history::record(&root, Path::new(path), config::get().history_size),
+    _ => Ok(()),
+  }
+}
+
+fn save_file(path: &str, buf: &Lines, format: FileFormat) -> io::Result<()> {
+  write_file(path, buf, format)?;
+  record_history(path)
+}
+
+// line indexing
+fn line_len(line: &Line) -> usize {
+  line.chars().count()
+}
+
+fn byte_index(line: &Line, col: usize) -> usize {
+  line.char_indices().nth(col).map_or(line.len(), |(i, _)| i)
+}
+
+// C0 controls other than tab, DEL, and the C1 controls, which some
+// terminals act on like escape sequences
+fn is_control(c: char) -> bool {
+  (c < ' ' && c != '\t')
+    || (c == '\u{7F}' && config::get().caret_del)
+    || ('\u{80}'..='\u{9F}').contains(&c)
+}
+
+fn control_repr(c: char) -> String {
+  if c >= '\u{80}' {
+    return format!("<{:02x}>", c as u32);
+  }
+  match config::get().control_chars {
+    ControlChars::Caret => format!("^{}", (c as u8 ^ 0x40) as char),
+    ControlChars::Symbol if c == '\u{7F}' => String::from("\u{2421}"),
+    ControlChars::Symbol => std::char::from_u32(0x2400 + c as u32).unwrap().to_string(),
+  }
+}
+
+// East Asian wide characters and emoji occupy two terminal cells
+fn char_width(c: char) -> usize {
+  if is_control(c) {
+    return control_repr(c).chars().count();
+  }
+  match c as u32 {
+    // DEL is not drawn by the terminal when it is not shown as a control
+    0x7F => 0,
+    0x1100..=0x115F
+    | 0x2E80..=0x303E
+    | 0x3041..=0x33FF
+    | 0x3400..=0x4DBF
+    | 0x4E00..=0x9FFF
+    | 0xA000..=0xA4CF
+    | 0xAC00..=0xD7A3
+    | 0xF900..=0xFAFF
+    | 0xFE30..=0xFE4F
+    | 0xFF00..=0xFF60
+    | 0xFFE0..=0xFFE6
+    | 0x1F300..=0x1F64F
+    | 0x1F680..=0x1F6FF
+    | 0x1F900..=0x1F9FF
+    | 0x1FA70..=0x1FAFF
+    | 0x20000..=0x3FFFD => 2,
+    _ => 1,
+  }
+}
+
+// number of terminal cells taken by the first `col` characters of the line
+fn line_width(line: &Line, col: usize) -> usize {
+  line.chars().take(col).map(char_width).sum()
+}
+
+// number of terminal cells taken by the text
+fn text_cells(text: &str) -> usize {
+  text.chars().map(char_width).sum()
+}
+
+// Virtual edit mode is only used without soft wrapping, where there is room
+// to the right of each line.
+fn virtual_edit() -> bool {
+  let cfg = config::get();
+  cfg.virtual_edit && !cfg.wrap
+}
+
+// buffer mutations
+fn init_buffer_if_empty(buf: &mut Buffer) {
+  if buf.is_empty() {
+    buf.push(String::new());
+    buf.note(0, 0, 1);
+  }
+}
+
+fn insert_at(ch: char, cur: &Cursor, buf: &mut Buffer) -> BufResult {
+  if cur.row >= buf.len() {
+    return Err(BufError::PastEndOfBuffer);
+  }
+  pad_line_to_cursor(cur, buf);
+  let i = byte_index(&buf[cur.row], cur.col);
+  buf[cur.row].insert(i, ch);
+  buf.touch();
+  Ok(())
+}
+
+// Fills the line with spaces up to a cursor past its end.
+fn pad_line_to_cursor(cur: &Cursor, buf: &mut Buffer) {
+  let len = line_len(&buf[cur.row]);
+  if cur.col > len {
+    buf[cur.row].extend(std::iter::repeat_n(' ', cur.col - len));
+    buf.touch();
+  }
+}
+
+fn delete_before(cur: &Cursor, buf: &mut Buffer) -> BufResult {
+  if cur.col == 0 {
+    return Err(BufError::BeforeStart);
+  }
+  if cur.row >= buf.len() {
+    return Err(BufError::PastEndOfBuffer);
+  }
+  if cur.col > line_len(&buf[cur.row]) {
+    return Err(BufError::PastEndOfLine);
+  }
+  let i = byte_index(&buf[cur.row], cur.col - 1);
+  buf[cur.row].remove(i);
+  buf.touch();
+  Ok(())
+}
+
+fn delete_at(cur: &Cursor, buf: &mut Buffer) -> BufResult {
+  if cur.row >= buf.len() {
+    return Err(BufError::PastEndOfBuffer);
+  }
+  if cur.col >= line_len(&buf[cur.row]) {
+    return Err(BufError::PastEndOfLine);
+  }
+  let i = byte_index(&buf[cur.row], cur.col);
+  buf[cur.row].remove(i);
+  buf.touch();
+  Ok(())
+}
+
+fn merge_next_line_into(cur: &Cursor, buf: &mut Buffer) -> BufResult {
+  if cur.row + 1 >= buf.len() {
+    return Err(BufError::PastEndOfBuffer);
+  }
+  let line = buf.remove(cur.row + 1);
+  buf[cur.row].push_str(&line);
+  buf.note(cur.row, 2, 1);
+  Ok(())
+}
+
+fn break_line_at(cur: &Cursor, buf: &mut Buffer) -> BufResult {
+  if cur.row >= buf.len() {
+    return Err(BufError::PastEndOfBuffer);
+  }
+  let i = byte_index(&buf[cur.row], cur.col);
+  let new_line = buf[cur.row].split_off(i);
+  buf.insert(cur.row + 1, new_line);
+  buf.note(cur.row, 1, 2);
+  Ok(())
+}
+
+fn indentation(line: &str) -> &str {
+  &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+}
+
+// The whitespace starting a line broken off after `before`: the indentation
+// of the line, and with `smart`, one more level after a `{` or `:`.
+fn new_line_indent(before: &str, smart: bool, shift_width: usize) -> String {
+  let mut indent = String::from(indentation(before));
+  if smart && before.trim_end().ends_with(['{', ':']) {
+    if indent.starts_with('\t') {
+      indent.push('\t');
+    } else {
+      indent.push_str(&" ".repeat(shift_width));
+    }
+  }
+  indent
+}
+
+// Adds a level of indentation to the lines that aren't empty: a tab for lines
+// indented with tabs, and `shift_width` spaces for the others.
+fn indent_lines(buf: &mut Buffer, rows: Range<usize>, shift_width: usize) -> bool {
+  let mut changed = false;
+  for line in buf.range_mut(rows) {
+    if line.is_empty() {
+      continue;
+    }
+    let level = if line.starts_with('\t') { String::from("\t") } else { " ".repeat(shift_width) };
+    line.insert_str(0, &level);
+    changed = true;
+  }
+  if changed {
+    buf.touch();
+  }
+  changed
+}
+
+// Removes a level of indentation: a tab, or up to `shift_width` spaces.
+fn dedent_lines(buf: &mut Buffer, rows: Range<usize>, shift_width: usize) -> bool {
+  let mut changed = false;
+  for line in buf.range_mut(rows) {
+    let n = match line.strip_prefix('\t') {
+      Some(_) => 1,
+      None => line.chars().take(shift_width).take_while(|&c| c == ' ').count(),
+    };
+    line.replace_range(..n, "");
+    changed |= n > 0;
+  }
+  if changed {
+    buf.touch();
+  }
+  changed
+}
+
+// Breaks the line at the cursor, indenting the new line when `auto_indent` is
+// on, and returns the column its text starts at.
+fn break_line_with_indent(cur: &Cursor, buf: &mut Buffer) -> Result<usize, BufError> {
+  let line = buf.get(cur.row).ok_or(BufError::PastEndOfBuffer)?;
+  let cfg = config::get();
+  let indent = match cfg.auto_indent {
+    true => new_line_indent(&line[..byte_index(line, cur.col)], cfg.smart_indent, cfg.shift_width),
+    false => String::new(),
+  };
+  break_line_at(cur, buf)?;
+  buf[cur.row + 1].insert_str(0, &indent);
+  Ok(line_len(&indent))
+}
+
+// Swaps the character under the cursor with the next one. At the end of the
+// line, the two characters before the cursor are swapped instead.
+fn transpose_chars(cur: &Cursor, buf: &mut Buffer) -> bool {
+  let line = match buf.get_mut(cur.row) {
+    Some(line) => line,
+    None => return false,
+  };
+  let mut chars: Vec<char> = line.chars().collect();
+  let i = if cur.col + 1 < chars.len() { cur.col } else { chars.len().saturating_sub(2) };
+  if i + 1 >= chars.len() {
+    return false;
+  }
+  chars.swap(i, i + 1);
+  *line = chars.into_iter().collect();
+  buf.touch();
+  true
+}
+
+fn swap_lines(a: usize, b: usize, buf: &mut Buffer) -> bool {
+  if a >= buf.len() || b >= buf.len() || a == b {
+    return false;
+  }
+  buf.swap(a, b);
+  buf.touch();
+  true
+}
+
+fn push_new_line_if_at_end(cur: &Cursor, buf: &mut Buffer) {
+  if cur.row == buf.len() {
+    buf.push(Line::new());
+    buf.note(cur.row, 0, 1);
+  }
+}
+
+// screen updating
+// The lines from the top of the screen to the last one shown on it, with
+// those hidden by closed folds between them
+fn buffer_line_range(cur: &Cursor, size: &Size) -> Range<usize> {
+  let mut end = cur.top;
+  let mut shown = 0;
+  while shown < size.rows {
+    if !cur.folds.is_hidden(end) {
+      shown += 1;
+    }
+    end += 1;
+  }
+  cur.top..end
+}
+
+fn buffer_char_range(cur: &Cursor, size: &Size) -> Range<usize> {
+  cur.left..(cur.left + size.cols)
+}
+
+// The cells of padding before each character of a line shown as part of a
+// table, or none otherwise. Tables aren't lined up when soft wrapping.
+fn table_padding(cur: &Cursor, line: &Line) -> Vec<usize> {
+  match cur.table {
+    Some(ref table) if !config::get().wrap => table.padding(line, text_cells),
+    _ => Vec::new(),
+  }
+}
+
+// Lines up the columns of a table shown on the screen.
+fn measure_table(cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  let rows = buffer_line_range(cur, size);
+  let Cursor{ref mut table, ref folds, ..} = *cur;
+  if let Some(table) = table {
+    let shown = rows.filter(|&row| !folds.is_hidden(row)).filter_map(|row| buf.get(row));
+    table.measure(shown, text_cells);
+  }
+}
+
+// Columns past the end of the line, reached in virtual edit mode, are one
+// cell wide.
+fn cursor_cell(cur: &Cursor, buf: &Buffer) -> usize {
+  buf.get(cur.row).map_or(0, |line| {
+    let padding: usize = table_padding(cur, line).iter().take(cur.col + 1).sum();
+    line_width(line, cur.col) + padding + cur.col.saturating_sub(line_len(line))
+  })
+}
+
+fn cursor_cell_width(cur: &Cursor, buf: &Buffer) -> usize {
+  buf.get(cur.row).and_then(|line| line.chars().nth(cur.col)).map_or(1, char_width)
+}
+
+// Soft wrapping splits a line into the ranges of characters shown on each
+// screen row. Continuation rows start with the wrap marker, and the end of the
+// line takes a cell for the line end marker, so the last range ends one past
+// the last character.
+fn wrap_marker_width() -> usize {
+  let marker = config::get().wrap_marker.clone();
+  line_width(&marker, usize::MAX)
+}
+
+fn wrap_line(line: &Line, size: &Size) -> Vec<Range<usize>> {
+  let rest = size.cols.saturating_sub(wrap_marker_width()).max(1);
+  let mut rows = Vec::new();
+  let (mut start, mut used, mut cap) = (0, 0, size.cols);
+  let widths = line.chars().map(char_width).chain(std::iter::once(1));
+  for (i, w) in widths.enumerate() {
+    if used + w > cap && i > start {
+      rows.push(start..i);
+      start = i;
+      used = 0;
+      cap = rest;
+    }
+    used += w;
+  }
+  rows.push(start..line_len(line) + 1);
+  rows
+}
+
+fn wrapped_line_height(row: usize, buf: &Buffer, size: &Size) -> usize {
+  buf.get(row).map_or(1, |line| wrap_line(line, size).len())
+}
+
+// position of the cursor on screen when wrapping, as (row, cell)
+fn wrapped_cursor_cell(cur: &Cursor, buf: &Buffer, size: &Size) -> (usize, usize) {
+  let above: usize = (cur.top..cur.row)
+    .filter(|&i| !cur.folds.is_hidden(i))
+    .map(|i| wrapped_line_height(i, buf, size))
+    .sum();
+  let line = match buf.get(cur.row) {
+    Some(line) => line,
+    None => return (above, 0),
+  };
+  let rows = wrap_line(line, size);
+  let sub = rows.iter().position(|r| r.contains(&cur.col)).unwrap_or(rows.len() - 1);
+  let start = rows[sub].start;
+  let marker = if sub > 0 { wrap_marker_width() } else { 0 };
+  let cell = marker + line.chars().skip(start).take(cur.col - start).map(char_width).sum::<usize>();
+  (above + sub, cell)
+}
+
+fn cursor_screen_position(cur: &Cursor, buf: &Buffer, size: &Size) -> (u16, u16) {
+  if config::get().wrap {
+    let (r, c) = wrapped_cursor_cell(cur, buf, size);
+    return ((r + 1) as u16, (size.left + c + 1) as u16);
+  }
+  let row = cur.folds.shown(cur.top..cur.row);
+  ((row + 1) as u16, (size.left + cursor_cell(cur, buf).saturating_sub(cur.left) + 1) as u16)
+}
+
+fn replace_invisibles(c: char) -> char {
+  match c {
+    '\t' => '\u{00BB}',
+    ' ' => '\u{0387}',
+    '\n' => '\u{00AC}',
+    c => c,
+  }
+}
+
+lazy_static! {
+  static ref SET_NORMAL_COLORS: Vec<u8> = format!(
+    "{}",
+    termion::color::Fg(termion::color::Reset),
+  ).into_bytes();
+  static ref SET_INVISIBLE_COLORS: Vec<u8> = format!(
+    "{}",
+    termion::color::Fg(termion::color::LightBlack),
+  ).into_bytes();
+  static ref SET_CONTROL_COLORS: Vec<u8> = format!(
+    "{}",
+    termion::color::Fg(termion::color::Blue),
+  ).into_bytes();
+  static ref SET_DELETED_COLORS: Vec<u8> = format!(
+    "{}",
+    termion::color::Fg(termion::color::Red),
+  ).into_bytes();
+  static ref SET_INSERTED_COLORS: Vec<u8> = format!(
+    "{}",
+    termion::color::Fg(termion::color::Green),
+  ).into_bytes();
+  static ref SET_CHANGED_COLORS: Vec<u8> = format!(
+    "{}",
+    termion::color::Fg(termion::color::Yellow),
+  ).into_bytes();
+  static ref SET_TODO_COLORS: Vec<u8> = format!(
+    "{}",
+    termion::color::Fg(termion::color::LightYellow),
+  ).into_bytes();
+  static ref SET_NORMAL_BACKGROUND: Vec<u8> = format!(
+    "{}",
+    termion::color::Bg(termion::color::Reset),
+  ).into_bytes();
+}
+
+const CONFLICT_MARKER_BACKGROUND: Color = Color::Indexed(238);
+const CONFLICT_OURS_BACKGROUND: Color = Color::Indexed(22);
+const CONFLICT_BASE_BACKGROUND: Color = Color::Indexed(236);
+const CONFLICT_THEIRS_BACKGROUND: Color = Color::Indexed(24);
+const TRAILING_WHITESPACE_BACKGROUND: Color = Color::Basic(1);
+const CURSOR_LINE_BACKGROUND: Color = Color::Indexed(235);
+const CURSOR_COLUMN_BACKGROUND: Color = Color::Indexed(237);
+
+fn set_normal_colors(scr: &mut Screen) -> io::Result<()> {
+  scr.write(&SET_NORMAL_COLORS).map(|_|())
+}
+
+fn set_invisible_colors(scr: &mut Screen) -> io::Result<()> {
+  scr.write(&SET_INVISIBLE_COLORS).map(|_|())
+}
+
+fn set_control_colors(scr: &mut Screen) -> io::Result<()> {
+  scr.write(&SET_CONTROL_COLORS).map(|_|())
+}
+
+fn set_todo_colors(scr: &mut Screen) -> io::Result<()> {
+  scr.write(&SET_TODO_COLORS).map(|_|())
+}
+
+fn set_normal_background(scr: &mut Screen) -> io::Result<()> {
+  scr.write(&SET_NORMAL_BACKGROUND).map(|_|())
+}
+
+// Lines inside merge conflicts are shaded by which side they belong to.
+fn line_background(row: usize, conflicts: &[Conflict]) -> Option<Color> {
+  conflicts.iter().find_map(|c| c.part(row)).map(|part| match part {
+    Part::Marker => CONFLICT_MARKER_BACKGROUND,
+    Part::Ours => CONFLICT_OURS_BACKGROUND,
+    Part::Base => CONFLICT_BASE_BACKGROUND,
+    Part::Theirs => CONFLICT_THEIRS_BACKGROUND,
+  })
+}
+
+// With `cursor_line`, the line of the cursor is shaded to the edge of the
+// screen, unless it is in a merge conflict, which is shaded already.
+fn cursor_line_background(row: usize, cur: &Cursor) -> Option<Color> {
+  match config::get().cursor_line && row == cur.row {
+    true => Some(CURSOR_LINE_BACKGROUND),
+    false => None,
+  }
+}
+
+fn fill_row_background(scr: &mut Screen, background: Option<Color>) -> io::Result<()> {
+  match background {
+    Some(CURSOR_LINE_BACKGROUND) => write!(scr, "{}", termion::clear::UntilNewline),
+    _ => Ok(()),
+  }
+}
+
+fn write_line_background(scr: &mut Screen, background: Option<Color>) -> io::Result<()> {
+  match background {
+    Some(background) => scr.set_bg(background),
+    None => Ok(()),
+  }
+}
+
+// Goes back to the background of the line after a mark with its own.
+fn restore_line_background(scr: &mut Screen, background: Option<Color>) -> io::Result<()> {
+  match background {
+    Some(background) => scr.set_bg(background),
+    None => set_normal_background(scr),
+  }
+}
+
+fn write_invisible_to_screen(scr: &mut Screen, mut c: char) -> io::Result<()> {
+  c = replace_invisibles(c);
+  set_invisible_colors(scr)?;
+  write!(scr, "{}", c)?;
+  set_normal_colors(scr)
+}
+
+fn write_control_to_screen(scr: &mut Screen, c: char) -> io::Result<()> {
+  set_control_colors(scr)?;
+  write!(scr, "{}", control_repr(c))?;
+  set_normal_colors(scr)
+}
+
+fn write_char_to_screen(scr: &mut Screen, c: char) -> io::Result<()> {
+  match c {
+    '\t' | ' ' => write_invisible_to_screen(scr, c),
+    c if is_control(c) => write_control_to_screen(scr, c),
+    '\u{7F}' => Ok(()),
+    c => write!(scr, "{}", c),
+  }
+}
+
+// Highlighted ranges of characters in a line
+#[derive(Clone, Copy, PartialEq)]
+enum Mark {
+  Selected,
+  Todo,
+  // spaces and tabs at the end of a line
+  Trailing,
+  Syntax(syntax::Kind),
+  // the column of a table the cursor is in
+  Column,
+}
+
+fn line_marks(
+  row: usize,
+  line: &Line,
+  markers: &[String],
+  sel: Option<&Selection>,
+  syntax: &[(Range<usize>, syntax::Kind)],
+) -> Vec<(Range<usize>, Mark)> {
+  let mut marks: Vec<_> = sel.and_then(|sel| sel.cols_in(row, line))
+    .map(|cols| (cols, Mark::Selected))
+    .into_iter()
+    .collect();
+  marks.extend(todo::find_in_line(line, markers).into_iter().map(|cols| (cols, Mark::Todo)));
+  let trailing = transform::trailing_whitespace(line);
+  if !trailing.is_empty() {
+    marks.push((trailing, Mark::Trailing));
+  }
+  if config::get().syntax {
+    marks.extend(syntax.iter().map(|(cols, kind)| (cols.clone(), Mark::Syntax(*kind))));
+  }
+  marks
+}
+
+fn syntax_color(kind: syntax::Kind) -> Color {
+  match kind {
+    syntax::Kind::Comment => Color::Basic(6),
+    syntax::Kind::String => Color::Basic(2),
+    syntax::Kind::Number => Color::Basic(3),
+    syntax::Kind::Keyword => Color::Basic(5),
+    syntax::Kind::Function => Color::Basic(12),
+  }
+}
+
+// Writes the character at `i` in a line, or its end, highlighted by the
+// first mark covering it, on the background of the line.
+fn write_marked_to_screen<F>(
+  scr: &mut Screen,
+  i: usize,
+  marks: &[(Range<usize>, Mark)],
+  background: Option<Color>,
+  write: F,
+) -> io::Result<()>
+where
+  F: FnOnce(&mut Screen) -> io::Result<()>,
+{
+  match marks.iter().find(|(cols, _)| cols.contains(&i)).map(|mark| mark.1) {
+    Some(Mark::Selected) => {
+      write!(scr, "{}", termion::style::Invert)?;
+      write(scr)?;
+      write!(scr, "{}", termion::style::NoInvert)
+    }
+    Some(Mark::Todo) => {
+      set_todo_colors(scr)?;
+      write(scr)?;
+      set_normal_colors(scr)
+    }
+    Some(Mark::Trailing) => {
+      scr.set_bg(TRAILING_WHITESPACE_BACKGROUND)?;
+      write(scr)?;
+      restore_line_background(scr, background)
+    }
+    Some(Mark::Syntax(kind)) => {
+      scr.set_fg(syntax_color(kind))?;
+      write(scr)?;
+      set_normal_colors(scr)
+    }
+    Some(Mark::Column) => {
+      scr.set_bg(CURSOR_COLUMN_BACKGROUND)?;
+      write(scr)?;
+      restore_line_background(scr, background)
+    }
+    None => write(scr),
+  }
+}
+
+fn write_line_end(scr: &mut Screen) -> io::Result<()> {
+  write_invisible_to_screen(scr, '\n')
+}
+
+// fills the cells of a wide character cut off by the edge of the screen
+fn write_clipped_to_screen(scr: &mut Screen, cells: usize) -> io::Result<()> {
+  write!(scr, "{:1$}", "", cells)
+}
+
+fn write_line_to_screen(
+  scr: &mut Screen,
+  cur: &Cursor,
+  line: &Line,
+  marks: &[(Range<usize>, Mark)],
+  background: Option<Color>,
+  size: &Size,
+) -> io::Result<()> {
+  set_normal_colors(scr)?;
+  let range = buffer_char_range(cur, size);
+  let padding = table_padding(cur, line);
+  let mut x = 0;
+  for (i, c) in line.chars().enumerate() {
+    let pad = padding.get(i).copied().unwrap_or(0);
+    if pad > 0 {
+      let (from, to) = (x.max(range.start), (x + pad).min(range.end));
+      write_clipped_to_screen(scr, to.saturating_sub(from))?;
+      x += pad;
+    }
+    let end = x + char_width(c);
+    if end > range.end {
+      return write_clipped_to_screen(scr, range.end - x.max(range.start));
+    }
+    if x >= range.start {
+      write_marked_to_screen(scr, i, marks, background, |scr| write_char_to_screen(scr, c))?;
+    } else if end > range.start {
+      write_clipped_to_screen(scr, end - range.start)?;
+    }
+    x = end;
+  }
+  if range.contains(&x) {
+    write_marked_to_screen(scr, line_len(line), marks, background, write_line_end)?;
+  }
+  Ok(())
+}
+
+// The gutter shows how each line differs from the index of a git repository,
+// and which lines have notes.
+fn write_sign_to_screen(scr: &mut Screen, sign: Option<Sign>, mark: Option<char>, size: &Size) -> io::Result<()> {
+  let width = size.left.saturating_sub(fold_column_width());
+  if width == 0 {
+    return Ok(());
+  }
+  if let Some(c) = mark {
+    set_todo_colors(scr)?;
+    write!(scr, "{:1$}", c, width)?;
+    return set_normal_colors(scr);
+  }
+  match sign {
+    Some(Sign::Added) => scr.write_all(&SET_INSERTED_COLORS)?,
+    Some(Sign::Modified) => scr.write_all(&SET_CHANGED_COLORS)?,
+    Some(Sign::Deleted) => scr.write_all(&SET_DELETED_COLORS)?,
+    None => (),
+  }
+  let c = match sign {
+    Some(Sign::Added) => '+',
+    Some(Sign::Modified) => '~',
+    Some(Sign::Deleted) => '-',
+    None => ' ',
+  };
+  write!(scr, "{:1$}", c, width)?;
+  set_normal_colors(scr)
+}
+
+// The fold column shows `+` by a closed fold, `-` by the start of an open
+// one, and a bar by the lines inside one.
+fn write_fold_mark_to_screen(
+  scr: &mut Screen,
+  row: usize,
+  cur: &Cursor,
+  buf: &Buffer,
+  ann: &Annotations,
+  size: &Size,
+) -> io::Result<()> {
+  if fold_column_width() == 0 || size.left == 0 {
+    return Ok(());
+  }
+  let c = if cur.folds.is_closed(row) {
+    '+'
+  } else if fold::starts_fold(buf, row) {
+    '-'
+  } else if ann.fold_levels.get(row).is_some_and(|&level| level > 0) {
+    '\u{2502}'
+  } else {
+    ' '
+  };
+  set_invisible_colors(scr)?;
+  write!(scr, "{}", c)?;
+  set_normal_colors(scr)
+}
+
+// A closed fold shows how many lines it hides after the line that starts it,
+// when there is room.
+fn write_fold_size_to_screen(scr: &mut Screen, cur: &Cursor, row: usize, line: &Line, size: &Size) -> io::Result<()> {
+  let hidden = cur.folds.hidden_after(row);
+  if hidden == 0 {
+    return Ok(());
+  }
+  let text = format!(" \u{22ef} {} lines", hidden);
+  // after the line and the cell of its end
+  let used = line_width(line, line_len(line)).saturating_sub(cur.left) + 1;
+  if used + text.chars().count() > size.cols {
+    return Ok(());
+  }
+  set_invisible_colors(scr)?;
+  write!(scr, "{}", text)?;
+  set_normal_colors(scr)
+}
+
+// Rows below the end of the buffer start with a `~`, so that they can be told
+// apart from empty lines.
+fn write_end_markers_to_screen(scr: &mut Screen, from: usize, size: &Size) -> io::Result<()> {
+  for row in from..size.rows {
+    scr.start_row(row)?;
+    set_invisible_colors(scr)?;
+    write!(scr, "~")?;
+    set_normal_colors(scr)?;
+  }
+  Ok(())
+}
+
+// The field of a line in the column of the table the cursor is in
+fn cursor_column(row: usize, cur: &Cursor, buf: &Buffer) -> Option<Range<usize>> {
+  let table = cur.table.as_ref()?;
+  let field = table::field_at(buf.get(cur.row)?, table.sep, cur.col);
+  table::fields(&buf[row], table.sep).get(field).cloned()
+}
+
+fn write_buffer_to_screen(
+  scr: &mut Screen,
+  cur: &Cursor,
+  buf: &Buffer,
+  sel: Option<&Selection>,
+  ann: &Annotations,
+  size: &Size,
+) -> io::Result<()> {
+  let markers = config::get().todo_markers.clone();
+  let mut screen_row = 0;
+  for i in buffer_line_range(cur, size) {
+    if cur.folds.is_hidden(i) {
+      continue;
+    }
+    if i >= buf.len() {
+      write_end_markers_to_screen(scr, screen_row, size)?;
+      break;
+    }
+    scr.start_row(screen_row)?;
+    write_sign_to_screen(scr, ann.signs.get(i).copied().flatten(), ann.line_mark(i), size)?;
+    write_fold_mark_to_screen(scr, i, cur, buf, ann, size)?;
+    let background = line_background(i, &ann.conflicts).or_else(|| cursor_line_background(i, cur));
+    write_line_background(scr, background)?;
+    let mut marks = line_marks(i, &buf[i], &markers, sel, ann.syntax.spans(i));
+    marks.extend(cursor_column(i, cur, buf).map(|cols| (cols, Mark::Column)));
+    write_line_to_screen(scr, cur, &buf[i], &marks, background, size)?;
+    write_fold_size_to_screen(scr, cur, i, &buf[i], size)?;
+    fill_row_background(scr, background)?;
+    set_normal_background(scr)?;
+    screen_row += 1;
+  }
+  scr.end_row();
+  let (r, c) = cursor_screen_position(cur, buf, size);
+  write!(scr, "{}", termion::cursor::Goto(c, r))
+}
+
+fn write_wrap_marker(scr: &mut Screen) -> io::Result<()> {
+  set_invisible_colors(scr)?;
+  write!(scr, "{}", config::get().wrap_marker)?;
+  set_normal_colors(scr)
+}
+
+fn write_wrapped_buffer_to_screen(
+  scr: &mut Screen,
+  cur: &Cursor,
+  buf: &Buffer,
+  sel: Option<&Selection>,
+  ann: &Annotations,
+  size: &Size,
+) -> io::Result<()> {
+  let mut screen_row = 0;
+  let markers = config::get().todo_markers.clone();
+  'lines: for (row, line) in buf.iter().enumerate().skip(cur.top) {
+    if cur.folds.is_hidden(row) {
+      continue;
+    }
+    let background = line_background(row, &ann.conflicts).or_else(|| cursor_line_background(row, cur));
+    let marks = line_marks(row, line, &markers, sel, ann.syntax.spans(row));
+    let chars: Vec<char> = line.chars().collect();
+    for (sub, range) in wrap_line(line, size).into_iter().enumerate() {
+      if screen_row == size.rows {
+        break 'lines;
+      }
+      scr.start_row(screen_row)?;
+      set_normal_colors(scr)?;
+      let sign = if sub == 0 { ann.signs.get(row).copied().flatten() } else { None };
+      let mark = if sub == 0 { ann.line_mark(row) } else { None };
+      write_sign_to_screen(scr, sign, mark, size)?;
+      write_fold_mark_to_screen(scr, row, cur, buf, ann, size)?;
+      if sub > 0 {
+        write_wrap_marker(scr)?;
+      }
+      write_line_background(scr, background)?;
+      for i in range {
+        match chars.get(i) {
+          Some(&c) => write_marked_to_screen(scr, i, &marks, background, |scr| write_char_to_screen(scr, c))?,
+          None => write_marked_to_screen(scr, i, &marks, background, write_line_end)?,
+        }
+      }
+      fill_row_background(scr, background)?;
+      set_normal_background(scr)?;
+      screen_row += 1;
+    }
+  }
+  write_end_markers_to_screen(scr, screen_row, size)?;
+  scr.end_row();
+  let (r, c) = cursor_screen_position(cur, buf, size);
+  write!(scr, "{}", termion::cursor::Goto(c, r))
+}
+
+// A line of input typed on the bottom row of the screen
+// Writes text cut off before `cols` cells, so that it cannot wrap or scroll
+// the screen.
+fn write_text_clipped_to_screen(scr: &mut Screen, text: &str, cols: usize) -> io::Result<()> {
+  let mut width = 0;
+  for c in text.chars() {
+    width += char_width(c);
+    if width >= cols {
+      break;
+    }
+    if is_control(c) {
+      write_control_to_screen(scr, c)?;
+    } else {
+      write!(scr, "{}", c)?;
+    }
+  }
+  Ok(())
+}
+
+// Writes a prompt or message on the bottom row.
+fn write_prompt_to_screen(scr: &mut Screen, prompt: &str, size: &Size) -> io::Result<()> {
+  scr.start_row(size.rows - 1)?;
+  set_normal_colors(scr)?;
+  write_text_clipped_to_screen(scr, prompt, size.cols)?;
+  scr.end_row();
+  Ok(())
+}
+
+// The bottom row of the screen shows a prompt being typed, a message, or else
+// the status of the buffer.
+struct StatusLine {
+  row: usize,
+  // the width of the text area, beside the preview too
+  cols: usize,
+  text: String,
+  // whether the text is a prompt, which keeps the cursor at its end
+  prompt: bool,
+}
+
+fn mode_name(mode: &Mode) -> &'static str {
+  match mode {
+    Mode::Insert | Mode::Literal(_) | Mode::Digraph(_) | Mode::Dynamic | Mode::Expression(_) =>
+      "INSERT",
+    Mode::Visual(_) => "VISUAL",
+    Mode::VisualBlock(_) => "VISUAL BLOCK",
+    Mode::Terminal(_) => "TERMINAL",
+    Mode::Tree => "TREE",
+    _ => "NORMAL",
+  }
+}
+
+// The mode, path and modified flag on the left, and the cursor position on
+// the right.
+fn status_text(mode: &Mode, path: &str, modified: bool, cur: &Cursor, cols: usize) -> String {
+  let name = if path.is_empty() { NO_NAME } else { path };
+  let left = format!("{}  {}{}", mode_name(mode), name, if modified { " [+]" } else { "" });
+  let right = format!("{}:{}", cur.row + 1, cur.col + 1);
+  let width: usize = left.chars().map(char_width).sum();
+  // the last column is left empty, like for prompts
+  let pad = cols.saturating_sub(width + right.len() + 1).max(1);
+  format!("{}{}{}", left, " ".repeat(pad), right)
+}
+
+fn write_status_line_to_screen(scr: &mut Screen, status: &StatusLine) -> io::Result<()> {
+  scr.start_row(status.row - 1)?;
+  set_normal_colors(scr)?;
+  if status.prompt {
+    write_text_clipped_to_screen(scr, &status.text, status.cols)?;
+  } else {
+    write!(scr, "{}", termion::style::Invert)?;
+    write_text_clipped_to_screen(scr, &status.text, status.cols)?;
+    write!(scr, "{}", termion::style::NoInvert)?;
+  }
+  scr.end_row();
+  Ok(())
+}
+
+// The hex panel shows the bytes of the current line as they would be saved,
+// below the text, with the byte under the cursor highlighted.
+const HEX_PANEL_ROWS: usize = 4;
+
+// Rows taken from the bottom of the screen by the panel shown, if any
+fn panel_rows(mode: &Mode, ses: &Session) -> usize {
+  match mode {
+    Mode::TodoList(..) => TODO_LIST_ROWS,
+    Mode::BookmarkList(_) => BOOKMARK_LIST_ROWS,
+    Mode::Buffers(..) => BUFFER_LIST_ROWS,
+    Mode::Note(_) => NOTE_ROWS,
+    _ if ses.shell.is_some() => TERMINAL_ROWS,
+    _ if ses.hex => HEX_PANEL_ROWS,
+    _ => 0,
+  }
+}
+
+// Files in a git repository or with notes or bookmarks have a gutter left of
+// the text for the signs of their changed lines, notes and bookmarks, and
+// with `fold_column` every file has a column there for its folds.
+const GUTTER_WIDTH: usize = 1;
+
+const NOTE_SIGN: char = '*';
+const BOOKMARK_SIGN: char = '>';
+
+fn fold_column_width() -> usize {
+  if config::get().fold_column { 1 } else { 0 }
+}
+
+fn with_gutter(size: Size, file: &OpenFile) -> Size {
+  let signs = file.git.is_some() || !file.notes.is_empty() || !file.bookmarks.is_empty();
+  let width = if signs { GUTTER_WIDTH } else { 0 } + fold_column_width();
+  match width {
+    0 => size,
+    _ if size.cols > width => Size{cols: size.cols - width, left: width, ..size},
+    _ => size,
+  }
+}
+
+// The tree of files takes a third of the text area on the right, up to
+// `TREE_COLS`, less a column for the rule between them.
+fn with_tree(size: Size, ses: &Session) -> Size {
+  let width = (size.cols / 3).min(TREE_COLS);
+  match ses.tree {
+    Some(_) if width >= MIN_TREE_COLS => Size{cols: size.cols - width - 1, ..size},
+    _ => size,
+  }
+}
+
+const TREE_COLS: usize = 30;
+const MIN_TREE_COLS: usize = 10;
+
+// The selected entry is highlighted while the tree has the keys, with the
+// cursor on it.
+fn write_tree_to_screen(scr: &mut Screen, tree: &mut Tree, focused: bool, size: &Size, cols: usize) -> io::Result<()> {
+  let rule = size.left + size.cols;
+  let width = cols.saturating_sub(rule + 2);
+  tree.scroll(size.rows);
+  let mut cursor = None;
+  for i in 0..size.rows {
+    scr.continue_row(i);
+    write!(scr, "{}", termion::cursor::Goto(rule as u16 + 1, i as u16 + 1))?;
+    set_normal_colors(scr)?;
+    write!(scr, "{}", termion::clear::UntilNewline)?;
+    set_invisible_colors(scr)?;
+    write!(scr, "\u{2502} ")?;
+    set_normal_colors(scr)?;
+    let n = tree.top + i;
+    let entry = match tree.entries.get(n) {
+      Some(entry) => entry,
+      None => continue,
+    };
+    let indent = "  ".repeat(entry.depth);
+    let text = match (entry.dir, tree.is_expanded(entry)) {
+      (true, true) => format!("{}\u{25be} {}/", indent, entry.name()),
+      (true, false) => format!("{}\u{25b8} {}/", indent, entry.name()),
+      (false, _) => format!("{}  {}", indent, entry.name()),
+    };
+    if focused && n == tree.selected {
+      write!(scr, "{}", termion::style::Invert)?;
+      cursor = Some((i, indent.len()));
+    }
+    write_text_clipped_to_screen(scr, &text, width)?;
+    write!(scr, "{}", termion::style::NoInvert)?;
+  }
+  scr.end_row();
+  if let Some((row, col)) = cursor {
+    let col = (rule + 2 + col).min(cols.saturating_sub(1));
+    write!(scr, "{}", termion::cursor::Goto(col as u16 + 1, row as u16 + 1))?;
+  }
+  Ok(())
+}
+
+// The markdown preview takes the right half of the text area, less a column
+// for the rule between them, unless the tree of files is there.
+fn with_preview(size: Size, ses: &Session, file: &OpenFile) -> Size {
+  match ses.preview && ses.tree.is_none() && markdown::is_markdown(&file.path) {
+    true if size.cols >= 2 * MIN_PREVIEW_COLS => Size{cols: size.cols / 2, ..size},
+    _ => size,
+  }
+}
+
+const MIN_PREVIEW_COLS: usize = 20;
+const PREVIEW_CODE_FOREGROUND: Color = Color::Basic(6);
+
+// The preview is drawn from the row rendered from the top line shown, so
+// that it follows the text as it scrolls.
+fn write_preview_to_screen(
+  scr: &mut Screen,
+  rows: &[markdown::Row],
+  top: usize,
+  size: &Size,
+  cols: usize,
+) -> io::Result<()> {
+  let rule = size.left + size.cols;
+  let width = cols.saturating_sub(rule + 3);
+  let first = rows.iter().position(|row| row.source >= top).unwrap_or(rows.len());
+  for i in 0..size.rows {
+    scr.continue_row(i);
+    write!(scr, "{}", termion::cursor::Goto(rule as u16 + 1, i as u16 + 1))?;
+    set_normal_colors(scr)?;
+    write!(scr, "{}", termion::clear::UntilNewline)?;
+    set_invisible_colors(scr)?;
+    write!(scr, "\u{2502} ")?;
+    set_normal_colors(scr)?;
+    let mut room = width;
+    'spans: for (style, text) in rows.get(first + i).map_or(&[][..], |row| &row.spans[..]) {
+      if style.heading || style.bold {
+        write!(scr, "{}", termion::style::Bold)?;
+      }
+      if style.heading {
+        write!(scr, "{}", termion::style::Underline)?;
+      }
+      if style.italic {
+        write!(scr, "{}", termion::style::Italic)?;
+      }
+      if style.code {
+        scr.set_fg(PREVIEW_CODE_FOREGROUND)?;
+      }
+      for c in text.chars() {
+        let n = char_width(c);
+        if n > room {
+          break 'spans;
+        }
+        room -= n;
+        if is_control(c) {
+          write_control_to_screen(scr, c)?;
+        } else {
+          write!(scr, "{}", c)?;
+        }
+      }
+      write!(scr, "{}", termion::style::Reset)?;
+      set_normal_colors(scr)?;
+    }
+    write!(scr, "{}", termion::style::Reset)?;
+    set_normal_colors(scr)?;
+  }
+  scr.end_row();
+  Ok(())
+}
+
+// The text area leaves the bottom row of the screen for the status line, and
+// the panel rows above it.
+fn text_area_size(size: Size, panel_rows: usize) -> Size {
+  if size.rows > panel_rows + 1 {
+    Size{rows: size.rows - panel_rows - 1, ..size}
+  } else {
+    size
+  }
+}
+
+// Bytes per row of the hex panel: 16, or the largest power of two that fits
+// on the screen along with the offset and the characters.
+fn hex_row_bytes(cols: usize) -> usize {
+  let mut n = 16;
+  while n > 1 && 12 + 4 * n > cols {
+    n /= 2;
+  }
+  n
+}
+
+// Rows of the current line's bytes shown in the hex panel, ending with the
+// row of the byte under the cursor once the line does not fit.
+fn hex_panel_rows(len: usize, byte: usize, per_row: usize) -> Range<usize> {
+  let start = (byte / per_row + 1).saturating_sub(HEX_PANEL_ROWS);
+  start..(start + HEX_PANEL_ROWS).min(len.div_ceil(per_row))
+}
+
+fn write_hex_byte(scr: &mut Screen, text: &str, under_cursor: bool) -> io::Result<()> {
+  if under_cursor {
+    write!(scr, "{}{}{}", termion::style::Invert, text, termion::style::NoInvert)
+  } else {
+    write!(scr, "{}", text)
+  }
+}
+
+// The bytes of a line as they would be saved in the file's format, with
+// their offset in the file and the index of the byte under the cursor.
+fn hex_panel_bytes(cur: &Cursor, buf: &Lines, format: FileFormat) -> io::Result<(usize, Vec<u8>, usize)> {
+  let line = &buf[cur.row];
+  let first = FileFormat{bom: format.bom && cur.row == 0, ..format};
+  let offset = match cur.row {
+    0 => 0,
+    row => buffer_text(buf.range(0..row), FileFormat{final_newline: true, ..format})?.len(),
+  };
+  let last = cur.row + 1 == buf.len();
+  let bytes = buffer_text(std::iter::once(line), FileFormat{final_newline: format.final_newline || !last, ..first})?;
+  let before = String::from(&line[..byte_index(line, cur.col)]);
+  let byte = buffer_text(std::iter::once(&before), FileFormat{final_newline: false, ..first})?.len();
+  Ok((offset, bytes, byte))
+}
+
+fn write_hex_panel_to_screen(
+  scr: &mut Screen,
+  cur: &Cursor,
+  buf: &Buffer,
+  format: FileFormat,
+  size: &Size,
+) -> io::Result<()> {
+  if cur.row >= buf.len() {
+    return Ok(());
+  }
+  let (offset, bytes, byte) = match hex_panel_bytes(cur, buf, format) {
+    Ok(bytes) => bytes,
+    Err(err) => {
+      scr.start_row(size.rows)?;
+      set_normal_colors(scr)?;
+      write!(scr, "{}", err)?;
+      scr.end_row();
+      let (r, c) = cursor_screen_position(cur, buf, size);
+      return write!(scr, "{}", termion::cursor::Goto(c, r));
+    }
+  };
+  let per_row = hex_row_bytes(size.cols);
+  for (i, row) in hex_panel_rows(bytes.len(), byte, per_row).enumerate() {
+    scr.start_row(size.rows + i)?;
+    set_normal_colors(scr)?;
+    write!(scr, "{:08x} ", offset + row * per_row)?;
+    let start = row * per_row;
+    let chunk = &bytes[start..(start + per_row).min(bytes.len())];
+    for (j, b) in chunk.iter().enumerate() {
+      write!(scr, " ")?;
+      write_hex_byte(scr, &format!("{:02x}", b), start + j == byte)?;
+    }
+    write!(scr, "{:1$} |", "", 3 * (per_row - chunk.len()))?;
+    for (j, b) in chunk.iter().enumerate() {
+      let c = if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' };
+      write_hex_byte(scr, &c.to_string(), start + j == byte)?;
+    }
+    write!(scr, "|")?;
+  }
+  scr.end_row();
+  let (r, c) = cursor_screen_position(cur, buf, size);
+  write!(scr, "{}", termion::cursor::Goto(c, r))
+}
+
+// The TODO list shows the lines with markers below the text, with the
+// selected one highlighted.
+const TODO_LIST_ROWS: usize = 8;
+
+fn write_todo_list_to_screen(
+  scr: &mut Screen,
+  cur: &Cursor,
+  buf: &Buffer,
+  rows: &[usize],
+  selected: usize,
+  size: &Size,
+) -> io::Result<()> {
+  let first = (selected + 1).saturating_sub(TODO_LIST_ROWS);
+  for (i, (n, row)) in rows.iter().enumerate().skip(first).take(TODO_LIST_ROWS).enumerate() {
+    scr.start_row(size.rows + i)?;
+    set_normal_colors(scr)?;
+    if n == selected {
+      write!(scr, "{}", termion::style::Invert)?;
+    }
+    let text = format!("{:>5}: {}", row + 1, buf[*row].trim());
+    write_text_clipped_to_screen(scr, &text, size.cols)?;
+    write!(scr, "{}", termion::style::NoInvert)?;
+  }
+  scr.end_row();
+  let (r, c) = cursor_screen_position(cur, buf, size);
+  write!(scr, "{}", termion::cursor::Goto(c, r))
+}
+
+// The bookmark list shows the bookmarked lines below the text, with the
+// lines around the selected one dimmed above and below it.
+const BOOKMARK_LIST_ROWS: usize = 8;
+
+fn write_bookmark_list_to_screen(
+  scr: &mut Screen,
+  cur: &Cursor,
+  buf: &Buffer,
+  rows: &[usize],
+  selected: usize,
+  size: &Size,
+) -> io::Result<()> {
+  // each entry is a line, and whether it is bookmarked or around the
+  // selected bookmark
+  let mut entries = Vec::new();
+  for (n, &row) in rows.iter().enumerate() {
+    if n == selected && row > 0 && (n == 0 || rows[n - 1] < row - 1) {
+      entries.push((row - 1, false));
+    }
+    entries.push((row, true));
+    if n == selected && row + 1 < buf.len() && rows.get(n + 1) != Some(&(row + 1)) {
+      entries.push((row + 1, false));
+    }
+  }
+  let chosen = entries.iter().position(|&(row, marked)| marked && row == rows[selected]).unwrap_or(0);
+  let first = (chosen + 2).saturating_sub(BOOKMARK_LIST_ROWS);
+  for (i, &(row, marked)) in entries.iter().skip(first).take(BOOKMARK_LIST_ROWS).enumerate() {
+    scr.start_row(size.rows + i)?;
+    let text = match marked {
+      true => format!("{:>5}: {}", row + 1, buf[row].trim()),
+      false => format!("{:>5}  {}", row + 1, buf[row].trim()),
+    };
+    if !marked {
+      set_invisible_colors(scr)?;
+    } else if row == rows[selected] {
+      set_normal_colors(scr)?;
+      write!(scr, "{}", termion::style::Invert)?;
+    } else {
+      set_normal_colors(scr)?;
+    }
+    write_text_clipped_to_screen(scr, &text, size.cols)?;
+    write!(scr, "{}", termion::style::NoInvert)?;
+    set_normal_colors(scr)?;
+  }
+  scr.end_row();
+  let (r, c) = cursor_screen_position(cur, buf, size);
+  write!(scr, "{}", termion::cursor::Goto(c, r))
+}
+
+// A note is shown below the text, under a rule naming its line, until the
+// next key.
+const NOTE_ROWS: usize = 4;
+
+fn write_note_to_screen(scr: &mut Screen, row: usize, text: &str, size: &Size) -> io::Result<()> {
+  let cols = size.left + size.cols;
+  scr.start_row(size.rows)?;
+  set_invisible_colors(scr)?;
+  let title = format!("\u{2500} Note on line {} ", row + 1);
+  write_text_clipped_to_screen(scr, &title, cols)?;
+  write!(scr, "{}", "\u{2500}".repeat(cols.saturating_sub(text_cells(&title))))?;
+  set_normal_colors(scr)?;
+  let rows = reflow::reflow(&[text.to_string()], cols.max(1), text_cells);
+  for (i, line) in rows.iter().take(NOTE_ROWS - 1).enumerate() {
+    scr.start_row(size.rows + 1 + i)?;
+    write_text_clipped_to_screen(scr, line, cols + 1)?;
+  }
+  scr.end_row();
+  Ok(())
+}
+
+// The open files matching what was typed are listed below the text, the
+// best match first.
+const BUFFER_LIST_ROWS: usize = 8;
+
+const BUFFERS_PROMPT: &str = "Buffer: ";
+
+fn write_buffer_list_to_screen(
+  scr: &mut Screen,
+  files: &BufferList,
+  query: &str,
+  selected: usize,
+  size: &Size,
+) -> io::Result<()> {
+  let first = (selected + 1).saturating_sub(BUFFER_LIST_ROWS);
+  let found = files.matching(query);
+  for (i, (n, &file)) in found.iter().enumerate().skip(first).take(BUFFER_LIST_ROWS).enumerate() {
+    scr.start_row(size.rows + i)?;
+    set_normal_colors(scr)?;
+    if n == selected {
+      write!(scr, "{}", termion::style::Invert)?;
+    }
+    let file = &files.files[file];
+    let modified = if file.modified() && file.scratch.is_none() { " [+]" } else { "" };
+    write_text_clipped_to_screen(scr, &format!("{}{}", buffer_name(file), modified), size.left + size.cols)?;
+    write!(scr, "{}", termion::style::NoInvert)?;
+  }
+  scr.end_row();
+  Ok(())
+}
+
+// The shell of `:term` is shown below the text, under a rule, for as long as
+// it runs. The last rows it wrote are shown, as in a terminal.
+const TERMINAL_ROWS: usize = 12;
+
+// The size of the shell's terminal, inside the panel of a screen `cols` wide
+fn terminal_size(cols: usize) -> (usize, usize) {
+  (TERMINAL_ROWS - 1, cols)
+}
+
+fn write_terminal_to_screen(scr: &mut Screen, shell: &pty::Shell, focused: bool, size: &Size) -> io::Result<()> {
+  let cols = size.left + size.cols;
+  scr.start_row(size.rows)?;
+  set_invisible_colors(scr)?;
+  write!(scr, "{}", "\u{2500}".repeat(cols))?;
+  set_normal_colors(scr)?;
+  let vt = &shell.vt;
+  let top = vt.top();
+  for (i, line) in vt.lines.iter().skip(top).take(TERMINAL_ROWS - 1).enumerate() {
+    scr.start_row(size.rows + 1 + i)?;
+    let text: String = line.iter().collect();
+    write_text_clipped_to_screen(scr, &text, cols + 1)?;
+  }
+  scr.end_row();
+  if focused {
+    let row = size.rows + 2 + vt.row.saturating_sub(top);
+    write!(scr, "{}", termion::cursor::Goto(vt.col.min(cols - 1) as u16 + 1, row as u16))?;
+  }
+  Ok(())
+}
+
+// Keys typed into the shell are sent as the bytes a terminal sends for them.
+// Escape goes back to the file, leaving the shell running, unless it comes
+// after Ctrl-V.
+fn terminal_key_bytes(key: Key) -> Vec<u8> {
+  match key {
+    // Enter sends a carriage return, as it does in a terminal
+    Key::Char('\n') => vec![b'\r'],
+    Key::Char(c) => c.to_string().into_bytes(),
+    // termion reads the control codes after Ctrl-Z as Ctrl-4 to Ctrl-7
+    Key::Ctrl(c @ '4'..='7') => vec![c as u8 - b'4' + 0x1c],
+    Key::Ctrl(c) => vec![(c as u8) & 0x1f],
+    Key::Alt(c) => format!("\x1b{}", c).into_bytes(),
+    Key::Esc => vec![0x1b],
+    Key::Backspace => vec![0x7f],
+    Key::Delete => b"\x1b[3~".to_vec(),
+    Key::Up => b"\x1b[A".to_vec(),
+    Key::Down => b"\x1b[B".to_vec(),
+    Key::Right => b"\x1b[C".to_vec(),
+    Key::Left => b"\x1b[D".to_vec(),
+    Key::Home => b"\x1b[H".to_vec(),
+    Key::End => b"\x1b[F".to_vec(),
+    Key::BackTab => b"\x1b[Z".to_vec(),
+    _ => Vec::new(),
+  }
+}
+
+fn handle_key_terminal_mode(key: Key, literal: bool, ses: &mut Session) -> io::Result<Mode> {
+  let shell = match ses.shell {
+    Some(ref mut shell) => shell,
+    None => return Ok(Mode::Normal),
+  };
+  match key {
+    Key::Esc if !literal => Ok(Mode::Normal),
+    Key::Ctrl('v') if !literal => Ok(Mode::Terminal(true)),
+    key => {
+      shell.send(&terminal_key_bytes(key))?;
+      Ok(Mode::Terminal(false))
+    }
+  }
+}
+
+// Starts a shell in the panel, or goes back to the one running.
+fn start_terminal(ses: &mut Session, screen: &Size) -> io::Result<Mode> {
+  if ses.shell.is_none() {
+    let program = env::var("SHELL").unwrap_or_else(|_| String::from("/bin/sh"));
+    let (rows, cols) = terminal_size(screen.left + screen.cols);
+    ses.shell = Some(pty::Shell::spawn(&[&program], rows, cols)?);
+  }
+  Ok(Mode::Terminal(false))
+}
+
+fn blank_screen(scr: &mut Screen) -> io::Result<()> {
+  scr.forget();
+  write!(scr, "{}{}", termion::cursor::Goto(1, 1), termion::clear::All)
+}
+
+// The terminal settings from before raw mode, read the first time the screen
+// is set up, to put back on a panic or while the editor is suspended
+fn saved_termios() -> Option<&'static libc::termios> {
+  static TERMIOS: OnceLock<Option<libc::termios>> = OnceLock::new();
+  TERMIOS.get_or_init(get_termios).as_ref()
+}
+
+fn get_termios() -> Option<libc::termios> {
+  let mut termios: libc::termios = unsafe { mem::zeroed() };
+  match unsafe { libc::tcgetattr(libc::STDOUT_FILENO, &mut termios) } {
+    0 => Some(termios),
+    _ => None,
+  }
+}
+
+fn set_termios(termios: &libc::termios) {
+  unsafe { libc::tcsetattr(libc::STDOUT_FILENO, libc::TCSANOW, termios) };
+}
+
+// A panic would print its message on the alternate screen, where it is lost,
+// and leave the shell in raw mode. The hook puts the terminal back the way it
+// was before the message is printed.
+fn restore_terminal_on_panic() {
+  static HOOK: OnceLock<()> = OnceLock::new();
+  HOOK.get_or_init(|| {
+    let termios = match saved_termios() {
+      Some(termios) => *termios,
+      None => return,
+    };
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+      set_termios(&termios);
+      let mut out = io::stdout();
+      let _ = write!(
+        out,
+        "{}{}{}{}",
+        screen::RESET_CURSOR_SHAPE,
+        screen::DISABLE_BRACKETED_PASTE,
+        termion::screen::ToMainScreen,
+        termion::cursor::Show,
+      );
+      let _ = out.flush();
+      hook(info);
+    }));
+  });
+}
+
+extern "C" fn ignore_signal(_: libc::c_int) {}
+
+// Held while another program has the terminal, so that the keys typed for it
+// aren't read by the editor
+static KEYS_PAUSED: Mutex<()> = Mutex::new(());
+
+// Input read only while no other program has the terminal. It waits for
+// input before it takes the lock, so that pausing never waits for a key.
+struct PausableInput<R>(R);
+
+impl<R: Read + AsRawFd> Read for PausableInput<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+      wait_for_input(self.0.as_raw_fd(), -1)?;
+      let _paused = KEYS_PAUSED.lock().unwrap_or_else(PoisonError::into_inner);
+      // the other program may have read it in the meantime
+      if wait_for_input(self.0.as_raw_fd(), 0)? {
+        return self.0.read(buf);
+      }
+    }
+  }
+}
+
+// Whether there is input to read within `timeout` milliseconds, or ever
+// with -1.
+fn wait_for_input(fd: RawFd, timeout: libc::c_int) -> io::Result<bool> {
+  let mut poll = libc::pollfd{fd, events: libc::POLLIN, revents: 0};
+  match unsafe { libc::poll(&mut poll, 1, timeout) } {
+    -1 => match io::Error::last_os_error() {
+      err if err.kind() == io::ErrorKind::Interrupted => Ok(false),
+      err => Err(err),
+    },
+    n => Ok(n > 0),
+  }
+}
+
+// Runs `f` with the terminal as it was before the editor started, for a
+// command that uses it, and with no keys read until it is done. Ctrl-C then
+// stops the command but not the editor, whose handler is not kept by the
+// command.
+fn with_terminal_restored<T>(f: impl FnOnce() -> T) -> T {
+  let (Some(saved), Some(raw)) = (saved_termios(), get_termios()) else {
+    return f();
+  };
+  let _paused = KEYS_PAUSED.lock().unwrap_or_else(PoisonError::into_inner);
+  set_termios(saved);
+  let handler = ignore_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+  let previous = unsafe { libc::signal(libc::SIGINT, handler) };
+  let result = f();
+  unsafe { libc::signal(libc::SIGINT, previous) };
+  set_termios(&raw);
+  result
+}
+
+// In raw mode, Ctrl-Z is just a key, so the editor stops itself: it puts the
+// terminal back the way it was and stops until `fg`, then takes the terminal
+// again and has the whole screen drawn.
+fn suspend(scr: &mut Screen) -> io::Result<()> {
+  let (Some(saved), Some(raw)) = (saved_termios(), get_termios()) else {
+    return Ok(());
+  };
+  let paste = scr.bracketed_paste();
+  scr.set_bracketed_paste(false)?;
+  write!(scr, "{}{}{}", screen::RESET_CURSOR_SHAPE, termion::screen::ToMainScreen, termion::cursor::Show)?;
+  scr.flush()?;
+  set_termios(saved);
+  unsafe { libc::raise(libc::SIGTSTP) };
+  set_termios(&raw);
+  write!(scr, "{}", termion::screen::ToAlternateScreen)?;
+  scr.set_bracketed_paste(paste)?;
+  scr.forget();
+  Ok(())
+}
+
+fn init_screen() -> io::Result<Screen> {
+  saved_termios();
+  restore_terminal_on_panic();
+  termion::screen::AlternateScreen::from(io::stdout())
+    .into_raw_mode().map(|out| Screen::new(Box::new(BufWriter::new(out))))
+}
+
+fn update_screen(
+  scr: &mut Screen,
+  cur: &Cursor,
+  buf: &Buffer,
+  sel: Option<&Selection>,
+  ann: &Annotations,
+  size: &Size,
+  status: &StatusLine,
+) -> io::Result<()> {
+  scr.begin_frame();
+  if config::get().wrap {
+    write_wrapped_buffer_to_screen(scr, cur, buf, sel, ann, size)?;
+  } else {
+    write_buffer_to_screen(scr, cur, buf, sel, ann, size)?;
+  }
+  write_status_line_to_screen(scr, status)?;
+  if !status.prompt {
+    let (r, c) = cursor_screen_position(cur, buf, size);
+    write!(scr, "{}", termion::cursor::Goto(c, r))?;
+  }
+  Ok(())
+}
+
+// While a character is being composed with Ctrl-V or Ctrl-K, what has been
+// typed of it is shown underlined over the cursor, the way a terminal shows
+// an input method's preedit text, without changing the buffer.
+fn preedit_text(mode: &Mode) -> Option<String> {
+  match mode {
+    Mode::Literal(code) if code.is_empty() => Some(String::from("^")),
+    Mode::Literal(code) => Some(code.clone()),
+    Mode::Digraph(None) => Some(String::from("?")),
+    Mode::Digraph(Some(first)) => Some(first.to_string()),
+    _ => None,
+  }
+}
+
+fn write_preedit_to_screen(scr: &mut Screen, text: &str, cur: &Cursor, buf: &Buffer, size: &Size) -> io::Result<()> {
+  let (r, c) = cursor_screen_position(cur, buf, size);
+  let room = (size.left + size.cols + 1).saturating_sub(c as usize);
+  let text: String = text.chars().take(room).collect();
+  scr.continue_row(r as usize - 1);
+  write!(scr, "{}{}{}{}", termion::cursor::Goto(c, r), termion::style::Underline, text, termion::style::Reset)?;
+  scr.end_row();
+  write!(scr, "{}", termion::cursor::Goto(c, r))
+}
+
+// The visual bell flashes the screen in reverse video, which each loop
+// reading keys turns back off once it has lasted this long, so that handling
+// input is never delayed.
+const VISUAL_BELL_DURATION: Duration = Duration::from_millis(100);
+
+fn ring_bell(scr: &mut Screen) -> io::Result<()> {
+  let bell = config::get().bell;
+  match bell {
+    Bell::Visual => scr.flash(),
+    Bell::Audible => write!(scr, "\x07"),
+    Bell::None => Ok(()),
+  }
+}
+
+// Cursor movement
+fn move_cursor_left(cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  if cur.col > 0 {
+    cur.col -= 1;
+  } else if cur.row > 0 {
+    cur.row -= 1;
+    cur.col = line_len(&buf[cur.row]);
+  }
+  align_cursor(cur, buf, size);
+}
+
+fn move_cursor_right(cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  if cur.row < buf.len() {
+    if cur.col < line_len(&buf[cur.row]) {
+      cur.col += 1;
+    } else {
+      cur.row += 1;
+      cur.col = 0;
+    }
+  }
+  align_cursor(cur, buf, size);
+}
+
+// Like moving right, except that in virtual edit mode the cursor carries on
+// past the end of the line instead of going to the next one.
+fn move_cursor_forward(cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  if virtual_edit() && cur.row < buf.len() && cur.col >= line_len(&buf[cur.row]) {
+    cur.col += 1;
+    align_cursor(cur, buf, size);
+  } else {
+    move_cursor_right(cur, buf, size);
+  }
+}
+
+fn move_cursor_up(cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  if cur.row > 0 {
+    cur.row = cur.folds.up(cur.row, 1);
+  }
+  truncate_cursor_to_line(cur, buf);
+  align_cursor(cur, buf, size);
+}
+
+fn move_cursor_down(cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  if cur.row < buf.len() {
+    cur.row += 1;
+    // over the lines of a closed fold
+    while cur.folds.is_hidden(cur.row) {
+      cur.row += 1;
+    }
+  }
+  truncate_cursor_to_line(cur, buf);
+  align_cursor(cur, buf, size);
+}
+
+fn move_cursor_end_of_prev_line(cur: &mut Cursor, buf: &Buffer, size: &Size) -> BufResult {
+  if cur.row == 0 || cur.row > buf.len() {
+    return Err(BufError::BeforeStart);
+  }
+  cur.row -= 1;
+  cur.col = line_len(&buf[cur.row]);
+  align_cursor(cur, buf, size);
+  Ok(())
+}
+
+fn move_cursor_start_of_next_line(cur: &mut Cursor, buf: &Buffer, size: &Size) -> BufResult {
+  if cur.row >= buf.len() {
+    return Err(BufError::PastEndOfBuffer);
+  }
+  cur.row += 1;
+  cur.col = 0;
+  align_cursor(cur, buf, size);
+  Ok(())
+}
+
+fn get_char(cur: &mut Cursor, buf: &Buffer) -> char {
+  buf[cur.row].chars().nth(cur.col).unwrap_or('\n')
+}
+
+fn is_whitespace(c: char) -> bool {
+  c.is_ascii_whitespace()
+}
+
+fn is_blank(cur: &mut Cursor, buf: &Buffer) -> bool {
+  cur.row >= buf.len() || line_len(&buf[cur.row]) == cur.col || is_whitespace(get_char(cur, buf))
+}
+
+fn is_blank_line(cur: &mut Cursor, buf: &Buffer) -> bool {
+  if cur.row < buf.len() && !buf[cur.row].is_empty() {
+    for c in buf[cur.row].chars() {
+      if !is_whitespace(c) {
+        return false;
+      }
+    }
+  }
+  true
+}
+
+// Repeats a movement while `test` holds for the cursor position, stopping at
+// the edge of the buffer.
+fn move_cursor_while(
+  cur: &mut Cursor,
+  buf: &Buffer,
+  size: &Size,
+  test: fn(&mut Cursor, &Buffer) -> bool,
+  step: fn(&mut Cursor, &Buffer, &Size),
+) {
+  while test(cur, buf) {
+    let (row, col) = (cur.row, cur.col);
+    step(cur, buf, size);
+    if (row, col) == (cur.row, cur.col) {
+      break;
+    }
+  }
+}
+
+fn is_not_blank(cur: &mut Cursor, buf: &Buffer) -> bool {
+  !is_blank(cur, buf)
+}
+
+fn is_not_blank_line(cur: &mut Cursor, buf: &Buffer) -> bool {
+  !is_blank_line(cur, buf)
+}
+
+fn move_cursor_to_next_blank(cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  move_cursor_while(cur, buf, size, is_not_blank, move_cursor_right);
+  move_cursor_while(cur, buf, size, is_blank, move_cursor_right);
+}
+
+fn move_cursor_to_prev_blank(cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  move_cursor_while(cur, buf, size, is_not_blank, move_cursor_left);
+  move_cursor_while(cur, buf, size, is_blank, move_cursor_left);
+}
+
+fn move_cursor_to_next_blank_line(cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  move_cursor_while(cur, buf, size, is_not_blank_line, move_cursor_down);
+  move_cursor_while(cur, buf, size, is_blank_line, move_cursor_down);
+}
+
+fn move_cursor_to_prev_blank_line(cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  move_cursor_while(cur, buf, size, is_not_blank_line, move_cursor_up);
+  move_cursor_while(cur, buf, size, is_blank_line, move_cursor_up);
+}
+
+// Words are runs of letters, digits and underscores, or runs of other
+// non-blank characters. Line breaks are blank, except that an empty line is a
+// word of its own.
+#[derive(Clone, Copy, PartialEq)]
+enum CharClass {
+  Blank,
+  Word,
+  Punctuation,
+  EmptyLine,
+}
+
+fn class_at(buf: &Buffer, (row, col): (usize, usize)) -> CharClass {
+  let line = match buf.get(row) {
+    Some(line) => line,
+    None => return CharClass::Blank,
+  };
+  match line.chars().nth(col) {
+    Some(c) if c.is_whitespace() => CharClass::Blank,
+    Some(c) if c.is_alphanumeric() || c == '_' => CharClass::Word,
+    Some(_) => CharClass::Punctuation,
+    None if line.is_empty() => CharClass::EmptyLine,
+    None => CharClass::Blank,
+  }
+}
+
+// The positions before and after one, counting the end of each line.
+fn next_pos(buf: &Buffer, (row, col): (usize, usize)) -> Option<(usize, usize)> {
+  if col < line_len(buf.get(row)?) {
+    Some((row, col + 1))
+  } else if row + 1 < buf.len() {
+    Some((row + 1, 0))
+  } else {
+    None
+  }
+}
+
+fn prev_pos(buf: &Buffer, (row, col): (usize, usize)) -> Option<(usize, usize)> {
+  if col > 0 {
+    Some((row, col - 1))
+  } else if row > 0 {
+    buf.get(row - 1).map(|line| (row - 1, line_len(line)))
+  } else {
+    None
+  }
+}
+
+fn word_motion_start(cur: &Cursor, buf: &Buffer) -> (usize, usize) {
+  let len = buf.get(cur.row).map_or(0, line_len);
+  (cur.row, cur.col.min(len))
+}
+
+fn next_word_start(buf: &Buffer, pos: (usize, usize)) -> (usize, usize) {
+  let class = class_at(buf, pos);
+  let mut pos = pos;
+  while let Some(next) = next_pos(buf, pos) {
+    pos = next;
+    if class_at(buf, pos) != class || class == CharClass::EmptyLine {
+      break;
+    }
+  }
+  while class_at(buf, pos) == CharClass::Blank {
+    match next_pos(buf, pos) {
+      Some(next) => pos = next,
+      None => break,
+    }
+  }
+  pos
+}
+
+fn prev_word_start(buf: &Buffer, pos: (usize, usize)) -> (usize, usize) {
+  let mut pos = match prev_pos(buf, pos) {
+    Some(prev) => prev,
+    None => return pos,
+  };
+  while class_at(buf, pos) == CharClass::Blank {
+    match prev_pos(buf, pos) {
+      Some(prev) => pos = prev,
+      None => return pos,
+    }
+  }
+  let class = class_at(buf, pos);
+  while let Some(prev) = prev_pos(buf, pos) {
+    if class_at(buf, prev) != class || class == CharClass::EmptyLine {
+      break;
+    }
+    pos = prev;
+  }
+  pos
+}
+
+// Empty lines are skipped on the way to the end of a word.
+fn word_end(buf: &Buffer, pos: (usize, usize)) -> (usize, usize) {
+  let mut pos = match next_pos(buf, pos) {
+    Some(next) => next,
+    None => return pos,
+  };
+  while let CharClass::Blank | CharClass::EmptyLine = class_at(buf, pos) {
+    match next_pos(buf, pos) {
+      Some(next) => pos = next,
+      None => return pos,
+    }
+  }
+  let class = class_at(buf, pos);
+  while let Some(next) = next_pos(buf, pos) {
+    if class_at(buf, next) != class {
+      break;
+    }
+    pos = next;
+  }
+  pos
+}
+
+fn move_cursor_to_next_word(cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  let pos = next_word_start(buf, word_motion_start(cur, buf));
+  move_cursor_to(pos, cur, buf, size);
+}
+
+fn move_cursor_to_prev_word(cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  let pos = prev_word_start(buf, word_motion_start(cur, buf));
+  move_cursor_to(pos, cur, buf, size);
+}
+
+fn move_cursor_to_word_end(cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  let pos = word_end(buf, word_motion_start(cur, buf));
+  move_cursor_to(pos, cur, buf, size);
+}
+
+// Moves the cursor to the start of the next field of a table, on the next
+// line after the last one.
+fn move_cursor_to_next_cell(cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  let (sep, line) = match (&cur.table, buf.get(cur.row)) {
+    (Some(table), Some(line)) => (table.sep, line),
+    _ => return,
+  };
+  let fields = table::fields(line, sep);
+  let pos = match fields.get(table::field_at(line, sep, cur.col) + 1) {
+    Some(field) => (cur.row, field.start),
+    None if cur.row + 1 < buf.len() => (cur.row + 1, 0),
+    None => return,
+  };
+  move_cursor_to(pos, cur, buf, size);
+}
+
+// Moves the cursor to the start of its field of a table, or of the one
+// before, on the line before from the first one.
+fn move_cursor_to_prev_cell(cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  let (sep, line) = match (&cur.table, buf.get(cur.row)) {
+    (Some(table), Some(line)) => (table.sep, line),
+    _ => return,
+  };
+  let fields = table::fields(line, sep);
+  let i = table::field_at(line, sep, cur.col);
+  let pos = match fields[i].start {
+    start if start < cur.col => (cur.row, start),
+    _ if i > 0 => (cur.row, fields[i - 1].start),
+    _ if cur.row > 0 => {
+      let above = table::fields(&buf[cur.row - 1], sep);
+      (cur.row - 1, above[above.len() - 1].start)
+    }
+    _ => return,
+  };
+  move_cursor_to(pos, cur, buf, size);
+}
+
+// Moves the cursor to the first character of the line that isn't a space or
+// a tab.
+fn move_cursor_to_line_start(cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  if let Some(line) = buf.get(cur.row) {
+    move_cursor_to((cur.row, indentation(line).chars().count()), cur, buf, size);
+  }
+}
+
+// Moves the cursor to the last character of the line.
+fn move_cursor_to_line_end(cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  if let Some(line) = buf.get(cur.row) {
+    move_cursor_to((cur.row, line_len(line).saturating_sub(1)), cur, buf, size);
+  }
+}
+
+fn move_cursor_to_next_conflict(cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  if let Some(c) = conflict::find_all(buf).into_iter().find(|c| c.start > cur.row) {
+    cur.row = c.start;
+    cur.col = 0;
+  }
+  align_cursor(cur, buf, size);
+}
+
+fn move_cursor_to_prev_conflict(cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  if let Some(c) = conflict::find_all(buf).into_iter().rev().find(|c| c.start < cur.row) {
+    cur.row = c.start;
+    cur.col = 0;
+  }
+  align_cursor(cur, buf, size);
+}
+
+fn align_wrapped_cursor(cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  cur.left = 0;
+  if cur.row < cur.top {
+    cur.top = cur.row;
+  }
+  while cur.top < cur.row && wrapped_cursor_cell(cur, buf, size).0 >= size.rows {
+    cur.top += 1;
+  }
+}
+
+fn align_cursor(cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  if config::get().wrap {
+    return align_wrapped_cursor(cur, buf, size);
+  }
+  if cur.row < cur.top {
+    cur.top = cur.row;
+  }
+  if cur.folds.shown(cur.top..cur.row + 1) > size.rows {
+    cur.top = cur.folds.up(cur.row, size.rows.saturating_sub(1));
+  }
+  measure_table(cur, buf, size);
+  let x = cursor_cell(cur, buf);
+  let end = x + cursor_cell_width(cur, buf);
+  if x < cur.left {
+    cur.left = x;
+  }
+  // a screen too narrow for the cursor's character still shows its first cell
+  let cols = size.cols.max(1);
+  if end > cur.left + cols {
+    cur.left = end.saturating_sub(cols).min(x);
+  }
+}
+
+fn truncate_cursor_to_line(cur: &mut Cursor, buf: &Buffer) {
+  if cur.row < buf.len() {
+    if cur.col > line_len(&buf[cur.row]) && !virtual_edit() {
+      cur.col = line_len(&buf[cur.row]);
+    }
+  } else {
+    cur.col = 0;
+  }
+}
+
+// Editing helpers
+fn break_line_and_return_cursor(cur: &mut Cursor, buf: &mut Buffer, size: &Size) -> BufResult {
+  let col = break_line_with_indent(cur, buf)?;
+  move_cursor_start_of_next_line(cur, buf, size)?;
+  cur.col = col;
+  align_cursor(cur, buf, size);
+  Ok(())
+}
+
+fn insert_and_move_cursor(
+  ch: char,
+  cur: &mut Cursor,
+  buf: &mut Buffer,
+  size: &Size,
+) -> BufResult {
+  push_new_line_if_at_end(cur, buf);
+  insert_at(ch, cur, buf)?;
+  move_cursor_right(cur, buf, size);
+  Ok(())
+}
+
+fn delete_in_place(cur: &mut Cursor, buf: &mut Buffer, _size: &Size) -> BufResult {
+  if cur.row < buf.len() && cur.col < line_len(&buf[cur.row]) {
+    delete_at(cur, buf)
+  } else if cur.row + 1 < buf.len() && cur.col == line_len(&buf[cur.row]) {
+    merge_next_line_into(cur, buf)
+  } else {
+    Ok(())
+  }
+}
+
+fn delete_and_move_cursor(cur: &mut Cursor, buf: &mut Buffer, size: &Size) -> BufResult {
+  if cur.row < buf.len() && cur.col > line_len(&buf[cur.row]) {
+    move_cursor_left(cur, buf, size);
+  } else if cur.col > 0 {
+    delete_before(cur, buf)?;
+    move_cursor_left(cur, buf, size);
+  } else if cur.row > 0 {
+    move_cursor_end_of_prev_line(cur, buf, size)?;
+    if cur.row + 1 < buf.len() {
+      merge_next_line_into(cur, buf)?;
+    }
+  }
+  Ok(())
+}
+
+// Moves the current line past its neighbour, returning whether it moved.
+fn move_line_up(cur: &mut Cursor, buf: &mut Buffer, size: &Size) -> bool {
+  if cur.row == 0 || !swap_lines(cur.row - 1, cur.row, buf) {
+    return false;
+  }
+  move_cursor_up(cur, buf, size);
+  true
+}
+
+fn move_line_down(cur: &mut Cursor, buf: &mut Buffer, size: &Size) -> bool {
+  if !swap_lines(cur.row, cur.row + 1, buf) {
+    return false;
+  }
+  move_cursor_down(cur, buf, size);
+  true
+}
+
+fn transpose_chars_and_move_cursor(cur: &mut Cursor, buf: &mut Buffer, size: &Size) -> bool {
+  if !transpose_chars(cur, buf) {
+    return false;
+  }
+  if cur.col + 1 < line_len(&buf[cur.row]) {
+    move_cursor_right(cur, buf, size);
+  }
+  true
+}
+
+// Removes the current line, returning whether there was one.
+fn delete_line(cur: &mut Cursor, src: &mut Buffer, size: &Size) -> bool {
+  if cur.row >= src.len() {
+    return false;
+  }
+  src.remove(cur.row);
+  src.note(cur.row, 1, 0);
+  truncate_cursor_to_line(cur, src);
+  align_cursor(cur, src, size);
+  true
+}
+
+// Keeps one or both sides of the conflict under the cursor, returning whether
+// there was one.
+fn resolve_conflict(
+  cur: &mut Cursor,
+  buf: &mut Buffer,
+  size: &Size,
+  ours: bool,
+  theirs: bool,
+) -> bool {
+  match conflict::find_at(cur.row, buf) {
+    Some(c) => {
+      let (len, removed) = (buf.len(), c.end + 1 - c.start);
+      conflict::resolve(&c, ours, theirs, buf);
+      buf.note(c.start, removed, buf.len() + removed - len);
+      cur.row = c.start;
+      truncate_cursor_to_line(cur, buf);
+      align_cursor(cur, buf, size);
+      true
+    }
+    None => false,
+  }
+}
+
+// Rewraps the paragraph under the cursor to the configured text width and
+// moves the cursor to its start.
+fn reflow_paragraph(cur: &mut Cursor, buf: &mut Buffer, size: &Size) -> bool {
+  let rows = match reflow::paragraph(cur.row, buf) {
+    Some(rows) => rows,
+    None => return false,
+  };
+  let lines = reflow::reflow(&buf.slice(rows.clone()), config::get().text_width, text_cells);
+  cur.row = rows.start;
+  cur.col = 0;
+  let n = lines.len();
+  buf.splice(rows.clone(), lines);
+  buf.note(rows.start, rows.len(), n);
+  align_cursor(cur, buf, size);
+  true
+}
+
+// Cuts the lines into the clipboard, as one clip, leaving the cursor on the
+// line after them.
+fn delete_lines(rows: Range<usize>, cur: &mut Cursor, src: &mut Buffer, dst: &mut Clipboard, size: &Size) -> io::Result<()> {
+  let lines = src.drain(rows.clone());
+  src.note(rows.start, rows.len(), 0);
+  dst.push(Clip::lines(lines))?;
+  cur.row = rows.start;
+  truncate_cursor_to_line(cur, src);
+  align_cursor(cur, src, size);
+  Ok(())
+}
+
+// Copies the lines into the clipboard, as one clip.
+fn yank_lines(rows: Range<usize>, src: &Buffer, dst: &mut Clipboard) -> io::Result<()> {
+  dst.push(Clip::lines(src.slice(rows)))
+}
+
+// Cuts `count` lines from the cursor into the clipboard, as one clip.
+fn cut_lines(count: usize, cur: &mut Cursor, src: &mut Buffer, dst: &mut Clipboard, size: &Size) -> io::Result<bool> {
+  if cur.row >= src.len() {
+    return Ok(false);
+  }
+  let end = (cur.row + count).min(src.len());
+  delete_lines(cur.row..end, cur, src, dst, size)?;
+  Ok(true)
+}
+
+// Copies `count` lines from the cursor into the clipboard, as one clip, and
+// moves the cursor past them.
+fn copy_lines(count: usize, cur: &mut Cursor, src: &Buffer, dst: &mut Clipboard, size: &Size) -> io::Result<bool> {
+  if cur.row >= src.len() {
+    return Ok(false);
+  }
+  let end = (cur.row + count).min(src.len());
+  yank_lines(cur.row..end, src, dst)?;
+  cur.row = end;
+  truncate_cursor_to_line(cur, src);
+  align_cursor(cur, src, size);
+  Ok(true)
+}
+
+// Inserts text at the cursor, with a line break between each of its lines.
+fn insert_lines_at(lines: Vec<Line>, cur: &Cursor, buf: &mut Buffer) {
+  let n = lines.len();
+  let i = byte_index(&buf[cur.row], cur.col);
+  let tail = buf[cur.row].split_off(i);
+  let mut lines = lines.into_iter();
+  buf[cur.row].push_str(&lines.next().unwrap_or_default());
+  buf.splice(cur.row + 1..cur.row + 1, lines);
+  buf[cur.row + n - 1].push_str(&tail);
+  buf.note(cur.row, 1, n);
+}
+
+// Inserts text as it is, such as a paste or the value of an expression,
+// without the indentation and key bindings that typing it would go through,
+// and moves the cursor to its end. Line breaks in it start new lines.
+fn insert_text(text: &str, cur: &mut Cursor, buf: &mut Buffer, size: &Size) -> BufResult {
+  if cur.row > buf.len() {
+    return Err(BufError::PastEndOfBuffer);
+  }
+  push_new_line_if_at_end(cur, buf);
+  pad_line_to_cursor(cur, buf);
+  let lines: Vec<Line> = text.replace("\r\n", "\n").split(['\n', '\r']).map(Line::from).collect();
+  let n = lines.len();
+  let last = line_len(&lines[n - 1]);
+  insert_lines_at(lines, cur, buf);
+  cur.col = if n == 1 { cur.col + last } else { last };
+  cur.row += n - 1;
+  align_cursor(cur, buf, size);
+  Ok(())
+}
+
+// Pastes the clip on top of the clipboard and removes it: lines go above the
+// cursor, and text goes at it. The cursor stays at the start of the paste.
+fn paste(cur: &mut Cursor, src: &mut Clipboard, dst: &mut Buffer, size: &Size) -> io::Result<bool> {
+  let clip = match src.pop()? {
+    Some(clip) => clip,
+    None => return Ok(false),
+  };
+  match clip.kind {
+    clipboard::Kind::Lines => {
+      let (row, n) = (cur.row.min(dst.len()), clip.lines.len());
+      dst.splice(row..row, clip.lines);
+      dst.note(row, 0, n);
+    }
+    clipboard::Kind::Text => {
+      push_new_line_if_at_end(cur, dst);
+      insert_lines_at(clip.lines, cur, dst);
+    }
+    clipboard::Kind::Block => {
+      let (row, len) = (cur.row, dst.len());
+      let added = (row + clip.lines.len()).saturating_sub(len);
+      dst.extend(std::iter::repeat_n(Line::new(), added));
+      for (line, text) in dst.range_mut(row..len + added).zip(clip.lines) {
+        let n = line_len(line);
+        if n < cur.col {
+          line.extend(std::iter::repeat_n(' ', cur.col - n));
+        }
+        let i = byte_index(line, cur.col);
+        line.insert_str(i, &text);
+      }
+      dst.note(len, 0, added);
+    }
+  }
+  truncate_cursor_to_line(cur, dst);
+  align_cursor(cur, dst, size);
+  Ok(true)
+}
+
+// Selected text, from `start` to `end` inclusive. A column at the end of a
+// line selects its line break. A block selects the same columns of each line
+// from the top left corner to the bottom right one.
+struct Selection {
+  start: (usize, usize),
+  end: (usize, usize),
+  block: bool,
+}
+
+impl Selection {
+  fn new(anchor: (usize, usize), cur: &Cursor) -> Self {
+    let (start, end) = if anchor <= (cur.row, cur.col) {
+      (anchor, (cur.row, cur.col))
+    } else {
+      ((cur.row, cur.col), anchor)
+    };
+    Selection{start, end, block: false}
+  }
+
+  fn block(anchor: (usize, usize), cur: &Cursor) -> Self {
+    let start = (anchor.0.min(cur.row), anchor.1.min(cur.col));
+    let end = (anchor.0.max(cur.row), anchor.1.max(cur.col));
+    Selection{start, end, block: true}
+  }
+
+  // The selected columns of a line, including its end.
+  fn cols_in(&self, row: usize, line: &Line) -> Option<Range<usize>> {
+    if row < self.start.0 || row > self.end.0 {
+      return None;
+    }
+    if self.block {
+      let len = line_len(line);
+      return Some(self.start.1.min(len)..(self.end.1 + 1).min(len));
+    }
+    let len = line_len(line) + 1;
+    let from = if row == self.start.0 { self.start.1.min(len) } else { 0 };
+    let to = if row == self.end.0 { (self.end.1 + 1).min(len) } else { len };
+    Some(from..to)
+  }
+
+  // The selection as (row, column) bounds with an exclusive end, limited to
+  // the buffer. The line break at the end of the last line is not there to
+  // select.
+  fn bounds(&self, buf: &Buffer) -> Option<((usize, usize), (usize, usize))> {
+    let last = buf.len().checked_sub(1)?;
+    if self.start.0 > last {
+      return None;
+    }
+    let start = (self.start.0, self.start.1.min(line_len(&buf[self.start.0])));
+    let row = self.end.0.min(last);
+    let end = if self.end.0 > last || self.end.1 >= line_len(&buf[row]) {
+      if row < last { (row + 1, 0) } else { (row, line_len(&buf[row])) }
+    } else {
+      (row, self.end.1 + 1)
+    };
+    Some((start, end))
+  }
+
+  fn text(&self, buf: &Buffer) -> String {
+    let (start, end) = match self.bounds(buf) {
+      Some(bounds) => bounds,
+      None => return String::new(),
+    };
+    let mut text = String::new();
+    for (row, line) in buf.iter().enumerate().take(end.0 + 1).skip(start.0) {
+      let from = if row == start.0 { byte_index(line, start.1) } else { 0 };
+      if row == end.0 {
+        text.push_str(&line[from..byte_index(line, end.1)]);
+      } else {
+        text.push_str(&line[from..]);
+        text.push('\n');
+      }
+    }
+    text
+  }
+
+  // Whether the selection is made of whole lines, with their line breaks.
+  fn is_whole_lines(&self, buf: &Buffer) -> bool {
+    match self.bounds(buf) {
+      Some((start, end)) => start.1 == 0 && (
+        (end.1 == 0 && end.0 > start.0) ||
+        (end.0 + 1 == buf.len() && end.1 == line_len(&buf[end.0]))
+      ),
+      None => false,
+    }
+  }
+
+  // The text of a block on each of its lines, less on lines short of it
+  fn block_lines(&self, buf: &Buffer) -> Vec<String> {
+    let width = self.end.1 + 1 - self.start.1;
+    buf.iter().take(self.end.0 + 1).skip(self.start.0)
+      .map(|line| line.chars().skip(self.start.1).take(width).collect())
+      .collect()
+  }
+
+  fn delete(&self, buf: &mut Buffer) {
+    if self.block {
+      let rows = self.start.0..(self.end.0 + 1).min(buf.len());
+      for line in buf.range_mut(rows) {
+        let from = byte_index(line, self.start.1.min(line_len(line)));
+        let to = byte_index(line, (self.end.1 + 1).min(line_len(line)));
+        line.replace_range(from..to, "");
+      }
+      buf.touch();
+      return;
+    }
+    let (start, end) = match self.bounds(buf) {
+      Some(bounds) => bounds,
+      None => return,
+    };
+    let head = &buf[start.0][..byte_index(&buf[start.0], start.1)];
+    let tail = &buf[end.0][byte_index(&buf[end.0], end.1)..];
+    let line = format!("{}{}", head, tail);
+    buf.splice(start.0..end.0 + 1, Some(line));
+    buf.note(start.0, end.0 + 1 - start.0, 1);
+  }
+}
+
+// Selected lines are copied as lines, a block as a block, and anything else
+// as text.
+fn copy_selection(sel: &Selection, src: &Buffer, dst: &mut Clipboard) -> io::Result<()> {
+  if sel.block {
+    return dst.push(Clip::block(sel.block_lines(src)));
+  }
+  let text = sel.text(src);
+  match sel.is_whole_lines(src) {
+    true => {
+      let text = text.strip_suffix('\n').unwrap_or(&text);
+      dst.push(Clip::lines(text.split('\n').map(String::from).collect()))
+    }
+    false => dst.push(Clip::text(&text)),
+  }
+}
+
+enum Mode {
+  Insert,
+  Literal(String),
+  Digraph(Option<char>),
+  Dynamic,
+  Expression(String),
+  Normal,
+  // the start of a sequence of keys bound in normal mode, after a count or an
+  // operator, and when the last of them was typed
+  Keys(Prefix, Vec<Key>, Instant),
+  // a mark command waiting for the name of the mark
+  Pending(char),
+  // a count typed before a normal mode command, to repeat it
+  Count(usize),
+  // showing lines appended to the file, which has been read up to the offset
+  Follow(u64),
+  // comparing the buffer with the file on disk
+  Diff(Box<DiffView>),
+  // typing a command to pipe the lines through
+  Filter(Range<usize>, String),
+  // choosing from the list of lines with TODO markers, by their rows as they
+  // were when the list was opened
+  TodoList(Vec<usize>, usize),
+  // choosing from the list of bookmarks, with the selected one's place in it
+  BookmarkList(usize),
+  // showing the note of a line, until the next key
+  Note(usize),
+  // typing part of the name of an open file to switch to, with the match
+  // selected
+  Buffers(String, usize),
+  // moving through the tree of files beside the text
+  Tree,
+  // typing the name of a new file in the tree, or the new name of the one
+  // being renamed
+  TreeName(Option<PathBuf>, String),
+  // asking whether to delete a file in the tree
+  TreeDelete(PathBuf),
+  // typing a search
+  Search(String),
+  // selecting text from the anchor position to the cursor
+  Visual((usize, usize)),
+  // selecting the block with the anchor and the cursor at its corners
+  VisualBlock((usize, usize)),
+  // typing a command after `:`
+  Command(String),
+  // typing into the shell of `:term`, and whether the next key is sent as it
+  // is
+  Terminal(bool),
+  // typing the name to save the file as, and whether to quit after
+  SaveAs(String, bool),
+  // asking whether to create the missing directory of a file to save it in
+  CreateDir(String, bool),
+  // asking whether to save the file over another one that exists
+  Overwrite(String, bool),
+  // asking whether to overwrite a file changed on disk, or read it again
+  Changed(bool),
+  // stopping the editor until it is resumed from the shell
+  Suspend,
+  Quit,
+}
+
+// An operator waiting for a motion, with the count typed before it
+#[derive(Clone, Copy)]
+struct Operator {
+  action: Action,
+  count: usize,
+}
+
+// What was typed before the keys of a sequence: a count, or an operator
+// waiting for a motion
+#[derive(Clone, Copy)]
+struct Prefix {
+  count: Option<usize>,
+  op: Option<Operator>,
+}
+
+impl Prefix {
+  fn new() -> Self {
+    Prefix{count: None, op: None}
+  }
+}
+
+fn is_operator(action: Action) -> bool {
+  matches!(action, Action::Cut | Action::Copy | Action::Change)
+}
+
+// A bar shows that keys insert text, in the buffer or on the bottom line.
+fn cursor_shape(mode: &Mode) -> CursorShape {
+  match mode {
+    Mode::Insert | Mode::Literal(_) | Mode::Digraph(_) | Mode::Dynamic => CursorShape::Bar,
+    Mode::Command(_) | Mode::Search(_) | Mode::Expression(_) | Mode::Filter(..) => CursorShape::Bar,
+    Mode::SaveAs(..) | Mode::Buffers(..) | Mode::TreeName(..) | Mode::Terminal(_) => CursorShape::Bar,
+    _ => CursorShape::Block,
+  }
+}
+
+// Splits a pending literal code into its radix, digits, and maximum digits.
+// Codes are `u` + 4 hex digits, `U` + 8 hex digits, `x` + 2 hex digits, or up
+// to 3 decimal digits.
+fn split_literal_code(code: &str) -> (u32, &str, usize) {
+  match code.chars().next() {
+    Some('u') => (16, &code[1..], 4),
+    Some('U') => (16, &code[1..], 8),
+    Some('x') | Some('X') => (16, &code[1..], 2),
+    _ => (10, code, 3),
+  }
+}
+
+fn parse_literal_code(code: &str) -> Option<char> {
+  let (radix, digits, _) = split_literal_code(code);
+  u32::from_str_radix(digits, radix).ok().and_then(std::char::from_u32)
+}
+
+fn literal_key_char(key: Key) -> Option<char> {
+  match key {
+    Key::Char(ch) => Some(ch),
+    Key::Ctrl(ch @ 'a'..='z') => Some((ch as u8 - b'a' + 1) as char),
+    Key::Ctrl(ch @ '4'..='7') => Some((ch as u8 - b'4' + 0x1C) as char),
+    Key::Esc => Some('\u{1B}'),
+    Key::Null => Some('\0'),
+    _ => None,
+  }
+}
+
+fn handle_key_literal_mode(
+  key: Key,
+  mut code: String,
+  cur: &mut Cursor,
+  buf: &mut Buffer,
+  size: &Size,
+  fb: &mut Feedback,
+) -> io::Result<Mode> {
+  if code.is_empty() {
+    match key {
+      Key::Char(ch @ ('u' | 'U' | 'x' | 'X' | '0'..='9')) => code.push(ch),
+      Key::Char('\n') => fb.edit(break_line_and_return_cursor(cur, buf, size)),
+      _ => if let Some(ch) = literal_key_char(key) {
+        fb.edit(insert_and_move_cursor(ch, cur, buf, size));
+      },
+    }
+    return Ok(if code.is_empty() { Mode::Insert } else { Mode::Literal(code) });
+  }
+  let (radix, digits, max) = split_literal_code(&code);
+  match key {
+    Key::Char(ch) if ch.is_digit(radix) && digits.len() < max => {
+      code.push(ch);
+      let (_, digits, max) = split_literal_code(&code);
+      if digits.len() < max {
+        return Ok(Mode::Literal(code));
+      }
+      if let Some(ch) = parse_literal_code(&code) {
+        fb.edit(insert_and_move_cursor(ch, cur, buf, size));
+      }
+      Ok(Mode::Insert)
+    }
+    // A code is also ended early by any other key, which is then handled as
+    // if typed in insert mode. A prefix without any digits is inserted as it
+    // is.
+    _ => {
+      match parse_literal_code(&code) {
+        Some(ch) => fb.edit(insert_and_move_cursor(ch, cur, buf, size)),
+        None if digits.is_empty() => fb.edit(insert_text(&code, cur, buf, size)),
+        None => fb.bell = true,
+      }
+      handle_key_insert_mode(key, cur, buf, size, fb)
+    }
+  }
+}
+
+fn eval_expression(text: &str) -> Result<String, String> {
+  let cfg = config::get().clone();
+  expr::eval(text, &cfg.date_format, &cfg.time_format)
+}
+
+// Inserts dynamic text chosen by the key after Ctrl-R: `d` for the date, `t`
+// for the time, `u` for a UUID, `r` for a random token, or `=` to type an
+// expression.
+fn handle_key_dynamic_mode(
+  key: Key,
+  cur: &mut Cursor,
+  buf: &mut Buffer,
+  size: &Size,
+  fb: &mut Feedback,
+) -> io::Result<Mode> {
+  let name = match key {
+    Key::Char('=') => return Ok(Mode::Expression(String::new())),
+    Key::Char('d') => "date",
+    Key::Char('t') => "time",
+    Key::Char('u') => "uuid",
+    Key::Char('r') => "token",
+    Key::Esc => return Ok(Mode::Insert),
+    _ => {
+      fb.bell = true;
+      return Ok(Mode::Insert);
+    }
+  };
+  match eval_expression(name) {
+    Ok(text) => fb.edit(insert_text(&text, cur, buf, size)),
+    Err(_) => fb.bell = true,
+  }
+  Ok(Mode::Insert)
+}
+
+fn handle_key_expression_mode(
+  key: Key,
+  mut text: String,
+  cur: &mut Cursor,
+  buf: &mut Buffer,
+  size: &Size,
+  fb: &mut Feedback,
+) -> io::Result<Mode> {
+  match key {
+    Key::Char('\n') => {
+      match eval_expression(&text) {
+        Ok(value) => fb.edit(insert_text(&value, cur, buf, size)),
+        Err(_) => fb.bell = true,
+      }
+      return Ok(Mode::Insert);
+    }
+    Key::Char(ch) => text.push(ch),
+    Key::Backspace if text.is_empty() => return Ok(Mode::Insert),
+    Key::Backspace => {
+      text.pop();
+    }
+    Key::Esc => return Ok(Mode::Insert),
+    _ => (),
+  }
+  Ok(Mode::Expression(text))
+}
+
+fn handle_key_digraph_mode(
+  key: Key,
+  first: Option<char>,
+  cur: &mut Cursor,
+  buf: &mut Buffer,
+  size: &Size,
+  fb: &mut Feedback,
+) -> io::Result<Mode> {
+  match (first, key) {
+    (None, Key::Char(ch)) if ch != '\n' => Ok(Mode::Digraph(Some(ch))),
+    (Some(a), Key::Char(b)) if b != '\n' => {
+      let ch = digraph::lookup(a, b).unwrap_or(b);
+      fb.edit(insert_and_move_cursor(ch, cur, buf, size));
+      Ok(Mode::Insert)
+    }
+    _ => Ok(Mode::Insert),
+  }
+}
+
+fn handle_key_insert_mode(
+  key: Key,
+  cur: &mut Cursor,
+  buf: &mut Buffer,
+  size: &Size,
+  fb: &mut Feedback,
+) -> io::Result<Mode> {
+  let action = config::get().insert_keys.get(&key);
+  match (action, key) {
+    (Some(Action::BreakLine), _) => fb.edit(break_line_and_return_cursor(cur, buf, size)),
+    (Some(Action::Delete), _) => fb.edit(delete_in_place(cur, buf, size)),
+    (Some(Action::Backspace), _) => fb.edit(delete_and_move_cursor(cur, buf, size)),
+    (Some(Action::Literal), _) => return Ok(Mode::Literal(String::new())),
+    (Some(Action::Digraph), _) => return Ok(Mode::Digraph(None)),
+    (Some(Action::Dynamic), _) => return Ok(Mode::Dynamic),
+    (Some(Action::Normal), _) => return Ok(Mode::Normal),
+    (_, Key::Char(ch)) if ch != '\n' => fb.edit(insert_and_move_cursor(ch, cur, buf, size)),
+    _ => (),
+  };
+  Ok(Mode::Insert)
+}
+
+fn motion_for_action(action: Action) -> Option<fn(&mut Cursor, &Buffer, &Size)> {
+  let motion: fn(&mut Cursor, &Buffer, &Size) = match action {
+    Action::MoveLeft => move_cursor_left,
+    Action::MoveRight => move_cursor_forward,
+    Action::MoveUp => move_cursor_up,
+    Action::MoveDown => move_cursor_down,
+    Action::PrevBlank => move_cursor_to_prev_blank,
+    Action::NextBlank => move_cursor_to_next_blank,
+    Action::PrevBlankLine => move_cursor_to_prev_blank_line,
+    Action::NextBlankLine => move_cursor_to_next_blank_line,
+    Action::NextWord => move_cursor_to_next_word,
+    Action::PrevWord => move_cursor_to_prev_word,
+    Action::WordEnd => move_cursor_to_word_end,
+    Action::NextCell => move_cursor_to_next_cell,
+    Action::PrevCell => move_cursor_to_prev_cell,
+    Action::LineStart => move_cursor_to_line_start,
+    Action::LineEnd => move_cursor_to_line_end,
+    Action::NextConflict => move_cursor_to_next_conflict,
+    Action::PrevConflict => move_cursor_to_prev_conflict,
+    _ => return None,
+  };
+  Some(motion)
+}
+
+fn normal_action(key: &Key) -> Option<Action> {
+  config::get().normal_keys.get(key)
+}
+
+// The action run by a sequence of normal mode keys. Keys that also start
+// longer sequences only run their own action once the key timeout has passed.
+fn sequence_action(keys: &[Key], timed_out: bool, pending: bool) -> Option<Action> {
+  match lookup_keys(keys, pending) {
+    Lookup::Action(action) => Some(action),
+    Lookup::Prefix(action) if timed_out => action,
+    _ => None,
+  }
+}
+
+// After an operator, the keys of text objects come before those of normal
+// mode.
+fn lookup_keys(keys: &[Key], pending: bool) -> Lookup {
+  let cfg = config::get();
+  match cfg.object_keys.lookup(keys) {
+    lookup if pending && lookup != Lookup::Unbound => lookup,
+    _ => cfg.normal_keys.lookup(keys),
+  }
+}
+
+// Adds a key to the normal mode keys typed so far, or, without one, gives up
+// waiting for more, and runs the action they are bound to. After an
+// operator, the action is the motion for it.
+fn handle_key_sequence(
+  pre: Prefix,
+  mut keys: Vec<Key>,
+  key: Option<Key>,
+  files: &mut BufferList,
+  ses: &mut Session,
+  size: &Size,
+  fb: &mut Feedback,
+) -> io::Result<Mode> {
+  let timed_out = key.is_none();
+  keys.extend(key);
+  match (pre.op, sequence_action(&keys, timed_out, pre.op.is_some())) {
+    (None, Some(action)) if is_operator(action) => {
+      let op = Operator{action, count: pre.count.unwrap_or(1)};
+      return Ok(Mode::Keys(Prefix{count: None, op: Some(op)}, Vec::new(), Instant::now()));
+    }
+    (None, Some(action)) => return match pre.count {
+      Some(count) => run_counted_action(count, action, files, ses, size, fb),
+      None => run_normal_action(action, files, ses, size, fb),
+    },
+    (Some(op), Some(motion)) => return apply_operator(op, motion, files.current_mut(), ses, size, fb),
+    _ => (),
+  }
+  match keys[..] {
+    _ if timed_out => Ok(Mode::Normal),
+    _ if matches!(lookup_keys(&keys, pre.op.is_some()), Lookup::Prefix(_)) =>
+      Ok(Mode::Keys(pre, keys, Instant::now())),
+    [Key::Char(c @ '1'..='9')] if pre.op.is_none() && pre.count.is_none() =>
+      Ok(Mode::Count(c as usize - '0' as usize)),
+    // Escape cancels a sequence, and any other key that ends none rings the
+    // bell.
+    [_] if pre.op.is_none() && pre.count.is_none() => Ok(Mode::Normal),
+    [.., Key::Esc] => Ok(Mode::Normal),
+    _ => {
+      fb.bell = true;
+      Ok(Mode::Normal)
+    }
+  }
+}
+
+// Motions between lines make an operator apply to whole lines.
+fn is_line_motion(action: Action) -> bool {
+  matches!(action, Action::MoveUp | Action::MoveDown | Action::GotoLine | Action::GotoFirstLine)
+}
+
+// The text between two positions a motion moves between. It takes in the
+// character at the end for motions like `e` and `$`. A motion that ends in
+// the indentation of a later line stops at the end of the line before, so
+// that `dw` on the last word of a line leaves the line break.
+fn motion_selection(from: (usize, usize), to: (usize, usize), inclusive: bool, buf: &Buffer) -> Option<Selection> {
+  let (start, last) = if from <= to { (from, to) } else { (to, from) };
+  let len = |row: usize| line_len(&buf[row]);
+  let mut end = if inclusive { (last.0, (last.1 + 1).min(len(last.0))) } else { last };
+  if !inclusive && end.0 > start.0 && end.1 <= indentation(&buf[end.0]).chars().count() {
+    end = (end.0 - 1, len(end.0 - 1));
+  }
+  text_selection(start, end, buf)
+}
+
+// The selection of the text from `start` up to `end`, which ends on its last
+// character, or the line break before `end`
+fn text_selection(start: (usize, usize), end: (usize, usize), buf: &Buffer) -> Option<Selection> {
+  let len = |row: usize| line_len(&buf[row]);
+  if end <= start {
+    return None;
+  }
+  let end = match end.1 {
+    0 => (end.0 - 1, len(end.0 - 1)),
+    col => (end.0, col - 1),
+  };
+  Some(Selection{start, end, block: false})
+}
+
+// Applies an operator to what the motion moves over: the same operator again,
+// like `dd`, and motions between lines apply it to whole lines, and other
+// motions to the text from the cursor.
+fn apply_operator(
+  op: Operator,
+  motion: Action,
+  file: &mut OpenFile,
+  ses: &mut Session,
+  size: &Size,
+  fb: &mut Feedback,
+) -> io::Result<Mode> {
+  if file.cur.row >= file.buf.len() {
+    fb.bell = true;
+    return Ok(Mode::Normal);
+  }
+  // text objects and motions by the structure of the code need the file
+  // lexed, and ring the bell where there is no such thing
+  let object = text_object(motion).map(|(target, around)| find_object(target, around, file));
+  let structure = is_structure_motion(motion).then(|| structure_target(motion, file));
+  if object == Some(None) || structure == Some(None) {
+    fb.bell = true;
+    return Ok(Mode::Normal);
+  }
+  let (object, structure) = (object.flatten(), structure.flatten());
+  let OpenFile{ref mut buf, ref mut cur, ..} = *file;
+  // `cw` changes to the end of the word, leaving the space after it
+  let motion = match (op.action, motion) {
+    (Action::Change, Action::NextWord) => Action::WordEnd,
+    _ => motion,
+  };
+  let from = (cur.row, cur.col);
+  let to = match (motion, structure) {
+    _ if object.is_some() => from,
+    _ if motion == op.action => (cur.row + op.count - 1, 0),
+    (Action::GotoLine, _) => (buf.len() - 1, 0),
+    (Action::GotoFirstLine, _) => (0, 0),
+    (_, Some(to)) => to,
+    _ => match motion_for_action(motion) {
+      Some(motion) => {
+        let mut to = cur.clone();
+        for _ in 0..op.count {
+          motion(&mut to, buf, size);
+        }
+        (to.row, to.col)
+      }
+      None => {
+        fb.bell = true;
+        return Ok(Mode::Normal);
+      }
+    },
+  };
+  let rows = match object {
+    Some(Object::Lines(ref rows)) => Some(rows.clone()),
+    Some(Object::Chars(..)) => None,
+    None if motion == op.action || is_line_motion(motion) => Some(from.0.min(to.0)..(from.0.max(to.0) + 1).min(buf.len())),
+    None => None,
+  };
+  if let Some(rows) = rows {
+    let indent = String::from(indentation(&buf[rows.start]));
+    match op.action {
+      Action::Copy => {
+        yank_lines(rows.clone(), buf, &mut ses.clip)?;
+        move_cursor_to((rows.start, cur.col), cur, buf, size);
+      }
+      _ => delete_lines(rows.clone(), cur, buf, &mut ses.clip, size)?,
+    }
+    if op.action == Action::Change {
+      let indent = if config::get().auto_indent { indent } else { String::new() };
+      let col = line_len(&indent);
+      buf.insert(rows.start, indent);
+      buf.note(rows.start, 0, 1);
+      move_cursor_to((rows.start, col), cur, buf, size);
+      return Ok(Mode::Insert);
+    }
+    return Ok(Mode::Normal);
+  }
+  let inclusive = matches!(motion, Action::WordEnd | Action::LineEnd);
+  let sel = match object {
+    Some(Object::Chars(start, end)) => text_selection(start, end, buf),
+    _ => motion_selection(from, to, inclusive, buf),
+  };
+  let sel = match sel {
+    Some(sel) => sel,
+    None => {
+      fb.bell = true;
+      return Ok(Mode::Normal);
+    }
+  };
+  copy_selection(&sel, buf, &mut ses.clip)?;
+  if op.action != Action::Copy {
+    sel.delete(buf);
+  }
+  move_cursor_to(sel.start, cur, buf, size);
+  Ok(if op.action == Action::Change { Mode::Insert } else { Mode::Normal })
+}
+
+fn language(path: &str) -> Option<&'static syntax::Language> {
+  filetype::detect(path).and_then(|ft| ft.syntax)
+}
+
+fn is_structure_motion(action: Action) -> bool {
+  matches!(action, Action::PrevFunction | Action::NextFunction | Action::BlockStart | Action::BlockEnd)
+}
+
+// Where a motion by the structure of the code goes from the cursor: `(` and
+// `)` to the start of the previous and next function, and `{` and `}` to the
+// brackets of the block around it, or the first and last line of an indented
+// one.
+fn structure_target(action: Action, file: &mut OpenFile) -> Option<(usize, usize)> {
+  let lang = language(&file.path)?;
+  file.update_annotations();
+  let OpenFile{ref buf, ref cur, ref annotations, ..} = *file;
+  let pos = (cur.row, cur.col);
+  let starts = || annotations.syntax.functions(buf, lang).into_iter().map(|function| function.start);
+  match action {
+    Action::PrevFunction => starts().rev().find(|&start| start < pos),
+    Action::NextFunction => starts().find(|&start| start > pos),
+    Action::BlockStart | Action::BlockEnd if !lang.braces => {
+      let fold = fold::fold_at(buf, cur.row)?;
+      let row = if action == Action::BlockStart { fold.start - 1 } else { fold.end - 1 };
+      Some((row, indentation(&buf[row]).chars().count()))
+    }
+    Action::BlockStart => annotations.syntax.pairs().into_iter().rev()
+      .find(|pair| pair.bracket == '{' && pair.open < pos && pos <= pair.close)
+      .map(|pair| pair.open),
+    Action::BlockEnd => annotations.syntax.pairs().into_iter().rev()
+      .find(|pair| pair.bracket == '{' && pair.open <= pos && pos < pair.close)
+      .map(|pair| pair.close),
+    _ => None,
+  }
+}
+
+fn text_object(action: Action) -> Option<(Target, bool)> {
+  match action {
+    Action::AroundFunction => Some((Target::Function, true)),
+    Action::InnerFunction => Some((Target::Function, false)),
+    Action::AroundBlock => Some((Target::Block, true)),
+    Action::InnerBlock => Some((Target::Block, false)),
+    Action::AroundArgument => Some((Target::Argument, true)),
+    Action::InnerArgument => Some((Target::Argument, false)),
+    _ => None,
+  }
+}
+
+// The function, block or argument around the cursor, for an operator
+fn find_object(target: Target, around: bool, file: &mut OpenFile) -> Option<Object> {
+  let lang = language(&file.path)?;
+  file.update_annotations();
+  let OpenFile{ref buf, ref cur, ref annotations, ..} = *file;
+  annotations.syntax.object(buf, lang, target, around, (cur.row, cur.col))
+}
+
+// Moves the cursor by the structure of the code.
+fn move_by_structure(action: Action, file: &mut OpenFile, size: &Size, fb: &mut Feedback) {
+  match structure_target(action, file) {
+    Some(pos) => move_cursor_to(pos, &mut file.cur, &file.buf, size),
+    None => fb.bell = true,
+  }
+}
+
+fn run_normal_action(
+  action: Action,
+  files: &mut BufferList,
+  ses: &mut Session,
+  size: &Size,
+  fb: &mut Feedback,
+) -> io::Result<Mode> {
+  if let Action::NextBuffer | Action::PrevBuffer = action {
+    fb.bell = !files.cycle(action == Action::NextBuffer);
+    return Ok(Mode::Normal);
+  }
+  if let Action::ShowNote = action {
+    return Ok(show_note(files.current(), fb));
+  }
+  if let Action::ToggleBookmark | Action::NextBookmark | Action::PrevBookmark | Action::BookmarkList = action {
+    return Ok(run_bookmark_action(action, files.current_mut(), size, fb));
+  }
+  if let Action::Tree = action {
+    return open_tree(files, ses);
+  }
+  if let Action::SwitchBuffer = action {
+    // the file shown before this one comes first after it
+    return Ok(Mode::Buffers(String::new(), (files.files.len() > 1) as usize));
+  }
+  if let Action::Save = action {
+    return Ok(save_or_ask_for_name(files.current_mut(), false, fb));
+  }
+  if let Action::SaveAs = action {
+    return Ok(Mode::SaveAs(files.current().path.clone(), false));
+  }
+  if is_structure_motion(action) {
+    move_by_structure(action, files.current_mut(), size, fb);
+    return Ok(Mode::Normal);
+  }
+  // Enter goes to the place named in the line, as in the output of `:make`.
+  if let Action::OpenLocation = action {
+    let file = files.current();
+    match file.buf.get(file.cur.row).and_then(|line| location::find(line)) {
+      Some(loc) => {
+        if let Some(i) = ses.locations.iter().position(|other| *other == loc) {
+          ses.location = Some(i);
+        }
+        open_location(&loc, files, size, fb);
+      }
+      None => fb.bell = true,
+    }
+    return Ok(Mode::Normal);
+  }
+  let OpenFile{ref path, ref mut buf, ref mut cur, ref mut jumps, format, length, ..} = *files.current_mut();
+  if let Some(motion) = motion_for_action(action) {
+    let before = (cur.row, cur.col);
+    motion(cur, buf, size);
+    fb.bell = before == (cur.row, cur.col);
+    return Ok(Mode::Normal);
+  }
+  match action {
+    Action::Insert => return Ok(Mode::Insert),
+    Action::Delete => {
+      fb.edit(delete_in_place(cur, buf, size));
+      return Ok(Mode::Insert);
+    }
+    Action::Backspace => {
+      fb.edit(delete_and_move_cursor(cur, buf, size));
+      return Ok(Mode::Insert);
+    }
+    // cut-paste buffer
+    Action::DeleteLine => fb.bell = !delete_line(cur, buf, size),
+    Action::CopyLine => fb.bell = !copy_lines(1, cur, buf, &mut ses.clip, size)?,
+    Action::PasteLine => fb.bell = !paste(cur, &mut ses.clip, buf, size)?,
+    Action::Visual if cur.row < buf.len() => return Ok(Mode::Visual((cur.row, cur.col))),
+    Action::VisualBlock if cur.row < buf.len() => return Ok(Mode::VisualBlock((cur.row, cur.col))),
+    Action::CutLine => fb.bell = !cut_lines(1, cur, buf, &mut ses.clip, size)?,
+    Action::DeleteChar => fb.edit(delete_at(cur, buf)),
+    Action::Transpose => fb.bell = !transpose_chars_and_move_cursor(cur, buf, size),
+    Action::GotoLine => jump_to_line(buf.len(), cur, buf, size),
+    Action::Indent => fb.bell = !shift_lines(cur.row, 1, true, cur, buf, size),
+    Action::Dedent => fb.bell = !shift_lines(cur.row, 1, false, cur, buf, size),
+    Action::MoveLineUp => fb.bell = !move_line_up(cur, buf, size),
+    Action::MoveLineDown => fb.bell = !move_line_down(cur, buf, size),
+    Action::GotoFirstLine => jump_to_line(1, cur, buf, size),
+    Action::Filter if cur.row < buf.len() => return Ok(Mode::Filter(cur.row..cur.row + 1, String::new())),
+    Action::ToggleComment if cur.row < buf.len() =>
+      toggle_comment(cur.row..cur.row + 1, path, cur, buf, size, fb),
+    Action::Reflow => fb.bell = !reflow_paragraph(cur, buf, size),
+    Action::ToggleFold | Action::OpenFold | Action::CloseFold | Action::OpenAllFolds | Action::CloseAllFolds => {
+      fb.bell = !fold_lines(action, cur, buf, size);
+      if let Err(err) = remember_folds(path, &cur.folds) {
+        fb.message = Some(format!("Could not remember the folds: {}", err));
+      }
+    }
+    Action::HexPanel => ses.hex = !ses.hex,
+    Action::Stats => fb.message = Some(buffer_stats(buf, format)),
+    Action::NextTodo => fb.bell = !move_cursor_to_todo(cur, buf, size, true),
+    Action::PrevTodo => fb.bell = !move_cursor_to_todo(cur, buf, size, false),
+    Action::TodoList => return Ok(start_todo_list(cur, buf, size, fb)),
+    Action::ScrollTop => scroll_cursor_to_row(0, cur, buf, size),
+    Action::ScrollMiddle => scroll_cursor_to_row(size.rows / 2, cur, buf, size),
+    Action::ScrollBottom => scroll_cursor_to_row(size.rows - 1, cur, buf, size),
+    Action::SetMark => return Ok(Mode::Pending('m')),
+    Action::GotoMark => return Ok(Mode::Pending('\'')),
+    // merge conflicts
+    Action::KeepOurs => fb.bell = !resolve_conflict(cur, buf, size, true, false),
+    Action::KeepTheirs => fb.bell = !resolve_conflict(cur, buf, size, false, true),
+    Action::KeepBoth => fb.bell = !resolve_conflict(cur, buf, size, true, true),
+    Action::Follow => return Ok(start_following(length, cur, buf, size)),
+    Action::Command => return Ok(Mode::Command(String::new())),
+    Action::Search => {
+      ses.search_origin = (cur.row, cur.col);
+      return Ok(Mode::Search(String::new()));
+    }
+    Action::SearchNext => match search_forward(&ses.search, (cur.row, cur.col), buf, fb) {
+      Some(pos) => move_cursor_to(pos, cur, buf, size),
+      None => fb.bell = true,
+    },
+    Action::SearchPrev => match search_backward(&ses.search, (cur.row, cur.col), buf, fb) {
+      Some(pos) => move_cursor_to(pos, cur, buf, size),
+      None => fb.bell = true,
+    },
+    Action::JumpBack => move_cursor_to_jump(jumps.back((cur.row, cur.col)), cur, buf, size, fb),
+    Action::JumpForward => move_cursor_to_jump(jumps.forward(), cur, buf, size, fb),
+    Action::Quit => return Ok(quit_unless_modified(files, fb)),
+    Action::ForceQuit => return Ok(Mode::Quit),
+    Action::Suspend => return Ok(Mode::Suspend),
+    _ => (),
+  };
+  Ok(Mode::Normal)
+}
+
+fn is_jump(action: Option<Action>) -> bool {
+  matches!(
+    action,
+    Some(Action::GotoLine | Action::GotoFirstLine | Action::NextBlankLine | Action::PrevBlankLine)
+      | Some(Action::NextConflict | Action::PrevConflict | Action::NextTodo | Action::PrevTodo)
+      | Some(Action::NextBookmark | Action::PrevBookmark)
+      | Some(Action::SearchNext | Action::SearchPrev | Action::OpenLocation)
+      | Some(Action::PrevFunction | Action::NextFunction | Action::BlockStart | Action::BlockEnd)
+  )
+}
+
+// Where the cursor jumps from, if the key, or running out of time for one,
+// makes a jump worth coming back from: searching, going to a line, or moving
+// by paragraph, conflict or function.
+fn jump_start(mode: &Mode, key: Option<Key>, cur: &Cursor, ses: &Session) -> Option<(usize, usize)> {
+  let here = Some((cur.row, cur.col));
+  match (mode, key) {
+    (Mode::Normal, Some(key)) | (Mode::Count(_), Some(key)) if is_jump(normal_action(&key)) => here,
+    (Mode::Keys(Prefix{op: None, ..}, keys, _), key) => {
+      let keys: Vec<Key> = keys.iter().copied().chain(key).collect();
+      if is_jump(sequence_action(&keys, key.is_none(), false)) { here } else { None }
+    }
+    (Mode::Pending('\''), Some(Key::Char(_))) => here,
+    (Mode::Command(_), Some(Key::Char('\n'))) => here,
+    (Mode::Search(_), Some(Key::Char('\n'))) => Some(ses.search_origin),
+    _ => None,
+  }
+}
+
+// Moves the cursor to a position from the jump list or a mark, ringing the
+// bell if there is none. The jump list keeps positions in lines that may have
+// been deleted since.
+fn move_cursor_to_jump(
+  pos: Option<(usize, usize)>,
+  cur: &mut Cursor,
+  buf: &Buffer,
+  size: &Size,
+  fb: &mut Feedback,
+) {
+  match pos {
+    Some((row, col)) => move_cursor_to((row.min(buf.len()), col), cur, buf, size),
+    None => fb.bell = true,
+  }
+}
+
+// Counts stop growing at seven digits, which is more than any buffer needs.
+const MAX_COUNT: usize = 9_999_999;
+
+// Repeats the command after the count, stopping early if it rings the bell
+// or leaves normal mode.
+fn handle_key_count_mode(
+  key: Key,
+  count: usize,
+  files: &mut BufferList,
+  ses: &mut Session,
+  size: &Size,
+  fb: &mut Feedback,
+) -> io::Result<Mode> {
+  match key {
+    Key::Char(c @ '0'..='9') if normal_action(&key).is_none() => {
+      let count = count * 10 + (c as usize - '0' as usize);
+      return Ok(Mode::Count(count.min(MAX_COUNT)));
+    }
+    Key::Esc => return Ok(Mode::Normal),
+    _ => (),
+  }
+  handle_key_sequence(Prefix{count: Some(count), op: None}, Vec::new(), Some(key), files, ses, size, fb)
+}
+
+// Motions and edits that a count repeats
+fn is_repeatable(action: Action) -> bool {
+  motion_for_action(action).is_some() || matches!(
+    action,
+    Action::NextTodo | Action::PrevTodo | Action::NextBookmark | Action::PrevBookmark
+      | Action::SearchNext | Action::SearchPrev
+      | Action::JumpBack | Action::JumpForward | Action::Delete | Action::Backspace
+      | Action::DeleteChar | Action::DeleteLine | Action::PasteLine | Action::Transpose
+      | Action::MoveLineUp | Action::MoveLineDown | Action::PrevFunction | Action::NextFunction
+  )
+}
+
+// Runs an action after a count. A count of lines is shifted, cut or copied
+// once, rather than one line many times, and a count before `G` or `gg` is
+// the line to go to. Other motions and edits are repeated, and anything else,
+// like saving, is run once.
+fn run_counted_action(
+  count: usize,
+  action: Action,
+  files: &mut BufferList,
+  ses: &mut Session,
+  size: &Size,
+  fb: &mut Feedback,
+) -> io::Result<Mode> {
+  let OpenFile{ref mut buf, ref mut cur, ..} = *files.current_mut();
+  match action {
+    Action::CutLine => {
+      fb.bell = !cut_lines(count, cur, buf, &mut ses.clip, size)?;
+      return Ok(Mode::Normal);
+    }
+    Action::CopyLine => {
+      fb.bell = !copy_lines(count, cur, buf, &mut ses.clip, size)?;
+      return Ok(Mode::Normal);
+    }
+    Action::Indent | Action::Dedent => {
+      fb.bell = !shift_lines(cur.row, count, action == Action::Indent, cur, buf, size);
+      return Ok(Mode::Normal);
+    }
+    Action::GotoLine | Action::GotoFirstLine => {
+      jump_to_line(count, cur, buf, size);
+      return Ok(Mode::Normal);
+    }
+    _ => (),
+  }
+  let times = if is_repeatable(action) { count } else { 1 };
+  for _ in 0..times {
+    let mode = run_normal_action(action, files, ses, size, fb)?;
+    if !matches!(mode, Mode::Normal) || fb.bell {
+      return Ok(mode);
+    }
+  }
+  Ok(Mode::Normal)
+}
+
+// Following a file shows the lines appended to it by another program, like
+// `tail -f`, keeping the last line in view.
+const FOLLOW_PROMPT: &str = "Following the file, press any key to stop";
+
+// Following starts from where the file was last read, so that lines added
+// since are not missed.
+fn start_following(length: u64, cur: &mut Cursor, buf: &Buffer, size: &Size) -> Mode {
+  cur.row = buf.len().saturating_sub(1);
+  cur.col = 0;
+  align_cursor(cur, buf, size);
+  Mode::Follow(length)
+}
+
+// Adds the whole lines written to the file past `offset` to the buffer,
+// returning whether there were any. A file that has shrunk, such as a
+// rotated log, is read again from the start, unless that would drop the
+// buffer's own changes.
+fn follow_file(
+  path: &str,
+  offset: &mut u64,
+  modified: bool,
+  cur: &mut Cursor,
+  buf: &mut Buffer,
+  size: &Size,
+) -> io::Result<bool> {
+  let len = match fs::metadata(path) {
+    Ok(metadata) => metadata.len(),
+    Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+    Err(err) => return Err(err),
+  };
+  if len < *offset {
+    if modified {
+      return Err(io::Error::other("the file got shorter, and reading it again would drop unsaved changes"));
+    }
+    *offset = 0;
+    let n = buf.len();
+    buf.clear();
+    buf.note(0, n, 0);
+  }
+  let mut text = Vec::new();
+  let mut file = fs::File::open(path)?;
+  file.seek(io::SeekFrom::Start(*offset))?;
+  file.take(len - *offset).read_to_end(&mut text)?;
+  let end = match text.iter().rposition(|&b| b == b'\n') {
+    Some(i) => i + 1,
+    None => return Ok(false),
+  };
+  *offset += end as u64;
+  if buf.len() == 1 && buf[0].is_empty() {
+    buf.clear();
+    buf.note(0, 1, 0);
+  }
+  let lines = io::Cursor::new(&text[..end]).lines().collect::<io::Result<Vec<_>>>()?;
+  let (row, n) = (buf.len(), lines.len());
+  buf.extend(lines);
+  buf.note(row, 0, n);
+  init_buffer_if_empty(buf);
+  cur.row = buf.len() - 1;
+  cur.col = 0;
+  align_cursor(cur, buf, size);
+  Ok(true)
+}
+
+// Moves the cursor to the first marker in a line.
+fn move_cursor_to_todo_in(row: usize, cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  let marks = todo::find_in_line(&buf[row], &config::get().todo_markers);
+  cur.row = row;
+  cur.col = marks.first().map_or(0, |mark| mark.start);
+  align_cursor(cur, buf, size);
+}
+
+// Moves the cursor to the next or previous line with a marker, returning
+// whether there was one.
+fn move_cursor_to_todo(cur: &mut Cursor, buf: &Buffer, size: &Size, forward: bool) -> bool {
+  let rows = todo::find_all(buf, &config::get().todo_markers);
+  let row = if forward {
+    rows.into_iter().find(|&row| row > cur.row)
+  } else {
+    rows.into_iter().rev().find(|&row| row < cur.row)
+  };
+  match row {
+    Some(row) => {
+      move_cursor_to_todo_in(row, cur, buf, size);
+      true
+    }
+    None => false,
+  }
+}
+
+fn note_shown<'a>(mode: &Mode, notes: &'a Notes) -> Option<(usize, &'a str)> {
+  match *mode {
+    Mode::Note(row) => notes.get(row).map(|text| (row, text)),
+    _ => None,
+  }
+}
+
+fn show_note(file: &OpenFile, fb: &mut Feedback) -> Mode {
+  match file.notes.get(file.cur.row) {
+    Some(_) => Mode::Note(file.cur.row),
+    None => {
+      fb.bell = true;
+      fb.message = Some(String::from("No note on this line"));
+      Mode::Normal
+    }
+  }
+}
+
+// Attaches a note to the current line, or removes it. The notes of a file
+// without unsaved changes are written at once, and otherwise with the file,
+// so that they are saved for the lines they are on.
+fn set_note(file: &mut OpenFile, text: Option<&str>, fb: &mut Feedback) -> io::Result<()> {
+  let row = file.cur.row;
+  match text {
+    Some(text) => file.notes.set(row, text),
+    None if file.notes.remove(row) => (),
+    None => {
+      fb.bell = true;
+      fb.message = Some(String::from("No note on this line"));
+      return Ok(());
+    }
+  }
+  file.annotations.generation = None;
+  if !file.modified() && !file.path.is_empty() && file.scratch.is_none() {
+    file.notes.save(&file.path)?;
+  }
+  Ok(())
+}
+
+// Brings back an earlier or later state of the buffer, keeping the cursor on
+// its line where it can.
+fn go_to_state(file: &mut OpenFile, step: Step, back: bool, size: &Size, fb: &mut Feedback) {
+  let (lines, time) = match file.timeline.go(step, back) {
+    Some(state) => state,
+    None => {
+      fb.bell = true;
+      fb.message = Some(String::from(if back { "Already at the oldest change" } else { "Already at the newest change" }));
+      return;
+    }
+  };
+  file.buf.replace(lines);
+  file.timeline.restored(file.buf.generation);
+  file.cur.row = file.cur.row.min(file.buf.len().saturating_sub(1));
+  truncate_cursor_to_line(&mut file.cur, &file.buf);
+  align_cursor(&mut file.cur, &file.buf, size);
+  let age = time.elapsed().unwrap_or_default();
+  fb.message = Some(format!("{} the text of {} ago", if back { "Back to" } else { "Forward to" }, timeline::format_age(age)));
+}
+
+// Bookmarks the line or takes its bookmark away, goes to the next or
+// previous bookmark, or lists them.
+fn run_bookmark_action(action: Action, file: &mut OpenFile, size: &Size, fb: &mut Feedback) -> Mode {
+  let OpenFile{ref mut bookmarks, ref mut cur, ref buf, ref mut annotations, ..} = *file;
+  match action {
+    Action::ToggleBookmark if cur.row < buf.len() => {
+      bookmarks.toggle(cur.row);
+      annotations.generation = None;
+    }
+    Action::NextBookmark | Action::PrevBookmark => match bookmarks.next(cur.row, action == Action::NextBookmark) {
+      Some(row) => jump_to_line(row + 1, cur, buf, size),
+      None => fb.bell = true,
+    },
+    Action::BookmarkList if !bookmarks.is_empty() => {
+      let rows = bookmarks.rows();
+      let selected = rows.iter().position(|&row| row >= cur.row).unwrap_or(rows.len() - 1);
+      jump_to_line(rows[selected] + 1, cur, buf, size);
+      return Mode::BookmarkList(selected);
+    }
+    _ => {
+      fb.bell = true;
+      fb.message = Some(String::from("No bookmarks; M bookmarks a line"));
+    }
+  }
+  Mode::Normal
+}
+
+// Moving through the list moves the cursor along to the selected line, and
+// `M` takes its bookmark away.
+fn handle_key_bookmark_list_mode(key: Key, mut selected: usize, file: &mut OpenFile, size: &Size, fb: &mut Feedback) -> Mode {
+  let OpenFile{ref mut bookmarks, ref mut cur, ref buf, ref mut annotations, ..} = *file;
+  let len = bookmarks.rows().len();
+  match key {
+    Key::Char('j') | Key::Down if selected + 1 < len => selected += 1,
+    Key::Char('k') | Key::Up if selected > 0 => selected -= 1,
+    Key::Char('M') => {
+      bookmarks.toggle(bookmarks.rows()[selected]);
+      annotations.generation = None;
+      if bookmarks.is_empty() {
+        return Mode::Normal;
+      }
+      selected = selected.min(len - 2);
+    }
+    Key::Char('\n') | Key::Char('q') | Key::Esc => return Mode::Normal,
+    _ => fb.bell = true,
+  }
+  jump_to_line(bookmarks.rows()[selected] + 1, cur, buf, size);
+  Mode::BookmarkList(selected)
+}
+
+fn start_todo_list(cur: &mut Cursor, buf: &Buffer, size: &Size, fb: &mut Feedback) -> Mode {
+  let rows = todo::find_all(buf, &config::get().todo_markers);
+  if rows.is_empty() {
+    fb.bell = true;
+    return Mode::Normal;
+  }
+  let selected = rows.iter().position(|&row| row >= cur.row).unwrap_or(rows.len() - 1);
+  move_cursor_to_todo_in(rows[selected], cur, buf, size);
+  Mode::TodoList(rows, selected)
+}
+
+// Moving through the list moves the cursor along to the selected line.
+fn handle_key_todo_list_mode(
+  key: Key,
+  rows: Vec<usize>,
+  mut selected: usize,
+  cur: &mut Cursor,
+  buf: &Buffer,
+  size: &Size,
+  fb: &mut Feedback,
+) -> io::Result<Mode> {
+  match key {
+    Key::Char('j') | Key::Down if selected + 1 < rows.len() => selected += 1,
+    Key::Char('k') | Key::Up if selected > 0 => selected -= 1,
+    Key::Char('\n') | Key::Char('q') | Key::Esc => return Ok(Mode::Normal),
+    _ => fb.bell = true,
+  }
+  move_cursor_to_todo_in(rows[selected], cur, buf, size);
+  Ok(Mode::TodoList(rows, selected))
+}
+
+// Shows the tree of files, with the current file selected, and gives it the
+// keys.
+fn open_tree(files: &BufferList, ses: &mut Session) -> io::Result<Mode> {
+  if ses.tree.is_none() {
+    ses.tree = Some(Tree::new(Path::new(""))?);
+  }
+  let path = Path::new(&files.current().path);
+  let dir = env::current_dir()?;
+  if let Some(tree) = ses.tree.as_mut() {
+    tree.reveal(path.strip_prefix(&dir).unwrap_or(path))?;
+  }
+  Ok(Mode::Tree)
+}
+
+const NEW_FILE_PROMPT: &str = "New file: ";
+const RENAME_PROMPT: &str = "Rename to: ";
+
+fn delete_prompt(path: &Path) -> String {
+  match path.is_dir() {
+    true => format!("Delete {}/ and everything in it? (y/n)", path.display()),
+    false => format!("Delete {}? (y/n)", path.display()),
+  }
+}
+
+// `j` and `k` move through the tree, `l` or `Enter` expands a directory or
+// opens a file, and `h` collapses a directory or goes up to the one it is
+// in. `a`, `r` and `d` add, rename and delete files, `R` reads the tree
+// again, and `q` or `Escape` goes back to the text.
+fn handle_key_tree_mode(key: Key, files: &mut BufferList, ses: &mut Session, fb: &mut Feedback) -> io::Result<Mode> {
+  let tree = match ses.tree.as_mut() {
+    Some(tree) => tree,
+    None => return Ok(Mode::Normal),
+  };
+  let selected = tree.selected().map(|entry| (entry.path.clone(), entry.dir));
+  match (key, selected) {
+    (Key::Char('j') | Key::Down, _) if tree.selected + 1 < tree.entries.len() => tree.selected += 1,
+    (Key::Char('k') | Key::Up, _) if tree.selected > 0 => tree.selected -= 1,
+    (Key::Char('l') | Key::Char('\n'), Some((_, true))) => tree.toggle()?,
+    (Key::Char('l') | Key::Char('\n'), Some((path, false))) => {
+      files.open(&path.to_string_lossy())?;
+      return Ok(Mode::Normal);
+    }
+    (Key::Char('h'), _) => fb.bell = !tree.collapse()?,
+    (Key::Char('a'), _) => {
+      let dir = tree.target_dir();
+      let name = if dir.as_os_str().is_empty() { String::new() } else { format!("{}/", dir.display()) };
+      return Ok(Mode::TreeName(None, name));
+    }
+    (Key::Char('r'), Some((path, _))) => {
+      let name = path.to_string_lossy().into_owned();
+      return Ok(Mode::TreeName(Some(path), name));
+    }
+    (Key::Char('d'), Some((path, _))) => return Ok(Mode::TreeDelete(path)),
+    (Key::Char('R'), _) => tree.refresh()?,
+    (Key::Char(':'), _) => return Ok(Mode::Command(String::new())),
+    (Key::Char('q') | Key::Esc, _) => return Ok(Mode::Normal),
+    _ => fb.bell = true,
+  }
+  Ok(Mode::Tree)
+}
+
+// Creates the file named, or renames the one selected, back in the tree. A
+// new file is opened, and an open file renamed keeps being edited under its
+// new name.
+fn handle_key_tree_name_mode(
+  key: Key,
+  from: Option<PathBuf>,
+  mut name: String,
+  files: &mut BufferList,
+  ses: &mut Session,
+  fb: &mut Feedback,
+) -> Mode {
+  match key {
+    Key::Char('\n') if !name.is_empty() => (),
+    Key::Esc => return Mode::Tree,
+    Key::Backspace => {
+      name.pop();
+      return Mode::TreeName(from, name);
+    }
+    Key::Char(c) if c != '\n' => {
+      name.push(c);
+      return Mode::TreeName(from, name);
+    }
+    _ => return Mode::TreeName(from, name),
+  }
+  let to = PathBuf::from(name.trim_end_matches('/'));
+  let res = match from {
+    Some(ref from) => tree::rename(from, &to).map(|_| {
+      for file in files.files.iter_mut() {
+        match Path::new(&file.path).strip_prefix(from) {
+          Ok(rest) if rest.as_os_str().is_empty() => file.path = to.to_string_lossy().into_owned(),
+          Ok(rest) => file.path = to.join(rest).to_string_lossy().into_owned(),
+          Err(_) => (),
+        }
+      }
+    }),
+    None => tree::create(&name),
+  };
+  let res = res.and_then(|_| match ses.tree.as_mut() {
+    Some(tree) => tree.reveal(&to).map(|_| ()),
+    None => Ok(()),
+  });
+  if let Err(err) = res {
+    fb.bell = true;
+    fb.message = Some(format!("{}: {}", name, err));
+    return Mode::Tree;
+  }
+  if from.is_none() && !name.ends_with('/') {
+    if let Err(err) = files.open(&name) {
+      fb.message = Some(format!("{}: {}", name, err));
+      return Mode::Tree;
+    }
+    return Mode::Normal;
+  }
+  Mode::Tree
+}
+
+// `y` deletes the file, or the directory and everything in it, and any other
+// key keeps it.
+fn handle_key_tree_delete_mode(key: Key, path: PathBuf, ses: &mut Session, fb: &mut Feedback) -> Mode {
+  if key != Key::Char('y') {
+    return Mode::Tree;
+  }
+  let res = tree::remove(&path).and_then(|_| match ses.tree.as_mut() {
+    Some(tree) => tree.refresh(),
+    None => Ok(()),
+  });
+  if let Err(err) = res {
+    fb.bell = true;
+    fb.message = Some(format!("{}: {}", path.display(), err));
+  }
+  Mode::Tree
+}
+
+// Typing narrows the list of files down, the arrows or `Ctrl-N` and `Ctrl-P`
+// move through it, and `Enter` switches to the file selected.
+fn handle_key_buffers_mode(
+  key: Key,
+  mut query: String,
+  mut selected: usize,
+  files: &mut BufferList,
+  fb: &mut Feedback,
+) -> Mode {
+  let found = files.matching(&query);
+  match key {
+    Key::Char('\n') => match found.get(selected) {
+      Some(&i) => {
+        files.current = i;
+        return Mode::Normal;
+      }
+      None => fb.bell = true,
+    },
+    Key::Esc | Key::Ctrl('c') => return Mode::Normal,
+    Key::Down | Key::Ctrl('n') if selected + 1 < found.len() => selected += 1,
+    Key::Up | Key::Ctrl('p') if selected > 0 => selected -= 1,
+    Key::Backspace if query.pop().is_some() => selected = 0,
+    Key::Char(c) if !c.is_control() => {
+      query.push(c);
+      selected = 0;
+    }
+    _ => fb.bell = true,
+  }
+  Mode::Buffers(query, selected)
+}
+
+// Searches go on from the top of the buffer after the last match, and from
+// the bottom before the first one.
+fn search_forward(
+  query: &str,
+  from: (usize, usize),
+  buf: &Buffer,
+  fb: &mut Feedback,
+) -> Option<(usize, usize)> {
+  search::find_next(buf, query, from.0, from.1).or_else(|| {
+    let found = search::find_first(buf, query);
+    if found.is_some() {
+      fb.message = Some(String::from("Search went on from the top"));
+    }
+    found
+  })
+}
+
+fn search_backward(
+  query: &str,
+  from: (usize, usize),
+  buf: &Buffer,
+  fb: &mut Feedback,
+) -> Option<(usize, usize)> {
+  search::find_prev(buf, query, from.0, from.1).or_else(|| {
+    let found = search::find_last(buf, query);
+    if found.is_some() {
+      fb.message = Some(String::from("Search went on from the bottom"));
+    }
+    found
+  })
+}
+
+// Indents or dedents `count` lines from the given row, leaving the cursor at
+// the start of the text on that row.
+fn shift_lines(row: usize, count: usize, indent: bool, cur: &mut Cursor, buf: &mut Buffer, size: &Size) -> bool {
+  if row >= buf.len() {
+    return false;
+  }
+  let rows = row..(row + count).min(buf.len());
+  let shift_width = config::get().shift_width;
+  let changed = match indent {
+    true => indent_lines(buf, rows, shift_width),
+    false => dedent_lines(buf, rows, shift_width),
+  };
+  move_cursor_to((row, indentation(&buf[row]).len()), cur, buf, size);
+  changed
+}
+
+// Jumps to the start of the text on a line, counted from 1 and clamped to the
+// buffer. A line out of view is brought to the middle of the screen, without
+// scrolling past the end of the buffer.
+fn jump_to_line(line: usize, cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  let row = line.saturating_sub(1).min(buf.len().saturating_sub(1));
+  if row < cur.top || row >= cur.top + size.rows {
+    cur.top = row.saturating_sub(size.rows / 2).min(buf.len().saturating_sub(size.rows));
+  }
+  let col = buf.get(row).map_or(0, |line| indentation(line).len());
+  move_cursor_to((row, col), cur, buf, size);
+}
+
+// Scrolls the view, without moving the cursor, so that its line is shown on
+// the given row of the text area, or as close to it as the buffer allows.
+fn scroll_cursor_to_row(row: usize, cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  cur.top = cur.row.saturating_sub(row.min(size.rows - 1));
+  align_cursor(cur, buf, size);
+}
+
+fn move_cursor_to(pos: (usize, usize), cur: &mut Cursor, buf: &Buffer, size: &Size) {
+  cur.row = pos.0;
+  cur.col = pos.1;
+  truncate_cursor_to_line(cur, buf);
+  align_cursor(cur, buf, size);
+}
+
+// Motions extend the selection, and an operation on it ends visual mode with
+// the cursor at its start.
+fn handle_key_visual_mode(
+  key: Key,
+  anchor: (usize, usize),
+  block: bool,
+  file: &mut OpenFile,
+  ses: &mut Session,
+  size: &Size,
+  fb: &mut Feedback,
+) -> io::Result<Mode> {
+  let same = if block { Mode::VisualBlock(anchor) } else { Mode::Visual(anchor) };
+  let visual = config::get().visual_keys.get(&key);
+  if let (None, Some(action)) = (visual, normal_action(&key).filter(|&action| is_structure_motion(action))) {
+    move_by_structure(action, file, size, fb);
+    return Ok(same);
+  }
+  let OpenFile{ref path, ref mut buf, ref mut cur, ..} = *file;
+  let action = match visual {
+    Some(action) => action,
+    None => {
+      if let Some(motion) = normal_action(&key).and_then(motion_for_action) {
+        let before = (cur.row, cur.col);
+        motion(cur, buf, size);
+        fb.bell = before == (cur.row, cur.col);
+      }
+      return Ok(same);
+    }
+  };
+  let sel = if block { Selection::block(anchor, cur) } else { Selection::new(anchor, cur) };
+  match action {
+    Action::Delete => sel.delete(buf),
+    Action::Cut => {
+      copy_selection(&sel, buf, &mut ses.clip)?;
+      sel.delete(buf);
+    }
+    Action::Copy => copy_selection(&sel, buf, &mut ses.clip)?,
+    Action::Indent | Action::Dedent => {
+      let count = sel.end.0 + 1 - sel.start.0;
+      fb.bell = !shift_lines(sel.start.0, count, action == Action::Indent, cur, buf, size);
+      return Ok(Mode::Normal);
+    }
+    Action::Filter => {
+      let end = sel.end.0.min(buf.len().saturating_sub(1));
+      return Ok(Mode::Filter(sel.start.0..end + 1, String::new()));
+    }
+    Action::ToggleComment => {
+      let end = sel.end.0.min(buf.len().saturating_sub(1));
+      cur.row = sel.start.0;
+      toggle_comment(sel.start.0..end + 1, path, cur, buf, size, fb);
+      return Ok(Mode::Normal);
+    }
+    // Typing on the first line of a block, the text typed goes on the others
+    // too once insert mode is left.
+    Action::BlockInsert | Action::BlockAppend if block => {
+      let append = action == Action::BlockAppend;
+      let end = (sel.end.0 + 1).min(buf.len());
+      let col = if append { sel.end.1 + 1 } else { sel.start.1 };
+      ses.block_insert = Some(BlockInsert{
+        row: sel.start.0,
+        rows: sel.start.0 + 1..end,
+        col,
+        append,
+        before: buf[sel.start.0].clone(),
+        len: buf.len(),
+      });
+      cur.row = sel.start.0;
+      cur.col = col;
+      align_cursor(cur, buf, size);
+      return Ok(Mode::Insert);
+    }
+    Action::VisualBlock if !block => return Ok(Mode::VisualBlock(anchor)),
+    Action::Normal | Action::VisualBlock => return Ok(Mode::Normal),
+    _ => {
+      fb.bell = true;
+      return Ok(same);
+    }
+  }
+  move_cursor_to(sel.start, cur, buf, size);
+  Ok(Mode::Normal)
+}
+
+// Repeats the text typed on the first line of a block on the rest of it,
+// unless the first line changed in any other way.
+fn finish_block_insert(block: BlockInsert, buf: &mut Buffer) {
+  let BlockInsert{row, rows, col, append, before, len} = block;
+  if buf.len() != len {
+    return;
+  }
+  let mut before: Vec<char> = before.chars().collect();
+  if before.len() < col {
+    before.resize(col, ' ');
+  }
+  let after: Vec<char> = buf[row].chars().collect();
+  let added = match after.len().checked_sub(before.len()) {
+    Some(added) if added > 0 => added,
+    _ => return,
+  };
+  if after[..col] != before[..col] || after[col + added..] != before[col..] {
+    return;
+  }
+  let text: String = after[col..col + added].iter().collect();
+  for line in buf.range_mut(rows) {
+    let n = line_len(line);
+    // a line that ends before the block has nothing in it to insert before
+    if !append && n <= col {
+      continue;
+    }
+    if n < col {
+      line.extend(std::iter::repeat_n(' ', col - n));
+    }
+    let i = byte_index(line, col);
+    line.insert_str(i, &text);
+  }
+  buf.touch();
+}
+
+// Comments the lines out, or back in if they are all comments already, with
+// the line comment of the file type.
+fn toggle_comment(rows: Range<usize>, path: &str, cur: &mut Cursor, buf: &mut Buffer, size: &Size, fb: &mut Feedback) {
+  match filetype::detect(path) {
+    Some(ft) => {
+      let mut lines = buf.slice(rows.clone());
+      comment::toggle(&mut lines, ft.comment);
+      buf.splice(rows, lines);
+      buf.touch();
+      move_cursor_to((cur.row, cur.col), cur, buf, size);
+    }
+    None => {
+      fb.bell = true;
+      fb.message = Some(String::from("No line comment for this kind of file"));
+    }
+  }
+}
+
+// Replaces `old` with `new` in the lines, in the whole of each line or only
+// at its first match, returning the last line changed.
+fn substitute(rows: Range<usize>, old: &str, new: &str, global: bool, buf: &mut Buffer) -> Option<usize> {
+  let mut last = None;
+  for row in rows {
+    if !buf[row].contains(old) {
+      continue;
+    }
+    buf[row] = match global {
+      true => buf[row].replace(old, new),
+      false => buf[row].replacen(old, new, 1),
+    };
+    last = Some(row);
+  }
+  if last.is_some() {
+    buf.touch();
+  }
+  last
+}
+
+fn run_command(
+  command: Command,
+  files: &mut BufferList,
+  ses: &mut Session,
+  size: &Size,
+  fb: &mut Feedback,
+) -> io::Result<Mode> {
+  let OpenFile{ref path, ref mut buf, ref mut cur, ref mut format, ..} = *files.current_mut();
+  let range = match command {
+    Command::Filter(range, _) | Command::Delete(range) | Command::Yank(range) => Some(range),
+    Command::Substitute(range, ..) => Some(range),
+    Command::Sort(range, _) | Command::Reverse(range) | Command::Uniq(range) => Some(range),
+    Command::Comment(range) => Some(range),
+    _ => None,
+  };
+  let rows = match range.map(|range| range.rows(cur.row, buf.len())) {
+    Some(Err(msg)) => {
+      fb.bell = true;
+      fb.message = Some(msg);
+      return Ok(Mode::Normal);
+    }
+    Some(Ok(rows)) => rows,
+    None => 0..0,
+  };
+  match command {
+    Command::Write | Command::WriteQuit =>
+      return Ok(save_or_ask_for_name(files.current_mut(), command == Command::WriteQuit, fb)),
+    Command::WriteAs(path) => return Ok(save_as(&expand_path(&path), false, files.current_mut(), fb)),
+    Command::Quit => return Ok(quit_unless_modified(files, fb)),
+    Command::ForceQuit => return Ok(Mode::Quit),
+    Command::Edit(path) => if let Err(err) = files.open(&expand_path(&path)) {
+      fb.message = Some(err.to_string());
+    },
+    Command::Recover => recover(files.current_mut(), fb)?,
+    Command::Reload => {
+      let file = files.current_mut();
+      if file.path.is_empty() {
+        fb.bell = true;
+      } else {
+        file.reload()?;
+      }
+    }
+    Command::NextBuffer => fb.bell = !files.cycle(true),
+    Command::PrevBuffer => fb.bell = !files.cycle(false),
+    Command::Goto(line) => jump_to_line(line, cur, buf, size),
+    Command::Crlf(crlf) => {
+      fb.bell = format.crlf == crlf;
+      format.crlf = crlf;
+    }
+    Command::Commit(message) => {
+      let file = files.current_mut();
+      fb.message = Some(if file.git.is_none() {
+        String::from("Not in a git repository")
+      } else if file.modified() {
+        String::from("Save the file before committing it")
+      } else {
+        match git::commit(&file.path, &message) {
+          Ok(summary) => {
+            file.read_index();
+            summary
+          }
+          Err(err) => err.to_string(),
+        }
+      });
+    }
+    Command::Filter(_, command) => filter_lines(&command, rows, cur, buf, size, fb),
+    Command::Delete(_) => delete_lines(rows, cur, buf, &mut ses.clip, size)?,
+    Command::Yank(_) => {
+      let n = rows.len();
+      fb.message = Some(format!("Copied {} line{}", n, if n == 1 { "" } else { "s" }));
+      yank_lines(rows, buf, &mut ses.clip)?;
+    }
+    Command::Comment(_) => toggle_comment(rows, path, cur, buf, size, fb),
+    Command::Sort(..) | Command::Reverse(_) | Command::Uniq(_) => {
+      let mut lines = buf.slice(rows.clone());
+      match command {
+        Command::Sort(_, order) => {
+          transform::sort(&mut lines, order);
+          buf.splice(rows, lines);
+          buf.touch();
+        }
+        Command::Reverse(_) => {
+          transform::reverse(&mut lines);
+          buf.splice(rows, lines);
+          buf.touch();
+        }
+        _ => {
+          let lines = transform::uniq(&lines);
+          let n = lines.len();
+          buf.splice(rows.clone(), lines);
+          buf.note(rows.start, rows.len(), n);
+        }
+      }
+      // the cursor stays on its row, which may now hold a shorter line
+      move_cursor_to((cur.row.min(buf.len() - 1), cur.col), cur, buf, size);
+    }
+    Command::Substitute(_, old, new, global) => match substitute(rows, &old, &new, global, buf) {
+      Some(row) => move_cursor_to((row, 0), cur, buf, size),
+      None => {
+        fb.bell = true;
+        fb.message = Some(format!("Not found: {}", old));
+      }
+    },
+    Command::Terminal => return start_terminal(ses, size),
+    Command::Man(topic) => {
+      let text = man::page(&topic, size.cols)?;
+      let mut buf: Buffer = text.lines().map(Line::from).collect::<Vec<Line>>().into();
+      init_buffer_if_empty(&mut buf);
+      show_scratch(&format!("[man {}]", topic), buf, true, files);
+    }
+    Command::Note(text) if text.is_empty() => return Ok(show_note(files.current(), fb)),
+    Command::Note(text) => set_note(files.current_mut(), Some(&text), fb)?,
+    Command::Unnote => set_note(files.current_mut(), None, fb)?,
+    Command::Earlier(step) => go_to_state(files.current_mut(), step, true, size, fb),
+    Command::Later(step) => go_to_state(files.current_mut(), step, false, size, fb),
+    Command::Csv if cur.table.is_some() => cur.table = None,
+    Command::Csv => {
+      let first = buf.first().map_or("", |line| line.as_str());
+      cur.table = Some(Table::new(table::separator(path, first)));
+    }
+    Command::Tree if ses.tree.is_some() => ses.tree = None,
+    Command::Tree => return open_tree(files, ses),
+    Command::Preview if ses.preview || markdown::is_markdown(path) => ses.preview = !ses.preview,
+    Command::Preview => {
+      fb.bell = true;
+      fb.message = Some(String::from("Not a markdown file"));
+    }
+    Command::Make(args) => {
+      let program = config::get().make_program.clone();
+      let command = if args.is_empty() { program } else { format!("{} {}", program, args) };
+      run_for_output("make", &command, files, ses, fb)?;
+    }
+    Command::Run(command) => run_for_output("run", &command, files, ses, fb)?,
+    Command::Location(forward) => {
+      let n = ses.locations.len();
+      let next = match (ses.location, forward) {
+        (None, true) => Some(0),
+        (None, false) => n.checked_sub(1),
+        (Some(i), true) => Some(i + 1),
+        (Some(i), false) => i.checked_sub(1),
+      };
+      match next.filter(|&i| i < n) {
+        Some(i) => {
+          ses.location = Some(i);
+          fb.message = Some(format!("Location {} of {}", i + 1, n));
+          open_location(&ses.locations[i].clone(), files, size, fb);
+        }
+        None => {
+          fb.bell = true;
+          fb.message = Some(String::from(if n == 0 { "No locations" } else { "No more locations" }));
+        }
+      }
+    }
+    Command::Diff => {
+      let view = diff_with_saved(files.current())?;
+      if view.a == view.b {
+        fb.message = Some(String::from("No changes"));
+      } else {
+        return Ok(Mode::Diff(Box::new(view)));
+      }
+    }
+  }
+  Ok(Mode::Normal)
+}
+
+// Runs a command for what it prints, like a build, and shows that in a
+// buffer named after it, which running it again replaces. The places in files
+// it names are kept for `:cn` and `:cp`.
+fn run_for_output(
+  name: &str,
+  command: &str,
+  files: &mut BufferList,
+  ses: &mut Session,
+  fb: &mut Feedback,
+) -> io::Result<()> {
+  fb.redraw = true;
+  let (output, status) = with_terminal_restored(|| filter::capture(command))?;
+  let (mut buf, _) = decode_text(command, &output, None)?;
+  init_buffer_if_empty(&mut buf);
+  ses.locations = location::find_all(&buf);
+  ses.location = None;
+  let n = ses.locations.len();
+  show_scratch(&format!("[{}]", name), buf, false, files);
+  let status = match status.code() {
+    Some(0) => String::from("finished"),
+    Some(code) => format!("exited with {}", code),
+    None => String::from("was stopped"),
+  };
+  fb.message = Some(format!("{} {}; {} location{}", command, status, n, if n == 1 { "" } else { "s" }));
+  Ok(())
+}
+
+// Shows a buffer of text that isn't a file, replacing the one with the same
+// name, if any.
+fn show_scratch(name: &str, buf: Buffer, read_only: bool, files: &mut BufferList) {
+  let mut file = OpenFile::new("", buf);
+  file.scratch = Some(name.to_string());
+  if read_only {
+    file.read_only = Some(file.buf.lines.clone());
+  }
+  match files.files.iter().position(|file| file.scratch.as_deref() == Some(name)) {
+    Some(i) => {
+      files.files[i] = file;
+      files.current = i;
+    }
+    None => {
+      files.files.push(file);
+      files.current = files.files.len() - 1;
+    }
+  }
+}
+
+fn is_inserting(mode: &Mode) -> bool {
+  matches!(mode, Mode::Insert | Mode::Literal(_) | Mode::Digraph(_) | Mode::Dynamic | Mode::Expression(_))
+}
+
+// A read-only buffer gets its lines back after an edit, and insert mode is
+// left as soon as it is entered.
+fn undo_read_only_edit(file: &mut OpenFile, mode: &mut Mode, fb: &mut Feedback) {
+  let Some(ref lines) = file.read_only else {
+    return;
+  };
+  let inserting = is_inserting(mode);
+  if file.saved == Some(file.buf.generation) && !inserting {
+    return;
+  }
+  file.buf.replace(lines.clone());
+  file.saved = Some(file.buf.generation);
+  file.cur.row = file.cur.row.min(file.buf.len().saturating_sub(1));
+  truncate_cursor_to_line(&mut file.cur, &file.buf);
+  if inserting {
+    *mode = Mode::Normal;
+  }
+  fb.bell = true;
+  fb.message = Some(format!("{} is read-only", file.scratch.as_deref().unwrap_or(NO_NAME)));
+}
+
+// Shows the file a location names, with the cursor on its line and column. A
+// relative path is found from where the editor started, as the command that
+// named it ran there.
+fn open_location(loc: &Location, files: &mut BufferList, size: &Size, fb: &mut Feedback) {
+  if !Path::new(&loc.path).is_file() {
+    fb.bell = true;
+    fb.message = Some(format!("No file {}", loc.path));
+    return;
+  }
+  if let Err(err) = files.open(&loc.path) {
+    fb.bell = true;
+    fb.message = Some(err.to_string());
+    return;
+  }
+  let OpenFile{ref buf, ref mut cur, ..} = *files.current_mut();
+  jump_to_line(loc.line, cur, buf, size);
+  move_cursor_to((cur.row, loc.col - 1), cur, buf, size);
+}
+
+const SAVE_AS_PROMPT: &str = "Save as: ";
+
+// A file that can't be saved, say on a full disk or without permission,
+// stays modified and open, with the reason on the bottom line. A saved file
+// that can't be copied into the history is still saved.
+fn save_or_report(file: &mut OpenFile, fb: &mut Feedback) -> bool {
+  match file.save() {
+    Ok(()) => {
+      if let Err(err) = file.save_side_files() {
+        fb.message = Some(format!("Saved {}, but not its notes and positions: {}", file.path, err));
+      } else if let Err(err) = record_history(&file.path) {
+        fb.message = Some(format!("Saved {}, but not in its history: {}", file.path, err));
+      }
+      true
+    }
+    Err(err) => {
+      fb.bell = true;
+      fb.message = Some(format!("Could not save {}: {}", file.path, err));
+      false
+    }
+  }
+}
+
+fn save_or_ask_for_name(file: &mut OpenFile, quit: bool, fb: &mut Feedback) -> Mode {
+  if file.path.is_empty() {
+    return Mode::SaveAs(String::new(), quit);
+  }
+  if file.changed_on_disk() {
+    return Mode::Changed(quit);
+  }
+  match save_or_report(file, fb) {
+    true if quit => Mode::Quit,
+    _ => Mode::Normal,
+  }
+}
+
+// The path of a file with symlinks, `.` and `..` resolved, for a new file too
+// as long as its directory exists.
+fn canonical_path(path: &str) -> Option<PathBuf> {
+  let path = Path::new(path);
+  fs::canonicalize(path).ok().or_else(|| {
+    let dir = match path.parent() {
+      Some(dir) if !dir.as_os_str().is_empty() => dir,
+      _ => Path::new("."),
+    };
+    Some(fs::canonicalize(dir).ok()?.join(path.file_name()?))
+  })
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+  a == b || matches!((canonical_path(a), canonical_path(b)), (Some(a), Some(b)) if a == b)
+}
+
+// Saves the file under a new name, which it keeps from then on, asking first
+// to create a directory for it that doesn't exist, or to replace another file.
+// Its own name is saved as usual, asking first when it changed on disk.
+fn save_as(path: &str, quit: bool, file: &mut OpenFile, fb: &mut Feedback) -> Mode {
+  if !file.path.is_empty() && same_file(path, &file.path) {
+    return save_or_ask_for_name(file, quit, fb);
+  }
+  match Path::new(path).parent() {
+    Some(dir) if !dir.as_os_str().is_empty() && !dir.exists() =>
+      return Mode::CreateDir(path.to_string(), quit),
+    _ => (),
+  }
+  if fs::symlink_metadata(path).is_ok() {
+    return Mode::Overwrite(path.to_string(), quit);
+  }
+  save_under(path, quit, file, fb)
+}
+
+// A file that can't be saved under the new name keeps its old one.
+fn save_under(path: &str, quit: bool, file: &mut OpenFile, fb: &mut Feedback) -> Mode {
+  let old = mem::replace(&mut file.path, path.to_string());
+  if !save_or_report(file, fb) {
+    file.path = old;
+    return Mode::Normal;
+  }
+  if file.path != old {
+    file.read_index();
+  }
+  file.scratch = None;
+  file.read_only = None;
+  if quit { Mode::Quit } else { Mode::Normal }
+}
+
+fn changed_prompt(path: &str) -> String {
+  format!("{} changed on disk; o overwrites it, r reads it again", path)
+}
+
+// `o` saves over the other program's changes, `r` drops the buffer's own
+// changes for them, and any other key does neither.
+fn handle_key_changed_mode(key: Key, quit: bool, file: &mut OpenFile, fb: &mut Feedback) -> io::Result<Mode> {
+  match key {
+    Key::Char('o') => Ok(match save_or_report(file, fb) {
+      true if quit => Mode::Quit,
+      _ => Mode::Normal,
+    }),
+    Key::Char('r') => file.reload().map(|_| Mode::Normal),
+    _ => Ok(Mode::Normal),
+  }
+}
+
+fn overwrite_prompt(path: &str) -> String {
+  format!("{} exists; overwrite it? (y/n)", path)
+}
+
+// `y` saves the file over the other one, and any other key gives up on saving.
+fn handle_key_overwrite_mode(key: Key, path: String, quit: bool, file: &mut OpenFile, fb: &mut Feedback) -> Mode {
+  match key {
+    Key::Char('y') => save_under(&path, quit, file, fb),
+    _ => Mode::Normal,
+  }
+}
+
+fn create_dir_prompt(path: &str) -> String {
+  let dir = Path::new(path).parent().map_or(Path::new(""), |dir| dir);
+  format!("Create {}? (y/n)", dir.display())
+}
+
+// `y` creates the directory and saves the file in it, and any other key
+// gives up on saving.
+fn handle_key_create_dir_mode(key: Key, path: String, quit: bool, file: &mut OpenFile, fb: &mut Feedback) -> Mode {
+  if key != Key::Char('y') {
+    return Mode::Normal;
+  }
+  let dir = Path::new(&path).parent().map_or(Path::new(""), |dir| dir);
+  match fs::create_dir_all(dir) {
+    Ok(()) => save_as(&path, quit, file, fb),
+    Err(err) => {
+      fb.bell = true;
+      fb.message = Some(format!("Could not create {}: {}", dir.display(), err));
+      Mode::Normal
+    }
+  }
+}
+
+// Enter saves the file under the name typed, and Escape gives up on it.
+fn handle_key_save_as_mode(key: Key, mut name: String, quit: bool, file: &mut OpenFile, fb: &mut Feedback) -> Mode {
+  match key {
+    Key::Char('\n') if !name.is_empty() => return save_as(&expand_path(&name), quit, file, fb),
+    Key::Esc => return Mode::Normal,
+    Key::Backspace => {
+      name.pop();
+    }
+    Key::Char(c) if c != '\n' => name.push(c),
+    _ => (),
+  }
+  Mode::SaveAs(name, quit)
+}
+
+// Completes the last part of a path as far as the names in its directory
+// agree, adding a `/` to a directory. Hidden names are only offered once the
+// part starts with a `.`.
+fn complete_path(path: &str) -> Option<String> {
+  let (dir, part) = match path.rfind('/') {
+    Some(i) => (&path[..i + 1], &path[i + 1..]),
+    None => ("", path),
+  };
+  let search = if dir.is_empty() { String::from(".") } else { expand_path(dir) };
+  let mut names: Vec<(String, bool)> = fs::read_dir(search).ok()?
+    .filter_map(|entry| entry.ok())
+    .map(|entry| {
+      let is_dir = entry.path().is_dir();
+      (entry.file_name().to_string_lossy().into_owned(), is_dir)
+    })
+    .filter(|(name, _)| name.starts_with(part) && (part.starts_with('.') || !name.starts_with('.')))
+    .collect();
+  names.sort();
+  let (first, is_dir) = names.first()?.clone();
+  let common = names.iter()
+    .map(|(name, _)| first.chars().zip(name.chars()).take_while(|(a, b)| a == b).count())
+    .min()
+    .unwrap_or(0);
+  let mut completed = format!("{}{}", dir, first.chars().take(common).collect::<String>());
+  if names.len() == 1 && is_dir {
+    completed.push('/');
+  }
+  Some(completed)
+}
+
+// Enter runs the command, and Escape drops it. Tab completes the file name
+// after `e`.
+fn handle_key_command_mode(
+  key: Key,
+  mut text: String,
+  files: &mut BufferList,
+  ses: &mut Session,
+  size: &Size,
+  fb: &mut Feedback,
+) -> io::Result<Mode> {
+  match key {
+    Key::Char('\n') => return match command::parse(&text) {
+      Ok(command) => run_command(command, files, ses, size, fb),
+      Err(msg) => {
+        fb.bell = true;
+        fb.message = Some(msg);
+        Ok(Mode::Normal)
+      }
+    },
+    Key::Esc => return Ok(Mode::Normal),
+    Key::Backspace if text.is_empty() => return Ok(Mode::Normal),
+    Key::Backspace => {
+      text.pop();
+    }
+    Key::Char('\t') => match text.strip_prefix("e ").map(|path| (path, complete_path(path))) {
+      Some((path, Some(completed))) if completed != path => text = format!("e {}", completed),
+      _ => fb.bell = true,
+    },
+    Key::Char(c) => text.push(c),
+    _ => (),
+  }
+  Ok(Mode::Command(text))
+}
+
+// Pipes whole lines through a shell command, replacing them with its output,
+// which is read like a file. The command runs with the terminal as it was
+// before the editor started, so Ctrl-C stops it, and a command that fails
+// leaves the lines as they were.
+fn filter_lines(
+  command: &str,
+  rows: Range<usize>,
+  cur: &mut Cursor,
+  buf: &mut Buffer,
+  size: &Size,
+  fb: &mut Feedback,
+) {
+  let mut input = String::new();
+  for line in buf.range(rows.clone()) {
+    input.push_str(line);
+    input.push('\n');
+  }
+  fb.redraw = true;
+  let output = with_terminal_restored(|| filter::pipe(&["sh", "-c", command], input.as_bytes()));
+  let lines = match output.and_then(|output| decode_text(command, &output, None)) {
+    Ok((lines, _)) => lines,
+    Err(err) => {
+      fb.message = Some(err.to_string());
+      return;
+    }
+  };
+  let n = lines.len();
+  buf.splice(rows.clone(), lines.lines);
+  buf.note(rows.start, rows.len(), n);
+  init_buffer_if_empty(buf);
+  move_cursor_to((rows.start.min(buf.len() - 1), 0), cur, buf, size);
+}
+
+// The command for the lines selected in visual mode is typed on the bottom
+// line.
+fn handle_key_filter_mode(
+  key: Key,
+  rows: Range<usize>,
+  mut text: String,
+  cur: &mut Cursor,
+  buf: &mut Buffer,
+  size: &Size,
+  fb: &mut Feedback,
+) -> Mode {
+  match key {
+    Key::Char('\n') if text.trim().is_empty() => fb.bell = true,
+    Key::Char('\n') => filter_lines(&text, rows, cur, buf, size, fb),
+    Key::Esc => (),
+    Key::Backspace if text.is_empty() => (),
+    Key::Backspace => {
+      text.pop();
+      return Mode::Filter(rows, text);
+    }
+    Key::Char(c) => {
+      text.push(c);
+      return Mode::Filter(rows, text);
+    }
+    _ => return Mode::Filter(rows, text),
+  }
+  Mode::Normal
+}
+
+// The cursor moves to the next match as the query is typed. Enter keeps it
+// there, and Escape puts it back where the search started.
+fn handle_key_search_mode(
+  key: Key,
+  mut query: String,
+  cur: &mut Cursor,
+  buf: &Buffer,
+  size: &Size,
+  ses: &mut Session,
+  fb: &mut Feedback,
+) -> io::Result<Mode> {
+  let origin = ses.search_origin;
+  match key {
+    // An empty search repeats the last one.
+    Key::Char('\n') if query.is_empty() => {
+      match search_forward(&ses.search, origin, buf, fb) {
+        Some(pos) => move_cursor_to(pos, cur, buf, size),
+        None => fb.bell = true,
+      }
+      return Ok(Mode::Normal);
+    }
+    Key::Char('\n') => {
+      ses.search = query;
+      return Ok(Mode::Normal);
+    }
+    Key::Esc => {
+      move_cursor_to(origin, cur, buf, size);
+      return Ok(Mode::Normal);
+    }
+    Key::Backspace if query.is_empty() => {
+      move_cursor_to(origin, cur, buf, size);
+      return Ok(Mode::Normal);
+    }
+    Key::Backspace => {
+      query.pop();
+    }
+    Key::Char(c) => query.push(c),
+    _ => return Ok(Mode::Search(query)),
+  }
+  search_as_typed(&query, origin, cur, buf, size, fb);
+  Ok(Mode::Search(query))
+}
+
+// Moves the cursor to the first match of the query typed so far, or back to
+// where the search started.
+fn search_as_typed(query: &str, origin: (usize, usize), cur: &mut Cursor, buf: &Buffer, size: &Size, fb: &mut Feedback) {
+  match search_forward(query, origin, buf, fb) {
+    Some(pos) => move_cursor_to(pos, cur, buf, size),
+    None => {
+      move_cursor_to(origin, cur, buf, size);
+      fb.bell = !query.is_empty();
+    }
+  }
+}
+
+// Describes the buffer as it would be saved.
+fn buffer_stats(buf: &Buffer, format: FileFormat) -> String {
+  let text = match buffer_text(buf.iter(), format) {
+    Ok(text) => text,
+    Err(err) => return err.to_string(),
+  };
+  let encoding = match format.encoding {
+    Encoding::Utf8 if text.is_ascii() => "ASCII",
+    encoding => encoding.name(),
+  };
+  let bom = if format.bom { " with BOM" } else { "" };
+  let lines = if buf.len() == 1 { "line" } else { "lines" };
+  let final_newline = if format.final_newline { "" } else { ", no final newline" };
+  format!(
+    "{} bytes, {} {}, {}{}, {}{}, SHA-256 {}",
+    text.len(), buf.len(), lines, encoding, bom, format.line_endings(), final_newline,
+    sha256::hex_digest(&text),
+  )
+}
+
+// A mark named by a capital letter is taken from any other file it was in,
+// and remembered at once.
+fn set_global_mark(name: char, files: &mut BufferList) -> io::Result<()> {
+  for file in files.files.iter_mut() {
+    file.marks.remove(name);
+  }
+  let file = files.current_mut();
+  file.marks.set(name, (file.cur.row, file.cur.col));
+  remember_positions(file)
+}
+
+// Goes to the file with a mark named by a capital letter, opening it if it
+// was remembered from another session.
+fn goto_global_mark(name: char, files: &mut BufferList, size: &Size, fb: &mut Feedback) -> io::Result<()> {
+  match files.files.iter().position(|file| file.marks.get(name).is_some()) {
+    Some(i) => files.current = i,
+    None => match remembered_global_mark(name) {
+      Some((path, _)) if path.exists() => files.open(&path.to_string_lossy())?,
+      Some((path, _)) => {
+        fb.bell = true;
+        fb.message = Some(format!("{} is gone", path.display()));
+        return Ok(());
+      }
+      None => {
+        fb.bell = true;
+        return Ok(());
+      }
+    },
+  }
+  let OpenFile{ref buf, ref mut cur, ref marks, ..} = *files.current_mut();
+  move_cursor_to_jump(marks.get(name), cur, buf, size, fb);
+  Ok(())
+}
+
+fn handle_key_pending_mode(first: char, key: Key, files: &mut BufferList, size: &Size, fb: &mut Feedback) -> io::Result<Mode> {
+  match (first, key) {
+    ('m', Key::Char(c)) if c.is_ascii_uppercase() => set_global_mark(c, files)?,
+    ('\'', Key::Char(c)) if c.is_ascii_uppercase() => goto_global_mark(c, files, size, fb)?,
+    _ => return Ok(handle_key_pending_mode_in_file(first, key, files.current_mut(), size, fb)),
+  }
+  Ok(Mode::Normal)
+}
+
+fn handle_key_pending_mode_in_file(first: char, key: Key, file: &mut OpenFile, size: &Size, fb: &mut Feedback) -> Mode {
+  let OpenFile{ref mut buf, ref mut cur, ref mut marks, ..} = *file;
+  match (first, key) {
+    ('m', Key::Char(c)) if c.is_ascii_alphabetic() => marks.set(c, (cur.row, cur.col)),
+    ('\'', Key::Char(c)) => move_cursor_to_jump(marks.get(c), cur, buf, size, fb),
+    (_, Key::Esc) => (),
+    _ => fb.bell = true,
+  }
+  Mode::Normal
+}
+
+// Keys are read on their own thread, so that the editor can also wake up
+// without one.
+fn read_keys_in_background<R: io::Read + Send + 'static>(input: R) -> mpsc::Receiver<io::Result<Key>> {
+  let (tx, rx) = mpsc::channel();
+  thread::spawn(move || {
+    for res in input.keys() {
+      if tx.send(res).is_err() {
+        break;
+      }
+    }
+  });
+  rx
+}
+
+// The editor without its terminal: it takes keys one at a time and draws the
+// current file on whatever screen it is given, so that it can be embedded in
+// another program or driven by tests.
+pub struct Editor {
+  files: BufferList,
+  ses: Session,
+  mode: Mode,
+  fb: Feedback,
+  // the size of the whole screen, status line included
+  screen: Size,
+  stamps: Vec<Option<SystemTime>>,
+  // whether the last key asked to suspend the editor
+  suspend: bool,
+  // a message on the bottom line, and when it was shown
+  echo: Option<(String, Instant)>,
+  // when the file was last checked for changes on disk
+  checked: Instant,
+  // when the last key was typed, and whether the idle work was done since
+  typed: Instant,
+  idle: bool,
+}
+
+impl Editor {
+  fn new(mut files: BufferList, screen: Size) -> Self {
+    let stamps = config::stamps(Path::new(&files.current().path));
+    let echo = files.current_mut().warning.take().map(|msg| (msg, Instant::now()));
+    Editor{
+      files,
+      ses: Session::new(),
+      mode: Mode::Normal,
+      fb: Feedback::new(),
+      screen,
+      stamps,
+      suspend: false,
+      echo,
+      checked: Instant::now(),
+      typed: Instant::now(),
+      idle: false,
+    }
+  }
+
+  // Opens files the way the command line does, or an unnamed file without
+  // any, for a screen of the given size.
+  pub fn open(args: &[String], rows: usize, cols: usize) -> io::Result<Self> {
+    let screen = Size::new(rows, cols);
+    Ok(Editor::new(open_files(args, None, false, screen)?, screen))
+  }
+
+  pub fn resize(&mut self, rows: usize, cols: usize) {
+    self.screen = Size::new(rows, cols);
+  }
+
+  fn text_size(&self) -> Size {
+    let size = text_area_size(self.screen, panel_rows(&self.mode, &self.ses));
+    let size = with_tree(with_gutter(size, self.files.current()), &self.ses);
+    with_preview(size, &self.ses, self.files.current())
+  }
+
+  // Shows a message on the bottom line until the next key or until
+  // `MESSAGE_TIMEOUT` has passed.
+  pub fn notify(&mut self, msg: &str) {
+    self.echo = Some((msg.to_string(), Instant::now()));
+  }
+
+  // Handles a key, returning false once the editor quits.
+  pub fn handle_key(&mut self, key: Key) -> io::Result<bool> {
+    // Escape typed just before another key can arrive together with it, and
+    // be read as Alt and the key. Unless that is bound, it is taken as the two
+    // keys.
+    if let Key::Alt(c) = key {
+      if self.escape_before(c) {
+        return Ok(self.handle(Some(Key::Esc))? && self.handle(Some(Key::Char(c)))?);
+      }
+    }
+    self.handle(Some(key))
+  }
+
+  fn escape_before(&self, c: char) -> bool {
+    let cfg = config::get();
+    match self.mode {
+      Mode::Insert => cfg.insert_keys.get(&Key::Alt(c)).is_none(),
+      Mode::Normal => cfg.normal_keys.lookup(&[Key::Alt(c)]) == Lookup::Unbound,
+      Mode::Keys(_, ref keys, _) => {
+        let keys: Vec<Key> = keys.iter().copied().chain(Some(Key::Alt(c))).collect();
+        cfg.normal_keys.lookup(&keys) == Lookup::Unbound
+      }
+      _ => false,
+    }
+  }
+
+  // Whether the editor has quit, which a key sequence that times out can do
+  // from `tick`
+  pub fn has_quit(&self) -> bool {
+    matches!(self.mode, Mode::Quit)
+  }
+
+  // Handles a key, or the key timeout passing without one.
+  fn handle(&mut self, key: Option<Key>) -> io::Result<bool> {
+    self.echo = None;
+    if key.is_some() {
+      (self.typed, self.idle) = (Instant::now(), false);
+    }
+    let size = self.text_size();
+    let screen = self.screen;
+    let Editor{ref mut files, ref mut ses, ref mut mode, ref mut fb, ref mut suspend, ..} = *self;
+    let current = files.current;
+    let jump = jump_start(mode, key, &files.current().cur, ses);
+    let next = match (std::mem::replace(mode, Mode::Normal), key) {
+      (Mode::Keys(pre, keys, _), key) => handle_key_sequence(pre, keys, key, files, ses, &size, fb),
+      // nothing else waits for the key timeout
+      (mode, None) => Ok(mode),
+      (Mode::Normal, key) => handle_key_sequence(Prefix::new(), Vec::new(), key, files, ses, &size, fb),
+      (Mode::Count(count), Some(key)) => handle_key_count_mode(key, count, files, ses, &size, fb),
+      (Mode::Command(text), Some(key)) => handle_key_command_mode(key, text, files, ses, &size, fb),
+      (Mode::SaveAs(name, quit), Some(key)) =>
+        Ok(handle_key_save_as_mode(key, name, quit, files.current_mut(), fb)),
+      (Mode::CreateDir(path, quit), Some(key)) =>
+        Ok(handle_key_create_dir_mode(key, path, quit, files.current_mut(), fb)),
+      (Mode::Overwrite(path, quit), Some(key)) =>
+        Ok(handle_key_overwrite_mode(key, path, quit, files.current_mut(), fb)),
+      (Mode::Changed(quit), Some(key)) => handle_key_changed_mode(key, quit, files.current_mut(), fb),
+      (Mode::Pending(first), Some(key)) => handle_key_pending_mode(first, key, files, &size, fb),
+      (Mode::Diff(view), Some(key)) => Ok(handle_key_diff_mode(key, view, &screen, fb)),
+      (Mode::Visual(anchor), Some(key)) =>
+        handle_key_visual_mode(key, anchor, false, files.current_mut(), ses, &size, fb),
+      (Mode::VisualBlock(anchor), Some(key)) =>
+        handle_key_visual_mode(key, anchor, true, files.current_mut(), ses, &size, fb),
+      (Mode::Terminal(literal), Some(key)) => handle_key_terminal_mode(key, literal, ses),
+      (Mode::Tree, Some(key)) => handle_key_tree_mode(key, files, ses, fb),
+      (Mode::TreeName(from, name), Some(key)) => Ok(handle_key_tree_name_mode(key, from, name, files, ses, fb)),
+      (Mode::TreeDelete(path), Some(key)) => Ok(handle_key_tree_delete_mode(key, path, ses, fb)),
+      (Mode::Buffers(query, selected), Some(key)) =>
+        Ok(handle_key_buffers_mode(key, query, selected, files, fb)),
+      (Mode::BookmarkList(selected), Some(key)) =>
+        Ok(handle_key_bookmark_list_mode(key, selected, files.current_mut(), &size, fb)),
+      (mode, Some(key)) => {
+        let OpenFile{ref mut buf, ref mut cur, ..} = *files.current_mut();
+        match mode {
+          Mode::Insert => handle_key_insert_mode(key, cur, buf, &size, fb),
+          Mode::Literal(code) => handle_key_literal_mode(key, code, cur, buf, &size, fb),
+          Mode::Digraph(first) => handle_key_digraph_mode(key, first, cur, buf, &size, fb),
+          Mode::Dynamic => handle_key_dynamic_mode(key, cur, buf, &size, fb),
+          Mode::Expression(text) => handle_key_expression_mode(key, text, cur, buf, &size, fb),
+          Mode::Filter(rows, text) => Ok(handle_key_filter_mode(key, rows, text, cur, buf, &size, fb)),
+          // any key stops following, or closes a note
+          Mode::Follow(_) | Mode::Note(_) => Ok(Mode::Normal),
+          Mode::TodoList(rows, selected) =>
+            handle_key_todo_list_mode(key, rows, selected, cur, buf, &size, fb),
+          Mode::Search(query) => handle_key_search_mode(key, query, cur, buf, &size, ses, fb),
+          _ => Ok(Mode::Quit),
+        }
+      }
+    };
+    // A key that fails, like one that spills the clipboard to a full disk,
+    // is reported and the session goes on.
+    *mode = next.unwrap_or_else(|err| {
+      fb.bell = true;
+      fb.message = Some(err.to_string());
+      Mode::Normal
+    });
+    if let Mode::Quit = *mode {
+      // what is remembered of a file with unsaved changes would be for lines
+      // it doesn't have
+      for file in files.files.iter_mut() {
+        let _ = remove_swap(&file.path);
+        if !file.modified() {
+          file.adjust_marks();
+          let _ = remember_positions(file);
+        }
+      }
+      return Ok(false);
+    }
+    if let Mode::Suspend = *mode {
+      *mode = Mode::Normal;
+      *suspend = true;
+    }
+    if !is_inserting(mode) {
+      if let Some(block) = ses.block_insert.take() {
+        finish_block_insert(block, &mut files.files[current].buf);
+      }
+    }
+    undo_read_only_edit(&mut files.files[current], mode, fb);
+    if !is_inserting(mode) {
+      let OpenFile{ref buf, ref mut timeline, ..} = files.files[current];
+      timeline.record(buf, buf.generation);
+    }
+    reveal_cursor(files.current_mut(), &size);
+    files.mark_recent();
+    let same_file = files.current == current;
+    let file = files.current_mut();
+    if let Some(from) = jump {
+      if same_file && (file.cur.row, file.cur.col) != from {
+        file.jumps.push(from);
+      }
+    }
+    files.files[current].adjust_marks();
+    if let Some(msg) = fb.message.take().or_else(|| files.current_mut().warning.take()) {
+      self.notify(&msg);
+    }
+    Ok(true)
+  }
+
+  // Takes text the terminal marked as pasted. Insert mode inserts it in one
+  // go; any other mode takes it as typed keys.
+  pub fn paste(&mut self, text: &str) -> io::Result<bool> {
+    self.echo = None;
+    (self.typed, self.idle) = (Instant::now(), false);
+    let size = self.text_size();
+    let Editor{ref mut files, ref mut mode, ref mut ses, ref mut fb, ..} = *self;
+    let file = files.current_mut();
+    // A prompt takes the text as one line.
+    let line = || text.chars().filter(|&c| !c.is_control() || c == '\t').collect::<String>();
+    match *mode {
+      Mode::Insert => fb.edit(insert_text(text, &mut file.cur, &mut file.buf, &size)),
+      // a count or key sequence typed before the paste is dropped
+      Mode::Normal | Mode::Count(_) | Mode::Keys(..) => {
+        *mode = Mode::Normal;
+        fb.edit(insert_text(text, &mut file.cur, &mut file.buf, &size));
+      }
+      Mode::Command(ref mut prompt)
+      | Mode::Expression(ref mut prompt)
+      | Mode::Filter(_, ref mut prompt)
+      | Mode::SaveAs(ref mut prompt, _) => prompt.push_str(&line()),
+      Mode::Search(ref mut query) => {
+        query.push_str(&line());
+        search_as_typed(query, ses.search_origin, &mut file.cur, &file.buf, &size, fb);
+      }
+      Mode::Terminal(_) => if let Some(ref mut shell) = ses.shell {
+        shell.send(text.as_bytes())?;
+      },
+      _ => fb.bell = true,
+    }
+    undo_read_only_edit(file, mode, fb);
+    file.adjust_marks();
+    reveal_cursor(file, &size);
+    Ok(true)
+  }
+
+  // Applies any changes made to the config files since they were last read,
+  // returning whether there were any. A config with errors is reported and
+  // the current settings are kept. A changed project config is left out until
+  // it is trusted again, which is reported too.
+  fn reload_config_if_changed(&mut self) -> bool {
+    let path = PathBuf::from(&self.files.current().path);
+    let stamps = config::stamps(&path);
+    if stamps == self.stamps {
+      return false;
+    }
+    self.stamps = stamps;
+    match config::reload(&path) {
+      Ok((cfg, untrusted)) => {
+        config::set(cfg);
+        if let Some(project) = untrusted {
+          let msg = format!("{} is not used until it is trusted, the next time Red starts", project.display());
+          self.notify(&msg);
+        }
+      }
+      Err(err) => self.notify(&err.to_string()),
+    }
+    true
+  }
+
+  // Does what is due without a key, every `TICK_INTERVAL`: showing what the
+  // shell of `:term` wrote, clearing an old message, reading changed config
+  // files, warning when another program changes the file, and reading the
+  // lines added to a followed file. Returns whether there is anything new to
+  // draw.
+  pub fn tick(&mut self) -> io::Result<bool> {
+    let shell = self.read_shell();
+    let idle = self.idle_if_due();
+    Ok(self.tick_file()? || shell || idle)
+  }
+
+  // Once no key has been typed for `idle_time`, writes the swap files and
+  // remembers the marks and views of the files without unsaved changes, and
+  // drops what was found for highlighting the files not shown, which is
+  // found again when they are. A swap file that can't be written is
+  // reported, returning true.
+  fn idle_if_due(&mut self) -> bool {
+    let secs = config::get().idle_time;
+    if self.idle || secs == 0 || self.typed.elapsed() < Duration::from_secs(secs as u64) {
+      return false;
+    }
+    self.idle = true;
+    let current = self.files.current;
+    let mut failed = None;
+    for (i, file) in self.files.files.iter_mut().enumerate() {
+      if let Err(err) = write_swap(file) {
+        failed = Some(format!("Can't write the swap file of {}: {}", file.path, err));
+      }
+      if !file.modified() {
+        file.adjust_marks();
+        let _ = remember_positions(file);
+      }
+      if i != current {
+        file.annotations = Annotations::default();
+        file.buf.unload(0..0);
+      }
+    }
+    match failed {
+      Some(msg) => {
+        self.notify(&msg);
+        true
+      }
+      None => false,
+    }
+  }
+
+  // Takes in the output of the shell, and closes its panel once it exits.
+  fn read_shell(&mut self) -> bool {
+    let shell = match self.ses.shell {
+      Some(ref mut shell) => shell,
+      None => return false,
+    };
+    if shell.read() {
+      return true;
+    }
+    if !shell.has_exited() {
+      return false;
+    }
+    self.ses.shell = None;
+    if let Mode::Terminal(_) = self.mode {
+      self.mode = Mode::Normal;
+    }
+    self.notify("The shell exited");
+    true
+  }
+
+  fn tick_file(&mut self) -> io::Result<bool> {
+    // An operator waits for its motion as long as it takes, and only the keys
+    // of the motion time out.
+    if let Mode::Keys(_, ref keys, typed) = self.mode {
+      let timeout = config::get().key_timeout;
+      if !keys.is_empty() && timeout > 0 && typed.elapsed() >= Duration::from_millis(timeout as u64) {
+        self.handle(None)?;
+        return Ok(true);
+      }
+    }
+    if let Some((_, shown)) = self.echo {
+      if shown.elapsed() >= MESSAGE_TIMEOUT {
+        self.echo = None;
+        return Ok(true);
+      }
+    }
+    // The config files and the file being edited are checked for changes
+    // less often.
+    let due = self.checked.elapsed() >= DISK_CHECK_INTERVAL;
+    if due {
+      self.checked = Instant::now();
+      if self.reload_config_if_changed() {
+        return Ok(true);
+      }
+    }
+    let size = self.text_size();
+    let offset = match self.mode {
+      Mode::Follow(ref mut offset) => offset,
+      _ => {
+        if !due {
+          return Ok(false);
+        }
+        let file = self.files.current_mut();
+        let stamp = disk_stamp(&file.path);
+        if file.path.is_empty() || stamp == file.stamp || stamp == file.warned {
+          return Ok(false);
+        }
+        file.warned = stamp;
+        let msg = format!("{} changed on disk; :e! reads it again", file.path);
+        self.notify(&msg);
+        return Ok(true);
+      }
+    };
+    let modified = self.files.current().modified();
+    let OpenFile{ref path, ref mut buf, ref mut cur, ref mut saved, ref mut stamp, ref mut length, ..} =
+      *self.files.current_mut();
+    // Changes to a followed file are expected.
+    *stamp = disk_stamp(path);
+    let added = match follow_file(path, offset, modified, cur, buf, &size) {
+      Ok(added) => {
+        *length = *offset;
+        added
+      }
+      Err(err) => {
+        let msg = format!("Stopped following {}: {}", path, err);
+        self.mode = Mode::Normal;
+        self.notify(&msg);
+        return Ok(true);
+      }
+    };
+    // Lines read from the file leave an unmodified buffer unmodified.
+    if added && !modified {
+      *saved = Some(buf.generation);
+    }
+    self.files.current_mut().adjust_marks();
+    Ok(added)
+  }
+
+  // Draws the current file and the bottom line, after ringing the bell if
+  // the last key had no effect.
+  pub fn draw(&mut self, scr: &mut Screen) -> io::Result<()> {
+    if self.fb.redraw {
+      scr.forget();
+    }
+    if self.fb.bell {
+      ring_bell(scr)?;
+    }
+    scr.set_cursor_shape(cursor_shape(&self.mode))?;
+    if let Mode::Diff(ref view) = self.mode {
+      self.fb = Feedback::new();
+      return update_diff_screen(scr, view, &self.screen);
+    }
+    let modified = self.files.current().modified();
+    let panel = panel_rows(&self.mode, &self.ses);
+    let full = with_gutter(text_area_size(self.screen, panel), self.files.current());
+    let beside = with_tree(full, &self.ses);
+    let size = with_preview(beside, &self.ses, self.files.current());
+    if size != beside {
+      let file = self.files.current();
+      let width = self.screen.cols.saturating_sub(size.left + size.cols + 3);
+      let current = (self.files.current, file.buf.generation, width);
+      match self.ses.rendered {
+        Some(Preview{file, generation, width, ..}) if (file, generation, width) == current => (),
+        _ => self.ses.rendered = Some(Preview{
+          file: current.0,
+          generation: current.1,
+          width,
+          rows: markdown::render(&file.buf, width, text_cells),
+        }),
+      }
+    }
+    let file = self.files.current_mut();
+    file.update_annotations();
+    let OpenFile{ref path, ref buf, ref mut cur, ref annotations, format, ref scratch, ref notes, ..} = *file;
+    let prompt = match self.mode {
+      Mode::Expression(ref text) => Some(format!("={}", text)),
+      Mode::Filter(_, ref text) => Some(format!("!{}", text)),
+      Mode::Follow(_) => Some(String::from(FOLLOW_PROMPT)),
+      Mode::Search(ref query) => Some(format!("/{}", query)),
+      Mode::Command(ref text) => Some(format!(":{}", text)),
+      Mode::Buffers(ref query, _) => Some(format!("{}{}", BUFFERS_PROMPT, query)),
+      Mode::TreeName(None, ref name) => Some(format!("{}{}", NEW_FILE_PROMPT, name)),
+      Mode::TreeName(Some(_), ref name) => Some(format!("{}{}", RENAME_PROMPT, name)),
+      Mode::TreeDelete(ref path) => Some(delete_prompt(path)),
+      Mode::SaveAs(ref name, _) => Some(format!("{}{}", SAVE_AS_PROMPT, name)),
+      Mode::CreateDir(ref path, _) => Some(create_dir_prompt(path)),
+      Mode::Overwrite(ref path, _) => Some(overwrite_prompt(path)),
+      Mode::Changed(_) => Some(changed_prompt(path)),
+      _ => None,
+    };
+    align_cursor(cur, buf, &size);
+    // of a large file, only the lines around those shown are kept
+    let keep = cur.top.saturating_sub(size.rows)..cur.top + 2 * size.rows;
+    let sel = match self.mode {
+      Mode::Visual(anchor) => Some(Selection::new(anchor, cur)),
+      Mode::VisualBlock(anchor) => Some(Selection::block(anchor, cur)),
+      _ => None,
+    };
+    let status = StatusLine{
+      row: size.rows + panel + 1,
+      cols: full.cols,
+      prompt: prompt.is_some(),
+      text: match (prompt, &self.echo) {
+        (Some(prompt), _) => prompt,
+        (None, Some((message, _))) => message.clone(),
+        (None, None) => status_text(&self.mode, scratch.as_ref().unwrap_or(path), modified, cur, full.cols),
+      },
+    };
+    update_screen(scr, cur, buf, sel.as_ref(), annotations, &size, &status)?;
+    if let Some(text) = preedit_text(&self.mode) {
+      write_preedit_to_screen(scr, &text, cur, buf, &size)?;
+    }
+    if let (true, Some(preview)) = (size != beside, &self.ses.rendered) {
+      write_preview_to_screen(scr, &preview.rows, cur.top, &size, self.screen.cols)?;
+    }
+    if let (true, Some(tree)) = (beside != full, &mut self.ses.tree) {
+      write_tree_to_screen(scr, tree, matches!(self.mode, Mode::Tree), &size, self.screen.cols)?;
+    }
+    if let Mode::TodoList(ref rows, selected) = self.mode {
+      write_todo_list_to_screen(scr, cur, buf, rows, selected, &size)?;
+    } else if let Mode::BookmarkList(selected) = self.mode {
+      write_bookmark_list_to_screen(scr, cur, buf, &annotations.bookmarks, selected, &size)?;
+    } else if let Some((row, text)) = note_shown(&self.mode, notes) {
+      write_note_to_screen(scr, row, text, &size)?;
+    } else if let Mode::Buffers(ref query, selected) = self.mode {
+      write_buffer_list_to_screen(scr, &self.files, query, selected, &size)?;
+    } else if let Some(ref mut shell) = self.ses.shell {
+      let (rows, cols) = terminal_size(self.screen.cols);
+      shell.resize(rows, cols)?;
+      write_terminal_to_screen(scr, shell, matches!(self.mode, Mode::Terminal(_)), &size)?;
+    } else if self.ses.hex {
+      write_hex_panel_to_screen(scr, cur, buf, format, &size)?;
+    }
+    self.files.current_mut().buf.unload(keep);
+    scr.flush()?;
+    self.fb = Feedback::new();
+    Ok(())
+  }
+
+  // Follows the current file from its end, like `F`, until the next key.
+  pub fn follow(&mut self) -> io::Result<()> {
+    let size = self.text_size();
+    let OpenFile{ref buf, ref mut cur, length, ..} = *self.files.current_mut();
+    self.mode = start_following(length, cur, buf, &size);
+    Ok(())
+  }
+
+  // Whether the last key asked for the editor to be suspended, which is up to
+  // the program that owns the terminal.
+  pub fn take_suspend(&mut self) -> bool {
+    mem::take(&mut self.suspend)
+  }
+
+  pub fn path(&self) -> &str {
+    &self.files.current().path
+  }
+
+  // A copy of the lines of the current file
+  pub fn lines(&self) -> Vec<String> {
+    self.files.current().buf.to_vec()
+  }
+
+  // The row and column of the cursor, from zero
+  pub fn cursor(&self) -> (usize, usize) {
+    let cur = &self.files.current().cur;
+    (cur.row, cur.col)
+  }
+
+  pub fn modified(&self) -> bool {
+    self.files.current().modified()
+  }
+
+  // Takes a request from `red --remote`: files to open, named as on the
+  // command line, and commands after `--command` to run as if typed after
+  // `:`. Whatever was being typed in the text is ended first, but a prompt
+  // waiting for an answer turns the request away. Returns the message the
+  // commands left, or why the request failed.
+  pub fn remote(&mut self, args: &[String]) -> Result<String, String> {
+    match self.mode {
+      Mode::Normal | Mode::Insert | Mode::Visual(_) | Mode::VisualBlock(_) | Mode::Count(_) | Mode::Keys(..)
+      | Mode::Tree | Mode::Note(_) =>
+        self.mode = Mode::Normal,
+      _ => return Err(String::from("red is waiting for an answer to a prompt")),
+    }
+    let mut paths = Vec::new();
+    let mut commands = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+      match arg.as_str() {
+        "--command" => commands.push(args.next().ok_or("--command needs a command")?.clone()),
+        _ => paths.push(arg.clone()),
+      }
+    }
+    for arg in parse_file_args(&paths)? {
+      self.files.open(&arg.path).map_err(|err| format!("{}: {}", arg.path, err))?;
+      let size = self.text_size();
+      if let Some(pos) = arg.pos {
+        move_cursor_to_arg(pos, self.files.current_mut(), &size);
+      }
+    }
+    self.files.mark_recent();
+    self.echo = None;
+    for command in commands {
+      self.mode = Mode::Command(command);
+      if !self.handle(Some(Key::Char('\n'))).map_err(|err| err.to_string())? {
+        break;
+      }
+    }
+    Ok(self.echo.as_ref().map_or_else(String::new, |(msg, _)| msg.clone()))
+  }
+}
+
+// How often the views are woken up without a key, to check the terminal size
+// and for anything else that is due
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+// How long a message stays on the bottom line without a key
+const MESSAGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How often a file is checked for changes made by another program
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// Everything the editor waits for arrives on one channel, from threads of its
+// own, so that waiting for one never holds up the others.
+enum Event {
+  Key(io::Result<Key>),
+  Paste(String),
+  // the terminal has no more keys
+  Closed,
+  Resize(Size),
+  Tick,
+  // from the engine running the editor
+  Output(Output),
+}
+
+fn read_events_in_background<R: io::Read + Send + 'static>(input: R, tx: mpsc::Sender<Event>) {
+  let keys = tx.clone();
+  thread::spawn(move || {
+    // the bytes pasted so far, between the terminal's paste markers, kept as
+    // they came rather than as keys, which would lose tabs, escapes and
+    // control characters
+    let mut pasted: Option<Vec<u8>> = None;
+    for res in input.events_and_raw() {
+      let event = match (res, pasted.as_mut()) {
+        (Ok((termion::event::Event::Unsupported(ref seq), _)), _) if seq == screen::PASTE_START => {
+          pasted = Some(Vec::new());
+          continue;
+        }
+        (Ok((termion::event::Event::Unsupported(ref seq), _)), Some(_)) if seq == screen::PASTE_END =>
+          Event::Paste(String::from_utf8_lossy(&pasted.take().unwrap_or_default()).into_owned()),
+        (Ok((_, raw)), Some(bytes)) => {
+          bytes.extend(raw);
+          continue;
+        }
+        (Ok((termion::event::Event::Key(key), _)), None) => Event::Key(Ok(key)),
+        (Err(err), _) => Event::Key(Err(err)),
+        _ => continue,
+      };
+      if keys.send(event).is_err() {
+        return;
+      }
+    }
+    let _ = keys.send(Event::Closed);
+  });
+  thread::spawn(move || {
+    let mut size = get_screen_size().ok();
+    loop {
+      thread::sleep(TICK_INTERVAL);
+      let new_size = get_screen_size().ok();
+      if new_size != size {
+        size = new_size;
+        if let Some(size) = size {
+          if tx.send(Event::Resize(size)).is_err() {
+            return;
+          }
+        }
+      }
+      if tx.send(Event::Tick).is_err() {
+        return;
+      }
+    }
+  });
+}
+
+fn edit_buffers(
+  files: BufferList,
+  follow: bool,
+  listen: Option<&str>,
+  script: Option<Vec<Key>>,
+  notice: Option<&str>,
+  prof: &mut Profile,
+) -> io::Result<()> {
+  let start = Instant::now();
+  // the socket is taken before the terminal, so that one in use stops the
+  // editor before it starts
+  let socket = match listen {
+    Some(path) => Some(remote::bind(Path::new(path))?),
+    None => None,
+  };
+  let mut scr = init_screen()?;
+  let mut editor = Editor::new(files, get_screen_size()?);
+  if let Some(msg) = notice {
+    editor.notify(msg);
+  }
+  scr.set_bracketed_paste(true)?;
+  if follow {
+    editor.follow()?;
+  }
+  let (tx, events) = mpsc::channel();
+  let engine = {
+    let tx = tx.clone();
+    Engine::start(editor, move |output| tx.send(Event::Output(output)).is_ok())
+  };
+  // the keys of a script are played before any typed
+  if let Some(keys) = script {
+    engine.send(Request::Script(keys));
+  }
+  let _server = socket.map(|socket| {
+    let client = engine.client();
+    socket.serve(move |request| {
+      let args = request.args.clone();
+      client.send(Request::Remote(args, Box::new(move |res| request.reply(res))))
+    })
+  });
+  // Keys come from the terminal even when stdin was piped to the editor.
+  if termion::is_tty(&io::stdin()) {
+    read_events_in_background(PausableInput(io::stdin()), tx);
+  } else {
+    read_events_in_background(PausableInput(termion::get_tty()?), tx);
+  }
+  let mut res = Ok(());
+  for event in &events {
+    let request = match event {
+      Event::Key(Ok(key)) => Request::Key(key),
+      // Input that could not be decoded into a key, such as a partial
+      // sequence from an input method, is dropped instead of ending the
+      // session.
+      Event::Key(Err(ref err)) if err.kind() == io::ErrorKind::Other => continue,
+      Event::Key(Err(err)) => {
+        res = Err(err);
+        break;
+      }
+      Event::Paste(text) => Request::Paste(text),
+      Event::Closed => break,
+      Event::Resize(size) => Request::Resize(size.rows, size.cols),
+      Event::Tick => Request::Tick,
+      Event::Output(Output::Frame(bytes)) => {
+        scr.write_all(&bytes)?;
+        scr.flush()?;
+        if prof.first_render.is_zero() {
+          prof.first_render = start.elapsed();
+        }
+        continue;
+      }
+      Event::Output(Output::Suspend) => {
+        suspend(&mut scr)?;
+        Request::Redraw
+      }
+      Event::Output(Output::Quit) => break,
+      Event::Output(Output::Failed(err)) => {
+        res = Err(err);
+        break;
+      }
+    };
+    // An engine that stopped sent why before it did.
+    engine.send(request);
+  }
+  prof.keys = engine.finish();
+  // the last frame, which gives the terminal back the cursor it had
+  for event in events.try_iter() {
+    if let Event::Output(Output::Frame(bytes)) = event {
+      scr.write_all(&bytes)?;
+    }
+  }
+  scr.flush()?;
+  res
+}
+
+// Side-by-side diff view
+fn write_diff_half_to_screen(
+  scr: &mut Screen,
+  line: &Line,
+  changed: Range<usize>,
+  colors: &[u8],
+  left: usize,
+  width: usize,
+) -> io::Result<()> {
+  let mut x = 0;
+  for (i, c) in line.chars().enumerate() {
+    let end = x + char_width(c);
+    if end > left + width {
+      break;
+    }
+    if x >= left {
+      if changed.contains(&i) {
+        scr.write_all(colors)?;
+      } else {
+        set_normal_colors(scr)?;
+      }
+      write_char_to_screen(scr, c)?;
+    }
+    x = end;
+  }
+  set_normal_colors(scr)
+}
+
+fn is_diff_change(row: (Option<usize>, Option<usize>), a: &Buffer, b: &Buffer) -> bool {
+  match row {
+    (Some(i), Some(j)) => a[i] != b[j],
+    _ => true,
+  }
+}
+
+fn update_diff_screen(scr: &mut Screen, view: &DiffView, size: &Size) -> io::Result<()> {
+  let DiffView{ref a, ref b, ref rows, ref cur} = *view;
+  scr.begin_frame();
+  let width = size.cols.saturating_sub(1) / 2;
+  for (r, &row) in rows.iter().skip(cur.top).take(size.rows).enumerate() {
+    let (changed_a, changed_b) = match row {
+      (Some(i), Some(j)) => diff::changed_chars(&a[i], &b[j]),
+      _ => (0..usize::MAX, 0..usize::MAX),
+    };
+    let screen_row = (r + 1) as u16;
+    scr.start_row(r)?;
+    if let Some(i) = row.0 {
+      write_diff_half_to_screen(scr, &a[i], changed_a, &SET_DELETED_COLORS, cur.left, width)?;
+    }
+    write!(scr, "{}", termion::cursor::Goto(width as u16 + 1, screen_row))?;
+    set_invisible_colors(scr)?;
+    write!(scr, "\u{2502}")?;
+    set_normal_colors(scr)?;
+    if let Some(j) = row.1 {
+      write!(scr, "{}", termion::cursor::Goto(width as u16 + 2, screen_row))?;
+      write_diff_half_to_screen(scr, &b[j], changed_b, &SET_INSERTED_COLORS, cur.left, width)?;
+    }
+  }
+  scr.end_row();
+  write!(scr, "{}", termion::cursor::Goto(1, 1))?;
+  scr.flush()
+}
+
+// Two files next to each other with their differences highlighted. Both
+// sides scroll together, so the view only needs one cursor.
+struct DiffView {
+  a: Buffer,
+  b: Buffer,
+  rows: Vec<(Option<usize>, Option<usize>)>,
+  cur: Cursor,
+}
+
+impl DiffView {
+  fn new(a: Buffer, b: Buffer) -> Self {
+    let rows = diff::side_by_side(&diff::diff_lines(&a.to_vec(), &b.to_vec()));
+    DiffView{a, b, rows, cur: Cursor::new()}
+  }
+}
+
+// Scrolls the diff view, returning whether it moved.
+fn scroll_diff_view(key: Key, view: &mut DiffView, size: &Size) -> bool {
+  let DiffView{ref a, ref b, ref rows, ref mut cur} = *view;
+  let last = rows.len().saturating_sub(1);
+  let before = (cur.top, cur.left);
+  match key {
+    Key::Char('j') | Key::Down => cur.top = (cur.top + 1).min(last),
+    Key::Char('k') | Key::Up => cur.top = cur.top.saturating_sub(1),
+    Key::Char(' ') | Key::Ctrl('f') | Key::PageDown => cur.top = (cur.top + size.rows).min(last),
+    Key::Ctrl('b') | Key::PageUp => cur.top = cur.top.saturating_sub(size.rows),
+    Key::Char('l') | Key::Right => cur.left += 1,
+    Key::Char('h') | Key::Left => cur.left = cur.left.saturating_sub(1),
+    Key::Char('g') => cur.top = 0,
+    Key::Char('G') => cur.top = last,
+    Key::Char('n') => {
+      let start = (cur.top..rows.len()).find(|&i| !is_diff_change(rows[i], a, b));
+      if let Some(i) = start.and_then(|start| {
+        (start..rows.len()).find(|&i| is_diff_change(rows[i], a, b))
+      }) {
+        cur.top = i;
+      }
+    }
+    Key::Char('N') => {
+      let end = (0..cur.top).rev().find(|&i| is_diff_change(rows[i], a, b));
+      if let Some(i) = end {
+        cur.top = (0..i).rev()
+          .find(|&i| !is_diff_change(rows[i], a, b))
+          .map_or(0, |i| i + 1);
+      }
+    }
+    _ => (),
+  }
+  before != (cur.top, cur.left)
+}
+
+fn view_diff(a_path: &str, b_path: &str) -> io::Result<()> {
+  let mut view = DiffView::new(read_file(a_path)?, read_file(b_path)?);
+  let mut scr = init_screen()?;
+  let mut size = get_screen_size()?;
+  update_diff_screen(&mut scr, &view, &size)?;
+  let keys = read_keys_in_background(io::stdin());
+  loop {
+    scr.end_flash(VISUAL_BELL_DURATION)?;
+    let res = match keys.recv_timeout(TICK_INTERVAL) {
+      Ok(res) => res,
+      Err(mpsc::RecvTimeoutError::Timeout) => {
+        let new_size = get_screen_size()?;
+        if new_size != size {
+          size = new_size;
+          update_diff_screen(&mut scr, &view, &size)?;
+        }
+        continue;
+      }
+      Err(mpsc::RecvTimeoutError::Disconnected) => break,
+    };
+    let key = match res {
+      Ok(key) => key,
+      Err(ref err) if err.kind() == io::ErrorKind::Other => continue,
+      Err(err) => return Err(err),
+    };
+    size = get_screen_size()?;
+    if let Key::Char('q') = key {
+      break;
+    }
+    if !scroll_diff_view(key, &mut view, &size) {
+      ring_bell(&mut scr)?;
+    }
+    update_diff_screen(&mut scr, &view, &size)?;
+  }
+  Ok(())
+}
+
+// Compares the file on disk, or nothing if it has no name, with the buffer.
+fn diff_with_saved(file: &OpenFile) -> io::Result<DiffView> {
+  let saved = match file.path.as_str() {
+    "" => Buffer::new(),
+    path => read_file_with_format(path, Some(file.format.encoding))?.0,
+  };
+  Ok(DiffView::new(saved, file.buf.clone()))
+}
+
+// The diff view scrolls until `q` or Escape goes back to the file.
+fn handle_key_diff_mode(key: Key, mut view: Box<DiffView>, size: &Size, fb: &mut Feedback) -> Mode {
+  match key {
+    Key::Char('q') | Key::Esc => Mode::Normal,
+    key => {
+      fb.bell = !scroll_diff_view(key, &mut view, size);
+      Mode::Diff(view)
+    }
+  }
+}
+
+// Pager: a read-only view for long output, shown while it is still being
+// read. Without a file, the text is read from stdin and the keys from the
+// terminal.
+const PAGER_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+fn read_lines_in_background<R: io::Read + Send + 'static>(input: R) -> mpsc::Receiver<Line> {
+  let (tx, rx) = mpsc::channel();
+  thread::spawn(move || {
+    let mut input = BufReader::new(input);
+    let mut bytes = Vec::new();
+    while input.read_until(b'\n', &mut bytes).is_ok_and(|n| n > 0) {
+      while bytes.last().is_some_and(|&b| b == b'\n' || b == b'\r') {
+        bytes.pop();
+      }
+      if tx.send(String::from_utf8_lossy(&bytes).into_owned()).is_err() {
+        break;
+      }
+      bytes.clear();
+    }
+  });
+  rx
+}
+
+// Percentage of the text above the bottom of the screen
+fn pager_percent(top: usize, rows: usize, len: usize) -> usize {
+  if len == 0 {
+    return 100;
+  }
+  (top + rows).min(len) * 100 / len
+}
+
+fn update_pager_screen(
+  scr: &mut Screen,
+  buf: &Buffer,
+  cur: &Cursor,
+  size: &Size,
+  status: &str,
+) -> io::Result<()> {
+  blank_screen(scr)?;
+  for (r, line) in buf.iter().skip(cur.top).take(size.rows - 1).enumerate() {
+    write!(scr, "{}", termion::cursor::Goto(1, (r + 1) as u16))?;
+    write_line_to_screen(scr, cur, line, &[], None, size)?;
+  }
+  write_prompt_to_screen(scr, status, size)?;
+  scr.flush()
+}
+
+fn view_pager(path: Option<&str>) -> io::Result<()> {
+  let (name, lines, keys) = match path {
+    Some(path) => {
+      let file = fs::File::open(path)?;
+      (path, read_lines_in_background(file), read_keys_in_background(io::stdin()))
+    }
+    None => ("stdin", read_lines_in_background(io::stdin()), read_keys_in_background(termion::get_tty()?)),
+  };
+  let mut scr = init_screen()?;
+  let mut buf = Buffer::new();
+  let mut cur = Cursor::new();
+  let mut size = get_screen_size()?;
+  let mut loading = true;
+  // the search being typed, and the last one made
+  let mut typing: Option<String> = None;
+  let mut query = String::new();
+  let mut redraw = true;
+  loop {
+    scr.end_flash(VISUAL_BELL_DURATION)?;
+    while loading {
+      match lines.try_recv() {
+        Ok(line) => buf.push(line),
+        Err(mpsc::TryRecvError::Empty) => break,
+        Err(mpsc::TryRecvError::Disconnected) => loading = false,
+      }
+      redraw = true;
+    }
+    if redraw {
+      let rows = size.rows.saturating_sub(1);
+      let status = match typing {
+        Some(ref text) => format!("/{}", text),
+        None if loading => format!("{}  {}%  (reading)", name, pager_percent(cur.top, rows, buf.len())),
+        None => format!("{}  {}%", name, pager_percent(cur.top, rows, buf.len())),
+      };
+      update_pager_screen(&mut scr, &buf, &cur, &size, &status)?;
+      redraw = false;
+    }
+    let res = match keys.recv_timeout(PAGER_POLL_INTERVAL) {
+      Ok(res) => res,
+      Err(mpsc::RecvTimeoutError::Timeout) => {
+        let new_size = get_screen_size()?;
+        if new_size != size {
+          size = new_size;
+          redraw = true;
+        }
+        continue;
+      }
+      Err(mpsc::RecvTimeoutError::Disconnected) => break,
+    };
+    let key = match res {
+      Ok(key) => key,
+      Err(ref err) if err.kind() == io::ErrorKind::Other => continue,
+      Err(err) => return Err(err),
+    };
+    size = get_screen_size()?;
+    redraw = true;
+    if let Some(mut text) = typing.take() {
+      match key {
+        Key::Char('\n') => {
+          query = text;
+          match search::find_next(&buf, &query, cur.top, usize::MAX) {
+            Some((row, _)) => cur.top = row,
+            None => ring_bell(&mut scr)?,
+          }
+        }
+        Key::Esc => (),
+        Key::Backspace => {
+          text.pop();
+          typing = Some(text);
+        }
+        Key::Char(c) => {
+          text.push(c);
+          typing = Some(text);
+        }
+        _ => typing = Some(text),
+      }
+      continue;
+    }
+    let last = buf.len().saturating_sub(1);
+    let page = size.rows.saturating_sub(1).max(1);
+    let before = (cur.top, cur.left);
+    match key {
+      Key::Char('j') | Key::Char('\n') | Key::Down => cur.top = (cur.top + 1).min(last),
+      Key::Char('k') | Key::Up => cur.top = cur.top.saturating_sub(1),
+      Key::Char(' ') | Key::Char('f') | Key::Ctrl('f') | Key::PageDown =>
+        cur.top = (cur.top + page).min(last),
+      Key::Char('b') | Key::Ctrl('b') | Key::PageUp => cur.top = cur.top.saturating_sub(page),
+      Key::Char('l') | Key::Right => cur.left += 1,
+      Key::Char('h') | Key::Left => cur.left = cur.left.saturating_sub(1),
+      Key::Char('g') => cur.top = 0,
+      Key::Char('G') => cur.top = buf.len().saturating_sub(page),
+      Key::Char('/') => typing = Some(String::new()),
+      Key::Char('n') => if let Some((row, _)) = search::find_next(&buf, &query, cur.top, usize::MAX) {
+        cur.top = row;
+      },
+      Key::Char('N') => if let Some((row, _)) = search::find_prev(&buf, &query, cur.top, 0) {
+        cur.top = row;
+      },
+      Key::Char('q') => break,
+      _ => (),
+    }
+    if typing.is_none() && before == (cur.top, cur.left) {
+      ring_bell(&mut scr)?;
+    }
+  }
+  Ok(())
+}
+
+// Local history commands
+fn history_version(path: &str, n: &str) -> io::Result<PathBuf> {
+  let root = history_root().unwrap_or_default();
+  let versions = history::versions(&root, Path::new(path))?;
+  n.parse::<usize>().ok()
+    .and_then(|n| versions.into_iter().nth(n.wrapping_sub(1)))
+    .map(|(_, copy)| copy)
+    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no version {} of {}", n, path)))
+}
+
+fn list_history(path: &str) -> io::Result<()> {
+  let root = history_root().unwrap_or_default();
+  for (i, (millis, copy)) in history::versions(&root, Path::new(path))?.iter().enumerate() {
+    println!("{:>3}  {}  {}", i + 1, history::format_time(millis / 1000), copy.display());
+  }
+  Ok(())
+}
+
+fn restore_history(path: &str, n: &str) -> io::Result<()> {
+  let copy = history_version(path, n)?;
+  let (buf, format) = read_file_with_format(&copy.to_string_lossy(), None)?;
+  save_file(path, &buf, format)
+}
+
+// Text piped to the editor starts out unsaved, in a file without a name.
+fn open_stdin(encoding: Option<Encoding>) -> io::Result<OpenFile> {
+  let mut bytes = Vec::new();
+  io::stdin().read_to_end(&mut bytes)?;
+  let (mut buf, format) = decode_text("stdin", &bytes, encoding)?;
+  init_buffer_if_empty(&mut buf);
+  let mut file = OpenFile::new("", buf);
+  file.saved = None;
+  file.format = format;
+  file.saved_format = format;
+  Ok(file)
+}
+
+fn open_file(path: &str, encoding: Option<Encoding>) -> io::Result<OpenFile> {
+  // taken first, so that lines added while reading are followed rather than
+  // missed
+  let length = fs::metadata(path).map_or(0, |metadata| metadata.len());
+  let (mut buf, mut format) = if Path::new(path).exists() {
+    read_file_with_format(path, encoding)?
+  } else {
+    (read_new_file(path)?, FileFormat::default())
+  };
+  if let Some(encoding) = encoding {
+    format.encoding = encoding;
+  }
+  init_buffer_if_empty(&mut buf);
+  let mut file = OpenFile::new(path, buf);
+  file.format = format;
+  file.saved_format = format;
+  if !file.buf.is_lazy() {
+    file.git = git::index_lines(path);
+  }
+  if let Some(filter) = filter::find(Path::new(path)) {
+    file.filter_options = filter::options(filter, Path::new(path));
+  }
+  file.stamp = disk_stamp(path);
+  file.length = length;
+  file.cur.folds = remembered_folds(path, &file.buf);
+  file.notes = notes::load(path)?;
+  restore_positions(&mut file);
+  if let Ok(Some(_)) = swapped_lines(&file) {
+    file.warning = Some(format!("{} has unsaved changes left by a red that ended without saving; :recover takes them", path));
+  }
+  // Latin-1 is only guessed for a file that is no other encoding
+  if encoding.is_none() && format.encoding == Encoding::Latin1 {
+    file.warning = Some(format!("{} is not UTF-8, and was read as Latin-1", path));
+  }
+  Ok(file)
+}
+
+// A file named on the command line, with the line and column to start at,
+// counted from 1.
+#[derive(Debug, PartialEq)]
+struct FileArg {
+  path: String,
+  pos: Option<(usize, usize)>,
+}
+
+// Splits the position off names like `main.rs:12` or `main.rs:12:5:`, as
+// compilers print them, unless a file has the whole name.
+fn split_position(arg: &str) -> Option<(&str, (usize, usize))> {
+  if Path::new(arg).exists() {
+    return None;
+  }
+  let (rest, last) = arg.trim_end_matches(':').rsplit_once(':')?;
+  let last = last.parse().ok()?;
+  let (path, pos) = match rest.rsplit_once(':').map(|(path, line)| (path, line.parse())) {
+    Some((path, Ok(line))) => (path, (line, last)),
+    _ => (rest, (last, 1)),
+  };
+  if path.is_empty() {
+    return None;
+  }
+  Some((path, pos))
+}
+
+// Parses file names, each of which may follow `+<line>`, or `+` for the last
+// line.
+fn parse_file_args(args: &[String]) -> Result<Vec<FileArg>, String> {
+  let mut files = Vec::new();
+  let mut line = None;
+  for arg in args {
+    if let Some(num) = arg.strip_prefix('+') {
+      line = match num {
+        "" => Some(usize::MAX),
+        _ => Some(num.parse().map_err(|_| format!("invalid line `{}`", arg))?),
+      };
+      continue;
+    }
+    if arg == "-" && files.iter().any(|file: &FileArg| file.path == "-") {
+      return Err(String::from("stdin (`-`) can only be read once"));
+    }
+    let file = match split_position(arg) {
+      Some((path, pos)) => FileArg{path: String::from(path), pos: Some(pos)},
+      None => FileArg{path: arg.clone(), pos: line.map(|line| (line, 1))},
+    };
+    files.push(file);
+    line = None;
+  }
+  if line.is_some() {
+    return Err(String::from("usage: red [+<line>] <file>[:<line>[:<column>]] ..."));
+  }
+  Ok(files)
+}
+
+// Puts the cursor on a line and column counted from 1, as the command line
+// names them.
+fn move_cursor_to_arg((line, col): (usize, usize), file: &mut OpenFile, size: &Size) {
+  let row = line.saturating_sub(1).min(file.buf.len().saturating_sub(1));
+  move_cursor_to((row, col.saturating_sub(1)), &mut file.cur, &file.buf, size);
+}
+
+// Opens the files named by the command line arguments. Without any, the
+// editor starts on what was piped to it, if `stdin` says there was something,
+// or on an unnamed file.
+fn open_files(
+  args: &[String],
+  encoding: Option<Encoding>,
+  stdin: bool,
+  screen: Size,
+) -> io::Result<BufferList> {
+  let args = parse_file_args(args).map_err(|msg| io::Error::new(io::ErrorKind::InvalidInput, msg))?;
+  let size = text_area_size(screen, 0);
+  let mut files = Vec::new();
+  if args.is_empty() {
+    files.push(if stdin { open_stdin(encoding)? } else { OpenFile::new("", Buffer::from(vec![Line::new()])) });
+  }
+  for arg in args {
+    // a file named twice is opened once
+    if files.iter().any(|file: &OpenFile| !file.path.is_empty() && same_file(&file.path, &arg.path)) {
+      continue;
+    }
+    let mut file = match arg.path.as_str() {
+      "-" if stdin => open_stdin(encoding)?,
+      path => open_file(path, encoding)?,
+    };
+    if let Some(pos) = arg.pos {
+      move_cursor_to_arg(pos, &mut file, &size);
+    }
+    files.push(file);
+  }
+  Ok(BufferList::new(files))
+}
+
+fn edit_files(
+  args: &[String],
+  encoding: Option<Encoding>,
+  listen: Option<&str>,
+  script: Option<Vec<Key>>,
+  notice: Option<&str>,
+  prof: &mut Profile,
+) -> io::Result<()> {
+  let start = Instant::now();
+  let files = open_files(args, encoding, !termion::is_tty(&io::stdin()), get_screen_size()?)?;
+  prof.load = start.elapsed();
+  edit_buffers(files, false, listen, script, notice, prof)
+}
+
+// Sends the arguments to the editor listening on the socket, with the files
+// named from where this one runs, and prints what it replies.
+fn remote_control(socket: &str, args: &[String]) -> io::Result<()> {
+  let dir = env::current_dir()?;
+  let mut sent = Vec::new();
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--command" => {
+        sent.push(arg.clone());
+        sent.extend(args.next().cloned());
+      }
+      "-" => return Err(io::Error::new(io::ErrorKind::InvalidInput, "stdin (`-`) can't be sent to another red")),
+      _ if arg.starts_with('+') => sent.push(arg.clone()),
+      _ => sent.push(dir.join(arg).to_string_lossy().into_owned()),
+    }
+  }
+  let msg = remote::send(Path::new(socket), &sent)?;
+  if !msg.is_empty() {
+    println!("{}", msg);
+  }
+  Ok(())
+}
+
+// Opens a file, such as a log, already following it.
+fn follow_log(path: &str, notice: Option<&str>) -> io::Result<()> {
+  let files = open_files(&[path.to_string()], None, false, get_screen_size()?)?;
+  edit_buffers(files, true, None, None, notice, &mut Profile::default())
+}
+
+// Reads the keys of a script, leaving out the newline that ends the file.
+fn read_script(path: &str) -> io::Result<Vec<Key>> {
+  let text = fs::read_to_string(path)?;
+  let text = text.strip_suffix('\n').unwrap_or(&text);
+  keymap::parse_script(text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, err)))
+}
+
+// Asks on the terminal, since stdin may be the text to edit. Without a
+// terminal to ask on, the settings aren't used.
+fn ask_to_trust(path: &Path) -> io::Result<bool> {
+  let tty = match termion::get_tty() {
+    Ok(tty) => tty,
+    Err(_) => return Ok(false),
+  };
+  eprint!("Use the settings in {}? [y/N] ", path.display());
+  let mut answer = String::new();
+  BufRead::read_line(&mut io::BufReader::new(tty), &mut answer)?;
+  Ok(answer.trim().eq_ignore_ascii_case("y"))
+}
+
+// Runs the editor in the terminal with the command line arguments.
+pub fn run() -> io::Result<()> {
+  // A config with an error is left out, and the error shown once the editor
+  // starts, rather than keeping it from starting.
+  let mut notice = None;
+  let mut cfg = config::load().unwrap_or_else(|err| {
+    notice = Some(format!("{}; using the default settings", err));
+    config::Config::default()
+  });
+  let args = expand_path_args(&env::args().skip(1).collect::<Vec<_>>(), expand_path);
+  // the files of another editor are opened with its settings, and neither
+  // the socket, a script nor stdin names the project
+  let paths = match args.first().map(String::as_str) {
+    Some("--remote") => &[][..],
+    Some("--listen") | Some("--script") => args.get(2..).unwrap_or(&[]),
+    _ => &args[..],
+  };
+  if let Some(path) = paths.iter().find(|arg| !arg.starts_with("--") && !arg.starts_with('+') && *arg != "-") {
+    let mut project = cfg.clone();
+    match config::load_project(&mut project, Path::new(path), ask_to_trust) {
+      Ok(_) => cfg = project,
+      Err(err) => notice = Some(format!("{}; the project settings are not used", err)),
+    }
+  }
+  config::set(cfg);
+  let notice = notice.as_deref();
+  match args.first().map(String::as_str) {
+    Some("--diff") => match &args[1..] {
+      [a, b] => view_diff(a, b),
+      _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "usage: red --diff <old> <new>")),
+    },
+    Some("--history") => match &args[1..] {
+      [path] => list_history(path),
+      [path, n] => view_diff(&history_version(path, n)?.to_string_lossy(), path),
+      _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "usage: red --history <file> [n]")),
+    },
+    Some("--restore") => match &args[1..] {
+      [path, n] => restore_history(path, n),
+      _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "usage: red --restore <file> <n>")),
+    },
+    Some("--pager") => match &args[1..] {
+      [] => view_pager(None),
+      [path] => view_pager(Some(path)),
+      _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "usage: red --pager [file]")),
+    },
+    Some("--follow") => match &args[1..] {
+      [path] => follow_log(path, notice),
+      _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "usage: red --follow <file>")),
+    },
+    Some("--profile") => match &args[1..] {
+      [_] => {
+        let mut prof = Profile::default();
+        edit_files(&args[1..], None, None, None, notice, &mut prof)?;
+        eprint!("{}", prof.report());
+        Ok(())
+      }
+      _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "usage: red --profile <file>")),
+    },
+    Some("--encoding") => match &args[1..] {
+      [name, paths @ ..] if !paths.is_empty() => match Encoding::by_name(name) {
+        Some(encoding) => edit_files(paths, Some(encoding), None, None, notice, &mut Profile::default()),
+        None => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown encoding `{}`", name))),
+      },
+      _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "usage: red --encoding <name> <file>...")),
+    },
+    Some("--listen") => match &args[1..] {
+      [socket, paths @ ..] => edit_files(paths, None, Some(socket), None, notice, &mut Profile::default()),
+      _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "usage: red --listen <socket> [file]...")),
+    },
+    Some("--remote") => match &args[1..] {
+      [socket, rest @ ..] if !rest.is_empty() => remote_control(socket, rest),
+      _ => Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "usage: red --remote <socket> [+<line>] [file]... [--command <command>]...",
+      )),
+    },
+    Some("--script") => match &args[1..] {
+      [script, paths @ ..] => {
+        let keys = read_script(script)?;
+        edit_files(paths, None, None, Some(keys), notice, &mut Profile::default())
+      }
+      _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "usage: red --script <keys> [file]...")),
+    },
+    Some(_) => edit_files(&args, None, None, None, notice, &mut Profile::default()),
+    None => edit_files(&[], None, None, None, notice, &mut Profile::default()),
+  }
+}
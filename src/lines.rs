@@ -0,0 +1,600 @@
+// The lines of a buffer, kept in chunks of about a thousand lines each
+// rather than in one vector, so that breaking or joining a line moves only
+// the lines of its chunk and the start of each chunk after it, instead of
+// every line after it. In a file of millions of lines, that is the
+// difference between a key taking microseconds and taking milliseconds.
+//
+// Lines are found by the row each chunk starts at, and changed by `splice`,
+// which puts the chunks it touches back together and splits them again.
+// Chunks are shared between copies of the lines until one of them changes,
+// so that the states kept for `:earlier` cost only the chunks edited.
+//
+// The lines of a large file can also be left in it, each chunk knowing only
+// where its lines are until one of them is needed. Those chunks are read
+// again rather than shared when the lines are copied, so that `unload` can
+// let go of the ones far from what is shown.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::iter;
+use std::mem;
+use std::ops::{Bound, Index, IndexMut, Range, RangeBounds};
+use std::os::unix::fs::FileExt;
+use std::slice;
+use std::sync::{Arc, OnceLock};
+
+// The most lines in a chunk, and the fewest in one that isn't the last, past
+// which chunks are split or joined
+const MAX_CHUNK: usize = 1024;
+const MIN_CHUNK: usize = MAX_CHUNK / 4;
+
+#[derive(Default)]
+pub struct Lines {
+  chunks: Vec<Arc<Chunk>>,
+  // the row each chunk starts at
+  starts: Vec<usize>,
+  len: usize,
+  // the file the chunks not read yet are in
+  source: Option<Arc<Source>>,
+}
+
+// A file that lines are read from as they are needed
+struct Source {
+  file: File,
+  // why some lines couldn't be read as they were
+  error: OnceLock<String>,
+}
+
+impl Source {
+  // Reads the lines in a range of bytes, of which there should be `len`.
+  fn read(&self, bytes: &Range<u64>, len: usize) -> Vec<String> {
+    let mut data = vec![0; (bytes.end - bytes.start) as usize];
+    if let Err(err) = self.file.read_exact_at(&mut data, bytes.start) {
+      self.error.get_or_init(|| err.to_string());
+      data.clear();
+    }
+    let mut pieces = data.split(|&b| b == b'\n').peekable();
+    let mut lines = Vec::with_capacity(len);
+    while let Some(piece) = pieces.next() {
+      // a carriage return ends a line only before a newline
+      let piece = match pieces.peek() {
+        Some(_) => piece.strip_suffix(b"\r").unwrap_or(piece),
+        None => piece,
+      };
+      let line = String::from_utf8_lossy(piece).into_owned();
+      if line.len() != piece.len() {
+        self.error.get_or_init(|| String::from("not all UTF-8"));
+      }
+      lines.push(line);
+    }
+    // a newline at the end leaves an empty piece after it
+    lines.resize(len, String::new());
+    lines
+  }
+}
+
+enum Chunk {
+  Memory(Vec<String>),
+  // lines still in the source, read the first time one of them is needed
+  Disk{bytes: Range<u64>, len: usize, lines: OnceLock<Vec<String>>},
+}
+
+impl Chunk {
+  fn len(&self) -> usize {
+    match self {
+      Chunk::Memory(lines) => lines.len(),
+      Chunk::Disk{len, ..} => *len,
+    }
+  }
+
+  fn lines(&self, source: Option<&Source>) -> &Vec<String> {
+    match self {
+      Chunk::Memory(lines) => lines,
+      Chunk::Disk{bytes, len, lines} => lines.get_or_init(|| read(source, bytes, *len)),
+    }
+  }
+
+  // The lines to change, which are then kept in memory
+  fn lines_mut(&mut self, source: Option<&Source>) -> &mut Vec<String> {
+    if let Chunk::Disk{bytes, len, lines} = self {
+      let lines = lines.take().unwrap_or_else(|| read(source, bytes, *len));
+      *self = Chunk::Memory(lines);
+    }
+    match self {
+      Chunk::Memory(lines) => lines,
+      Chunk::Disk{..} => unreachable!(),
+    }
+  }
+}
+
+fn read(source: Option<&Source>, bytes: &Range<u64>, len: usize) -> Vec<String> {
+  source.expect("lines on disk without their file").read(bytes, len)
+}
+
+// A chunk on disk is copied without its lines, to be read again if needed.
+impl Clone for Chunk {
+  fn clone(&self) -> Self {
+    match self {
+      Chunk::Memory(lines) => Chunk::Memory(lines.clone()),
+      Chunk::Disk{bytes, len, ..} => Chunk::Disk{bytes: bytes.clone(), len: *len, lines: OnceLock::new()},
+    }
+  }
+}
+
+impl Clone for Lines {
+  fn clone(&self) -> Self {
+    let chunks = self.chunks.iter().map(|chunk| match **chunk {
+      Chunk::Memory(_) => Arc::clone(chunk),
+      Chunk::Disk{..} => Arc::new((**chunk).clone()),
+    });
+    Lines{chunks: chunks.collect(), starts: self.starts.clone(), len: self.len, source: self.source.clone()}
+  }
+}
+
+impl Lines {
+  pub fn new() -> Self {
+    Lines::default()
+  }
+
+  // Finds where each chunk of lines is in a file, leaving the lines in it
+  // to be read as they are needed.
+  pub fn read_from(mut file: File) -> io::Result<Lines> {
+    let mut chunks = Vec::new();
+    let mut block = vec![0; 1 << 20];
+    let (mut start, mut offset, mut count, mut last) = (0, 0, 0, b'\n');
+    loop {
+      let n = match file.read(&mut block) {
+        Ok(0) => break,
+        Ok(n) => n,
+        Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+        Err(err) => return Err(err),
+      };
+      for (i, _) in block[..n].iter().enumerate().filter(|&(_, &b)| b == b'\n') {
+        count += 1;
+        if count == MAX_CHUNK {
+          let end = offset + i as u64 + 1;
+          chunks.push(Arc::new(Chunk::Disk{bytes: start..end, len: count, lines: OnceLock::new()}));
+          start = end;
+          count = 0;
+        }
+      }
+      offset += n as u64;
+      last = block[n - 1];
+    }
+    if offset > start {
+      // the last line may not end with a newline
+      let len = count + (last != b'\n') as usize;
+      chunks.push(Arc::new(Chunk::Disk{bytes: start..offset, len, lines: OnceLock::new()}));
+    }
+    let source = Source{file, error: OnceLock::new()};
+    let mut lines = Lines{chunks, source: Some(Arc::new(source)), ..Lines::default()};
+    lines.update_starts(0);
+    Ok(lines)
+  }
+
+  // Whether the lines were left in a file to be read as they are needed
+  pub fn is_lazy(&self) -> bool {
+    self.source.is_some()
+  }
+
+  // Lets go of the lines read from the file outside the rows to keep, which
+  // are read again when they are next needed.
+  pub fn unload(&mut self, keep: Range<usize>) {
+    for (chunk, &start) in self.chunks.iter_mut().zip(&self.starts) {
+      let outside = start + chunk.len() <= keep.start || start >= keep.end;
+      if let (true, Some(Chunk::Disk{lines, ..})) = (outside, Arc::get_mut(chunk)) {
+        lines.take();
+      }
+    }
+  }
+
+  // The number of lines read from the file and kept, or changed
+  #[cfg(test)]
+  pub fn loaded(&self) -> usize {
+    let loaded = |chunk: &Chunk| match chunk {
+      Chunk::Disk{lines, ..} => lines.get().is_some(),
+      Chunk::Memory(_) => true,
+    };
+    self.chunks.iter().filter(|chunk| loaded(chunk)).map(|chunk| chunk.len()).sum()
+  }
+
+  // The number of lines kept in memory in chunks that the other lines don't
+  // share, which is what keeping both costs over keeping the other
+  pub fn unshared(&self, other: &Lines) -> usize {
+    let shared: HashSet<*const Chunk> = other.chunks.iter().map(Arc::as_ptr).collect();
+    self.chunks.iter()
+      .filter(|chunk| matches!(***chunk, Chunk::Memory(_)) && !shared.contains(&Arc::as_ptr(chunk)))
+      .map(|chunk| chunk.len())
+      .sum()
+  }
+
+  // Fails if lines were read from the file other than they are in it, as
+  // when they aren't UTF-8, so that they aren't saved that way.
+  pub fn check(&self) -> io::Result<()> {
+    match self.source.as_ref().and_then(|source| source.error.get()) {
+      Some(err) => Err(io::Error::new(io::ErrorKind::InvalidData, err.clone())),
+      None => Ok(()),
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  // The chunk a row is in, or the last chunk for the row after the end
+  fn chunk_of(&self, row: usize) -> usize {
+    self.starts.partition_point(|&start| start <= row).saturating_sub(1)
+  }
+
+  pub fn get(&self, row: usize) -> Option<&String> {
+    if row >= self.len {
+      return None;
+    }
+    let i = self.chunk_of(row);
+    self.chunks[i].lines(self.source.as_deref()).get(row - self.starts[i])
+  }
+
+  pub fn get_mut(&mut self, row: usize) -> Option<&mut String> {
+    if row >= self.len {
+      return None;
+    }
+    let i = self.chunk_of(row);
+    Arc::make_mut(&mut self.chunks[i]).lines_mut(self.source.as_deref()).get_mut(row - self.starts[i])
+  }
+
+  pub fn first(&self) -> Option<&String> {
+    self.get(0)
+  }
+
+  pub fn last(&self) -> Option<&String> {
+    self.len.checked_sub(1).and_then(|row| self.get(row))
+  }
+
+  pub fn iter(&self) -> Iter<'_> {
+    let source = self.source.as_deref();
+    Iter{chunks: self.chunks.iter(), source, front: [].iter(), back: [].iter(), len: self.len}
+  }
+
+  pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut String> {
+    let source = self.source.as_deref();
+    self.chunks.iter_mut().flat_map(move |chunk| Arc::make_mut(chunk).lines_mut(source).iter_mut())
+  }
+
+  fn bounds(&self, range: impl RangeBounds<usize>) -> Range<usize> {
+    let start = match range.start_bound() {
+      Bound::Included(&n) => n,
+      Bound::Excluded(&n) => n + 1,
+      Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+      Bound::Included(&n) => n + 1,
+      Bound::Excluded(&n) => n,
+      Bound::Unbounded => self.len,
+    };
+    start..end
+  }
+
+  // The lines in a range
+  pub fn range(&self, range: impl RangeBounds<usize>) -> iter::Take<iter::Skip<Iter<'_>>> {
+    let range = self.bounds(range);
+    self.iter().skip(range.start).take(range.len())
+  }
+
+  pub fn range_mut(&mut self, range: impl RangeBounds<usize>) -> impl Iterator<Item = &mut String> {
+    let range = self.bounds(range);
+    let first = self.chunk_of(range.start);
+    let skip = range.start - self.starts.get(first).copied().unwrap_or(0);
+    let count = self.chunks.len();
+    let chunks = self.chunks[first.min(count)..].iter_mut();
+    let source = self.source.as_deref();
+    chunks.flat_map(move |chunk| Arc::make_mut(chunk).lines_mut(source).iter_mut()).skip(skip).take(range.len())
+  }
+
+  // The lines in a range, copied
+  pub fn slice(&self, range: impl RangeBounds<usize>) -> Vec<String> {
+    self.range(range).cloned().collect()
+  }
+
+  pub fn to_vec(&self) -> Vec<String> {
+    self.iter().cloned().collect()
+  }
+
+  pub fn into_vec(self) -> Vec<String> {
+    let source = self.source.as_deref();
+    self.chunks.into_iter().flat_map(|chunk| owned(chunk, source)).collect()
+  }
+
+  // Replaces the lines in a range with others, returning those it replaced.
+  pub fn splice<I: IntoIterator<Item = String>>(&mut self, range: Range<usize>, lines: I) -> Vec<String> {
+    assert!(range.start <= range.end && range.end <= self.len, "lines {:?} of {}", range, self.len);
+    let (first, end) = match self.chunks.len() {
+      0 => (0, 0),
+      _ => (self.chunk_of(range.start), self.chunk_of(range.end.saturating_sub(1).max(range.start)) + 1),
+    };
+    let base = self.starts.get(first).copied().unwrap_or(0);
+    let source = self.source.as_deref();
+    let mut joined: Vec<String> = self.chunks.drain(first..end).flat_map(|chunk| owned(chunk, source)).collect();
+    let removed: Vec<String> = joined.splice(range.start - base..range.end - base, lines).collect();
+    // a chunk left small is joined to the one after it
+    if joined.len() < MIN_CHUNK && first < self.chunks.len() {
+      joined.extend(owned(self.chunks.remove(first), source));
+    }
+    let pieces = split(joined).into_iter().map(|lines| Arc::new(Chunk::Memory(lines)));
+    self.chunks.splice(first..first, pieces);
+    self.update_starts(first);
+    removed
+  }
+
+  // Sets the start of each chunk from the one given on.
+  fn update_starts(&mut self, from: usize) {
+    self.starts.truncate(from);
+    let mut row = match from {
+      0 => 0,
+      _ => self.starts[from - 1] + self.chunks[from - 1].len(),
+    };
+    for chunk in &self.chunks[from..] {
+      self.starts.push(row);
+      row += chunk.len();
+    }
+    self.len = row;
+  }
+
+  pub fn insert(&mut self, row: usize, line: String) {
+    self.splice(row..row, iter::once(line));
+  }
+
+  pub fn remove(&mut self, row: usize) -> String {
+    self.splice(row..row + 1, iter::empty()).remove(0)
+  }
+
+  pub fn push(&mut self, line: String) {
+    let len = self.len;
+    match self.chunks.last_mut() {
+      Some(chunk) if chunk.len() < MAX_CHUNK => {
+        Arc::make_mut(chunk).lines_mut(self.source.as_deref()).push(line);
+        self.len += 1;
+      }
+      _ => self.insert(len, line),
+    }
+  }
+
+  pub fn pop(&mut self) -> Option<String> {
+    let len = self.len;
+    len.checked_sub(1).map(|row| self.remove(row))
+  }
+
+  pub fn extend<I: IntoIterator<Item = String>>(&mut self, lines: I) {
+    let len = self.len;
+    self.splice(len..len, lines);
+  }
+
+  pub fn drain(&mut self, range: Range<usize>) -> Vec<String> {
+    self.splice(range, iter::empty())
+  }
+
+  pub fn truncate(&mut self, len: usize) {
+    if len < self.len {
+      let end = self.len;
+      self.splice(len..end, iter::empty());
+    }
+  }
+
+  pub fn clear(&mut self) {
+    *self = Lines::new();
+  }
+
+  pub fn swap(&mut self, a: usize, b: usize) {
+    if a != b {
+      let line = mem::take(&mut self[a]);
+      let other = mem::replace(&mut self[b], line);
+      self[a] = other;
+    }
+  }
+}
+
+// The lines of a chunk, copied if another copy of the lines shares it
+fn owned(chunk: Arc<Chunk>, source: Option<&Source>) -> Vec<String> {
+  match Arc::try_unwrap(chunk) {
+    Ok(Chunk::Memory(lines)) => lines,
+    Ok(Chunk::Disk{bytes, len, lines}) => lines.into_inner().unwrap_or_else(|| read(source, &bytes, len)),
+    Err(chunk) => chunk.lines(source).clone(),
+  }
+}
+
+// Splits lines into chunks of about the same length, each no longer than
+// `MAX_CHUNK`. Unless there is only one, each is at least half of that, so
+// that edits take a while to shrink it below `MIN_CHUNK`.
+fn split(mut lines: Vec<String>) -> Vec<Vec<String>> {
+  if lines.is_empty() {
+    return Vec::new();
+  }
+  let count = lines.len().div_ceil(MAX_CHUNK);
+  let size = lines.len().div_ceil(count);
+  let mut chunks = Vec::with_capacity(count);
+  while lines.len() > size {
+    let rest = lines.split_off(size);
+    chunks.push(mem::replace(&mut lines, rest));
+  }
+  chunks.push(lines);
+  chunks
+}
+
+impl Index<usize> for Lines {
+  type Output = String;
+
+  fn index(&self, row: usize) -> &String {
+    match self.get(row) {
+      Some(line) => line,
+      None => panic!("line {} of {}", row, self.len),
+    }
+  }
+}
+
+impl IndexMut<usize> for Lines {
+  fn index_mut(&mut self, row: usize) -> &mut String {
+    let len = self.len;
+    match self.get_mut(row) {
+      Some(line) => line,
+      None => panic!("line {} of {}", row, len),
+    }
+  }
+}
+
+impl From<Vec<String>> for Lines {
+  fn from(lines: Vec<String>) -> Self {
+    let chunks = split(lines).into_iter().map(|lines| Arc::new(Chunk::Memory(lines)));
+    let mut all = Lines{chunks: chunks.collect(), ..Lines::default()};
+    all.update_starts(0);
+    all
+  }
+}
+
+impl iter::FromIterator<String> for Lines {
+  fn from_iter<I: IntoIterator<Item = String>>(iter: I) -> Self {
+    Lines::from(iter.into_iter().collect::<Vec<_>>())
+  }
+}
+
+impl IntoIterator for Lines {
+  type Item = String;
+  type IntoIter = std::vec::IntoIter<String>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.into_vec().into_iter()
+  }
+}
+
+impl<'a> IntoIterator for &'a Lines {
+  type Item = &'a String;
+  type IntoIter = Iter<'a>;
+
+  fn into_iter(self) -> Iter<'a> {
+    self.iter()
+  }
+}
+
+// Lines that share their chunks, or have them in the same place in the same
+// file, are the same without comparing them.
+impl PartialEq for Lines {
+  fn eq(&self, other: &Lines) -> bool {
+    if self.len != other.len {
+      return false;
+    }
+    if self.starts != other.starts {
+      return self.iter().eq(other.iter());
+    }
+    let (source, other_source) = (self.source.as_deref(), other.source.as_deref());
+    let same_source = matches!((source, other_source), (Some(a), Some(b)) if std::ptr::eq(a, b));
+    self.chunks.iter().zip(&other.chunks).all(|(a, b)| match (&**a, &**b) {
+      _ if Arc::ptr_eq(a, b) => true,
+      (Chunk::Disk{bytes: a, ..}, Chunk::Disk{bytes: b, ..}) if same_source && a == b => true,
+      _ => a.lines(source) == b.lines(other_source),
+    })
+  }
+}
+
+impl<S: AsRef<str>> PartialEq<[S]> for Lines {
+  fn eq(&self, other: &[S]) -> bool {
+    self.len == other.len() && self.iter().zip(other).all(|(a, b)| a == b.as_ref())
+  }
+}
+
+impl<S: AsRef<str>> PartialEq<Vec<S>> for Lines {
+  fn eq(&self, other: &Vec<S>) -> bool {
+    *self == other[..]
+  }
+}
+
+impl PartialEq<Lines> for Vec<String> {
+  fn eq(&self, other: &Lines) -> bool {
+    *other == *self
+  }
+}
+
+impl PartialEq<Lines> for Vec<&str> {
+  fn eq(&self, other: &Lines) -> bool {
+    *other == *self
+  }
+}
+
+impl fmt::Debug for Lines {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_list().entries(self.iter()).finish()
+  }
+}
+
+// The lines in order, going from chunk to chunk, which skips whole chunks
+// at a time
+#[derive(Clone)]
+pub struct Iter<'a> {
+  chunks: slice::Iter<'a, Arc<Chunk>>,
+  source: Option<&'a Source>,
+  // the lines left in the chunks taken from the front and back
+  front: slice::Iter<'a, String>,
+  back: slice::Iter<'a, String>,
+  len: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+  type Item = &'a String;
+
+  fn next(&mut self) -> Option<&'a String> {
+    self.nth(0)
+  }
+
+  fn nth(&mut self, mut n: usize) -> Option<&'a String> {
+    if n >= self.len {
+      self.len = 0;
+      self.front = [].iter();
+      self.chunks = [].iter();
+      self.back = [].iter();
+      return None;
+    }
+    self.len -= n + 1;
+    loop {
+      if n < self.front.len() {
+        return self.front.nth(n);
+      }
+      n -= self.front.len();
+      match self.chunks.next() {
+        Some(chunk) => self.front = chunk.lines(self.source).iter(),
+        None => {
+          self.front = [].iter();
+          return self.back.nth(n);
+        }
+      }
+    }
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    (self.len, Some(self.len))
+  }
+}
+
+impl<'a> DoubleEndedIterator for Iter<'a> {
+  fn next_back(&mut self) -> Option<&'a String> {
+    if self.len == 0 {
+      return None;
+    }
+    self.len -= 1;
+    loop {
+      if let Some(line) = self.back.next_back() {
+        return Some(line);
+      }
+      match self.chunks.next_back() {
+        Some(chunk) => self.back = chunk.lines(self.source).iter(),
+        None => return self.front.next_back(),
+      }
+    }
+  }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+impl iter::FusedIterator for Iter<'_> {}
@@ -0,0 +1,36 @@
+// Places in files named in the output of a build or other command, like
+// `src/main.rs:12:5: error: ...` from a compiler or `notes.txt:3:text` from
+// `grep -n`, which the editor can go to.
+
+use crate::lines::Lines;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+  pub path: String,
+  // the line and column, counted from 1
+  pub line: usize,
+  pub col: usize,
+}
+
+// Parses a word like `path:line` or `path:line:col`, with anything after.
+fn parse_word(word: &str) -> Option<Location> {
+  let mut parts = word.split(':');
+  let path = parts.next()?;
+  if path.is_empty() || path.chars().all(|c| c.is_ascii_digit()) {
+    return None;
+  }
+  let line = parts.next()?.parse().ok().filter(|&line| line > 0)?;
+  let col = parts.next().and_then(|col| col.parse().ok()).filter(|&col| col > 0).unwrap_or(1);
+  Some(Location{path: String::from(path), line, col})
+}
+
+// The first place named in a line of output. Rust gives it after an arrow,
+// as in `--> src/main.rs:12:5`, and most other tools at the start.
+pub fn find(text: &str) -> Option<Location> {
+  text.split_whitespace().find_map(parse_word)
+}
+
+// The places named in the lines, in order.
+pub fn find_all(lines: &Lines) -> Vec<Location> {
+  lines.iter().filter_map(|line| find(line)).collect()
+}
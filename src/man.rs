@@ -0,0 +1,48 @@
+// Manual pages for `:man`, read as plain text: `man` is asked for the page
+// without a pager, as wide as the screen, and the bold and underlining it
+// makes by overstriking are taken out.
+
+use std::io;
+use std::process::{Command, Stdio};
+
+// Takes out `c\bc` bold and `_\bc` underlining, keeping the character
+// struck over, and any escape sequences for colors.
+pub fn strip_formatting(text: &str) -> String {
+  let mut out: Vec<char> = Vec::new();
+  let mut chars = text.chars();
+  while let Some(c) = chars.next() {
+    match c {
+      '\x08' => if let Some(next) = chars.next() {
+        out.pop();
+        out.push(next);
+      },
+      '\x1b' => if let Some('[') = chars.next() {
+        for c in chars.by_ref() {
+          if ('@'..='~').contains(&c) {
+            break;
+          }
+        }
+      },
+      c => out.push(c),
+    }
+  }
+  out.into_iter().collect()
+}
+
+// The page for a topic, which may start with a section, as in `3 printf`.
+pub fn page(topic: &str, width: usize) -> io::Result<String> {
+  let output = Command::new("man")
+    .args(topic.split_whitespace())
+    .env("MANPAGER", "cat")
+    .env("PAGER", "cat")
+    .env("MANWIDTH", width.to_string())
+    .env("GROFF_NO_SGR", "1")
+    .stdin(Stdio::null())
+    .output()?;
+  if !output.status.success() {
+    let msg = String::from_utf8_lossy(&output.stderr);
+    let msg = msg.lines().find(|line| !line.trim().is_empty()).unwrap_or("failed");
+    return Err(io::Error::other(format!("man: {}", msg)));
+  }
+  Ok(strip_formatting(&String::from_utf8_lossy(&output.stdout)))
+}
@@ -0,0 +1,162 @@
+// Markdown rendered for the preview beside the text: headings, emphasis,
+// inline code, lists, quotes, rules and code blocks are shown with terminal
+// styles, and paragraphs are refilled to the width of the preview. Other
+// markup, like links, is shown as it is written.
+
+use std::path::Path;
+
+use crate::reflow;
+use crate::lines::Lines;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Style {
+  pub heading: bool,
+  pub bold: bool,
+  pub italic: bool,
+  pub code: bool,
+}
+
+pub type Span = (Style, String);
+
+// A row of the preview, with the row of the source it was rendered from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Row {
+  pub source: usize,
+  pub spans: Vec<Span>,
+}
+
+pub fn is_markdown(path: &str) -> bool {
+  matches!(Path::new(path).extension().and_then(|ext| ext.to_str()), Some("md" | "markdown"))
+}
+
+fn is_fence(line: &str) -> bool {
+  line.trim_start().starts_with("```")
+}
+
+// The text of a heading like `## Usage`
+fn heading(line: &str) -> Option<&str> {
+  let level = line.len() - line.trim_start_matches('#').len();
+  match &line[level..] {
+    rest if (1..=6).contains(&level) && (rest.is_empty() || rest.starts_with(' ')) => Some(rest.trim()),
+    _ => None,
+  }
+}
+
+// A line of three or more `-`, `*` or `_`, which may have spaces between them
+fn is_rule(line: &str) -> bool {
+  let chars: Vec<char> = line.chars().filter(|c| !c.is_whitespace()).collect();
+  chars.len() >= 3 && ['-', '*', '_'].iter().any(|&c| chars.iter().all(|&d| d == c))
+}
+
+fn starts_block(line: &str) -> bool {
+  is_fence(line) || heading(line).is_some() || is_rule(line)
+}
+
+// Splits a line into spans by its inline markup, which a span can carry on
+// to the next line of the same paragraph. A backslash shows the character
+// after it as it is.
+fn inline(line: &str, style: &mut Style) -> Vec<Span> {
+  let mut spans: Vec<Span> = Vec::new();
+  let mut text = String::new();
+  let chars: Vec<char> = line.chars().collect();
+  let mut i = 0;
+  let flush = |spans: &mut Vec<Span>, text: &mut String, style: Style| {
+    if !text.is_empty() {
+      spans.push((style, std::mem::take(text)));
+    }
+  };
+  while i < chars.len() {
+    let c = chars[i];
+    let word_edge = |j: usize| chars.get(j).is_none_or(|c| !c.is_alphanumeric());
+    match c {
+      '`' => {
+        flush(&mut spans, &mut text, *style);
+        style.code = !style.code;
+      }
+      _ if style.code => text.push(c),
+      '\\' if i + 1 < chars.len() => {
+        text.push(chars[i + 1]);
+        i += 1;
+      }
+      '*' if chars.get(i + 1) == Some(&'*') => {
+        flush(&mut spans, &mut text, *style);
+        style.bold = !style.bold;
+        i += 1;
+      }
+      '*' => {
+        flush(&mut spans, &mut text, *style);
+        style.italic = !style.italic;
+      }
+      // an underscore inside a word, as in `snake_case`, is no markup
+      '_' if i == 0 || word_edge(i - 1) || word_edge(i + 1) => {
+        flush(&mut spans, &mut text, *style);
+        style.italic = !style.italic;
+      }
+      c => text.push(c),
+    }
+    i += 1;
+  }
+  flush(&mut spans, &mut text, *style);
+  spans
+}
+
+// Shows the marker of a list item as a bullet, and that of a quote as a bar.
+fn decorate(line: &str) -> String {
+  let indent = line.len() - line.trim_start().len();
+  let (lead, rest) = line.split_at(indent);
+  for (marker, shown) in [("- ", "\u{2022} "), ("+ ", "\u{2022} "), ("> ", "\u{2502} ")] {
+    if let Some(rest) = rest.strip_prefix(marker) {
+      return format!("{}{}{}", lead, shown, rest);
+    }
+  }
+  line.to_string()
+}
+
+// Renders the lines for a preview `width` cells wide, as `cells` measures
+// text.
+pub fn render(lines: &Lines, width: usize, cells: impl Fn(&str) -> usize) -> Vec<Row> {
+  let mut rows = Vec::new();
+  let mut row = 0;
+  let mut in_code = false;
+  let code = Style{code: true, ..Style::default()};
+  while row < lines.len() {
+    let line = &lines[row];
+    let source = row;
+    row += 1;
+    if is_fence(line) {
+      in_code = !in_code;
+    } else if in_code {
+      rows.push(Row{source, spans: vec![(code, format!("  {}", line))]});
+    } else if line.trim().is_empty() {
+      // runs of blank lines show as one
+      if rows.last().is_some_and(|last: &Row| !last.spans.is_empty()) {
+        rows.push(Row{source, spans: Vec::new()});
+      }
+    } else if let Some(text) = heading(line) {
+      let mut style = Style{heading: true, ..Style::default()};
+      rows.push(Row{source, spans: inline(text, &mut style)});
+    } else if is_rule(line) {
+      rows.push(Row{source, spans: vec![(Style::default(), "\u{2500}".repeat(width))]});
+    } else {
+      // a paragraph, item or quote, up to the next block, refilled
+      let end = reflow::paragraph(source, lines).map_or(row, |rows| rows.end);
+      let end = (row..end).find(|&r| starts_block(&lines[r])).unwrap_or(end);
+      // `*` items are refilled like `-` items rather than as comments
+      let text: Vec<String> = lines.range(source..end).map(|line| {
+        let indent = line.len() - line.trim_start().len();
+        match line[indent..].strip_prefix("* ") {
+          Some(rest) => format!("{}- {}", &line[..indent], rest),
+          None => line.clone(),
+        }
+      }).collect();
+      // words are measured as shown, without their markup
+      let shown = |word: &str| cells(&word.replace(['*', '`'], ""));
+      let mut style = Style::default();
+      for filled in reflow::reflow(&text, width, shown) {
+        rows.push(Row{source, spans: inline(&decorate(&filled), &mut style)});
+      }
+      row = end;
+    }
+  }
+  rows
+}
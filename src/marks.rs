@@ -0,0 +1,63 @@
+// Named positions in a file, set with `m` and a letter and gone back to with
+// `'` and the letter. A mark stays on its line as lines are added or removed
+// above it, and goes away with its line. A mark named by a capital letter is
+// in one file only, and goes to that file from any other.
+
+use std::collections::HashMap;
+
+use crate::lines::Lines;
+
+type Pos = (usize, usize);
+
+pub struct Marks {
+  marks: HashMap<char, Pos>,
+}
+
+impl Marks {
+  pub fn new() -> Self {
+    Marks{marks: HashMap::new()}
+  }
+
+  pub fn set(&mut self, name: char, pos: Pos) {
+    self.marks.insert(name, pos);
+  }
+
+  pub fn get(&self, name: char) -> Option<Pos> {
+    self.marks.get(&name).copied()
+  }
+
+  pub fn remove(&mut self, name: char) {
+    self.marks.remove(&name);
+  }
+
+  // The marks by name
+  pub fn all(&self) -> Vec<(char, Pos)> {
+    let mut marks: Vec<_> = self.marks.iter().map(|(&name, &pos)| (name, pos)).collect();
+    marks.sort();
+    marks
+  }
+
+  // Follows an edit that replaced `removed` lines at `start` with `added`
+  // others.
+  pub fn adjust(&mut self, start: usize, removed: usize, added: usize) {
+    self.marks.retain(|_, pos| {
+      if pos.0 >= start + removed {
+        pos.0 = pos.0 + added - removed;
+      } else if pos.0 >= start + added {
+        return false;
+      }
+      true
+    });
+  }
+}
+
+// Finds the lines an edit changed, as the first of them and how many there
+// were before and after, by skipping the lines that are the same at the start
+// and the end.
+pub fn changed_lines(old: &Lines, new: &Lines) -> (usize, usize, usize) {
+  let start = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+  let end = old.iter().skip(start).rev().zip(new.iter().skip(start).rev())
+    .take_while(|(a, b)| a == b)
+    .count();
+  (start, old.len() - start - end, new.len() - start - end)
+}
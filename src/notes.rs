@@ -0,0 +1,84 @@
+// Short notes attached to lines, as when reviewing code, kept in a file
+// beside the one they are about: `main.rs.notes` for `main.rs`, with a line
+// for each note of its line number, a tab and its text. A note stays on its
+// line as lines are added or removed above it, and goes away with its line.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Default)]
+pub struct Notes {
+  notes: BTreeMap<usize, String>,
+}
+
+impl Notes {
+  pub fn is_empty(&self) -> bool {
+    self.notes.is_empty()
+  }
+
+  pub fn get(&self, row: usize) -> Option<&str> {
+    self.notes.get(&row).map(String::as_str)
+  }
+
+  pub fn set(&mut self, row: usize, text: &str) {
+    self.notes.insert(row, text.to_string());
+  }
+
+  pub fn remove(&mut self, row: usize) -> bool {
+    self.notes.remove(&row).is_some()
+  }
+
+  // The lines with notes, in order
+  pub fn rows(&self) -> Vec<usize> {
+    self.notes.keys().copied().collect()
+  }
+
+  // Follows an edit that replaced `removed` lines at `start` with `added`
+  // others.
+  pub fn adjust(&mut self, start: usize, removed: usize, added: usize) {
+    let notes = std::mem::take(&mut self.notes);
+    for (row, text) in notes {
+      if row >= start + removed {
+        self.notes.insert(row + added - removed, text);
+      } else if row < start + added {
+        self.notes.insert(row, text);
+      }
+    }
+  }
+
+  // Writes the notes beside the file, or removes the file of notes once
+  // there are none.
+  pub fn save(&self, file: &str) -> io::Result<()> {
+    let path = path(file);
+    if self.notes.is_empty() {
+      return match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+      };
+    }
+    let text: String = self.notes.iter().map(|(row, text)| format!("{}\t{}\n", row + 1, text)).collect();
+    fs::write(path, text)
+  }
+}
+
+pub fn path(file: &str) -> PathBuf {
+  PathBuf::from(format!("{}.notes", file))
+}
+
+// Reads the notes of a file, skipping lines that aren't notes.
+pub fn load(file: &str) -> io::Result<Notes> {
+  let text = match fs::read_to_string(path(file)) {
+    Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Notes::default()),
+    res => res?,
+  };
+  let mut notes = Notes::default();
+  for line in text.lines() {
+    let note = line.split_once('\t').and_then(|(row, text)| Some((row.parse::<usize>().ok()?, text)));
+    if let Some((row, text)) = note.filter(|&(row, _)| row > 0) {
+      notes.set(row - 1, text);
+    }
+  }
+  Ok(notes)
+}
@@ -0,0 +1,39 @@
+// Timings of an editing session, collected with `red --profile` and reported
+// on exit.
+
+use std::time::Duration;
+
+#[derive(Default)]
+pub struct Profile {
+  pub load: Duration,
+  pub first_render: Duration,
+  // time from reading each key to the screen being updated
+  pub keys: Vec<Duration>,
+}
+
+fn millis(d: Duration) -> String {
+  format!("{:.3}ms", d.as_secs_f64() * 1000.0)
+}
+
+impl Profile {
+  pub fn report(&self) -> String {
+    let mut report = format!(
+      "load          {}\nfirst render  {}\nkeys          {}\n",
+      millis(self.load), millis(self.first_render), self.keys.len(),
+    );
+    if !self.keys.is_empty() {
+      let mut keys = self.keys.clone();
+      keys.sort();
+      let total: Duration = keys.iter().sum();
+      let percentile = |p: usize| keys[(keys.len() - 1) * p / 100];
+      report += &format!(
+        "  mean        {}\n  median      {}\n  99th        {}\n  max         {}\n",
+        millis(total / keys.len() as u32),
+        millis(percentile(50)),
+        millis(percentile(99)),
+        millis(keys[keys.len() - 1]),
+      );
+    }
+    report
+  }
+}
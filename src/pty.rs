@@ -0,0 +1,131 @@
+// A shell run in a pseudo-terminal for `:term`, so that it and the programs
+// it runs behave as they would in a terminal of their own, while its output
+// is shown in a panel below the text.
+
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::vt::Vt;
+
+pub struct Shell {
+  child: Child,
+  // the terminal end the shell's input is written to
+  master: File,
+  output: mpsc::Receiver<Vec<u8>>,
+  pub vt: Vt,
+  size: (usize, usize),
+}
+
+fn check(res: libc::c_int) -> io::Result<libc::c_int> {
+  match res {
+    -1 => Err(io::Error::last_os_error()),
+    res => Ok(res),
+  }
+}
+
+// Opens a new pseudo-terminal, returning its two ends.
+fn open_pty() -> io::Result<(File, File)> {
+  let fd = check(unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC) })?;
+  let master = unsafe { File::from_raw_fd(fd) };
+  check(unsafe { libc::grantpt(fd) })?;
+  check(unsafe { libc::unlockpt(fd) })?;
+  let mut name = [0 as libc::c_char; 128];
+  match unsafe { libc::ptsname_r(fd, name.as_mut_ptr(), name.len()) } {
+    0 => (),
+    err => return Err(io::Error::from_raw_os_error(err)),
+  }
+  let name = unsafe { CStr::from_ptr(name.as_ptr()) };
+  let slave = std::fs::OpenOptions::new()
+    .read(true)
+    .write(true)
+    .custom_flags(libc::O_NOCTTY)
+    .open(name.to_string_lossy().as_ref())?;
+  Ok((master, slave))
+}
+
+fn set_size(fd: libc::c_int, (rows, cols): (usize, usize)) -> io::Result<()> {
+  let size = libc::winsize{ws_row: rows as u16, ws_col: cols as u16, ws_xpixel: 0, ws_ypixel: 0};
+  check(unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &size) }).map(|_| ())
+}
+
+impl Shell {
+  // Starts the program in a terminal of `rows` by `cols`. It is the leader of
+  // a session of its own, with the terminal as its controlling terminal, so
+  // that Ctrl-C typed into it reaches what it runs.
+  pub fn spawn(args: &[&str], rows: usize, cols: usize) -> io::Result<Self> {
+    let (master, slave) = open_pty()?;
+    set_size(master.as_raw_fd(), (rows, cols))?;
+    let mut command = Command::new(args[0]);
+    command.args(&args[1..])
+      .env("TERM", "dumb")
+      .stdin(Stdio::from(slave.try_clone()?))
+      .stdout(Stdio::from(slave.try_clone()?))
+      .stderr(Stdio::from(slave));
+    unsafe {
+      command.pre_exec(|| {
+        check(libc::setsid())?;
+        check(libc::ioctl(0, libc::TIOCSCTTY, 0)).map(|_| ())
+      });
+    }
+    let child = command.spawn()?;
+    let mut reader = master.try_clone()?;
+    let (tx, output) = mpsc::channel();
+    thread::spawn(move || {
+      let mut bytes = [0; 4096];
+      // reading fails once the shell and everything it ran have exited
+      while let Ok(n @ 1..) = reader.read(&mut bytes) {
+        if tx.send(bytes[..n].to_vec()).is_err() {
+          break;
+        }
+      }
+    });
+    Ok(Shell{child, master, output, vt: Vt::new(rows), size: (rows, cols)})
+  }
+
+  // Takes in what the shell has written since, returning whether there was
+  // anything.
+  pub fn read(&mut self) -> bool {
+    let mut any = false;
+    while let Ok(bytes) = self.output.try_recv() {
+      self.vt.feed(&bytes);
+      any = true;
+    }
+    any
+  }
+
+  pub fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+    self.master.write_all(bytes)
+  }
+
+  // Tells the shell the size of its panel, which is sent the SIGWINCH it
+  // expects when it changes.
+  pub fn resize(&mut self, rows: usize, cols: usize) -> io::Result<()> {
+    if self.size == (rows, cols) {
+      return Ok(());
+    }
+    self.size = (rows, cols);
+    self.vt.resize(rows);
+    set_size(self.master.as_raw_fd(), (rows, cols))
+  }
+
+  pub fn has_exited(&mut self) -> bool {
+    !matches!(self.child.try_wait(), Ok(None))
+  }
+}
+
+// The shell and what it runs are hung up on when the editor is done with
+// them.
+impl Drop for Shell {
+  fn drop(&mut self) {
+    unsafe { libc::kill(-(self.child.id() as libc::pid_t), libc::SIGHUP) };
+    let _ = self.child.kill();
+    let _ = self.child.wait();
+  }
+}
@@ -0,0 +1,117 @@
+// Reflowing paragraphs of prose and comments to a maximum line width.
+//
+// A paragraph is a run of non-blank lines with the same prefix, where the
+// prefix is the indentation plus any comment marker, like `    // ` or `# `.
+// The words of the paragraph are refilled with the prefix of its first line.
+// An item of a list, starting with a marker like `-` or `1.`, is a paragraph
+// of its own, whose lines after the first are indented to its text.
+
+use std::ops::Range;
+
+use crate::lines::Lines;
+
+const COMMENT_MARKERS: &[&str] = &["///", "//!", "//", "#", "--", ";", ">", "*"];
+
+// Splits a line into its prefix and its text.
+pub fn split_prefix(line: &str) -> (&str, &str) {
+  let indent = line.len() - line.trim_start().len();
+  let mut end = indent;
+  if let Some(marker) = COMMENT_MARKERS.iter().find(|m| line[indent..].starts_with(*m)) {
+    end += marker.len();
+    end += line[end..].len() - line[end..].trim_start().len();
+  }
+  line.split_at(end)
+}
+
+// The length of a list marker, `-`, `+`, or a number and `.` or `)`, with the
+// spaces after it, at the start of the text of a line
+fn list_marker(text: &str) -> Option<usize> {
+  let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+  let marker = match text[digits..].chars().next() {
+    Some('-' | '+') if digits == 0 => 1,
+    Some('.' | ')') if digits > 0 => digits + 1,
+    _ => return None,
+  };
+  let rest = &text[marker..];
+  let spaces = rest.len() - rest.trim_start_matches(' ').len();
+  match spaces {
+    0 => None,
+    _ if rest.trim().is_empty() => None,
+    _ => Some(marker + spaces),
+  }
+}
+
+// Whether a line goes on the paragraph of the line before it: it has the
+// same prefix, or the indentation of a list item's text, and starts no item
+// of its own.
+fn continues(prev: &str, line: &str) -> bool {
+  let (prefix, text) = split_prefix(line);
+  let (prev_prefix, prev_text) = split_prefix(prev);
+  if text.trim().is_empty() || prev_text.trim().is_empty() || list_marker(text).is_some() {
+    return false;
+  }
+  match list_marker(prev_text) {
+    Some(n) => prefix.strip_prefix(prev_prefix).is_some_and(|hang| hang.len() == n && hang.trim().is_empty()),
+    None => prefix.trim_end() == prev_prefix.trim_end(),
+  }
+}
+
+// The rows of the paragraph containing `row`, if it is not blank.
+pub fn paragraph(row: usize, buf: &Lines) -> Option<Range<usize>> {
+  let (_, text) = split_prefix(buf.get(row)?);
+  if text.trim().is_empty() {
+    return None;
+  }
+  let mut start = row;
+  while start > 0 && continues(&buf[start - 1], &buf[start]) {
+    start -= 1;
+  }
+  let mut end = row + 1;
+  while end < buf.len() && continues(&buf[end - 1], &buf[end]) {
+    end += 1;
+  }
+  Some(start..end)
+}
+
+// Refills the words of `lines` into lines of at most `width` cells, as
+// `cells` measures them, except where a single word is longer than that.
+pub fn reflow(lines: &[String], width: usize, cells: impl Fn(&str) -> usize) -> Vec<String> {
+  let (prefix, first) = lines.first().map_or(("", ""), |line| split_prefix(line));
+  // the marker of a list item stays on its first line, and the lines after
+  // it are indented under its text
+  let (marker, hang) = match list_marker(first) {
+    Some(n) => (&first[..n], format!("{}{}", prefix, " ".repeat(n))),
+    None => ("", prefix.to_string()),
+  };
+  let first = format!("{}{}", prefix, marker);
+  let words = lines.iter().enumerate().flat_map(|(i, line)| {
+    let text = split_prefix(line).1;
+    match i {
+      0 => &text[marker.len()..],
+      _ => text,
+    }.split_whitespace()
+  });
+  let mut out = Vec::new();
+  let mut line = String::new();
+  let mut used = 0;
+  for word in words {
+    let n = cells(word);
+    if !line.is_empty() && used + 1 + n > width {
+      out.push(line);
+      line = String::new();
+    }
+    if line.is_empty() {
+      line.push_str(if out.is_empty() { &first } else { &hang });
+      used = cells(&line);
+    } else {
+      line.push(' ');
+      used += 1;
+    }
+    line.push_str(word);
+    used += n;
+  }
+  if !line.is_empty() {
+    out.push(line);
+  }
+  out
+}
@@ -0,0 +1,140 @@
+// Remote control of a running editor over a Unix socket. `red --listen
+// <socket>` takes requests on it, and `red --remote <socket> <args>` sends
+// one and waits for the reply.
+//
+// A request is the arguments of the client, one per line: the files to open,
+// named as on the command line, and `--command` followed by a command to run
+// as if typed after `:`. The reply is one line, `ok` or `error`, then a tab
+// and a message for the user, if there is one.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+// How long a client is kept waiting for an editor busy with something else
+const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Request {
+  pub args: Vec<String>,
+  reply: mpsc::Sender<Result<String, String>>,
+}
+
+impl Request {
+  pub fn reply(self, res: Result<String, String>) {
+    let _ = self.reply.send(res);
+  }
+}
+
+// The socket being listened on, which is removed when the editor stops
+pub struct Server {
+  path: PathBuf,
+}
+
+impl Drop for Server {
+  fn drop(&mut self) {
+    let _ = fs::remove_file(&self.path);
+  }
+}
+
+// A socket bound but not answering requests yet
+pub struct Socket {
+  listener: UnixListener,
+  server: Server,
+}
+
+// Binds the socket, taking the place of one left behind by an editor that
+// is gone, but not of one still listening, nor of anything that isn't a
+// socket. Only the user can connect, since a request can run commands: the
+// socket is bound in a directory only the user can enter, and linked into
+// place once its permissions are set.
+pub fn bind(path: &Path) -> io::Result<Socket> {
+  match fs::symlink_metadata(path) {
+    Ok(meta) if !meta.file_type().is_socket() =>
+      return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} is not a socket", path.display()))),
+    Ok(_) if UnixStream::connect(path).is_ok() =>
+      return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("{} is in use by another red", path.display()))),
+    Ok(_) => fs::remove_file(path)?,
+    Err(ref err) if err.kind() == io::ErrorKind::NotFound => (),
+    Err(err) => return Err(err),
+  }
+  let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+  let private = tempfile::Builder::new().prefix(".red-").tempdir_in(dir)?;
+  let bound = private.path().join("socket");
+  let listener = UnixListener::bind(&bound)?;
+  fs::set_permissions(&bound, fs::Permissions::from_mode(0o600))?;
+  fs::hard_link(&bound, path)?;
+  Ok(Socket{listener, server: Server{path: path.to_path_buf()}})
+}
+
+impl Socket {
+  // Passes each request to `handle` until it returns false.
+  pub fn serve<F>(self, handle: F) -> Server
+  where
+    F: Fn(Request) -> bool + Send + 'static,
+  {
+    let Socket{listener, server} = self;
+    thread::spawn(move || {
+      for stream in listener.incoming() {
+        let stream = match stream {
+          Ok(stream) => stream,
+          Err(_) => continue,
+        };
+        let (tx, rx) = mpsc::channel();
+        // a connection with nothing to ask is another red checking whether
+        // the socket is in use
+        let args = match read_args(&stream) {
+          Ok(args) if !args.is_empty() => args,
+          _ => continue,
+        };
+        if !handle(Request{args, reply: tx}) {
+          return;
+        }
+        let reply = rx.recv_timeout(REPLY_TIMEOUT).unwrap_or_else(|_| Err(String::from("red did not answer")));
+        let _ = write_reply(&stream, &reply);
+      }
+    });
+    server
+  }
+}
+
+fn read_args(stream: &UnixStream) -> io::Result<Vec<String>> {
+  stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+  let mut text = String::new();
+  BufReader::new(stream).read_to_string(&mut text)?;
+  Ok(text.lines().map(String::from).collect())
+}
+
+fn write_reply(mut stream: &UnixStream, reply: &Result<String, String>) -> io::Result<()> {
+  let line = match reply {
+    Ok(msg) => format!("ok\t{}", msg),
+    Err(msg) => format!("error\t{}", msg),
+  };
+  writeln!(stream, "{}", line.replace('\n', " "))
+}
+
+// Sends the arguments to the editor listening on the socket, returning the
+// message it replied with, or an error with the one it failed with.
+pub fn send(path: &Path, args: &[String]) -> io::Result<String> {
+  if let Some(arg) = args.iter().find(|arg| arg.contains('\n')) {
+    return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("`{}` can't be sent", arg.escape_debug())));
+  }
+  let mut stream = UnixStream::connect(path)
+    .map_err(|err| io::Error::new(err.kind(), format!("no red is listening on {}: {}", path.display(), err)))?;
+  for arg in args {
+    writeln!(stream, "{}", arg)?;
+  }
+  stream.shutdown(Shutdown::Write)?;
+  let mut line = String::new();
+  BufReader::new(stream).read_line(&mut line)?;
+  match line.trim_end_matches('\n').split_once('\t') {
+    Some(("ok", msg)) => Ok(msg.to_string()),
+    Some(("error", msg)) => Err(io::Error::other(msg.to_string())),
+    _ => Err(io::Error::other("red gave no answer")),
+  }
+}
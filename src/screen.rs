@@ -0,0 +1,257 @@
+// The terminal, with the last frame drawn on it remembered, so that a new
+// frame only redraws the rows that changed.
+//
+// Between `begin_frame` and `flush`, output following `start_row` is kept as
+// the contents of that row, and any other output, like moving the cursor, is
+// written after the rows. Outside of a frame, output goes straight to the
+// terminal and the remembered frame is forgotten.
+
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crate::color::{self, Color, Depth};
+
+// The shape of the text cursor, set with the DECSCUSR sequence, which
+// terminals that don't know it ignore
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CursorShape {
+  Block,
+  Bar,
+  Underline,
+}
+
+impl CursorShape {
+  fn sequence(self) -> &'static str {
+    match self {
+      CursorShape::Block => "\x1b[2 q",
+      CursorShape::Bar => "\x1b[6 q",
+      CursorShape::Underline => "\x1b[4 q",
+    }
+  }
+}
+
+// Gives the cursor back the shape the terminal chose for it.
+pub const RESET_CURSOR_SHAPE: &str = "\x1b[0 q";
+
+// Bracketed paste: the terminal marks pasted text with these, so that it can
+// be told apart from typing.
+pub const PASTE_START: &[u8] = b"\x1b[200~";
+pub const PASTE_END: &[u8] = b"\x1b[201~";
+pub const DISABLE_BRACKETED_PASTE: &str = "\x1b[?2004l";
+const ENABLE_BRACKETED_PASTE: &str = "\x1b[?2004h";
+
+// Reverse video for the whole screen, which the visual bell flashes
+const FLASH_ON: &[u8] = b"\x1b[?5h";
+const FLASH_OFF: &[u8] = b"\x1b[?5l";
+
+pub struct Screen<W: Write> {
+  out: W,
+  framing: bool,
+  // the frame being drawn, and the one on the terminal
+  rows: Vec<Vec<u8>>,
+  shown: Vec<Vec<u8>>,
+  row: Option<usize>,
+  tail: Vec<u8>,
+  // the size of the terminal when the last frame was drawn
+  size: Option<(u16, u16)>,
+  // how many colors the terminal shows
+  depth: Depth,
+  // the shape the cursor was given, if any
+  shape: Option<CursorShape>,
+  bracketed_paste: bool,
+  // when the screen was flashed, until it is turned back
+  flashed: Option<Instant>,
+}
+
+impl<W: Write> Screen<W> {
+  pub fn new(out: W) -> Self {
+    Screen{
+      out,
+      framing: false,
+      rows: Vec::new(),
+      shown: Vec::new(),
+      row: None,
+      tail: Vec::new(),
+      size: None,
+      depth: color::depth(),
+      shape: None,
+      bracketed_paste: false,
+      flashed: None,
+    }
+  }
+
+  // Overrides the colors found from the environment.
+  pub fn set_depth(&mut self, depth: Depth) {
+    self.depth = depth;
+  }
+
+  pub fn set_fg(&mut self, color: Color) -> io::Result<()> {
+    let seq = color::fg(color, self.depth);
+    self.write_all(seq.as_bytes())
+  }
+
+  pub fn set_bg(&mut self, color: Color) -> io::Result<()> {
+    let seq = color::bg(color, self.depth);
+    self.write_all(seq.as_bytes())
+  }
+
+  // Changes the shape of the cursor, if it has another one.
+  pub fn set_cursor_shape(&mut self, shape: CursorShape) -> io::Result<()> {
+    if self.shape == Some(shape) {
+      return Ok(());
+    }
+    self.shape = Some(shape);
+    self.write_all(shape.sequence().as_bytes())
+  }
+
+  pub fn bracketed_paste(&self) -> bool {
+    self.bracketed_paste
+  }
+
+  pub fn set_bracketed_paste(&mut self, on: bool) -> io::Result<()> {
+    if self.bracketed_paste == on {
+      return Ok(());
+    }
+    self.bracketed_paste = on;
+    let seq = if on { ENABLE_BRACKETED_PASTE } else { DISABLE_BRACKETED_PASTE };
+    self.write_all(seq.as_bytes())
+  }
+
+  // Turns the screen to reverse video until `end_flash`.
+  pub fn flash(&mut self) -> io::Result<()> {
+    self.flashed = Some(Instant::now());
+    self.out.write_all(FLASH_ON)?;
+    self.out.flush()
+  }
+
+  // Turns a flash back once it has lasted long enough, which the program's
+  // loop checks between keys and ticks, rather than another thread writing
+  // to the terminal behind its back.
+  pub fn end_flash(&mut self, after: Duration) -> io::Result<()> {
+    match self.flashed {
+      Some(flashed) if flashed.elapsed() >= after => {
+        self.flashed = None;
+        self.out.write_all(FLASH_OFF)?;
+        self.out.flush()
+      }
+      _ => Ok(()),
+    }
+  }
+
+  // Starts a new frame. After the terminal is resized, it is drawn in full.
+  pub fn begin_frame(&mut self) {
+    let size = termion::terminal_size().ok();
+    if size != self.size {
+      self.size = size;
+      self.shown.clear();
+    }
+    self.framing = true;
+    self.rows.clear();
+    self.row = None;
+    self.tail.clear();
+  }
+
+  // Starts drawing a row, counted from 0, replacing anything drawn on it
+  // before in the frame.
+  pub fn start_row(&mut self, row: usize) -> io::Result<()> {
+    if !self.framing {
+      self.shown.clear();
+      let goto = termion::cursor::Goto(1, row as u16 + 1);
+      return write!(self.out, "{}{}", goto, termion::clear::CurrentLine);
+    }
+    if self.rows.len() <= row {
+      self.rows.resize(row + 1, Vec::new());
+    }
+    self.rows[row].clear();
+    self.row = Some(row);
+    Ok(())
+  }
+
+  // Goes back to a row already drawn in the frame, adding to its end.
+  pub fn continue_row(&mut self, row: usize) {
+    if self.framing && row < self.rows.len() {
+      self.row = Some(row);
+    }
+  }
+
+  // Stops drawing the current row, so that output goes after the rows.
+  pub fn end_row(&mut self) {
+    self.row = None;
+  }
+
+  // Forgets the frame on the terminal, for when it has been cleared or
+  // drawn over, so that the next frame is drawn in full.
+  pub fn forget(&mut self) {
+    self.shown.clear();
+    self.shape = None;
+  }
+
+  fn present(&mut self) -> io::Result<()> {
+    for i in 0..self.rows.len().max(self.shown.len()) {
+      let row = self.rows.get(i).map_or(&[][..], |row| &row[..]);
+      if self.shown.get(i).map_or(&[][..], |row| &row[..]) == row {
+        continue;
+      }
+      let goto = termion::cursor::Goto(1, i as u16 + 1);
+      write!(self.out, "{}{}{}", goto, termion::style::Reset, termion::clear::CurrentLine)?;
+      self.out.write_all(row)?;
+    }
+    self.out.write_all(&self.tail)?;
+    self.shown = std::mem::take(&mut self.rows);
+    self.framing = false;
+    self.row = None;
+    self.tail.clear();
+    Ok(())
+  }
+
+  #[cfg(test)]
+  pub fn get_ref(&self) -> &W {
+    &self.out
+  }
+
+  // The rows of the frame on the terminal, as they were written
+  #[cfg(test)]
+  pub fn shown_rows(&self) -> &[Vec<u8>] {
+    &self.shown
+  }
+}
+
+impl<W: Write> Write for Screen<W> {
+  fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+    match (self.framing, self.row) {
+      (false, _) => self.out.write(data),
+      (true, Some(row)) => {
+        self.rows[row].extend_from_slice(data);
+        Ok(data.len())
+      }
+      (true, None) => {
+        self.tail.extend_from_slice(data);
+        Ok(data.len())
+      }
+    }
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    if self.framing {
+      self.present()?;
+    }
+    self.out.flush()
+  }
+}
+
+// A cursor given a shape, bracketed paste and a flash are left the way the
+// terminal had them.
+impl<W: Write> Drop for Screen<W> {
+  fn drop(&mut self) {
+    if self.flashed.is_some() {
+      let _ = self.out.write_all(FLASH_OFF);
+    }
+    if self.shape.is_some() {
+      let _ = self.out.write_all(RESET_CURSOR_SHAPE.as_bytes());
+    }
+    if self.bracketed_paste {
+      let _ = self.out.write_all(DISABLE_BRACKETED_PASTE.as_bytes());
+    }
+    let _ = self.out.flush();
+  }
+}
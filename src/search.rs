@@ -0,0 +1,61 @@
+// Finding text in a buffer. Positions are (row, column), with columns counted
+// in characters like the cursor's.
+
+use crate::lines::Lines;
+
+fn char_col(line: &str, byte: usize) -> usize {
+  line[..byte].chars().count()
+}
+
+fn byte_col(line: &str, col: usize) -> usize {
+  line.char_indices().nth(col).map_or(line.len(), |(i, _)| i)
+}
+
+// The first match of `query` after the position, searching forward to the
+// end of the buffer.
+pub fn find_next(buf: &Lines, query: &str, row: usize, col: usize) -> Option<(usize, usize)> {
+  if query.is_empty() {
+    return None;
+  }
+  let line = buf.get(row)?;
+  let from = line.char_indices().nth(col).map(|(i, c)| i + c.len_utf8());
+  if let Some(i) = from.and_then(|from| line[from..].find(query).map(|i| from + i)) {
+    return Some((row, char_col(line, i)));
+  }
+  buf.iter()
+    .enumerate()
+    .skip(row + 1)
+    .find_map(|(r, line)| line.find(query).map(|i| (r, char_col(line, i))))
+}
+
+// The last match of `query` before the position, searching backward to the
+// start of the buffer.
+pub fn find_prev(buf: &Lines, query: &str, row: usize, col: usize) -> Option<(usize, usize)> {
+  if query.is_empty() {
+    return None;
+  }
+  if let Some(line) = buf.get(row) {
+    let end = byte_col(line, col);
+    if let Some(i) = line.match_indices(query).map(|(i, _)| i).take_while(|&i| i < end).last() {
+      return Some((row, char_col(line, i)));
+    }
+  }
+  buf.iter()
+    .enumerate()
+    .take(row.min(buf.len()))
+    .rev()
+    .find_map(|(r, line)| line.rfind(query).map(|i| (r, char_col(line, i))))
+}
+
+pub fn find_first(buf: &Lines, query: &str) -> Option<(usize, usize)> {
+  if query.is_empty() {
+    return None;
+  }
+  buf.iter()
+    .enumerate()
+    .find_map(|(r, line)| line.find(query).map(|i| (r, char_col(line, i))))
+}
+
+pub fn find_last(buf: &Lines, query: &str) -> Option<(usize, usize)> {
+  find_prev(buf, query, buf.len(), 0)
+}
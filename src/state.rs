@@ -0,0 +1,78 @@
+// What the editor remembers about a file from one session to the next, like
+// its closed folds, kept under `~/.config/red/state/`.
+//
+// Each file gets a state file named after its absolute path with `/`
+// replaced by `%`, as in the history, holding one `key = value` line per
+// thing remembered. What isn't about one file, like the marks that name a
+// file, is kept the same way in a state file of its own, whose name has no
+// `%`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub type State = BTreeMap<String, String>;
+
+fn state_path(root: &Path, path: &Path) -> io::Result<PathBuf> {
+  let path = fs::canonicalize(path)?;
+  Ok(root.join(path.to_string_lossy().replace('/', "%")))
+}
+
+fn read(file: io::Result<PathBuf>) -> State {
+  let text = file.and_then(fs::read_to_string).unwrap_or_default();
+  text.lines()
+    .filter_map(|line| line.split_once(" = "))
+    .map(|(key, value)| (key.to_string(), value.to_string()))
+    .collect()
+}
+
+// The state of a file, which is empty for a file never seen before or one
+// whose state can't be read.
+pub fn load(root: &Path, path: &Path) -> State {
+  read(state_path(root, path))
+}
+
+// The state kept under a name rather than for a file
+pub fn load_named(root: &Path, name: &str) -> State {
+  read(Ok(root.join(name)))
+}
+
+// Sets a value in the state of a file, or takes it out if it is empty. The
+// state of a file with nothing to remember is removed.
+pub fn update(root: &Path, path: &Path, key: &str, value: &str) -> io::Result<()> {
+  update_all(root, path, &[(key, value)])
+}
+
+// Sets several values in the state of a file at once.
+pub fn update_all(root: &Path, path: &Path, values: &[(&str, &str)]) -> io::Result<()> {
+  write(root, state_path(root, path)?, values)
+}
+
+pub fn update_named(root: &Path, name: &str, key: &str, value: &str) -> io::Result<()> {
+  write(root, root.join(name), &[(key, value)])
+}
+
+fn write(root: &Path, file: PathBuf, values: &[(&str, &str)]) -> io::Result<()> {
+  let mut state = read(Ok(file.clone()));
+  let before = state.clone();
+  for &(key, value) in values {
+    if value.is_empty() {
+      state.remove(key);
+    } else {
+      state.insert(key.to_string(), value.to_string());
+    }
+  }
+  if state == before {
+    return Ok(());
+  }
+  if state.is_empty() {
+    return match fs::remove_file(&file) {
+      Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+      res => res,
+    };
+  }
+  fs::create_dir_all(root)?;
+  let text: String = state.iter().map(|(key, value)| format!("{} = {}\n", key, value)).collect();
+  fs::write(file, text)
+}
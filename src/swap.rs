@@ -0,0 +1,47 @@
+// Swap files: the unsaved text of a file, written while the editor is idle,
+// so that an editor that ends without saving it, like one that crashes or is
+// killed, leaves it behind for `:recover`. They are kept under
+// `~/.config/red/swap/`, named after the absolute path of the file like the
+// history, and removed when the file is saved or the editor quits.
+
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use crate::lines::Lines;
+
+fn swap_path(root: &Path, path: &Path) -> io::Result<PathBuf> {
+  let path = fs::canonicalize(path)?;
+  Ok(root.join(path.to_string_lossy().replace('/', "%")))
+}
+
+// Swap files can only be read by the user, like the file they are of may be.
+pub fn write(root: &Path, path: &Path, lines: &Lines) -> io::Result<()> {
+  let swap = swap_path(root, path)?;
+  fs::create_dir_all(root)?;
+  let mut text = String::new();
+  for line in lines {
+    text.push_str(line);
+    text.push('\n');
+  }
+  let mut file = fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(swap)?;
+  file.set_permissions(fs::Permissions::from_mode(0o600))?;
+  file.write_all(text.as_bytes())
+}
+
+// The text left in the swap file of a file, if there is one.
+pub fn read(root: &Path, path: &Path) -> io::Result<Option<Vec<String>>> {
+  match swap_path(root, path).and_then(fs::read_to_string) {
+    Ok(text) => Ok(Some(text.lines().map(String::from).collect())),
+    Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+    Err(err) => Err(err),
+  }
+}
+
+pub fn remove(root: &Path, path: &Path) -> io::Result<()> {
+  match swap_path(root, path).and_then(fs::remove_file) {
+    Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+    res => res,
+  }
+}
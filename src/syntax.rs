@@ -0,0 +1,590 @@
+// Syntax found by a small lexer for each kind of file. Comments, strings,
+// numbers and keywords are highlighted, and the brackets and commas outside
+// them give the structure of the code: its functions, blocks and arguments,
+// for motions and text objects. A line is lexed again only when its text or
+// the state it starts in, like inside a block comment, has changed.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+
+use crate::filetype::FileType;
+use crate::fold;
+use crate::lines::Lines;
+
+pub struct Language {
+  pub keywords: &'static [&'static str],
+  // the keywords that start the definition of a function
+  pub functions: &'static [&'static str],
+  pub block_comment: Option<(&'static str, &'static str)>,
+  // the characters that start and end a string
+  pub quotes: &'static str,
+  // whether a string not closed on its line goes on to the next
+  pub multiline_strings: bool,
+  // whether blocks are in braces, or are the more indented lines after a
+  // line, as in Python
+  pub braces: bool,
+  // whether a block after a header like `int main(void)` is a function, as
+  // in C, without a keyword
+  pub c_functions: bool,
+}
+
+pub const RUST: Language = Language{
+  keywords: &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while",
+  ],
+  functions: &["fn"],
+  block_comment: Some(("/*", "*/")),
+  quotes: "\"",
+  multiline_strings: true,
+  braces: true,
+  c_functions: false,
+};
+
+pub const C: Language = Language{
+  keywords: &[
+    "auto", "bool", "break", "case", "char", "class", "const", "continue", "default", "delete",
+    "do", "double", "else", "enum", "extern", "false", "float", "for", "goto", "if", "inline",
+    "int", "long", "namespace", "new", "nullptr", "private", "protected", "public", "register",
+    "return", "short", "signed", "sizeof", "static", "struct", "switch", "template", "this",
+    "true", "typedef", "typename", "union", "unsigned", "virtual", "void", "volatile", "while",
+  ],
+  functions: &[],
+  block_comment: Some(("/*", "*/")),
+  quotes: "\"'",
+  multiline_strings: false,
+  braces: true,
+  c_functions: true,
+};
+
+pub const GO: Language = Language{
+  keywords: &[
+    "break", "case", "chan", "const", "continue", "default", "defer", "else", "fallthrough",
+    "false", "for", "func", "go", "goto", "if", "import", "interface", "map", "nil", "package",
+    "range", "return", "select", "struct", "switch", "true", "type", "var",
+  ],
+  functions: &["func"],
+  block_comment: Some(("/*", "*/")),
+  quotes: "\"'`",
+  multiline_strings: true,
+  braces: true,
+  c_functions: false,
+};
+
+pub const JAVA: Language = Language{
+  keywords: &[
+    "abstract", "boolean", "break", "case", "catch", "class", "continue", "def", "default", "do",
+    "else", "enum", "extends", "false", "final", "finally", "for", "fun", "if", "implements",
+    "import", "interface", "new", "null", "object", "package", "private", "protected", "public",
+    "return", "static", "super", "switch", "this", "throw", "throws", "true", "try", "val", "var",
+    "void", "while",
+  ],
+  functions: &["fun", "def"],
+  block_comment: Some(("/*", "*/")),
+  quotes: "\"'",
+  multiline_strings: false,
+  braces: true,
+  c_functions: true,
+};
+
+pub const JAVASCRIPT: Language = Language{
+  keywords: &[
+    "async", "await", "break", "case", "catch", "class", "const", "continue", "default", "delete",
+    "do", "else", "export", "extends", "false", "finally", "for", "function", "if", "import", "in",
+    "instanceof", "interface", "let", "new", "null", "return", "super", "switch", "this", "throw",
+    "true", "try", "type", "typeof", "undefined", "var", "void", "while", "yield",
+  ],
+  functions: &["function"],
+  block_comment: Some(("/*", "*/")),
+  quotes: "\"'`",
+  multiline_strings: true,
+  braces: true,
+  c_functions: true,
+};
+
+pub const PYTHON: Language = Language{
+  keywords: &[
+    "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif",
+    "else", "except", "False", "finally", "for", "from", "global", "if", "import", "in", "is",
+    "lambda", "None", "nonlocal", "not", "or", "pass", "raise", "return", "True", "try", "while",
+    "with", "yield",
+  ],
+  functions: &["def"],
+  block_comment: None,
+  quotes: "\"'",
+  multiline_strings: false,
+  braces: false,
+  c_functions: false,
+};
+
+pub const RUBY: Language = Language{
+  keywords: &[
+    "and", "begin", "case", "class", "def", "do", "else", "elsif", "end", "ensure", "false", "for",
+    "if", "module", "nil", "not", "or", "rescue", "return", "self", "then", "true", "unless",
+    "until", "when", "while", "yield",
+  ],
+  functions: &["def"],
+  block_comment: None,
+  quotes: "\"'",
+  multiline_strings: false,
+  braces: false,
+  c_functions: false,
+};
+
+pub const LUA: Language = Language{
+  keywords: &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "if", "in", "local",
+    "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+  ],
+  functions: &["function"],
+  block_comment: None,
+  quotes: "\"'",
+  multiline_strings: false,
+  braces: false,
+  c_functions: false,
+};
+
+pub const SHELL: Language = Language{
+  keywords: &[
+    "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if", "in",
+    "local", "return", "then", "until", "while",
+  ],
+  functions: &["function"],
+  block_comment: None,
+  quotes: "\"'",
+  multiline_strings: true,
+  braces: true,
+  c_functions: true,
+};
+
+// Words that start a header like `if (x)`, which is no function
+const CONTROL: &[&str] = &["if", "for", "while", "switch", "catch", "else", "do", "try", "return", "synchronized"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+  Comment,
+  String,
+  Number,
+  Keyword,
+  // the name of a function where it is defined
+  Function,
+}
+
+// Where a line starts: in code, or in a block comment or string that an
+// earlier line left open
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+  Code,
+  Comment,
+  Str(char),
+}
+
+struct Lexed {
+  hash: u64,
+  start: State,
+  end: State,
+  spans: Vec<(Range<usize>, Kind)>,
+  // the brackets, commas and semicolons outside comments and strings
+  puncts: Vec<(usize, char)>,
+}
+
+fn starts_with(chars: &[char], i: usize, text: &str) -> bool {
+  !text.is_empty() && text.chars().enumerate().all(|(j, c)| chars.get(i + j) == Some(&c))
+}
+
+fn is_word_char(c: char) -> bool {
+  c.is_alphanumeric() || c == '_'
+}
+
+// Goes to the end of a block comment, or of the line if it doesn't end on it.
+fn close_comment(chars: &[char], mut i: usize, close: &str, state: &mut State) -> usize {
+  while i < chars.len() {
+    if starts_with(chars, i, close) {
+      *state = State::Code;
+      return i + close.chars().count();
+    }
+    i += 1;
+  }
+  *state = State::Comment;
+  i
+}
+
+// Goes past the quote ending a string, skipping escaped characters.
+fn close_string(chars: &[char], mut i: usize, quote: char, lang: &Language, state: &mut State) -> usize {
+  while i < chars.len() {
+    match chars[i] {
+      '\\' => i += 1,
+      c if c == quote => {
+        *state = State::Code;
+        return i + 1;
+      }
+      _ => (),
+    }
+    i += 1;
+  }
+  *state = if lang.multiline_strings { State::Str(quote) } else { State::Code };
+  chars.len()
+}
+
+fn lex(line: &str, start: State, comment: &str, lang: &Language) -> Lexed {
+  let chars: Vec<char> = line.chars().collect();
+  let mut spans = Vec::new();
+  let mut puncts = Vec::new();
+  let mut state = start;
+  let mut i = 0;
+  let mut after_function = false;
+  while i < chars.len() {
+    let from = i;
+    match state {
+      State::Comment => {
+        i = close_comment(&chars, i, lang.block_comment.map_or("", |(_, close)| close), &mut state);
+        spans.push((from..i, Kind::Comment));
+        continue;
+      }
+      State::Str(quote) => {
+        i = close_string(&chars, i, quote, lang, &mut state);
+        spans.push((from..i, Kind::String));
+        continue;
+      }
+      State::Code => (),
+    }
+    let c = chars[i];
+    if starts_with(&chars, i, comment) {
+      spans.push((i..chars.len(), Kind::Comment));
+      break;
+    }
+    if let Some((open, close)) = lang.block_comment.filter(|(open, _)| starts_with(&chars, i, open)) {
+      i = close_comment(&chars, i + open.chars().count(), close, &mut state);
+      spans.push((from..i, Kind::Comment));
+    } else if lang.quotes.contains(c) {
+      i = close_string(&chars, i + 1, c, lang, &mut state);
+      spans.push((from..i, Kind::String));
+    } else if c == '\'' && (chars.get(i + 2) == Some(&'\'') || chars.get(i + 1) == Some(&'\\')) {
+      // a character like `'x'` where a lone quote starts no string, as in
+      // Rust's lifetimes
+      i = (i + 2..chars.len().min(i + 12)).find(|&j| chars[j] == '\'').map_or(i + 1, |j| j + 1);
+      spans.push((from..i, Kind::String));
+    } else if c.is_ascii_digit() {
+      while i < chars.len() && (is_word_char(chars[i]) || chars[i] == '.') {
+        i += 1;
+      }
+      spans.push((from..i, Kind::Number));
+    } else if is_word_char(c) {
+      while i < chars.len() && is_word_char(chars[i]) {
+        i += 1;
+      }
+      let word: String = chars[from..i].iter().collect();
+      if after_function {
+        spans.push((from..i, Kind::Function));
+        after_function = false;
+      } else if lang.keywords.contains(&word.as_str()) {
+        spans.push((from..i, Kind::Keyword));
+        after_function = lang.functions.contains(&word.as_str());
+      }
+    } else {
+      if "(){}[],;".contains(c) {
+        puncts.push((i, c));
+        after_function = false;
+      }
+      i += 1;
+    }
+  }
+  if let State::Str(_) = state {
+    if !lang.multiline_strings {
+      state = State::Code;
+    }
+  }
+  Lexed{hash: hash(line), start, end: state, spans, puncts}
+}
+
+fn hash(line: &str) -> u64 {
+  let mut hasher = DefaultHasher::new();
+  line.hash(&mut hasher);
+  hasher.finish()
+}
+
+// A pair of matching brackets, by the positions of the two
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pair {
+  pub open: (usize, usize),
+  pub close: (usize, usize),
+  pub bracket: char,
+}
+
+// What a text object covers: whole lines, or the characters from one
+// position up to another
+#[derive(Clone, Debug, PartialEq)]
+pub enum Object {
+  Lines(Range<usize>),
+  Chars((usize, usize), (usize, usize)),
+}
+
+// A function, from the start of its definition, on the lines it takes up
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+  pub start: (usize, usize),
+  pub rows: Range<usize>,
+  // its body, without the braces
+  pub inner: Option<Object>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+  Function,
+  Block,
+  Argument,
+}
+
+// The lines of a file as lexed, for the kind of file they were lexed as
+#[derive(Default)]
+pub struct Highlights {
+  names: Option<&'static [&'static str]>,
+  lines: Vec<Option<Lexed>>,
+}
+
+impl Highlights {
+  // Lexes the lines again that changed, or start in another state than they
+  // did, as when a comment was opened above them.
+  pub fn update(&mut self, lines: &Lines, ft: Option<&'static FileType>) {
+    let ft = match ft {
+      Some(ft) if ft.syntax.is_some() => ft,
+      _ => {
+        *self = Highlights::default();
+        return;
+      }
+    };
+    if self.names.is_none_or(|names| !std::ptr::eq(names, ft.names)) {
+      *self = Highlights{names: Some(ft.names), lines: Vec::new()};
+    }
+    let lang = ft.syntax.unwrap();
+    self.lines.resize_with(lines.len(), || None);
+    let mut state = State::Code;
+    for (row, line) in lines.iter().enumerate() {
+      let fresh = self.lines[row].as_ref().is_some_and(|lexed| lexed.start == state && lexed.hash == hash(line));
+      if !fresh {
+        self.lines[row] = Some(lex(line, state, ft.comment, lang));
+      }
+      state = self.lines[row].as_ref().map_or(State::Code, |lexed| lexed.end);
+    }
+  }
+
+  // Moves the lexed lines with the lines added and removed, so that only the
+  // new ones are lexed again.
+  pub fn adjust(&mut self, start: usize, removed: usize, added: usize) {
+    if start <= self.lines.len() {
+      let end = (start + removed).min(self.lines.len());
+      self.lines.splice(start..end, (0..added).map(|_| None));
+    }
+  }
+
+  pub fn spans(&self, row: usize) -> &[(Range<usize>, Kind)] {
+    self.lines.get(row).and_then(Option::as_ref).map_or(&[], |lexed| &lexed.spans[..])
+  }
+
+  fn puncts(&self) -> impl Iterator<Item = ((usize, usize), char)> + '_ {
+    self.lines.iter().enumerate().flat_map(|(row, lexed)| {
+      lexed.iter().flat_map(move |lexed| lexed.puncts.iter().map(move |&(col, c)| ((row, col), c)))
+    })
+  }
+
+  // The matching brackets, in the order they open
+  pub fn pairs(&self) -> Vec<Pair> {
+    let mut pairs = Vec::new();
+    let mut open: Vec<((usize, usize), char)> = Vec::new();
+    for (pos, c) in self.puncts() {
+      let bracket = match c {
+        '(' | '[' | '{' => {
+          open.push((pos, c));
+          continue;
+        }
+        ')' => '(',
+        ']' => '[',
+        '}' => '{',
+        _ => continue,
+      };
+      // a bracket closing one that isn't the last opened closes that one
+      if let Some(i) = open.iter().rposition(|&(_, c)| c == bracket) {
+        pairs.push(Pair{open: open[i].0, close: pos, bracket});
+        open.truncate(i);
+      }
+    }
+    pairs.sort_by_key(|pair| pair.open);
+    pairs
+  }
+
+  // The code of the lines between two positions, as the positions of its
+  // characters, without comments and strings
+  fn code(&self, lines: &Lines, from: (usize, usize), to: (usize, usize)) -> Vec<((usize, usize), char)> {
+    let mut code = Vec::new();
+    for (row, line) in lines.iter().enumerate().take(to.0 + 1).skip(from.0) {
+      let spans = self.spans(row);
+      for (col, c) in line.chars().enumerate() {
+        let outside = spans.iter().all(|(cols, kind)| !cols.contains(&col) || !matches!(kind, Kind::Comment | Kind::String));
+        if (row, col) >= from && (row, col) < to && outside {
+          code.push(((row, col), c));
+        }
+      }
+      let end = (row, line.chars().count());
+      if end < to {
+        code.push((end, '\n'));
+      }
+    }
+    code
+  }
+
+  // The functions, in the order they start
+  pub fn functions(&self, lines: &Lines, lang: &Language) -> Vec<Function> {
+    if !lang.braces {
+      return indented_functions(lines, lang);
+    }
+    let pairs = self.pairs();
+    let ends: Vec<(usize, usize)> = self.puncts().filter(|&(_, c)| "{};".contains(c)).map(|(pos, _)| pos).collect();
+    let mut functions = Vec::new();
+    for pair in pairs.iter().filter(|pair| pair.bracket == '{') {
+      let from = ends.iter().rev().find(|&&end| end < pair.open).map_or((0, 0), |&(row, col)| (row, col + 1));
+      let code = self.code(lines, from, pair.open);
+      let start = match code.iter().find(|(_, c)| !c.is_whitespace()) {
+        Some(&(start, _)) => start,
+        None => continue,
+      };
+      let header: String = code.iter().map(|&(_, c)| c).collect();
+      let words: Vec<&str> = header.split(|c: char| !is_word_char(c)).filter(|word| !word.is_empty()).collect();
+      let keyword = words.iter().any(|word| lang.functions.contains(word));
+      let header = header.trim();
+      let c_like = lang.c_functions
+        && header.ends_with(')')
+        && !header.contains('=')
+        && header.starts_with(is_word_char)
+        && words.first().is_some_and(|word| !CONTROL.contains(word));
+      if keyword || c_like {
+        functions.push(Function{start, rows: start.0..pair.close.0 + 1, inner: inner(lines, pair)});
+      }
+    }
+    functions.sort_by_key(|function| function.start);
+    functions
+  }
+
+  // The text object around a position
+  pub fn object(&self, lines: &Lines, lang: &Language, target: Target, around: bool, pos: (usize, usize)) -> Option<Object> {
+    match target {
+      Target::Function => {
+        let function = self.functions(lines, lang).into_iter().rev().find(|function| function.rows.contains(&pos.0))?;
+        if around { Some(Object::Lines(function.rows)) } else { function.inner }
+      }
+      Target::Block if !lang.braces => {
+        let fold = fold::fold_at(lines, pos.0)?;
+        Some(Object::Lines(if around { fold.start - 1..fold.end } else { fold }))
+      }
+      Target::Block => {
+        let pair = enclosing(&self.pairs(), pos, "{")?;
+        if around { Some(Object::Chars(pair.open, next(lines, pair.close))) } else { inner(lines, &pair) }
+      }
+      Target::Argument => self.argument(lines, around, pos),
+    }
+  }
+
+  // An argument in parentheses or an item in brackets, split at the commas
+  // between them, and, around it, the comma and spaces that separate it from
+  // the next one, or else from the one before
+  fn argument(&self, lines: &Lines, around: bool, pos: (usize, usize)) -> Option<Object> {
+    let pair = self.pairs().into_iter().rev()
+      .find(|pair| "([".contains(pair.bracket) && pair.open < pos && pos <= pair.close)?;
+    let mut commas = Vec::new();
+    let mut depth = 0;
+    for (at, c) in self.puncts().filter(|&(at, _)| at > pair.open && at < pair.close) {
+      match c {
+        '(' | '[' | '{' => depth += 1,
+        ')' | ']' | '}' => depth -= 1,
+        ',' if depth == 0 => commas.push(at),
+        _ => (),
+      }
+    }
+    let mut bounds = vec![pair.open];
+    bounds.extend(commas);
+    bounds.push(pair.close);
+    let i = (0..bounds.len() - 1).find(|&i| pos <= bounds[i + 1])?;
+    let trimmed = |i: usize| trim(lines, next(lines, bounds[i]), bounds[i + 1]);
+    let (start, end) = trimmed(i)?;
+    if !around {
+      return Some(Object::Chars(start, end));
+    }
+    match (i + 2 < bounds.len()).then(|| trimmed(i + 1)).flatten() {
+      Some((next_start, _)) => Some(Object::Chars(start, next_start)),
+      None if i > 0 => Some(Object::Chars(bounds[i], end)),
+      None => Some(Object::Chars(start, end)),
+    }
+  }
+}
+
+fn line_len(lines: &Lines, row: usize) -> usize {
+  lines.get(row).map_or(0, |line| line.chars().count())
+}
+
+fn char_at(lines: &Lines, pos: (usize, usize)) -> char {
+  lines.get(pos.0).and_then(|line| line.chars().nth(pos.1)).unwrap_or('\n')
+}
+
+fn next(lines: &Lines, pos: (usize, usize)) -> (usize, usize) {
+  if pos.1 < line_len(lines, pos.0) { (pos.0, pos.1 + 1) } else { (pos.0 + 1, 0) }
+}
+
+fn prev(lines: &Lines, pos: (usize, usize)) -> (usize, usize) {
+  match pos {
+    (row, 0) if row > 0 => (row - 1, line_len(lines, row - 1)),
+    (row, col) => (row, col.saturating_sub(1)),
+  }
+}
+
+// The text between two positions without the space around it, if there is
+// any other
+fn trim(lines: &Lines, mut start: (usize, usize), mut end: (usize, usize)) -> Option<((usize, usize), (usize, usize))> {
+  while start < end && char_at(lines, start).is_whitespace() {
+    start = next(lines, start);
+  }
+  while end > start && char_at(lines, prev(lines, end)).is_whitespace() {
+    end = prev(lines, end);
+  }
+  (start < end).then_some((start, end))
+}
+
+// What is between two brackets: the lines between them when each is alone
+// at the end or start of its line, or else the characters
+fn inner(lines: &Lines, pair: &Pair) -> Option<Object> {
+  let ends_line = lines[pair.open.0].chars().skip(pair.open.1 + 1).all(char::is_whitespace);
+  let starts_line = lines[pair.close.0].chars().take(pair.close.1).all(char::is_whitespace);
+  if ends_line && starts_line && pair.close.0 > pair.open.0 {
+    return (pair.close.0 > pair.open.0 + 1).then(|| Object::Lines(pair.open.0 + 1..pair.close.0));
+  }
+  let start = next(lines, pair.open);
+  (start < pair.close).then_some(Object::Chars(start, pair.close))
+}
+
+// The innermost pair of the brackets around a position, which can be on
+// either of them
+pub fn enclosing(pairs: &[Pair], pos: (usize, usize), brackets: &str) -> Option<Pair> {
+  pairs.iter().rev().find(|pair| brackets.contains(pair.bracket) && pair.open <= pos && pos <= pair.close).copied()
+}
+
+// Functions in a language without braces are the lines indented below a
+// line starting with a keyword like `def`, with a line of `end` after them.
+fn indented_functions(lines: &Lines, lang: &Language) -> Vec<Function> {
+  let mut functions = Vec::new();
+  for (row, line) in lines.iter().enumerate() {
+    let words: Vec<&str> = line.split(|c: char| !is_word_char(c)).filter(|word| !word.is_empty()).take(2).collect();
+    if !words.iter().any(|word| lang.functions.contains(word)) {
+      continue;
+    }
+    if let Some(body) = fold::fold_from(lines, row) {
+      let indent = line.chars().take_while(|c| c.is_whitespace()).count();
+      let last = match lines.get(body.end) {
+        Some(next) if lang.keywords.contains(&"end") && next.trim() == "end" => body.end,
+        _ => body.end - 1,
+      };
+      functions.push(Function{start: (row, indent), rows: row..last + 1, inner: Some(Object::Lines(body))});
+    }
+  }
+  functions
+}
@@ -0,0 +1,87 @@
+// Separated values, like CSV and TSV files, shown as a table by `:csv`: each
+// field is padded on screen to the width of the widest one in its column
+// among the lines shown, while the text itself is left as it is. A field in
+// double quotes can hold the separator.
+
+use std::ops::Range;
+use std::path::Path;
+
+#[derive(Clone)]
+pub struct Table {
+  pub sep: char,
+  // the width of each column, in cells
+  pub widths: Vec<usize>,
+}
+
+impl Table {
+  pub fn new(sep: char) -> Self {
+    Table{sep, widths: Vec::new()}
+  }
+
+  // Makes each column as wide as its widest field in the lines.
+  pub fn measure<'a, I, F>(&mut self, lines: I, width: F)
+  where
+    I: Iterator<Item = &'a String>,
+    F: Fn(&str) -> usize,
+  {
+    self.widths.clear();
+    for line in lines {
+      for (i, field) in fields(line, self.sep).into_iter().enumerate() {
+        let cells = width(&slice(line, field));
+        match self.widths.get_mut(i) {
+          Some(widest) => *widest = (*widest).max(cells),
+          None => self.widths.push(cells),
+        }
+      }
+    }
+  }
+
+  // The cells of padding shown before each character of a line, and before
+  // its end: the padding of a field goes before the separator after it.
+  pub fn padding<F: Fn(&str) -> usize>(&self, line: &str, width: F) -> Vec<usize> {
+    let mut pads = vec![0; line.chars().count() + 1];
+    let fields = fields(line, self.sep);
+    let last = fields.len() - 1;
+    for (i, field) in fields.into_iter().enumerate().take(last) {
+      let widest = self.widths.get(i).copied().unwrap_or(0);
+      pads[field.end] = widest.saturating_sub(width(&slice(line, field.clone())));
+    }
+    pads
+  }
+}
+
+// The separator of a file: a tab for a `.tsv` file, or for one whose first
+// line has tabs but no commas, and a comma otherwise.
+pub fn separator(path: &str, first: &str) -> char {
+  let tsv = Path::new(path).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("tsv"));
+  if tsv || (first.contains('\t') && !first.contains(',')) { '\t' } else { ',' }
+}
+
+// The characters of each field of a line, without the separators.
+pub fn fields(line: &str, sep: char) -> Vec<Range<usize>> {
+  let mut fields = Vec::new();
+  let (mut start, mut quoted) = (0, false);
+  let mut len = 0;
+  for (i, c) in line.chars().enumerate() {
+    if c == '"' {
+      quoted = !quoted;
+    } else if c == sep && !quoted {
+      fields.push(start..i);
+      start = i + 1;
+    }
+    len = i + 1;
+  }
+  fields.push(start..len);
+  fields
+}
+
+// The field a column of a line is in, counting a separator as part of the
+// field before it.
+pub fn field_at(line: &str, sep: char, col: usize) -> usize {
+  let fields = fields(line, sep);
+  fields.iter().position(|field| col <= field.end).unwrap_or(fields.len() - 1)
+}
+
+fn slice(line: &str, field: Range<usize>) -> String {
+  line.chars().skip(field.start).take(field.len()).collect()
+}
@@ -1,9 +1,5 @@
 use super::*;
 
-use std::panic;
-
-use tempfile;
-
 #[test]
 fn test_size() {
   let size = get_screen_size().unwrap();
@@ -16,7 +12,7 @@ fn test_file_system() {
 
   { // read missing file
     let path = dir.path().join("missing");
-    let result = read_file(&path.to_str().unwrap());
+    let result = read_file(path.to_str().unwrap());
     assert!(result.is_ok());
     let buffer = result.unwrap();
     assert_eq!(0, buffer.len());
@@ -24,20 +20,42 @@ fn test_file_system() {
 
   { // write buffer to file
     let path = dir.path().join("new");
-    let mut buffer = Buffer::new();
-    buffer.push(Line::from("test"));
-    let result = write_file(&path.to_str().unwrap(), &buffer);
+    let buffer: Buffer = vec![Line::from("test")].into();
+    let result = write_file(path.to_str().unwrap(), &buffer, FileFormat::default());
     assert!(result.is_ok());
   }
 
   { // read existing file
     let path = dir.path().join("new");
-    let result = read_file(&path.to_str().unwrap());
+    let result = read_file(path.to_str().unwrap());
     assert!(result.is_ok());
     let buffer = result.unwrap();
     assert_eq!(1, buffer.len());
     assert_eq!(Line::from("test"), buffer[0]);
   }
+
+  { // overwrite through a symlink, keeping the permissions
+    use std::os::unix::fs::{symlink, PermissionsExt};
+    let path = dir.path().join("new");
+    let link = dir.path().join("link");
+    symlink(&path, &link).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+    let buffer: Buffer = vec![Line::from("again")].into();
+    write_file(link.to_str().unwrap(), &buffer, FileFormat::default()).unwrap();
+    assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+    assert_eq!("again\n", fs::read_to_string(&path).unwrap());
+    assert_eq!(0o600, fs::metadata(&path).unwrap().permissions().mode() & 0o777);
+    // no temporary files are left behind
+    assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+  }
+
+  { // overwrite a file with another hard link, keeping the link
+    let path = dir.path().join("new");
+    let other = dir.path().join("other");
+    fs::hard_link(&path, &other).unwrap();
+    write_file(path.to_str().unwrap(), &Lines::from(vec![Line::from("linked")]), FileFormat::default()).unwrap();
+    assert_eq!("linked\n", fs::read_to_string(&other).unwrap());
+  }
 }
 
 #[test]
@@ -55,15 +73,19 @@ fn test_buffer() {
 
   let cur = Cursor::new();
   // Inserting at beginning of buffer and line
-  insert_at('a', &cur, &mut buf);
-  assert_eq!('a' as u8, buf[0].as_bytes()[0]);
-  // Inserting past end of file should panic
-  assert!(panic::catch_unwind(|| {
-    let mut buf = Buffer::new();
-    let mut cur = Cursor::new();
-    cur.row = buf.len();
-    insert_at('b', &cur, &mut buf);
-  }).is_err())
+  assert_eq!(Ok(()), insert_at('a', &cur, &mut buf));
+  assert_eq!(b'a', buf[0].as_bytes()[0]);
+  // Edits outside the buffer are refused
+  let mut buf = Buffer::new();
+  assert_eq!(Err(BufError::PastEndOfBuffer), insert_at('b', &cur, &mut buf));
+  assert_eq!(Err(BufError::BeforeStart), delete_before(&cur, &mut buf));
+  assert_eq!(Err(BufError::PastEndOfBuffer), merge_next_line_into(&cur, &mut buf));
+  assert_eq!(Err(BufError::PastEndOfBuffer), break_line_at(&cur, &mut buf));
+  buf.push(Line::from("a"));
+  let mut cur = Cursor::new();
+  cur.col = 1;
+  assert_eq!(Err(BufError::PastEndOfLine), delete_at(&cur, &mut buf));
+  assert_eq!(vec![Line::from("a")], buf);
 }
 
 fn check_range(
@@ -76,6 +98,132 @@ fn check_range(
   assert_eq!(c, buffer_char_range(cur, size));
 }
 
+#[test]
+fn test_lines() {
+  // Lines split in chunks behave like one vector of them
+  let mut seed = 7u32;
+  let mut next = |n: usize| -> usize {
+    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+    (seed >> 8) as usize % (n + 1)
+  };
+  let mut lines = Lines::new();
+  let mut model: Vec<String> = Vec::new();
+  for step in 0..2000 {
+    let start = next(model.len());
+    let end = start + next((model.len() - start).min(if step % 50 == 0 { 3000 } else { 3 }));
+    let count = next(if step % 40 == 0 { 2500 } else { 2 });
+    let new: Vec<String> = (0..count).map(|i| format!("{}.{}", step, i)).collect();
+    let removed = lines.splice(start..end, new.clone());
+    assert_eq!(model.splice(start..end, new).collect::<Vec<_>>(), removed);
+    assert_eq!(model.len(), lines.len());
+    if step % 100 == 0 {
+      assert_eq!(model, lines);
+      assert!(model.iter().rev().eq(lines.iter().rev()));
+    }
+  }
+  assert_eq!(model, lines.to_vec());
+  let row = model.len() / 3;
+  assert_eq!(model.get(row + 1000), lines.range(row..).nth(1000));
+  assert_eq!(model.get(row), lines.get(row));
+
+  // copies share chunks until one changes
+  let copy = lines.clone();
+  lines[row].push('!');
+  assert_ne!(copy, lines);
+  assert_eq!(model, copy);
+}
+
+// Breaking and joining lines takes about as long anywhere in a buffer of
+// any length, unlike in one vector of them. Run with `--ignored --nocapture`.
+#[test]
+#[ignore]
+fn bench_lines() {
+  let text: Vec<String> = (0..300_000).map(|i| format!("line {}", i)).collect();
+  let mut lines = Lines::from(text.clone());
+  let mut vec = text;
+  let rounds = 1000;
+  let time = |edit: &mut dyn FnMut(usize)| {
+    let start = Instant::now();
+    for i in 0..rounds {
+      edit(i * 7 % 1000);
+    }
+    start.elapsed() / rounds as u32
+  };
+  // each round breaks a line near the start, then joins it again
+  let chunked = time(&mut |row| {
+    let rest = lines[row].split_off(2);
+    lines.insert(row + 1, rest);
+    let rest = lines.remove(row + 1);
+    lines[row].push_str(&rest);
+  });
+  let flat = time(&mut |row| {
+    let rest = vec[row].split_off(2);
+    vec.insert(row + 1, rest);
+    let rest = vec.remove(row + 1);
+    vec[row].push_str(&rest);
+  });
+  println!("breaking and joining a line of 300,000: {:?} in chunks, {:?} in one vector", chunked, flat);
+  assert_eq!(vec, lines);
+  assert!(chunked < flat);
+}
+
+#[test]
+fn test_large_file() {
+  // The lines of a large file are read as they are shown
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("log");
+  let text: Vec<String> = (0..5000).map(|i| format!("line {}", i)).collect();
+  fs::write(&path, text.join("\n")).unwrap();
+  let path = path.to_str().unwrap();
+  let (buf, format) = read_large_file(path).unwrap().unwrap();
+  assert!(!format.final_newline);
+  assert!(buf.is_lazy());
+  assert_eq!(5000, buf.len());
+  assert_eq!(0, buf.loaded());
+  assert_eq!("line 4321", buf[4321]);
+  assert!(buf.loaded() < 2000);
+  assert_eq!(text, buf.to_vec());
+
+  let mut file = OpenFile::new(path, buf);
+  file.format = format;
+  file.stamp = disk_stamp(path);
+  let mut editor = Editor::new(BufferList::new(vec![file]), Size::new(6usize, 40usize));
+  let mut scr = Screen::new(Box::new(io::sink()));
+  editor.draw(&mut scr).unwrap();
+  let mut driver = Driver{editor, scr};
+  assert!(driver.editor.files.current().buf.loaded() < 2000);
+  driver.keys("Gx");
+  assert_eq!("ine\u{387}4999\u{ac}", driver.frame()[4]);
+  driver.keys("ggx:w\n");
+  let mut saved = text.clone();
+  saved[0] = String::from("ine 0");
+  saved[4999] = String::from("ine 4999");
+  assert_eq!(saved.join("\n"), fs::read_to_string(path).unwrap());
+  // the lines not changed are still read from the file as it was
+  let buf = &mut driver.editor.files.current_mut().buf;
+  buf.unload(0..0);
+  assert!(buf.loaded() < 3000);
+  assert_eq!("line 2500", buf[2500]);
+  // and it isn't written over while they are
+  fs::hard_link(path, dir.path().join("link")).unwrap();
+  assert!(write_file(path, buf, format).is_err());
+  assert_eq!(saved.join("\n"), fs::read_to_string(path).unwrap());
+
+  // a large file is read whole if it doesn't start as UTF-8
+  fs::write(dir.path().join("latin1"), b"caf\xe9\n").unwrap();
+  assert!(read_large_file(dir.path().join("latin1").to_str().unwrap()).unwrap().is_none());
+  // and isn't saved if it isn't UTF-8 after that
+  let mut bytes = vec![b'a'; 100_000];
+  bytes.extend(b"\n\xff\n");
+  let invalid = dir.path().join("invalid");
+  fs::write(&invalid, &bytes).unwrap();
+  let invalid = invalid.to_str().unwrap();
+  let (buf, format) = read_large_file(invalid).unwrap().unwrap();
+  assert_eq!("\u{fffd}", buf[1]);
+  assert!(write_file(invalid, &buf, format).is_err());
+  assert_eq!(bytes, fs::read(invalid).unwrap());
+}
+
 #[test]
 fn test_cursor() {
   let buf: Buffer = vec![
@@ -84,7 +232,7 @@ fn test_cursor() {
     "3456".into(),
     "4567".into(),
     "5678".into(),
-  ];
+  ].into();
   let size = Size::new(3usize, 2usize);
   let mut cur = Cursor::new();
   assert_eq!(0, cur.top);
@@ -138,3 +286,2435 @@ fn test_cursor() {
   apply_and_check(move_cursor_up, 0..3, 0..2);
   apply_and_check(move_cursor_up, 0..3, 0..2);
 }
+
+#[test]
+fn test_multibyte_input() {
+  let mut buf: Buffer = vec![Line::new()].into();
+  let size = Size::new(3usize, 8usize);
+  let mut cur = Cursor::new();
+
+  // Composed characters are inserted whole and advance the cursor one column
+  for ch in "é語x".chars() {
+    insert_and_move_cursor(ch, &mut cur, &mut buf, &size).unwrap();
+  }
+  assert_eq!("é語x", buf[0]);
+  assert_eq!(3, cur.col);
+
+  // Deleting and breaking lines operate on characters, not bytes
+  move_cursor_left(&mut cur, &buf, &size);
+  delete_and_move_cursor(&mut cur, &mut buf, &size).unwrap();
+  assert_eq!("éx", buf[0]);
+  break_line_and_return_cursor(&mut cur, &mut buf, &size).unwrap();
+  assert_eq!(vec![Line::from("é"), Line::from("x")], buf);
+
+  // CJK and emoji, which take four bytes, are edited the same way
+  let mut buf: Buffer = vec![Line::new()].into();
+  let mut cur = Cursor::new();
+  for ch in "中😀文🚀".chars() {
+    insert_and_move_cursor(ch, &mut cur, &mut buf, &size).unwrap();
+  }
+  assert_eq!(4, cur.col);
+  cur.col = 1;
+  assert!(transpose_chars(&cur, &mut buf));
+  assert_eq!("中文😀🚀", buf[0]);
+  delete_in_place(&mut cur, &mut buf, &size).unwrap();
+  assert_eq!("中😀🚀", buf[0]);
+  break_line_at(&cur, &mut buf).unwrap();
+  assert_eq!(vec![Line::from("中"), Line::from("😀🚀")], buf);
+  merge_next_line_into(&cur, &mut buf).unwrap();
+  assert_eq!("中😀🚀", buf[0]);
+  assert_eq!(6, line_width(&buf[0], 3));
+  assert_eq!(2, char_width('🫠'));
+}
+
+#[test]
+fn test_auto_indent() {
+  let mut buf: Buffer = vec!["    if x {".into(), "\tfoo(a, b)".into()].into();
+  let size = Size::new(5usize, 20usize);
+  let mut cur = Cursor::new();
+  cur.col = 10;
+  break_line_and_return_cursor(&mut cur, &mut buf, &size).unwrap();
+  assert_eq!("    ", buf[1]);
+  assert_eq!((1, 4), (cur.row, cur.col));
+
+  // the text after the cursor keeps its place
+  cur.row = 2;
+  cur.col = 7;
+  break_line_and_return_cursor(&mut cur, &mut buf, &size).unwrap();
+  assert_eq!(vec!["\tfoo(a,", "\t b)"], buf.slice(2..));
+  assert_eq!((3, 1), (cur.row, cur.col));
+
+  assert_eq!("        ", new_line_indent("    if x {", true, 4));
+  assert_eq!("\t\t", new_line_indent("\tdef f():", true, 4));
+  assert_eq!("  ", new_line_indent("  x = 1", true, 4));
+  assert_eq!("", new_line_indent("else:", false, 4));
+}
+
+#[test]
+fn test_indent_lines() {
+  let mut buf: Buffer = vec!["a".into(), "".into(), "\tb".into(), "  c".into()].into();
+  assert!(indent_lines(&mut buf, 0..4, 4));
+  assert_eq!(vec!["    a", "", "\t\tb", "      c"], buf);
+  assert!(dedent_lines(&mut buf, 1..4, 4));
+  assert_eq!(vec!["    a", "", "\tb", "  c"], buf);
+  assert!(dedent_lines(&mut buf, 3..4, 4));
+  assert!(!dedent_lines(&mut buf, 3..4, 4));
+  assert_eq!("c", buf[3]);
+
+  // a count shifts that many lines, and the cursor goes to the text
+  let mut files = BufferList::new(vec![OpenFile::new("a", buf)]);
+  let mut ses = Session::new();
+  let size = Size::new(5usize, 20usize);
+  let mut fb = Feedback::new();
+  handle_key_count_mode(Key::Char('>'), 2, &mut files, &mut ses, &size, &mut fb).unwrap();
+  assert_eq!(vec!["        a", "", "\tb", "c"], files.current().buf);
+  assert_eq!(8, files.current().cur.col);
+}
+
+#[test]
+fn test_wide_characters() {
+  let buf: Buffer = vec!["a語b語".into()].into();
+  let size = Size::new(3usize, 4usize);
+  let mut cur = Cursor::new();
+
+  assert_eq!(1, char_width('a'));
+  assert_eq!(2, char_width('語'));
+  assert_eq!(2, char_width('😀'));
+  assert_eq!(6, line_width(&buf[0], 4));
+
+  // The cursor is placed by cell, not by character
+  move_cursor_right(&mut cur, &buf, &size);
+  move_cursor_right(&mut cur, &buf, &size);
+  assert_eq!((1, 4), cursor_screen_position(&cur, &buf, &size));
+
+  // A wide character that does not fit scrolls the view by both of its cells
+  move_cursor_right(&mut cur, &buf, &size);
+  check_range(&cur, &size, 0..3, 2..6);
+  assert_eq!((1, 3), cursor_screen_position(&cur, &buf, &size));
+
+  // A screen with no columns, or too few for the character, keeps the
+  // cursor's cell in view
+  for cols in 0..2usize {
+    let size = Size::new(3, cols);
+    let mut cur = Cursor::new();
+    move_cursor_right(&mut cur, &buf, &size);
+    assert_eq!(1, cur.left);
+    assert_eq!((1, 1), cursor_screen_position(&cur, &buf, &size));
+  }
+  let mut driver = Driver::new(&["a語b語"], 4, 0);
+  driver.keys("llx");
+  assert_eq!(vec!["a語語"], driver.editor.lines());
+}
+
+#[test]
+fn test_literal_and_digraph_input() {
+  assert_eq!(Some('é'), parse_literal_code("u00e9"));
+  assert_eq!(Some('😀'), parse_literal_code("U0001f600"));
+  assert_eq!(Some('A'), parse_literal_code("x41"));
+  assert_eq!(Some('A'), parse_literal_code("065"));
+  assert_eq!(None, parse_literal_code("ud800"));
+
+  let mut driver = Driver::new(&[""], 4, 20);
+  driver.keys("i<Ctrl-v>u<Ctrl-v>x41g<Esc>");
+  assert_eq!(vec!["uAg"], driver.editor.lines());
+
+  // what has been typed of a character is shown over the cursor
+  let mut driver = Driver::new(&["x"], 4, 20);
+  driver.keys("i<Ctrl-k>");
+  assert_eq!("x¬?", driver.frame()[0]);
+  driver.keys("e");
+  assert_eq!("x¬e", driver.frame()[0]);
+  driver.keys("'");
+  assert_eq!("éx¬", driver.frame()[0]);
+
+  // Digraphs may be typed in either order
+  assert_eq!(Some('é'), digraph::lookup('e', '\''));
+  assert_eq!(Some('é'), digraph::lookup('\'', 'e'));
+  assert_eq!(Some('ß'), digraph::lookup('s', 's'));
+  assert_eq!(None, digraph::lookup('q', 'q'));
+}
+
+#[test]
+fn test_config() {
+  let cfg = config::parse("").unwrap();
+  assert_eq!(ControlChars::Caret, cfg.control_chars);
+  assert!(cfg.caret_del);
+
+  let cfg = config::parse(
+    "# comment\ncontrol_chars = \"symbol\"\n\ncaret_del = false\n"
+  ).unwrap();
+  assert_eq!(ControlChars::Symbol, cfg.control_chars);
+  assert!(!cfg.caret_del);
+
+  assert!(config::parse("control_chars = \"wrong\"").is_err());
+  assert!(config::parse("caret_del").is_err());
+  assert!(config::parse("unknown = 1").is_err());
+}
+
+#[test]
+fn test_control_characters() {
+  assert!(is_control('\u{1B}'));
+  assert!(!is_control('\t'));
+  assert_eq!("^[", control_repr('\u{1B}'));
+  assert_eq!("^?", control_repr('\u{7F}'));
+  assert!(is_control('\u{9B}'));
+  assert_eq!("<9b>", control_repr('\u{9B}'));
+  assert_eq!(4, char_width('\u{9B}'));
+  assert_eq!(2, char_width('\u{1}'));
+}
+
+#[test]
+fn test_wrap_line() {
+  let size = Size::new(3usize, 4usize);
+  // The default marker takes two cells, leaving two for each continuation row
+  assert_eq!(vec![0..4, 4..6, 6..8], wrap_line(&"abcdefg".into(), &size));
+  assert_eq!(vec![0..1], wrap_line(&Line::new(), &size));
+  // Wide characters are never split across rows
+  assert_eq!(vec![0..3, 3..4, 4..5], wrap_line(&"ab語語".into(), &size));
+}
+
+#[test]
+fn test_diff() {
+  let a: Vec<String> = vec!["a".into(), "b".into(), "c".into(), "d".into()];
+  let b: Vec<String> = vec!["a".into(), "x".into(), "c".into(), "d".into(), "e".into()];
+  let edits = diff::diff_lines(&a, &b);
+  assert_eq!(vec![
+    diff::Edit::Same(0, 0),
+    diff::Edit::Delete(1),
+    diff::Edit::Insert(1),
+    diff::Edit::Same(2, 2),
+    diff::Edit::Same(3, 3),
+    diff::Edit::Insert(4),
+  ], edits);
+  assert_eq!(vec![
+    (Some(0), Some(0)),
+    (Some(1), Some(1)),
+    (Some(2), Some(2)),
+    (Some(3), Some(3)),
+    (None, Some(4)),
+  ], diff::side_by_side(&edits));
+
+  assert!(diff::diff_lines(&[], &[]).is_empty());
+  assert_eq!(vec![diff::Edit::Insert(0)], diff::diff_lines(&[], &a[..1]));
+
+  // the edits are as few as the longest common subsequence allows
+  let mut seed = 1u32;
+  let mut lines = |len: usize| -> Vec<String> {
+    (0..len).map(|_| {
+      seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+      ((seed >> 16) % 3).to_string()
+    }).collect()
+  };
+  for len in 0..30 {
+    let (a, b) = (lines(len), lines(30 - len));
+    let mut lcs = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+      for j in (0..b.len()).rev() {
+        lcs[i][j] = if a[i] == b[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+      }
+    }
+    let edits = diff::diff_lines(&a, &b);
+    let same: Vec<_> = edits.iter().filter_map(|edit| match *edit {
+      diff::Edit::Same(i, j) => Some((i, j)),
+      _ => None,
+    }).collect();
+    assert_eq!(lcs[0][0], same.len());
+    assert!(same.iter().all(|&(i, j)| a[i] == b[j]));
+    assert!(same.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 < w[1].1));
+    assert_eq!(a.len() + b.len(), edits.len() + same.len());
+  }
+  assert_eq!((4..5, 4..6), diff::changed_chars("let x = 1;", "let yy = 1;"));
+}
+
+#[test]
+fn test_conflicts() {
+  let buf: Buffer = vec![
+    "a".into(),
+    "<<<<<<< HEAD".into(),
+    "ours".into(),
+    "||||||| base".into(),
+    "base".into(),
+    "=======".into(),
+    "theirs".into(),
+    ">>>>>>> branch".into(),
+    "b".into(),
+  ].into();
+  let c = conflict::find_at(4, &buf).unwrap();
+  assert_eq!(conflict::Conflict{start: 1, base: Some(3), separator: 5, end: 7}, c);
+  assert_eq!(None, c.part(0));
+  assert_eq!(Some(Part::Marker), c.part(1));
+  assert_eq!(Some(Part::Ours), c.part(2));
+  assert_eq!(Some(Part::Base), c.part(4));
+  assert_eq!(Some(Part::Theirs), c.part(6));
+  assert_eq!(None, conflict::find_at(8, &buf));
+
+  let mut both = buf.clone();
+  conflict::resolve(&c, true, true, &mut both);
+  assert_eq!(vec!["a", "ours", "theirs", "b"], both);
+
+  // the conflicts shown are found again once one is resolved
+  let mut file = OpenFile::new("a", buf);
+  file.update_annotations();
+  assert_eq!(vec![c], file.annotations.conflicts);
+  file.cur.row = 4;
+  assert!(resolve_conflict(&mut file.cur, &mut file.buf, &Size::new(5usize, 20usize), false, true));
+  assert_eq!(vec!["a", "theirs", "b"], file.buf);
+  file.update_annotations();
+  assert!(file.annotations.conflicts.is_empty());
+}
+
+#[test]
+fn test_templates() {
+  let dir = tempfile::tempdir().unwrap();
+  fs::write(dir.path().join("main.rs"), "fn main() {\n}\n").unwrap();
+  fs::write(dir.path().join("rs"), "// new module\n").unwrap();
+
+  // The whole file name is preferred over the extension
+  assert_eq!(Some(dir.path().join("main.rs")), template_path("src/main.rs", dir.path()));
+  assert_eq!(Some(dir.path().join("rs")), template_path("src/lib.rs", dir.path()));
+  assert_eq!(None, template_path("index.html", dir.path()));
+  assert_eq!(None, template_path("Makefile", dir.path()));
+}
+
+#[test]
+fn test_project_config() {
+  let dir = tempfile::tempdir().unwrap();
+  let sub = dir.path().join("src");
+  fs::create_dir(&sub).unwrap();
+  fs::write(dir.path().join(config::PROJECT_FILE), "wrap = true\n").unwrap();
+
+  // Found from the directory of the file upwards
+  assert_eq!(
+    Some(dir.path().join(config::PROJECT_FILE)),
+    config::find_project_config(&sub.join("main.rs")),
+  );
+
+  // A new project config shows up as a change when reloading
+  let other = tempfile::tempdir().unwrap();
+  let before = config::stamps(&other.path().join("main.rs"));
+  fs::write(other.path().join(config::PROJECT_FILE), "wrap = true\n").unwrap();
+  assert_ne!(before, config::stamps(&other.path().join("main.rs")));
+}
+
+#[test]
+fn test_expand_path() {
+  let var = |name: &str| match name {
+    "HOME" => Some(String::from("/home/red")),
+    "DIR" => Some(String::from("src")),
+    _ => None,
+  };
+  assert_eq!("/home/red", expand_path_with("~", var));
+  assert_eq!("/home/red/notes", expand_path_with("~/notes", var));
+  assert_eq!("~user/notes", expand_path_with("~user/notes", var));
+  assert_eq!("/home/red/src/main.rs", expand_path_with("$HOME/$DIR/main.rs", var));
+  assert_eq!("src_old/x", expand_path_with("${DIR}_old/x", var));
+  assert_eq!("$NOPE/x", expand_path_with("$NOPE/x", var));
+  assert_eq!("cost$", expand_path_with("cost$", var));
+  assert_eq!("${DIR", expand_path_with("${DIR", var));
+
+  // only the arguments that are paths
+  let args: Vec<String> = ["--encoding", "$E", "+$N", "$F"].iter().map(|&arg| String::from(arg)).collect();
+  let expanded = expand_path_args(&args, |arg| arg.replace('$', "/"));
+  assert_eq!(vec!["--encoding", "$E", "+$N", "/F"], expanded);
+}
+
+#[test]
+fn test_history() {
+  let dir = tempfile::tempdir().unwrap();
+  let root = dir.path().join("history");
+  let path = dir.path().join("file");
+
+  for i in 0..4 {
+    fs::write(&path, format!("{}\n", i)).unwrap();
+    history::record(&root, &path, 3).unwrap();
+  }
+  // Only the newest versions are kept, listed newest first
+  let versions = history::versions(&root, &path).unwrap();
+  assert_eq!(3, versions.len());
+  assert_eq!("3\n", fs::read_to_string(&versions[0].1).unwrap());
+  assert_eq!("1\n", fs::read_to_string(&versions[2].1).unwrap());
+
+  // a large file is saved without a copy
+  let root = history_root().unwrap();
+  record_history(path.to_str().unwrap()).unwrap();
+  assert_eq!(1, history::versions(&root, &path).unwrap().len());
+  let large = dir.path().join("large");
+  fs::File::create(&large).unwrap().set_len((config::get().large_file as u64) << 20).unwrap();
+  record_history(large.to_str().unwrap()).unwrap();
+  assert!(history::versions(&root, &large).unwrap().is_empty());
+
+  assert_eq!("1970-01-01 00:00:00", history::format_time(0));
+  assert_eq!("2024-02-29 23:59:59", history::format_time(1_709_251_199));
+}
+
+#[test]
+fn test_move_line() {
+  let mut buf: Buffer = vec!["a".into(), "bc".into(), "d".into()].into();
+  let size = Size::new(3usize, 4usize);
+  let mut cur = Cursor::new();
+  cur.row = 1;
+  cur.col = 2;
+
+  assert!(move_line_down(&mut cur, &mut buf, &size));
+  assert_eq!(vec!["a", "d", "bc"], buf);
+  assert_eq!((2, 2), (cur.row, cur.col));
+  // The last line cannot move further down
+  assert!(!move_line_down(&mut cur, &mut buf, &size));
+
+  assert!(move_line_up(&mut cur, &mut buf, &size));
+  assert!(move_line_up(&mut cur, &mut buf, &size));
+  assert_eq!(vec!["bc", "a", "d"], buf);
+  assert!(!move_line_up(&mut cur, &mut buf, &size));
+}
+
+#[test]
+fn test_transpose() {
+  let mut buf: Buffer = vec!["abc".into(), "".into()].into();
+  let mut cur = Cursor::new();
+
+  // Swaps the character under the cursor with the next one
+  assert!(transpose_chars(&cur, &mut buf));
+  assert_eq!("bac", buf[0]);
+  // On the last character or past the end, the last two are swapped
+  cur.col = 3;
+  assert!(transpose_chars(&cur, &mut buf));
+  assert_eq!("bca", buf[0]);
+  // Nothing to swap on a short line or past the end of the buffer
+  cur.row = 1;
+  cur.col = 0;
+  assert!(!transpose_chars(&cur, &mut buf));
+  cur.row = 2;
+  assert!(!transpose_chars(&cur, &mut buf));
+
+  assert!(swap_lines(0, 1, &mut buf));
+  assert_eq!(vec!["", "bca"], buf);
+  assert!(!swap_lines(1, 2, &mut buf));
+  assert!(!swap_lines(1, 1, &mut buf));
+}
+
+#[test]
+fn test_expressions() {
+  let eval = |text| expr::eval(text, "%Y", "%H");
+  assert_eq!(Ok(String::from("1440")), eval("60 * 24"));
+  assert_eq!(Ok(String::from("7")), eval("1 + 2 * 3"));
+  assert_eq!(Ok(String::from("9")), eval("(1 + 2) * 3"));
+  assert_eq!(Ok(String::from("-1.5")), eval("-3 / 2"));
+  assert_eq!(Ok(String::from("1")), eval("7 % 3"));
+  assert!(eval("1 +").is_err());
+  assert!(eval("(1").is_err());
+  assert!(eval("1 / 0").is_err());
+  assert!(eval("two").is_err());
+  assert_eq!(4, eval("date").unwrap().len());
+
+  // a format with %n inserts more than one line
+  let text = expr::eval("date", "%Y%n%m", "%H").unwrap();
+  let mut buf = Buffer::from(vec![String::from("xy")]);
+  let mut cur = Cursor::new();
+  cur.col = 1;
+  insert_text(&text, &mut cur, &mut buf, &Size::new(5usize, 20usize)).unwrap();
+  assert_eq!(2, buf.len());
+  assert!(buf[1].ends_with('y'));
+  assert_eq!((1, 2), (cur.row, cur.col));
+}
+
+#[test]
+fn test_random_text() {
+  assert_eq!("00000000-0000-4000-8000-000000000000", expr::format_uuid([0; 16]));
+  assert_eq!("ffffffff-ffff-4fff-bfff-ffffffffffff", expr::format_uuid([0xff; 16]));
+  let uuid = expr::uuid().unwrap();
+  assert_eq!(36, uuid.len());
+  assert_eq!(Some('4'), uuid.chars().nth(14));
+
+  let token = expr::token().unwrap();
+  assert_eq!(20, token.len());
+  assert!(token.chars().all(|c| c.is_ascii_alphanumeric()));
+  assert_ne!(token, expr::token().unwrap());
+}
+
+#[test]
+fn test_reflow() {
+  let buf: Buffer = vec![
+    "fn main() {",
+    "  // one two three",
+    "  // four five six seven",
+    "  let x = 1;",
+    "",
+    "> quoted text",
+  ].into_iter().map(String::from).collect();
+  assert_eq!(("  // ", "one two three"), reflow::split_prefix(&buf[1]));
+  assert_eq!(Some(1..3), reflow::paragraph(2, &buf));
+  assert_eq!(Some(0..1), reflow::paragraph(0, &buf));
+  assert_eq!(None, reflow::paragraph(4, &buf));
+  assert_eq!(
+    vec!["  // one two", "  // three four", "  // five six", "  // seven"],
+    reflow::reflow(&buf.slice(1..3), 16, text_cells),
+  );
+  assert_eq!(vec!["> quoted", "> text"], reflow::reflow(&buf.slice(5..6), 3, text_cells));
+
+  // each item of a list is a paragraph, continued under its text
+  let buf: Buffer = vec![
+    "- one two",
+    "  three",
+    "- four",
+    "10. five six seven",
+    "* not a list",
+  ].into_iter().map(String::from).collect();
+  assert_eq!(Some(0..2), reflow::paragraph(1, &buf));
+  assert_eq!(Some(2..3), reflow::paragraph(2, &buf));
+  assert_eq!(Some(3..4), reflow::paragraph(3, &buf));
+  assert_eq!(vec!["- one two three"], reflow::reflow(&buf.slice(0..2), 20, text_cells));
+  assert_eq!(vec!["10. five", "    six", "    seven"], reflow::reflow(&buf.slice(3..4), 9, text_cells));
+  assert_eq!(vec!["* not a", "* list"], reflow::reflow(&buf.slice(4..5), 8, text_cells));
+
+  // wide characters take two cells each
+  let buf: Buffer = vec!["日本 語 abc"].into_iter().map(String::from).collect();
+  assert_eq!(vec!["日本 語", "abc"], reflow::reflow(&buf.to_vec(), 7, text_cells));
+}
+
+#[test]
+fn test_profile() {
+  let mut prof = Profile::default();
+  assert!(prof.report().contains("keys          0\n"));
+  assert!(!prof.report().contains("median"));
+  prof.keys = (1..=100).rev().map(Duration::from_millis).collect();
+  let report = prof.report();
+  assert!(report.contains("keys          100\n"));
+  assert!(report.contains("median      50.000ms\n"));
+  assert!(report.contains("99th        99.000ms\n"));
+  assert!(report.contains("max         100.000ms\n"));
+}
+
+#[test]
+fn test_clipboard() {
+  let mut clip = Clipboard::new(4);
+  let clips: Vec<Clip> = (0..10).map(|i| match i % 3 {
+    0 => Clip::lines(vec![format!("line {}\r", i), format!("broken\n{}", i)]),
+    1 => Clip::lines(vec![format!("line {}", i), String::new(), String::from("L1")]),
+    _ => Clip::text(&format!("text {}\nT1", i)),
+  }).collect();
+  for c in &clips {
+    clip.push(c.clone()).unwrap();
+  }
+  // Clips come back newest first, including those spilled to disk
+  for c in clips.into_iter().rev() {
+    assert_eq!(Some(c), clip.pop().unwrap());
+  }
+  assert_eq!(None, clip.pop().unwrap());
+
+  // lines are pasted above the cursor and text at it, all at once
+  let mut buf: Buffer = vec!["abc".into(), "def".into()].into();
+  let size = Size::new(5usize, 20usize);
+  let mut cur = Cursor::new();
+  cut_lines(2, &mut cur, &mut buf, &mut clip, &size).unwrap();
+  assert!(buf.is_empty());
+  buf.push(String::from("xy"));
+  assert!(paste(&mut cur, &mut clip, &mut buf, &size).unwrap());
+  assert_eq!(vec!["abc", "def", "xy"], buf);
+  clip.push(Clip::text("1\n2")).unwrap();
+  cur.row = 2;
+  cur.col = 1;
+  assert!(paste(&mut cur, &mut clip, &mut buf, &size).unwrap());
+  assert_eq!(vec!["abc", "def", "x1", "2y"], buf);
+  assert!(!paste(&mut cur, &mut clip, &mut buf, &size).unwrap());
+}
+
+#[test]
+fn test_filters() {
+  assert!(filter::find(Path::new("secrets.txt.gpg")).is_some());
+  assert!(filter::find(Path::new("secrets.txt.age")).is_some());
+  assert!(filter::find(Path::new("secrets.txt")).is_none());
+  assert_eq!(b"ABC\n".to_vec(), filter::pipe(&["tr", "a-z", "A-Z"], b"abc\n").unwrap());
+  assert!(filter::pipe(&["false"], b"").is_err());
+  // a command may leave some of its input unread
+  assert_eq!(b"x".to_vec(), filter::pipe(&["head", "-c1"], &[b'x'; 1 << 20]).unwrap());
+
+  // Encrypted files are encrypted again to the keys they were read with
+  let packets = "\
+# off=0 ctb=85 tag=1 hlen=3 plen=396
+:pubkey enc packet: version 3, algo 1, keyid 0FFD722E05F4296A
+\tdata: [3071 bits]
+:pubkey enc packet: version 3, algo 1, keyid 0000000000000000
+:symkey enc packet: version 4, cipher 9, aead 0,s2k 3, hash 2
+:encrypted data packet:
+";
+  let options = ["--encrypt", "--recipient", "0FFD722E05F4296A", "--symmetric"];
+  assert_eq!(options.to_vec(), filter::gpg_options_from(packets));
+  assert!(filter::gpg_options_from(":encrypted data packet:\n").is_empty());
+
+  // keys typed while a filter has the terminal are left for it
+  let (mut terminal, input) = std::os::unix::net::UnixStream::pair().unwrap();
+  let (tx, rx) = mpsc::channel();
+  let paused = KEYS_PAUSED.lock().unwrap();
+  thread::spawn(move || {
+    let (mut input, mut byte) = (PausableInput(input), [0]);
+    while input.read(&mut byte).unwrap() > 0 && tx.send(byte[0]).is_ok() {}
+  });
+  terminal.write_all(b"x").unwrap();
+  assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+  drop(paused);
+  assert_eq!(b'x', rx.recv_timeout(Duration::from_secs(5)).unwrap());
+
+  // Compressed files are read and written through the filters
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("log.gz");
+  let path = path.to_str().unwrap();
+  let buf: Buffer = vec![String::from("one"), String::from("two")].into();
+  write_file(path, &buf, FileFormat::default()).unwrap();
+  assert_eq!(b"\x1f\x8b".to_vec(), fs::read(path).unwrap()[..2].to_vec());
+  assert_eq!(buf, read_file(path).unwrap());
+}
+
+#[test]
+fn test_virtual_edit() {
+  let mut buf: Buffer = vec!["ab".into()].into();
+  let size = Size::new(3usize, 10usize);
+  let mut cur = Cursor{col: 4, ..Cursor::new()};
+  assert_eq!(4, cursor_cell(&cur, &buf));
+
+  // Backspace past the end only moves the cursor
+  delete_and_move_cursor(&mut cur, &mut buf, &size).unwrap();
+  assert_eq!((3, "ab"), (cur.col, buf[0].as_str()));
+
+  // Typing past the end fills the gap with spaces
+  insert_and_move_cursor('x', &mut cur, &mut buf, &size).unwrap();
+  assert_eq!((4, "ab x"), (cur.col, buf[0].as_str()));
+}
+
+#[test]
+fn test_hex_panel() {
+  assert_eq!(16, hex_row_bytes(80));
+  assert_eq!(8, hex_row_bytes(50));
+  assert_eq!(1, hex_row_bytes(10));
+  // Short lines are shown whole, long ones up to the cursor's row
+  assert_eq!(0..2, hex_panel_rows(20, 19, 16));
+  assert_eq!(0..4, hex_panel_rows(100, 10, 16));
+  assert_eq!(3..7, hex_panel_rows(200, 100, 16));
+
+  // The bytes are those the line is saved as
+  let buf = Lines::from(vec![Line::from("a"), Line::from("é")]);
+  let mut cur = Cursor::new();
+  cur.row = 1;
+  cur.col = 1;
+  let format = FileFormat{encoding: Encoding::Utf16Le, bom: true, crlf: true, final_newline: false};
+  assert_eq!((8, vec![0xe9, 0, b'\r', 0, b'\n', 0], 2), hex_panel_bytes(&cur, &buf, FileFormat{final_newline: true, ..format}).unwrap());
+  assert_eq!((8, vec![0xe9, 0], 2), hex_panel_bytes(&cur, &buf, format).unwrap());
+  let format = FileFormat{encoding: Encoding::Latin1, ..FileFormat::default()};
+  assert_eq!((2, vec![0xe9, b'\n'], 1), hex_panel_bytes(&cur, &buf, format).unwrap());
+}
+
+#[test]
+fn test_buffer_stats() {
+  assert_eq!(
+    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+    sha256::hex_digest(b""),
+  );
+  assert_eq!(
+    "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+    sha256::hex_digest(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+  );
+  let buf: Buffer = vec!["abc".into()].into();
+  assert_eq!(
+    "4 bytes, 1 line, ASCII, LF, \
+     SHA-256 edeaaff3f1774ad2888673770c6d64097e391bc362d7d6fb34982ddf0efd18cb",
+    buffer_stats(&buf, FileFormat::default()),
+  );
+}
+
+#[test]
+fn test_line_endings() {
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("dos.txt");
+  let path = path.to_str().unwrap();
+
+  // CRLF and a missing final newline are kept when saving
+  fs::write(path, "one\r\ntwo").unwrap();
+  let mut file = open_file(path, None).unwrap();
+  assert_eq!(vec!["one", "two"], file.buf);
+  assert!(file.format.crlf && !file.format.final_newline);
+  file.buf.push(String::from("three"));
+  write_file(path, &file.buf, file.format).unwrap();
+  assert_eq!("one\r\ntwo\r\nthree", fs::read_to_string(path).unwrap());
+
+  // converting the line endings is an unsaved change
+  let mut files = BufferList::new(vec![file]);
+  let size = Size::new(5usize, 20usize);
+  let mut fb = Feedback::new();
+  run_command(Command::Crlf(false), &mut files, &mut Session::new(), &size, &mut fb).unwrap();
+  let file = files.current();
+  assert!(file.modified());
+  write_file(path, &file.buf, file.format).unwrap();
+  assert_eq!("one\ntwo\nthree", fs::read_to_string(path).unwrap());
+
+  assert_eq!(FileFormat::default(), FileFormat::detect(b""));
+  assert_eq!(FileFormat::default(), FileFormat::detect(b"a\nb\r\n"));
+}
+
+#[test]
+fn test_encodings() {
+  use encoding::Encoding;
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("text");
+  let path = path.to_str().unwrap();
+
+  // files are saved back in the encoding they were read in
+  let files: &[(&[u8], Encoding, &str)] = &[
+    (b"caf\xe9\n", Encoding::Latin1, "café"),
+    (b"\xff\xfeh\0i\0\n\0", Encoding::Utf16Le, "hi"),
+    (b"\xfe\xff\0h\0i\0\n", Encoding::Utf16Be, "hi"),
+    (b"\xef\xbb\xbfhi\n", Encoding::Utf8, "hi"),
+  ];
+  for &(bytes, encoding, line) in files {
+    fs::write(path, bytes).unwrap();
+    let (buf, format) = read_file_with_format(path, None).unwrap();
+    assert_eq!(vec![line], buf);
+    assert_eq!(encoding, format.encoding);
+    write_file(path, &buf, format).unwrap();
+    assert_eq!(bytes.to_vec(), fs::read(path).unwrap());
+  }
+
+  // or in the one asked for
+  fs::write(path, b"\x93quoted\x94\n").unwrap();
+  let (buf, format) = read_file_with_format(path, Some(Encoding::Windows1252)).unwrap();
+  assert_eq!(vec!["\u{201c}quoted\u{201d}"], buf);
+  assert!(buffer_stats(&buf, format).contains("Windows-1252"));
+
+  // characters the encoding lacks are not saved
+  let format = FileFormat{encoding: Encoding::Latin1, ..FileFormat::default()};
+  assert!(write_file(path, &Lines::from(vec![String::from("\u{20ac}")]), format).is_err());
+  assert_eq!(b"\x93quoted\x94\n".to_vec(), fs::read(path).unwrap());
+
+  // UTF-16 is recognized without a byte order mark too
+  assert_eq!((Encoding::Utf16Le, false), encoding::detect(b"h\0i\0\n\0"));
+  assert_eq!((Encoding::Utf16Be, false), encoding::detect(b"\0h\0i\0\n"));
+  assert_eq!((Encoding::Utf8, false), encoding::detect(b"a\0b\0c"));
+
+  // a file guessed to be Latin-1 says so when shown, and compares with the
+  // saved file in Latin-1
+  fs::write(path, b"caf\xe9\n").unwrap();
+  let mut driver = Driver::open(&[path.to_string()], 4, 60);
+  assert_eq!(format!("{} is not UTF-8, and was read as Latin-1", path), driver.frame()[3]);
+  driver.keys(":diff<Enter>");
+  assert!(matches!(driver.editor.mode, Mode::Normal));
+  assert!(driver.frame()[3].starts_with("No changes"), "{:?}", driver.frame());
+
+  assert_eq!(Some(Encoding::Latin1), Encoding::by_name("ISO-8859-1"));
+  assert_eq!(None, Encoding::by_name("ebcdic"));
+}
+
+#[test]
+fn test_file_args() {
+  let args = |args: &[&str]| {
+    let args: Vec<String> = args.iter().map(|&arg| String::from(arg)).collect();
+    parse_file_args(&args).map(|files| {
+      files.into_iter().map(|file| (file.path, file.pos)).collect::<Vec<_>>()
+    })
+  };
+  assert_eq!(
+    Ok(vec![(String::from("a.rs"), Some((12, 1))), (String::from("b.rs"), None)]),
+    args(&["+12", "a.rs", "b.rs"]),
+  );
+  assert_eq!(Ok(vec![(String::from("a.rs"), Some((usize::MAX, 1)))]), args(&["+", "a.rs"]));
+  assert_eq!(Ok(vec![(String::from("src/a.rs"), Some((3, 7)))]), args(&["src/a.rs:3:7:"]));
+  assert_eq!(Ok(vec![(String::from("a:b"), Some((3, 1)))]), args(&["a:b:3"]));
+  assert_eq!(Ok(vec![(String::from("a.rs:x"), None)]), args(&["a.rs:x"]));
+  assert!(args(&["a.rs", "+3"]).is_err());
+  assert!(args(&["+x", "a.rs"]).is_err());
+  assert!(args(&["-", "-"]).is_err());
+}
+
+#[test]
+fn test_follow_file() {
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("log");
+  let path = path.to_str().unwrap();
+  fs::write(path, "one\n").unwrap();
+  let mut buf = read_file(path).unwrap();
+  let mut cur = Cursor::new();
+  let size = Size::new(3usize, 10usize);
+  let mut offset = 4;
+  assert!(!follow_file(path, &mut offset, false, &mut cur, &mut buf, &size).unwrap());
+
+  // Only whole lines are added
+  fs::write(path, "one\ntwo\nthr").unwrap();
+  assert!(follow_file(path, &mut offset, false, &mut cur, &mut buf, &size).unwrap());
+  assert_eq!(vec!["one", "two"], buf);
+  assert_eq!((8, 1), (offset, cur.row));
+
+  // A truncated file is read again from the start, unless the buffer has
+  // changes
+  fs::write(path, "new\n").unwrap();
+  assert!(follow_file(path, &mut offset, true, &mut cur, &mut buf, &size).is_err());
+  assert_eq!(vec!["one", "two"], buf);
+  assert!(follow_file(path, &mut offset, false, &mut cur, &mut buf, &size).unwrap());
+  assert_eq!(vec!["new"], buf);
+
+  // The editor follows the file from where it was read until a key
+  let mut driver = Driver::open(&[path.to_string()], 4, 20);
+  fs::write(path, "new\nlog\n").unwrap();
+  driver.editor.follow().unwrap();
+  assert!(driver.editor.tick().unwrap());
+  assert_eq!(vec!["new", "log"], driver.editor.lines());
+  assert_eq!((1, 0), driver.editor.cursor());
+  assert!(!driver.editor.modified());
+  driver.keys("k");
+  fs::write(path, "new\nlog\nmore\n").unwrap();
+  driver.editor.tick().unwrap();
+  assert_eq!(vec!["new", "log"], driver.editor.lines());
+}
+
+#[test]
+fn test_search() {
+  let buf: Buffer = vec!["one two one".into(), "three".into(), "ünö one".into()].into();
+  assert_eq!(Some((0, 8)), search::find_next(&buf, "one", 0, 0));
+  assert_eq!(Some((2, 4)), search::find_next(&buf, "one", 0, 8));
+  assert_eq!(None, search::find_next(&buf, "one", 2, 4));
+  assert_eq!(None, search::find_next(&buf, "", 0, 0));
+  assert_eq!(Some((0, 8)), search::find_prev(&buf, "one", 2, 4));
+  assert_eq!(Some((0, 0)), search::find_prev(&buf, "one", 0, 8));
+  assert_eq!(None, search::find_prev(&buf, "one", 0, 0));
+  assert_eq!(Some((0, 0)), search::find_first(&buf, "one"));
+  assert_eq!(Some((2, 4)), search::find_last(&buf, "one"));
+
+  assert_eq!(100, pager_percent(0, 9, 0));
+  assert_eq!(50, pager_percent(0, 9, 18));
+  assert_eq!(100, pager_percent(15, 9, 18));
+}
+
+#[test]
+fn test_todo_markers() {
+  let markers = vec![String::from("TODO"), String::from("FIXME")];
+  assert_eq!(vec![3..7, 15..20], todo::find_in_line("// TODO: and a FIXME", &markers));
+  assert!(todo::find_in_line("TODOS MyTODO TODO_", &markers).is_empty());
+  let buf: Buffer = vec!["TODO".into(), "done".into(), "(FIXME)".into()].into();
+  assert_eq!(vec![0, 2], todo::find_all(&buf, &markers));
+
+  // the list keeps the rows found when it was opened
+  let mut driver = Driver::new(&["TODO a", "b", "FIXME c"], 12, 20);
+  driver.keys("glj");
+  assert!(matches!(driver.editor.mode, Mode::TodoList(ref rows, 1) if *rows == [0, 2]));
+  assert_eq!((2, 0), driver.editor.cursor());
+  driver.keys("<Enter>");
+  assert!(matches!(driver.editor.mode, Mode::Normal));
+}
+
+#[test]
+fn test_selection() {
+  let mut cur = Cursor::new();
+  cur.row = 1;
+  cur.col = 1;
+  let buf: Buffer = vec!["one".into(), "tüo".into(), "three".into()].into();
+  let sel = Selection::new((0, 2), &cur);
+  assert_eq!("e\ntü", sel.text(&buf));
+  assert_eq!(Some(2..4), sel.cols_in(0, &buf[0]));
+  assert_eq!(Some(0..2), sel.cols_in(1, &buf[1]));
+  assert_eq!(None, sel.cols_in(2, &buf[2]));
+  let mut deleted = buf.clone();
+  sel.delete(&mut deleted);
+  assert_eq!(vec![String::from("ono"), String::from("three")], deleted);
+
+  // selecting the end of a line takes its line break, except on the last line
+  let sel = Selection{start: (1, 3), end: (2, 5), block: false};
+  assert_eq!("\nthree", sel.text(&buf));
+  let mut deleted = buf.clone();
+  sel.delete(&mut deleted);
+  assert_eq!(vec![String::from("one"), String::from("tüo")], deleted);
+
+  let mut clip = Clipboard::new(10);
+  copy_selection(&Selection{start: (0, 1), end: (1, 3), block: false}, &buf, &mut clip).unwrap();
+  assert_eq!(Some(Clip::text("ne\ntüo\n")), clip.pop().unwrap());
+  copy_selection(&Selection{start: (0, 0), end: (1, 3), block: false}, &buf, &mut clip).unwrap();
+  assert_eq!(Some(Clip::lines(vec!["one".into(), "tüo".into()])), clip.pop().unwrap());
+  assert_eq!(None, clip.pop().unwrap());
+}
+
+#[test]
+fn test_status_line() {
+  let mut cur = Cursor::new();
+  assert_eq!("INSERT  a.txt [+]         1:1", status_text(&Mode::Insert, "a.txt", true, &cur, 30));
+  cur.row = 9;
+  cur.col = 4;
+  assert_eq!("NORMAL  a.txt 10:5", status_text(&Mode::Normal, "a.txt", false, &cur, 10));
+  assert_eq!("VISUAL", mode_name(&Mode::Visual((0, 0))));
+}
+
+#[test]
+fn test_buffer_list() {
+  let mut files = BufferList::new(vec![
+    OpenFile::new("a", vec!["a".into()].into()),
+    OpenFile::new("b", vec!["b".into()].into()),
+    OpenFile::new("c", vec!["c".into()].into()),
+  ]);
+  assert!(files.cycle(false));
+  assert_eq!("c", files.current().path);
+  insert_at('!', &Cursor::new(), &mut files.current_mut().buf).unwrap();
+  assert!(files.current().modified());
+  assert!(files.cycle(true));
+  assert!(files.cycle(true));
+  assert_eq!("b", files.current().path);
+  assert!(!files.current().modified());
+  // an edit that can't be made leaves the file unmodified
+  let file = files.current_mut();
+  assert!(merge_next_line_into(&file.cur, &mut file.buf).is_err());
+  assert!(!file.modified());
+
+  let mut fb = Feedback::new();
+  assert!(matches!(quit_unless_modified(&files, &mut fb), Mode::Normal));
+  assert!(fb.bell);
+  files.files[2].saved = Some(files.files[2].buf.generation);
+  assert!(matches!(quit_unless_modified(&files, &mut fb), Mode::Quit));
+
+  let mut single = BufferList::new(vec![OpenFile::new("a", Buffer::new())]);
+  assert!(!single.cycle(true));
+
+  // A file open under another name is shown rather than opened again
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("a").to_str().unwrap().to_string();
+  fs::write(&path, "a\n").unwrap();
+  let name = dir.path().file_name().unwrap();
+  let other = dir.path().join("..").join(name).join(".").join("a").to_str().unwrap().to_string();
+  let mut files = open_files(&[path.clone(), other.clone()], None, false, Size::new(4usize, 20usize)).unwrap();
+  assert_eq!(1, files.files.len());
+  files.open(&other).unwrap();
+  assert_eq!(1, files.files.len());
+}
+
+#[test]
+fn test_unnamed_file() {
+  let mut file = OpenFile::new("", vec![Line::new()].into());
+  assert!(status_text(&Mode::Normal, &file.path, false, &file.cur, 40).contains("[No Name]"));
+
+  // saving asks for a name first, which Escape gives up on
+  let mut fb = Feedback::new();
+  let mut mode = save_or_ask_for_name(&mut file, true, &mut fb);
+  for key in [Key::Char('\n'), Key::Char('a'), Key::Char('b'), Key::Backspace] {
+    if let Mode::SaveAs(name, quit) = mode {
+      mode = handle_key_save_as_mode(key, name, quit, &mut file, &mut fb);
+    }
+  }
+  assert!(matches!(mode, Mode::SaveAs(ref name, true) if name == "a"));
+  let mode = handle_key_save_as_mode(Key::Esc, String::from("a"), true, &mut file, &mut fb);
+  assert!(matches!(mode, Mode::Normal));
+  assert_eq!("", file.path);
+}
+
+#[test]
+fn test_goto_line() {
+  let lines: Buffer = (1..=100).map(|i| format!("  {}", i)).collect();
+  let mut files = BufferList::new(vec![OpenFile::new("a", lines)]);
+  let mut ses = Session::new();
+  let size = Size::new(10usize, 20usize);
+  let mut fb = Feedback::new();
+
+  // a line out of view is centered
+  handle_key_count_mode(Key::Char('G'), 50, &mut files, &mut ses, &size, &mut fb).unwrap();
+  let cur = &files.current().cur;
+  assert_eq!((49, 2, 44), (cur.row, cur.col, cur.top));
+
+  // but the view stops at the end of the buffer
+  handle_key_sequence(Prefix::new(), Vec::new(), Some(Key::Char('G')), &mut files, &mut ses, &size, &mut fb).unwrap();
+  let cur = &files.current().cur;
+  assert_eq!((99, 90), (cur.row, cur.top));
+
+  handle_key_sequence(Prefix::new(), vec![Key::Char('g')], Some(Key::Char('g')), &mut files, &mut ses, &size, &mut fb).unwrap();
+  let cur = &files.current().cur;
+  assert_eq!((0, 2, 0), (cur.row, cur.col, cur.top));
+}
+
+#[test]
+fn test_scroll_cursor() {
+  let buf: Buffer = (1..=100).map(|i| i.to_string()).collect();
+  let size = Size::new(10usize, 20usize);
+  let mut cur = Cursor::new();
+  cur.row = 30;
+  cur.top = 25;
+  scroll_cursor_to_row(0, &mut cur, &buf, &size);
+  assert_eq!((30, 30), (cur.row, cur.top));
+  scroll_cursor_to_row(size.rows / 2, &mut cur, &buf, &size);
+  assert_eq!((30, 25), (cur.row, cur.top));
+  scroll_cursor_to_row(size.rows - 1, &mut cur, &buf, &size);
+  assert_eq!((30, 21), (cur.row, cur.top));
+
+  // the view can't scroll above the first line
+  cur.row = 3;
+  scroll_cursor_to_row(size.rows - 1, &mut cur, &buf, &size);
+  assert_eq!((3, 0), (cur.row, cur.top));
+}
+
+#[test]
+fn test_complete_path() {
+  let dir = tempfile::tempdir().unwrap();
+  let dir = dir.path().to_str().unwrap();
+  for name in &["notes.txt", "novel.md", ".hidden"] {
+    fs::write(format!("{}/{}", dir, name), "").unwrap();
+  }
+  fs::create_dir(format!("{}/src", dir)).unwrap();
+  let complete = |part: &str| complete_path(&format!("{}/{}", dir, part));
+  assert_eq!(Some(format!("{}/no", dir)), complete("n"));
+  assert_eq!(Some(format!("{}/notes.txt", dir)), complete("not"));
+  assert_eq!(Some(format!("{}/src/", dir)), complete("s"));
+  assert_eq!(Some(format!("{}/.hidden", dir)), complete("."));
+  assert_eq!(None, complete("x"));
+}
+
+#[test]
+fn test_commands() {
+  assert_eq!(Ok(Command::Write), command::parse("w"));
+  assert_eq!(Ok(Command::WriteQuit), command::parse(" wq "));
+  assert_eq!(Ok(Command::WriteAs(String::from("b.txt"))), command::parse("w b.txt"));
+  assert_eq!(Ok(Command::ForceQuit), command::parse("q!"));
+  assert_eq!(Ok(Command::Goto(42)), command::parse("42"));
+  assert_eq!(Ok(Command::Crlf(true)), command::parse("crlf"));
+  assert_eq!(Ok(Command::Edit(String::from("a b.txt"))), command::parse("e  a b.txt"));
+  assert!(command::parse("e").is_err());
+  assert!(command::parse("q now").is_err());
+  assert!(command::parse("frobnicate").is_err());
+}
+
+#[test]
+fn test_screen_redraws_changed_rows() {
+  fn draw(scr: &mut screen::Screen<Vec<u8>>, rows: &[&str]) -> String {
+    let before = scr.get_ref().len();
+    scr.begin_frame();
+    for (i, row) in rows.iter().enumerate() {
+      scr.start_row(i).unwrap();
+      write!(scr, "{}", row).unwrap();
+    }
+    scr.end_row();
+    write!(scr, "{}", termion::cursor::Goto(1, 1)).unwrap();
+    scr.flush().unwrap();
+    String::from_utf8_lossy(&scr.get_ref()[before..]).into_owned()
+  }
+  let mut scr = screen::Screen::new(Vec::new());
+  let out = draw(&mut scr, &["one", "two"]);
+  assert!(out.contains("one") && out.contains("two"));
+  let out = draw(&mut scr, &["one", "too"]);
+  assert!(!out.contains("one") && out.contains("too"));
+  // the cursor is moved after the rows are drawn
+  assert!(out.ends_with(&termion::cursor::Goto(1, 1).to_string()));
+  // a row that is no longer drawn is cleared
+  let out = draw(&mut scr, &["one"]);
+  assert!(out.contains(&termion::cursor::Goto(1, 2).to_string()));
+  assert_eq!(termion::cursor::Goto(1, 1).to_string(), draw(&mut scr, &["one"]));
+}
+
+#[test]
+fn test_keymap() {
+  assert_eq!(Some(Key::Ctrl('s')), keymap::parse_key("Ctrl-s"));
+  assert_eq!(Some(Key::Alt('J')), keymap::parse_key("Alt-J"));
+  assert_eq!(Some(Key::Char('\n')), keymap::parse_key("Enter"));
+  assert_eq!(Some(Key::F(5)), keymap::parse_key("F5"));
+  assert_eq!(Some(Key::Char('F')), keymap::parse_key("F"));
+  assert_eq!(None, keymap::parse_key("Ctrl-"));
+
+  let cfg = config::parse("normal.Ctrl-s = \"save\"\nnormal.s = \"none\"\nnormal.\":\" = \"quit\"").unwrap();
+  assert_eq!(Some(Action::Save), cfg.normal_keys.get(&Key::Ctrl('s')));
+  assert_eq!(None, cfg.normal_keys.get(&Key::Char('s')));
+  assert_eq!(Some(Action::Quit), cfg.normal_keys.get(&Key::Char(':')));
+  assert_eq!(Some(Action::Literal), cfg.insert_keys.get(&Key::Ctrl('v')));
+  assert!(config::parse("normal.x = \"fly\"").is_err());
+  assert!(config::parse("insert.Hyper-x = \"normal\"").is_err());
+}
+
+#[test]
+fn test_word_motions() {
+  let buf: Buffer = vec!["foo_bar->baz  qux".into(), "".into(), "  end.".into()].into();
+  assert_eq!((0, 7), next_word_start(&buf, (0, 0)));
+  assert_eq!((0, 9), next_word_start(&buf, (0, 7)));
+  assert_eq!((0, 14), next_word_start(&buf, (0, 9)));
+  assert_eq!((1, 0), next_word_start(&buf, (0, 14)));
+  assert_eq!((2, 2), next_word_start(&buf, (1, 0)));
+  assert_eq!((2, 5), next_word_start(&buf, (2, 2)));
+  assert_eq!((2, 6), next_word_start(&buf, (2, 5)));
+
+  assert_eq!((2, 2), prev_word_start(&buf, (2, 5)));
+  assert_eq!((1, 0), prev_word_start(&buf, (2, 2)));
+  assert_eq!((0, 14), prev_word_start(&buf, (1, 0)));
+  assert_eq!((0, 9), prev_word_start(&buf, (0, 11)));
+  assert_eq!((0, 0), prev_word_start(&buf, (0, 0)));
+
+  assert_eq!((0, 6), word_end(&buf, (0, 0)));
+  assert_eq!((0, 8), word_end(&buf, (0, 6)));
+  assert_eq!((0, 16), word_end(&buf, (0, 11)));
+  assert_eq!((2, 4), word_end(&buf, (0, 16)));
+}
+
+#[test]
+fn test_counts() {
+  let lines: Buffer = (1..=10).map(|i| i.to_string()).collect();
+  let mut files = BufferList::new(vec![OpenFile::new("a", lines)]);
+  let mut ses = Session::new();
+  let size = Size::new(5usize, 20usize);
+  let mut fb = Feedback::new();
+
+  let mut mode = Mode::Normal;
+  for key in "12".chars() {
+    mode = match mode {
+      Mode::Count(count) => handle_key_count_mode(Key::Char(key), count, &mut files, &mut ses, &size, &mut fb),
+      _ => handle_key_sequence(Prefix::new(), Vec::new(), Some(Key::Char(key)), &mut files, &mut ses, &size, &mut fb),
+    }.unwrap();
+  }
+  assert!(matches!(mode, Mode::Count(12)));
+  // the movement stops after the last line
+  handle_key_count_mode(Key::Char('j'), 12, &mut files, &mut ses, &size, &mut fb).unwrap();
+  assert_eq!(10, files.current().cur.row);
+  assert!(fb.bell);
+
+  let mut fb = Feedback::new();
+  files.current_mut().cur.row = 2;
+  let mut cut = |count, files: &mut BufferList, fb: &mut Feedback| {
+    let pre = match handle_key_count_mode(Key::Char('d'), count, files, &mut ses, &size, fb).unwrap() {
+      Mode::Keys(pre, ..) => pre,
+      _ => panic!("no operator"),
+    };
+    handle_key_sequence(pre, Vec::new(), Some(Key::Char('d')), files, &mut ses, &size, fb).unwrap();
+  };
+  cut(3, &mut files, &mut fb);
+  assert_eq!(vec!["1", "2", "6", "7", "8", "9", "10"], files.current().buf);
+  assert!(!fb.bell);
+
+  // cutting more lines than are left stops at the end
+  cut(9, &mut files, &mut fb);
+  assert_eq!(vec!["1", "2"], files.current().buf);
+}
+
+#[test]
+fn test_editor() {
+  let mut editor = Editor::open(&[], 6, 40).unwrap();
+  for key in "ihello".chars() {
+    assert!(editor.handle_key(Key::Char(key)).unwrap());
+  }
+  assert!(editor.handle_key(Key::Esc).unwrap());
+  assert_eq!(vec!["hello"], editor.lines());
+  assert_eq!((0, 5), editor.cursor());
+  assert!(editor.modified());
+  assert_eq!("", editor.path());
+
+  let mut scr = Screen::new(Box::new(io::sink()));
+  editor.draw(&mut scr).unwrap();
+  // quitting with changes asks for `Q` instead
+  assert!(editor.handle_key(Key::Char('q')).unwrap());
+  assert!(!editor.handle_key(Key::Char('Q')).unwrap());
+}
+
+// Drives the whole editor with a script of keys, written as characters and
+// key names in angle brackets like `ihello<Esc>`, drawing after each key.
+struct Driver {
+  editor: Editor,
+  scr: Screen,
+}
+
+impl Driver {
+  fn new(lines: &[&str], rows: usize, cols: usize) -> Self {
+    let file = OpenFile::new("a", lines.iter().map(|&line| Line::from(line)).collect());
+    let mut editor = Editor::new(BufferList::new(vec![file]), Size::new(rows, cols));
+    let mut scr = Screen::new(Box::new(io::sink()));
+    editor.draw(&mut scr).unwrap();
+    Driver{editor, scr}
+  }
+
+  fn open(args: &[String], rows: usize, cols: usize) -> Self {
+    let mut editor = Editor::open(args, rows, cols).unwrap();
+    let mut scr = Screen::new(Box::new(io::sink()));
+    editor.draw(&mut scr).unwrap();
+    Driver{editor, scr}
+  }
+
+  // Returns whether the editor is still running after the script.
+  fn keys(&mut self, script: &str) -> bool {
+    let mut chars = script.chars();
+    while let Some(c) = chars.next() {
+      let key = match c {
+        '<' => {
+          let name: String = chars.by_ref().take_while(|&c| c != '>').collect();
+          keymap::parse_key(&name).unwrap_or_else(|| panic!("unknown key {}", name))
+        }
+        c => Key::Char(c),
+      };
+      if !self.editor.handle_key(key).unwrap() {
+        return false;
+      }
+      self.editor.draw(&mut self.scr).unwrap();
+    }
+    true
+  }
+
+  // The rows on the screen, without escape sequences
+  fn frame(&self) -> Vec<String> {
+    self.scr.shown_rows().iter().map(|row| {
+      let text = String::from_utf8_lossy(row);
+      let mut plain = String::new();
+      let mut chars = text.chars();
+      while let Some(c) = chars.next() {
+        match c {
+          '\x1b' => match chars.next() {
+            Some('[') => for c in chars.by_ref() {
+              if ('@'..='~').contains(&c) {
+                break;
+              }
+            },
+            Some('(') => { chars.next(); }
+            _ => (),
+          },
+          c => plain.push(c),
+        }
+      }
+      plain.trim_end().to_string()
+    }).collect()
+  }
+}
+
+#[test]
+fn test_scripted_keys() {
+  let lines: Vec<String> = (1..=20).map(|i| i.to_string()).collect();
+  let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+  let mut driver = Driver::new(&lines, 6, 20);
+  assert_eq!(vec!["1¬", "2¬", "3¬", "4¬", "5¬", "NORMAL  a       1:1"], driver.frame());
+
+  // moving past the bottom of the screen scrolls it
+  assert!(driver.keys("15j"));
+  assert_eq!(vec!["12¬", "13¬", "14¬", "15¬", "16¬", "NORMAL  a      16:1"], driver.frame());
+
+  assert!(driver.keys("iab<Esc>"));
+  assert_eq!("ab16", driver.editor.lines()[15]);
+  assert!(driver.keys("dd"));
+  assert_eq!("17", driver.editor.lines()[15]);
+  assert_eq!("NORMAL  a [+]  16:3", driver.frame()[5]);
+
+  assert!(driver.keys(":3<Enter>"));
+  assert_eq!((2, 0), driver.editor.cursor());
+  assert_eq!("1¬", driver.frame()[0]);
+
+  // quitting ends the script early
+  assert!(!driver.keys("QQ"));
+}
+
+#[test]
+fn test_jump_list() {
+  let lines: Vec<String> = (1..=20).map(|i| i.to_string()).collect();
+  let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+  let mut driver = Driver::new(&lines, 6, 20);
+  driver.keys("G");
+  assert_eq!((19, 0), driver.editor.cursor());
+  driver.keys("<Ctrl-o>");
+  assert_eq!((0, 0), driver.editor.cursor());
+  driver.keys("<Alt-o>");
+  assert_eq!((19, 0), driver.editor.cursor());
+
+  // moving a line at a time is not a jump
+  driver.keys("k:5<Enter>/12<Enter>");
+  assert_eq!((11, 0), driver.editor.cursor());
+  driver.keys("<Ctrl-o>");
+  assert_eq!((4, 0), driver.editor.cursor());
+  driver.keys("<Ctrl-o>");
+  assert_eq!((18, 0), driver.editor.cursor());
+  driver.keys("<Alt-o><Alt-o>");
+  assert_eq!((11, 0), driver.editor.cursor());
+
+  // a jump after going back forgets the positions gone back from
+  driver.keys("<Ctrl-o><Ctrl-o>gg<Alt-o>");
+  assert_eq!((0, 0), driver.editor.cursor());
+  driver.keys("<Ctrl-o>");
+  assert_eq!((18, 0), driver.editor.cursor());
+}
+
+#[test]
+fn test_marks() {
+  let lines: Vec<String> = (1..=10).map(|i| i.to_string()).collect();
+  let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+  let mut driver = Driver::new(&lines, 6, 20);
+  driver.keys("5jmaGmbgg");
+  driver.keys("'a");
+  assert_eq!((5, 0), driver.editor.cursor());
+  // going to a mark is a jump
+  driver.keys("<Ctrl-o>");
+  assert_eq!((0, 0), driver.editor.cursor());
+
+  // marks move with their lines, and go away with them
+  driver.keys("dddd'a");
+  assert_eq!((3, 0), driver.editor.cursor());
+  assert_eq!("6", driver.editor.lines()[3]);
+  driver.keys("dd'a");
+  assert_eq!((3, 0), driver.editor.cursor());
+  driver.keys("gg'b");
+  assert_eq!((6, 0), driver.editor.cursor());
+  assert_eq!("10", driver.editor.lines()[6]);
+  driver.keys("'c");
+  assert_eq!((6, 0), driver.editor.cursor());
+  // a line deleted above the mark is told apart from the same lines around it
+  let mut driver = Driver::new(&["x", "x", "x"], 6, 20);
+  driver.keys("Gmaggdd'a");
+  assert_eq!((1, 0), driver.editor.cursor());
+
+  assert_eq!((2, 0, 1), marks::changed_lines(&Lines::from(vec!["a".into(), "b".into()]), &Lines::from(vec!["a".into(), "b".into(), "c".into()])));
+}
+
+#[test]
+fn test_diff_with_saved() {
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("a.txt").to_str().unwrap().to_string();
+  write_file(&path, &Lines::from(vec!["one".into(), "two".into()]), FileFormat::default()).unwrap();
+  let mut driver = Driver::open(&[path], 6, 40);
+  driver.keys(":diff<Enter>");
+  assert_eq!("No changes", driver.frame()[5]);
+
+  driver.keys("jiTWO <Esc>:diff<Enter>");
+  // the saved file is on the left
+  assert_eq!(vec!["one│one", "two│TWO\u{387}two"], driver.frame());
+  driver.keys("q");
+  assert!(driver.frame()[5].starts_with("NORMAL"));
+}
+
+#[test]
+fn test_git() {
+  let lines = |text: &str| -> Buffer { text.split(' ').map(String::from).collect() };
+  let (a, m, d) = (Some(Sign::Added), Some(Sign::Modified), Some(Sign::Deleted));
+  assert_eq!(vec![None, m, None, a], git::signs(&lines("a b c").to_vec(), &lines("a B c d")));
+  assert_eq!(vec![None, d], git::signs(&lines("a b c").to_vec(), &lines("a c")));
+  // lines deleted from the end are shown on the last line
+  assert_eq!(vec![d], git::signs(&lines("a b").to_vec(), &lines("a")));
+  // the signs are kept until an edit, and a long file has none
+  let mut file = OpenFile::new("a", lines("a B"));
+  file.git = Some(lines("a b").to_vec());
+  file.update_annotations();
+  assert_eq!(vec![None, m], file.annotations.signs);
+  file.git = Some(lines("a B").to_vec());
+  file.update_annotations();
+  assert_eq!(vec![None, m], file.annotations.signs);
+  insert_at('x', &Cursor::new(), &mut file.buf).unwrap();
+  file.update_annotations();
+  assert_eq!(vec![m, None], file.annotations.signs);
+  file.buf.extend(lines("c").iter().cycle().take(MAX_SIGNS_LINES).cloned());
+  file.buf.touch();
+  file.update_annotations();
+  assert!(file.annotations.signs.is_empty());
+
+  let dir = tempfile::tempdir().unwrap();
+  let git = |args: &[&str]| {
+    let status = std::process::Command::new("git").arg("-C").arg(dir.path()).args(args)
+      .stdout(std::process::Stdio::null()).status().unwrap();
+    assert!(status.success());
+  };
+  git(&["init", "-q"]);
+  git(&["config", "user.name", "Red"]);
+  git(&["config", "user.email", "red@example.com"]);
+  let path = dir.path().join("a.txt").to_str().unwrap().to_string();
+  write_file(&path, &lines("one two"), FileFormat::default()).unwrap();
+
+  // an untracked file is all new
+  let mut driver = Driver::open(&[path], 6, 40);
+  assert_eq!(vec!["+one¬", "+two¬"], &driver.frame()[..2]);
+  driver.keys(":commit First<Enter>");
+  assert!(driver.frame()[5].contains("First"), "{:?}", driver.frame());
+  assert_eq!(vec![" one¬", " two¬"], &driver.frame()[..2]);
+  assert_eq!((0, 0), driver.editor.cursor());
+
+  driver.keys("jix<Esc>:commit Second<Enter>");
+  assert_eq!("Save the file before committing it", driver.frame()[5]);
+  assert_eq!(vec![" one¬", "~xtwo¬"], &driver.frame()[..2]);
+}
+
+#[test]
+fn test_filter_lines() {
+  let mut driver = Driver::new(&["c", "b", "a", "z", "y"], 6, 20);
+  driver.keys("vjj!sort<Enter>");
+  assert_eq!(vec!["a", "b", "c", "z", "y"], driver.editor.lines());
+  assert_eq!((0, 0), driver.editor.cursor());
+
+  driver.keys(":!tr a-z A-Z<Enter>");
+  assert_eq!(vec!["A", "B", "C", "Z", "Y"], driver.editor.lines());
+  driver.keys(":!false<Enter>");
+  assert_eq!(vec!["A", "B", "C", "Z", "Y"], driver.editor.lines());
+  assert!(driver.frame()[5].starts_with("sh:"), "{:?}", driver.frame());
+}
+
+#[test]
+fn test_colors() {
+  use color::{Depth, bg, detect, fg, reduce};
+
+  assert_eq!(Depth::Basic, detect(Some("xterm"), None));
+  assert_eq!(Depth::Basic, detect(None, None));
+  assert_eq!(Depth::Indexed, detect(Some("screen-256color"), None));
+  assert_eq!(Depth::Rgb, detect(Some("xterm-256color"), Some("truecolor")));
+  assert_eq!(Depth::Rgb, detect(Some("xterm-direct"), None));
+
+  let orange = Color::Rgb(255, 135, 0);
+  assert_eq!("\x1b[38;2;255;135;0m", fg(orange, Depth::Rgb));
+  assert_eq!("\x1b[38;5;208m", fg(orange, Depth::Indexed));
+  assert_eq!("\x1b[33m", fg(orange, Depth::Basic));
+  assert_eq!(Color::Indexed(244), reduce(Color::Rgb(128, 128, 128), Depth::Indexed));
+
+  assert_eq!("\x1b[48;5;22m", bg(Color::Indexed(22), Depth::Rgb));
+  assert_eq!("\x1b[100m", bg(Color::Indexed(238), Depth::Basic));
+  assert_eq!("\x1b[41m", bg(Color::Indexed(1), Depth::Indexed));
+  assert_eq!("\x1b[49m", bg(Color::Indexed(235), Depth::Basic));
+  assert_eq!("\x1b[49m", bg(Color::Indexed(22), Depth::Basic));
+  assert_eq!("\x1b[40m", bg(Color::Basic(0), Depth::Basic));
+  assert_eq!("\x1b[32m", fg(Color::Basic(2), Depth::Rgb));
+}
+
+#[test]
+fn test_notify() {
+  let mut driver = Driver::new(&["one"], 4, 30);
+  driver.editor.notify("Written 1 line");
+  driver.editor.draw(&mut driver.scr).unwrap();
+  assert_eq!("Written 1 line", driver.frame()[3]);
+
+  // the message outlasts redraws until it times out
+  assert!(!driver.editor.tick().unwrap());
+  driver.editor.echo.as_mut().unwrap().1 -= MESSAGE_TIMEOUT;
+  assert!(driver.editor.tick().unwrap());
+  driver.editor.draw(&mut driver.scr).unwrap();
+  assert!(!driver.frame()[3].contains("Written"), "{:?}", driver.frame());
+
+  // or until the next key
+  driver.keys("g<Ctrl-g>");
+  let stats = driver.frame()[3].clone();
+  assert!(stats.contains("line"), "{:?}", stats);
+  driver.editor.draw(&mut driver.scr).unwrap();
+  assert_eq!(stats, driver.frame()[3]);
+  driver.keys("l");
+  assert_ne!(stats, driver.frame()[3]);
+}
+
+#[test]
+fn test_save_error() {
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("missing").join("a.txt").to_str().unwrap().to_string();
+  let mut driver = Driver::open(std::slice::from_ref(&path), 4, 80);
+  driver.keys("ix<Esc>s");
+  assert!(driver.frame()[3].starts_with(&format!("Could not save {}:", path)), "{:?}", driver.frame());
+  assert!(driver.editor.modified());
+
+  // the session goes on, and quitting still asks first
+  assert!(driver.keys(":wq<Enter>"));
+  assert!(driver.keys("q"));
+  assert_eq!(vec!["x"], driver.editor.lines());
+
+  // saving a buffer with every line deleted
+  driver.keys(":%d<Enter>s");
+  assert!(driver.frame()[3].starts_with(&format!("Could not save {}:", path)), "{:?}", driver.frame());
+}
+
+#[test]
+fn test_save_as() {
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("a.txt").to_str().unwrap().to_string();
+  let mut driver = Driver::open(std::slice::from_ref(&path), 4, 80);
+  driver.keys("S");
+  assert_eq!(format!("Save as: {}", path), driver.frame()[3]);
+  driver.keys("<Esc>");
+
+  // a missing directory is only created when asked to
+  let new = dir.path().join("new");
+  driver.keys(&format!(":w {}/b.txt<Enter>", new.display()));
+  assert_eq!(format!("Create {}? (y/n)", new.display()), driver.frame()[3]);
+  driver.keys("n");
+  assert!(!new.exists());
+  assert_eq!(path, driver.editor.path());
+
+  // another file is only replaced when asked to
+  let other = dir.path().join("c.txt");
+  fs::write(&other, "other\n").unwrap();
+  driver.keys(&format!(":w {}<Enter>", other.display()));
+  assert_eq!(format!("{} exists; overwrite it? (y/n)", other.display()), driver.frame()[3]);
+  driver.keys("n");
+  assert_eq!("other\n", fs::read_to_string(&other).unwrap());
+  assert_eq!(path, driver.editor.path());
+
+  // the file is saved, and under its new name, when its notes cannot be
+  let saved = dir.path().join("d.txt").to_str().unwrap().to_string();
+  fs::create_dir(notes::path(&saved)).unwrap();
+  driver.keys(&format!(":note check this<Enter>:w {}<Enter>", saved));
+  assert!(driver.frame()[3].starts_with(&format!("Saved {}, but not its notes and positions:", saved)), "{:?}", driver.frame());
+  assert_eq!(saved, driver.editor.path());
+  driver.keys("ix<Esc>:w<Enter>");
+  assert!(!driver.frame()[3].contains("changed on disk"), "{:?}", driver.frame());
+  assert_eq!("x\n", fs::read_to_string(&saved).unwrap());
+}
+
+#[test]
+fn test_changed_on_disk() {
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("a.txt").to_str().unwrap().to_string();
+  write_file(&path, &Lines::from(vec![String::from("one")]), FileFormat::default()).unwrap();
+  let mut driver = Driver::open(std::slice::from_ref(&path), 4, 80);
+  assert!(!driver.editor.tick().unwrap());
+
+  // another program changes the file, which is warned about once
+  write_file(&path, &Lines::from(vec![String::from("one"), String::from("two")]), FileFormat::default()).unwrap();
+  let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+  fs::File::options().write(true).open(&path).unwrap().set_modified(time).unwrap();
+  // the file is checked once a second
+  assert!(!driver.editor.tick().unwrap());
+  driver.editor.checked -= DISK_CHECK_INTERVAL;
+  assert!(driver.editor.tick().unwrap());
+  driver.editor.draw(&mut driver.scr).unwrap();
+  assert_eq!(format!("{} changed on disk; :e! reads it again", path), driver.frame()[3]);
+  assert!(!driver.editor.tick().unwrap());
+
+  // saving asks whether to overwrite it or read it again
+  driver.keys("ix<Esc>s");
+  assert!(driver.frame()[3].ends_with("changed on disk; o overwrites it, r reads it again"));
+  driver.keys("<Esc>");
+  assert_eq!(vec!["xone"], driver.editor.lines());
+  driver.keys("sr");
+  assert_eq!(vec!["one", "two"], driver.editor.lines());
+  assert!(!driver.editor.modified());
+  assert!(!driver.editor.tick().unwrap());
+}
+
+#[test]
+fn test_line_ranges() {
+  use command::LineRange;
+  let rows = |text: &str| match command::parse(text) {
+    Ok(Command::Delete(range)) => range.rows(4, 20),
+    other => panic!("{:?}", other),
+  };
+  assert_eq!(Ok(2..10), rows("3,10d"));
+  assert_eq!(Ok(4..10), rows(".,+5d"));
+  assert_eq!(Ok(3..5), rows("-,.d"));
+  assert_eq!(Ok(18..20), rows("$-1,$d"));
+  assert_eq!(Ok(0..20), rows("%d"));
+  assert_eq!(Ok(4..5), rows("d"));
+  assert!(rows("5,3d").is_err());
+  assert!(rows("1,21d").is_err());
+  assert!(rows("99999999999999999999999d").is_err());
+  assert_eq!(
+    Ok(Command::Substitute(LineRange::all(), String::from("a/b"), String::from("c"), true)),
+    command::parse("%s/a\\/b/c/g"));
+  assert_eq!(Ok(Command::Filter(LineRange::all(), String::from("sort"))), command::parse("!sort"));
+  assert!(command::parse("3,4").is_err());
+  assert!(command::parse("3,4q").is_err());
+
+  let mut driver = Driver::new(&["a", "b", "c", "d", "e"], 8, 20);
+  driver.keys(":2,3d<Enter>");
+  assert_eq!(vec!["a", "d", "e"], driver.editor.lines());
+  assert_eq!((1, 0), driver.editor.cursor());
+  driver.keys(":.,$y<Enter>");
+  assert_eq!("Copied 2 lines", driver.frame()[7]);
+  driver.keys("Gp");
+  assert_eq!(vec!["a", "d", "d", "e", "e"], driver.editor.lines());
+  driver.keys(":%s/e/x/<Enter>");
+  assert_eq!(vec!["a", "d", "d", "x", "x"], driver.editor.lines());
+  assert_eq!((4, 0), driver.editor.cursor());
+  driver.keys(":1s/z/y/<Enter>");
+  assert_eq!("Not found: z", driver.frame()[7]);
+}
+
+#[test]
+fn test_transform_lines() {
+  let mut lines: Vec<String> = ["b", "B", "a10", "a", "x-2", "a9"].iter().map(|s| s.to_string()).collect();
+  transform::sort(&mut lines, transform::Order::Text);
+  assert_eq!(vec!["B", "a", "a10", "a9", "b", "x-2"], lines);
+  transform::sort(&mut lines, transform::Order::IgnoreCase);
+  assert_eq!(vec!["a", "a10", "a9", "B", "b", "x-2"], lines);
+  transform::sort(&mut lines, transform::Order::Numeric);
+  assert_eq!(vec!["a", "B", "b", "x-2", "a9", "a10"], lines);
+  transform::reverse(&mut lines);
+  assert_eq!(vec!["a10", "a9", "x-2", "b", "B", "a"], lines);
+  let repeated: Vec<String> = ["a", "a", "b", "a", "a"].iter().map(|s| s.to_string()).collect();
+  assert_eq!(vec!["a", "b", "a"], transform::uniq(&repeated));
+
+  let mut driver = Driver::new(&["c", "b", "b", "a", "z"], 8, 20);
+  driver.keys(":1,4sort<Enter>");
+  assert_eq!(vec!["a", "b", "b", "c", "z"], driver.editor.lines());
+  driver.keys("G:uniq<Enter>");
+  assert_eq!(vec!["a", "b", "c", "z"], driver.editor.lines());
+  assert_eq!((3, 0), driver.editor.cursor());
+  driver.keys(":reverse<Enter>");
+  assert_eq!(vec!["z", "c", "b", "a"], driver.editor.lines());
+  assert!(command::parse("sort x").is_err());
+}
+
+#[test]
+fn test_toggle_comment() {
+  assert_eq!("//", filetype::detect("src/main.rs").unwrap().comment);
+  assert_eq!("#", filetype::detect("/a/Makefile").unwrap().comment);
+  assert_eq!("#", filetype::detect("setup.py").unwrap().comment);
+  assert!(filetype::detect("notes.txt").is_none());
+
+  let mut lines: Vec<String> = ["  a", "", "    b"].iter().map(|s| s.to_string()).collect();
+  assert!(comment::toggle(&mut lines, "#"));
+  assert_eq!(vec!["  # a", "", "  #   b"], lines);
+  assert!(!comment::toggle(&mut lines, "#"));
+  assert_eq!(vec!["  a", "", "    b"], lines);
+
+  // lines only partly commented are all commented
+  let mut lines: Vec<String> = ["// a", "b"].iter().map(|s| s.to_string()).collect();
+  assert!(comment::toggle(&mut lines, "//"));
+  assert_eq!(vec!["// // a", "// b"], lines);
+
+  // other whitespace isn't indentation
+  let mut lines: Vec<String> = [" a", "\u{a0}b"].iter().map(|s| s.to_string()).collect();
+  assert!(comment::toggle(&mut lines, "#"));
+  assert_eq!(vec!["#  a", "# \u{a0}b"], lines);
+
+  let mut driver = Driver::new(&["fn main() {", "  go();", "}"], 8, 30);
+  driver.editor.files.current_mut().path = String::from("main.rs");
+  driver.keys("jgc");
+  assert_eq!(vec!["fn main() {", "  // go();", "}"], driver.editor.lines());
+  driver.keys("ggvjj#");
+  assert_eq!(vec!["// fn main() {", "//   // go();", "// }"], driver.editor.lines());
+  driver.keys(":%comment<Enter>");
+  assert_eq!(vec!["fn main() {", "  // go();", "}"], driver.editor.lines());
+  driver.keys(":2comment<Enter>");
+  assert_eq!(vec!["fn main() {", "  go();", "}"], driver.editor.lines());
+}
+
+#[test]
+fn test_trailing_whitespace() {
+  assert_eq!(3..5, transform::trailing_whitespace("a b \t"));
+  assert_eq!(1..1, transform::trailing_whitespace("a"));
+  assert!(line_marks(0, &String::from("a  "), &[], None, &[]).contains(&(1..3, Mark::Trailing)));
+
+  let mut lines: Lines = ["a  ", "\tb\t", "", "  ", ""].iter().map(|s| s.to_string()).collect();
+  transform::strip_trailing_whitespace(&mut lines);
+  transform::strip_final_blank_lines(&mut lines);
+  assert_eq!(vec!["a", "\tb"], lines);
+  let mut blank = Lines::from(vec![String::new(), String::new()]);
+  transform::strip_final_blank_lines(&mut blank);
+  assert_eq!(vec![""], blank);
+
+  assert!(!config::parse("").unwrap().strip_whitespace);
+  assert!(config::parse("strip_whitespace = true").unwrap().strip_whitespace);
+}
+
+#[test]
+fn test_end_markers() {
+  let mut driver = Driver::new(&["one", ""], 6, 20);
+  driver.keys("j");
+  assert_eq!(vec!["one¬", "¬", "~", "~", "~"], &driver.frame()[..5]);
+  driver.keys("dd");
+  assert_eq!(vec!["one¬", "~", "~", "~", "~"], &driver.frame()[..5]);
+}
+
+#[test]
+fn test_cursor_shape() {
+  let mut scr = screen::Screen::new(Vec::new());
+  scr.set_cursor_shape(CursorShape::Bar).unwrap();
+  scr.set_cursor_shape(CursorShape::Bar).unwrap();
+  assert_eq!(b"\x1b[6 q", &scr.get_ref()[..]);
+
+  assert_eq!(CursorShape::Block, cursor_shape(&Mode::Normal));
+  assert_eq!(CursorShape::Bar, cursor_shape(&Mode::Insert));
+  assert_eq!(CursorShape::Bar, cursor_shape(&Mode::Command(String::new())));
+  assert_eq!(CursorShape::Block, cursor_shape(&Mode::Visual((0, 0))));
+}
+
+#[test]
+fn test_cursor_line() {
+  assert!(!config::parse("").unwrap().cursor_line);
+  assert!(config::parse("cursor_line = true").unwrap().cursor_line);
+  assert_eq!(None, cursor_line_background(0, &Cursor::new()));
+
+  // the shading goes on after trailing whitespace
+  let mut scr = Screen::new(Box::new(io::sink()));
+  scr.set_depth(color::Depth::Indexed);
+  scr.begin_frame();
+  scr.start_row(0).unwrap();
+  let marks = [(0..1, Mark::Trailing)];
+  write_line_to_screen(&mut scr, &Cursor::new(), &String::from(" "), &marks, Some(CURSOR_LINE_BACKGROUND), &Size::new(5usize, 20usize)).unwrap();
+  scr.flush().unwrap();
+  let row = String::from_utf8_lossy(&scr.shown_rows()[0]).to_string();
+  assert!(row.ends_with("\x1b[48;5;235m\x1b[38;5;8m¬\x1b[39m"), "{:?}", row);
+}
+
+#[test]
+fn test_bracketed_paste() {
+  let mut driver = Driver::new(&["  ab"], 6, 20);
+  driver.keys("i");
+  assert!(driver.editor.paste("x\n  y {\r\nz").unwrap());
+  assert_eq!(vec!["x", "  y {", "z  ab"], driver.editor.lines());
+  assert_eq!((2, 1), driver.editor.cursor());
+  // in normal mode the text is inserted too, rather than typed as keys
+  driver.keys("<Esc>");
+  assert!(driver.editor.paste("kk").unwrap());
+  assert_eq!(vec!["x", "  y {", "zkk  ab"], driver.editor.lines());
+  assert!(matches!(driver.editor.mode, Mode::Normal));
+  // and a prompt gets it as one line
+  driver.keys(":");
+  assert!(driver.editor.paste("e a\nb").unwrap());
+  assert!(matches!(driver.editor.mode, Mode::Command(ref text) if text == "e ab"));
+
+  // the pasted bytes are kept as they came, tabs and escapes too
+  let input = io::Cursor::new(b"\x1b[200~a\tb\x1bc\x01\x1b[201~q".to_vec());
+  let (tx, events) = mpsc::channel();
+  read_events_in_background(input, tx);
+  let mut events = events.iter().filter(|event| !matches!(event, Event::Tick | Event::Resize(_)));
+  assert!(matches!(events.next(), Some(Event::Paste(ref text)) if text == "a\tb\x1bc\x01"));
+  assert!(matches!(events.next(), Some(Event::Key(Ok(Key::Char('q'))))));
+
+  let mut scr = screen::Screen::new(Vec::new());
+  scr.set_bracketed_paste(true).unwrap();
+  scr.set_bracketed_paste(true).unwrap();
+  assert_eq!(b"\x1b[?2004h", &scr.get_ref()[..]);
+
+  // the visual bell is turned back off by the loop reading keys
+  let mut scr = screen::Screen::new(Vec::new());
+  scr.flash().unwrap();
+  scr.end_flash(Duration::from_secs(60)).unwrap();
+  assert_eq!(b"\x1b[?5h", &scr.get_ref()[..]);
+  scr.end_flash(Duration::ZERO).unwrap();
+  assert_eq!(b"\x1b[?5h\x1b[?5l", &scr.get_ref()[..]);
+}
+
+#[test]
+fn test_key_sequences() {
+  let g = Key::Char('g');
+  assert_eq!(Some(vec![g, Key::Ctrl('g')]), keymap::parse_keys("g Ctrl-g"));
+  assert_eq!(Some(vec![Key::Char(' ')]), keymap::parse_keys(" "));
+  let mut keys = keymap::normal_defaults();
+  assert_eq!(Lookup::Prefix(None), keys.lookup(&[g]));
+  assert_eq!(Lookup::Action(Action::GotoFirstLine), keys.lookup(&[g, g]));
+  assert_eq!(Lookup::Unbound, keys.lookup(&[g, Key::Char('!')]));
+  keys.insert(&[g], Action::GotoLine);
+  assert_eq!(Lookup::Prefix(Some(Action::GotoLine)), keys.lookup(&[g]));
+  let mut z = keymap::normal_defaults();
+  for c in ['t', 'z', 'b', 'a', 'o', 'c', 'R', 'M'] {
+    z.remove(&[Key::Char('z'), Key::Char(c)]);
+  }
+  assert_eq!(Lookup::Unbound, z.lookup(&[Key::Char('z')]));
+  let cfg = config::parse("normal.\"g x\" = \"stats\"\nkey_timeout = 0").unwrap();
+  assert_eq!(Lookup::Action(Action::Stats), cfg.normal_keys.lookup(&[g, Key::Char('x')]));
+  assert_eq!(0, cfg.key_timeout);
+  // keys bound to the prefixes of earlier versions start the same sequences
+  let cfg = config::parse("normal.Z = \"prefix_z\"").unwrap();
+  assert_eq!(Lookup::Action(Action::ScrollTop), cfg.normal_keys.lookup(&[Key::Char('Z'), Key::Char('t')]));
+  // the keys acting on a selection can be bound too
+  let cfg = config::parse("visual.Tab = \"indent\"\nvisual.\"#\" = \"none\"").unwrap();
+  assert_eq!(Some(Action::Indent), cfg.visual_keys.get(&Key::Char('\t')));
+  assert_eq!(None, cfg.visual_keys.get(&Key::Char('#')));
+  assert_eq!(Some(Action::Filter), cfg.visual_keys.get(&Key::Char('!')));
+
+  // a sequence left unfinished is dropped once the key timeout passes
+  let mut driver = Driver::new(&["one", "two"], 6, 20);
+  driver.keys("jg");
+  assert!(matches!(driver.editor.mode, Mode::Keys(..)));
+  driver.editor.mode = Mode::Keys(Prefix::new(), vec![g], Instant::now() - Duration::from_secs(2));
+  assert!(driver.editor.tick().unwrap());
+  assert!(matches!(driver.editor.mode, Mode::Normal));
+  driver.keys("gg");
+  assert_eq!((0, 0), driver.editor.cursor());
+  // a count before a sequence is kept for its action
+  driver.keys("2gg");
+  assert_eq!((1, 0), driver.editor.cursor());
+  driver.keys("gg");
+
+  // Escape arriving with the next key is read as Alt and the key
+  driver.keys("i");
+  assert!(driver.editor.handle_key(Key::Alt('j')).unwrap());
+  assert!(matches!(driver.editor.mode, Mode::Normal));
+  assert_eq!((1, 0), driver.editor.cursor());
+}
+
+#[test]
+fn test_operators() {
+  let mut driver = Driver::new(&["one two", "  three", "four"], 6, 20);
+  driver.keys("dw");
+  assert_eq!("two", driver.editor.lines()[0]);
+  driver.keys("d$");
+  assert_eq!("", driver.editor.lines()[0]);
+  driver.keys("p");
+  assert_eq!("two", driver.editor.lines()[0]);
+  // the last word of a line is cut without the line break
+  driver.keys("dw");
+  assert_eq!(vec!["", "  three", "four"], driver.editor.lines());
+
+  driver.keys("jccsix<Esc>");
+  assert_eq!("  six", driver.editor.lines()[1]);
+  driver.keys("yyjp");
+  assert_eq!(vec!["", "  six", "  six", "four"], driver.editor.lines());
+  driver.keys("2dd");
+  assert_eq!(vec!["", "  six"], driver.editor.lines());
+  driver.keys("ggdj");
+  assert!(driver.editor.lines().is_empty());
+
+  let mut driver = Driver::new(&["one two"], 6, 20);
+  driver.keys("cwsix<Esc>x");
+  assert_eq!("sixtwo", driver.editor.lines()[0]);
+  // a key that is no motion cancels the operator
+  driver.keys("dp");
+  assert!(matches!(driver.editor.mode, Mode::Normal));
+  assert_eq!("sixtwo", driver.editor.lines()[0]);
+
+  let cfg = config::parse("operators = false").unwrap();
+  assert_eq!(Some(Action::DeleteLine), cfg.normal_keys.get(&Key::Char('d')));
+  assert_eq!(None, cfg.normal_keys.get(&Key::Char('y')));
+}
+
+#[test]
+fn test_terminal() {
+  let mut vt = vt::Vt::new(3);
+  vt.feed(b"one\r\ntwo\x08\x08X\x1b[K\r\n\x1b[1mthr\xc3\xa9e\x1b[m\x1b]0;title\x07");
+  let lines: Vec<String> = vt.lines.iter().map(|line| line.iter().collect()).collect();
+  assert_eq!(vec!["one", "tX", "thrée"], lines);
+  assert_eq!((2, 5), (vt.row, vt.col));
+  vt.feed(b"\r\nfour");
+  assert_eq!(1, vt.top());
+
+  // the program runs in a terminal of the panel's size
+  let mut shell = pty::Shell::spawn(&["sh", "-c", "stty size"], 5, 40).unwrap();
+  let start = Instant::now();
+  while !shell.vt.lines.iter().any(|line| line.iter().collect::<String>() == "5 40") {
+    assert!(start.elapsed() < Duration::from_secs(5), "no output from the shell");
+    shell.read();
+    thread::sleep(Duration::from_millis(10));
+  }
+  assert_eq!(b"\r".to_vec(), terminal_key_bytes(Key::Char('\n')));
+  assert_eq!(vec![0x03], terminal_key_bytes(Key::Ctrl('c')));
+
+  // Escape leaves the shell running in its panel
+  let mut driver = Driver::new(&["a"], 20, 30);
+  driver.keys(":term\n");
+  assert!(matches!(driver.editor.mode, Mode::Terminal(false)));
+  assert_eq!("\u{2500}".repeat(30), driver.frame()[7]);
+  driver.keys("<Esc>");
+  assert!(matches!(driver.editor.mode, Mode::Normal));
+  assert!(driver.editor.ses.shell.is_some());
+  assert_eq!("NORMAL  a                 1:1", driver.frame()[19]);
+}
+
+#[test]
+fn test_locations() {
+  let loc = |path: &str, line, col| Some(location::Location{path: String::from(path), line, col});
+  assert_eq!(loc("src/main.rs", 12, 5), location::find("src/main.rs:12:5: error: expected `;`"));
+  assert_eq!(loc("src/lib.rs", 3, 1), location::find("   --> src/lib.rs:3:1"));
+  assert_eq!(loc("notes.txt", 7, 1), location::find("notes.txt:7:some text"));
+  assert_eq!(None, location::find("at 12:30, make: *** [all] Error 2"));
+
+  // the output goes in a buffer, and Enter and `:cn` go to the places it names
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("code.txt");
+  fs::write(&path, "one\ntwo\nthree\n").unwrap();
+  let path = path.to_str().unwrap();
+  let mut driver = Driver::new(&["a"], 10, 60);
+  driver.keys(&format!(":run printf '{0}:2:3: error\\nwarning\\n{0}:3:1: note\\n'; exit 1\n", path));
+  assert_eq!(Some("[run]"), driver.editor.files.current().scratch.as_deref());
+  assert_eq!(3, driver.editor.files.current().buf.len());
+  assert_eq!(2, driver.editor.ses.locations.len());
+  assert!(driver.frame()[9].starts_with("printf"));
+  driver.keys("\n");
+  let cur = &driver.editor.files.current().cur;
+  assert_eq!((1, 2), (cur.row, cur.col));
+  assert_eq!(path, driver.editor.files.current().path);
+  driver.keys(":cn\n");
+  let cur = &driver.editor.files.current().cur;
+  assert_eq!((2, 0), (cur.row, cur.col));
+  driver.keys(":cn\n");
+  assert_eq!("No more locations", driver.frame()[9]);
+
+  // running it again replaces the output, which never needs saving
+  driver.keys(":run echo again\n");
+  assert_eq!(3, driver.editor.files.files.len());
+  assert_eq!("again", driver.editor.files.current().buf[0]);
+  driver.keys("ix<Esc>");
+  assert!(!driver.keys(":q\n"));
+}
+
+#[test]
+fn test_markdown_preview() {
+  let lines: Lines = [
+    "# Title", "", "", "Some *emphasis* and `code`,", "on two lines.", "- one", "* two", "```", "let x = 1;", "```",
+  ].iter().map(|&line| Line::from(line)).collect();
+  let rows = markdown::render(&lines, 20, text_cells);
+  let text: Vec<String> = rows.iter().map(|row| row.spans.iter().map(|(_, text)| text.as_str()).collect()).collect();
+  assert_eq!(vec!["Title", "", "Some emphasis and", "code, on two lines.", "\u{2022} one", "\u{2022} two", "  let x = 1;"], text);
+  let italic = markdown::Style{italic: true, ..markdown::Style::default()};
+  assert_eq!((italic, String::from("emphasis")), rows[2].spans[1]);
+  assert!(rows[0].spans[0].0.heading);
+  assert!(rows[3].spans[0].0.code);
+  assert_eq!(3, rows[3].source);
+
+  // the preview takes the right half, and only for markdown files
+  let mut driver = Driver::new(&["# Title", "text"], 6, 60);
+  driver.keys(":preview\n");
+  assert!(!driver.editor.ses.preview);
+  driver.editor.files.current_mut().path = String::from("a.md");
+  driver.keys(":preview\n");
+  assert_eq!(vec!["#\u{387}Title\u{ac}\u{2502} Title", "text\u{ac}\u{2502} text", "~\u{2502}"], &driver.frame()[..3]);
+  assert_eq!("NORMAL  a.md                                            1:1", driver.frame()[5]);
+  driver.keys("jimore <Esc>");
+  assert_eq!("more\u{387}text\u{ac}\u{2502} more text", driver.frame()[1]);
+}
+
+#[test]
+fn test_man() {
+  assert_eq!("NAME\n  ls - list", man::strip_formatting("N\x08NA\x08AM\x08ME\x08E\n  _\x08l_\x08s - \x1b[1mlist\x1b[0m"));
+
+  // the page can be read and searched but not edited
+  let mut driver = Driver::new(&["a"], 6, 40);
+  let page = Buffer::from(vec![Line::from("LS(1)"), Line::from("NAME"), Line::from("  ls")]);
+  show_scratch("[man ls]", page, true, &mut driver.editor.files);
+  driver.keys("/NAME\ndd");
+  assert_eq!(3, driver.editor.files.current().buf.len());
+  assert_eq!("[man ls] is read-only", driver.frame()[5]);
+  driver.keys("ix");
+  assert!(matches!(driver.editor.mode, Mode::Normal));
+  assert_eq!("NAME", driver.editor.files.current().buf[1]);
+  assert_eq!(1, driver.editor.files.current().cur.row);
+  assert!(!driver.keys(":q\n"));
+}
+
+#[test]
+fn test_folds() {
+  let lines = ["fn a() {", "  one", "  if x {", "    two", "  }", "}", "", "end"];
+  let buf: Lines = lines.iter().map(|line| line.to_string()).collect();
+  assert_eq!(vec![1, 1, 2, 2, 1, 0, 0, 0], fold::levels(&buf));
+  assert_eq!(Some(1..5), fold::fold_from(&buf, 0));
+  assert_eq!(Some(3..4), fold::fold_from(&buf, 2));
+  assert_eq!(None, fold::fold_from(&buf, 1));
+  assert_eq!(Some(3..4), fold::fold_at(&buf, 3));
+  assert_eq!(Some(1..5), fold::fold_at(&buf, 4));
+
+  let mut driver = Driver::new(&lines, 8, 30);
+  let row = |driver: &Driver| driver.editor.files.current().cur.row;
+  driver.keys("jza");
+  assert_eq!(0, row(&driver));
+  assert!(driver.frame()[0].contains("4 lines"));
+  assert!(driver.frame()[1].starts_with('}'));
+  driver.keys("j");
+  assert_eq!(5, row(&driver));
+  driver.keys("zR");
+  driver.keys("zMggzo");
+  driver.keys("jj");
+  assert_eq!(2, row(&driver));
+  driver.keys("j");
+  assert_eq!(4, row(&driver));
+  // a search into a closed fold opens it
+  driver.keys("/two\n");
+  assert_eq!(3, row(&driver));
+  assert!(driver.frame()[3].contains("two"));
+  assert!(driver.editor.files.current().cur.folds.is_empty());
+
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("file");
+  std::fs::write(&path, "").unwrap();
+  state::update(dir.path(), &path, "folds", "1 3").unwrap();
+  assert_eq!(Some("1 3"), state::load(dir.path(), &path).get("folds").map(String::as_str));
+  state::update(dir.path(), &path, "folds", "").unwrap();
+  assert!(state::load(dir.path(), &path).is_empty());
+  assert_eq!(1, std::fs::read_dir(dir.path()).unwrap().count());
+}
+
+#[test]
+fn test_syntax() {
+  let mut lines: Lines = [
+    "// one /* not */",
+    "fn add(a: i32, b: i32) -> i32 {",
+    "  let s = \"}\";",
+    "  a + b",
+    "}",
+    "",
+    "/* block",
+    "fn not() {} */",
+    "fn main() {",
+    "  add(1, f(2, 3));",
+    "}",
+  ].iter().map(|line| line.to_string()).collect();
+  let mut hl = syntax::Highlights::default();
+  hl.update(&lines, filetype::detect("x.rs"));
+  use syntax::Kind;
+  assert_eq!(&[(0..16, Kind::Comment)], hl.spans(0));
+  assert_eq!(&[(0..2, Kind::Keyword), (3..6, Kind::Function)], &hl.spans(1)[..2]);
+  assert!(hl.spans(2).contains(&(10..13, Kind::String)));
+  assert_eq!(&[(0..14, Kind::Comment)], hl.spans(7));
+  assert!(hl.spans(9).contains(&(6..7, Kind::Number)));
+  let starts: Vec<_> = hl.functions(&lines, &syntax::RUST).iter().map(|function| function.start).collect();
+  assert_eq!(vec![(1, 0), (8, 0)], starts);
+  // a comment left open hides the code below it
+  lines[7] = String::from("fn not() {}");
+  hl.update(&lines, filetype::detect("x.rs"));
+  assert_eq!(&[(0..11, Kind::Comment)], hl.spans(8));
+  lines[7] = String::from("fn not() {} */");
+
+  let refs: Vec<&str> = lines.iter().map(String::as_str).collect();
+  let mut driver = Driver::new(&refs, 14, 40);
+  let file = driver.editor.files.current_mut();
+  file.path = String::from("x.rs");
+  file.annotations.generation = None;
+  let pos = |driver: &Driver| {
+    let cur = &driver.editor.files.current().cur;
+    (cur.row, cur.col)
+  };
+  driver.keys(")");
+  assert_eq!((1, 0), pos(&driver));
+  driver.keys(")");
+  assert_eq!((8, 0), pos(&driver));
+  driver.keys("(");
+  assert_eq!((1, 0), pos(&driver));
+  driver.keys("jj}");
+  assert_eq!((4, 0), pos(&driver));
+  driver.keys("/f(\ndaa");
+  assert_eq!("  add(1);", driver.editor.files.current().buf[9]);
+  driver.keys("dib");
+  assert_eq!(vec!["fn main() {", "}"], driver.editor.files.current().buf.slice(8..));
+  driver.keys("ggjjdaf");
+  assert_eq!("", driver.editor.files.current().buf[1]);
+  assert_eq!(6, driver.editor.files.current().buf.len());
+  // a block and arguments on one line are characters
+  let mut driver = Driver::new(&["fn f() { g(a, [b, c]) }"], 4, 40);
+  let file = driver.editor.files.current_mut();
+  file.path = String::from("x.rs");
+  file.annotations.generation = None;
+  driver.keys("/b\ncia");
+  assert_eq!("fn f() { g(a, [, c]) }", driver.editor.files.current().buf[0]);
+  driver.keys("<Esc>/a\ndib");
+  assert_eq!("fn f() {}", driver.editor.files.current().buf[0]);
+
+  // Python's blocks and functions are its indented lines
+  let mut driver = Driver::new(&["def f(x):", "    if x:", "        return 1", "    return 2", "", "y = 3"], 8, 40);
+  let file = driver.editor.files.current_mut();
+  file.path = String::from("x.py");
+  file.annotations.generation = None;
+  driver.keys("jjdib");
+  assert_eq!(vec!["def f(x):", "    if x:", "    return 2", "", "y = 3"], driver.editor.files.current().buf.to_vec());
+  driver.keys("daf");
+  assert_eq!(vec!["", "y = 3"], driver.editor.files.current().buf.to_vec());
+}
+
+#[test]
+fn test_remote() {
+  use std::os::unix::fs::PermissionsExt;
+  let dir = tempfile::tempdir().unwrap();
+  let socket = dir.path().join("red.sock");
+  let path = dir.path().join("b.txt");
+  fs::write(&path, "one\ntwo\nthree\n").unwrap();
+  let (tx, requests) = mpsc::channel();
+  let server = remote::bind(&socket).unwrap().serve(move |request| tx.send(request).is_ok());
+  assert!(remote::bind(&socket).is_err());
+  assert_eq!(0o600, fs::metadata(&socket).unwrap().permissions().mode() & 0o777);
+  // nothing but a socket is taken over
+  assert!(remote::bind(&path).is_err());
+  assert_eq!("one\ntwo\nthree\n", fs::read_to_string(&path).unwrap());
+
+  let mut driver = Driver::new(&["a"], 6, 40);
+  let mut ask = |args: Vec<String>| {
+    let client = {
+      let socket = socket.clone();
+      thread::spawn(move || remote::send(&socket, &args).map_err(|err| err.to_string()))
+    };
+    let request = requests.recv().unwrap();
+    let res = driver.editor.remote(&request.args);
+    request.reply(res);
+    client.join().unwrap()
+  };
+  let file = path.to_str().unwrap().to_string();
+  assert_eq!(Ok(String::new()), ask(vec![String::from("+2"), file.clone()]));
+  assert_eq!(None, ask(vec![String::from("--command"), String::from("nope")]).ok().filter(String::is_empty));
+  assert_eq!(Ok(String::new()), ask(vec![format!("{}:3:2", file), String::from("--command"), String::from("1")]));
+  assert!(ask(vec![String::from("--command")]).is_err());
+
+  assert_eq!(2, driver.editor.files.files.len());
+  assert_eq!((0, 0), driver.editor.cursor());
+  driver.keys(":");
+  assert_eq!(Err(String::from("red is waiting for an answer to a prompt")), driver.editor.remote(&[file]));
+  drop(server);
+  assert!(!socket.exists());
+}
+
+#[test]
+fn test_engine() {
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("b.txt");
+  fs::write(&path, "one\ntwo\n").unwrap();
+  let editor = Editor::open(&[path.to_str().unwrap().to_string()], 6, 40).unwrap();
+  let (tx, outputs) = mpsc::channel();
+  let engine = Engine::start(editor, move |output| tx.send(output).is_ok());
+  let frame = || match outputs.recv().unwrap() {
+    Output::Frame(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+    _ => panic!("expected a frame"),
+  };
+  assert!(frame().contains("one"));
+
+  assert!(engine.send(Request::Key(Key::Char('x'))));
+  assert!(frame().contains("ne"));
+  let (reply, answer) = mpsc::channel();
+  let args = vec![String::from("--command"), String::from("w")];
+  assert!(engine.client().send(Request::Remote(args, Box::new(move |res| reply.send(res).unwrap()))));
+  assert!(answer.recv().unwrap().is_ok());
+  frame();
+  assert_eq!("ne\ntwo\n", fs::read_to_string(&path).unwrap());
+
+  for c in ":q\n".chars() {
+    engine.send(Request::Key(Key::Char(c)));
+  }
+  while let Ok(output) = outputs.recv() {
+    if let Output::Quit = output {
+      break;
+    }
+  }
+  assert_eq!(4, engine.finish().len());
+}
+
+#[test]
+fn test_script() {
+  assert_eq!(Ok(vec![Key::Char('i'), Key::Char('<'), Key::Esc, Key::Char('\n')]), keymap::parse_script("i<<><Esc>\n"));
+  assert_eq!(Err(String::from("Unknown key <Foo>")), keymap::parse_script("<Foo>"));
+
+  let editor = Driver::new(&["one"], 6, 40).editor;
+  let (tx, outputs) = mpsc::channel();
+  let engine = Engine::start(editor, move |output| tx.send(output).is_ok());
+  let frame = || match outputs.recv().unwrap() {
+    Output::Frame(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+    _ => panic!("expected a frame"),
+  };
+  frame();
+  // hundreds of edits, drawn once they are all done
+  let keys = keymap::parse_script(&"0i.<Esc>".repeat(300)).unwrap();
+  assert!(engine.send(Request::Script(keys)));
+  assert!(frame().contains(&".".repeat(30)));
+  assert!(engine.send(Request::Key(Key::Char('x'))));
+  frame();
+  assert!(outputs.try_recv().is_err());
+  assert!(engine.send(Request::Script(keymap::parse_script(":q!\n").unwrap())));
+  // the frame that gives the terminal back, and no other
+  frame();
+  assert!(matches!(outputs.recv().unwrap(), Output::Quit));
+  assert_eq!(1, engine.finish().len());
+}
+
+#[test]
+fn test_buffer_switcher() {
+  assert_eq!(None, fuzzy::score("mx", "src/main.rs"));
+  assert!(fuzzy::score("ma", "src/main.rs") > fuzzy::score("ma", "src/format.rs"));
+  assert!(fuzzy::score("M", "src/main.rs").is_none());
+  assert!(fuzzy::score("m", "src/Main.rs").is_some());
+
+  let mut driver = Driver::new(&["a"], 10, 40);
+  driver.editor.files.files.extend(vec![
+    OpenFile::new("src/main.rs", vec!["main".into()].into()),
+    OpenFile::new("src/memory.rs", vec!["memory".into()].into()),
+    OpenFile::new("README.md", vec!["readme".into()].into()),
+  ]);
+  // the files not shown yet follow in order
+  driver.keys("<Ctrl-p>");
+  assert!(matches!(driver.editor.mode, Mode::Buffers(_, 1)));
+  assert_eq!(vec![0, 1, 2, 3], driver.editor.files.matching(""));
+  driver.keys("mr\n");
+  assert_eq!("src/main.rs", driver.editor.path());
+  // the file shown before is selected first
+  driver.keys("<Ctrl-p>\n");
+  assert_eq!("a", driver.editor.path());
+  driver.keys("<Tab><Tab><Tab><Ctrl-p>");
+  assert_eq!(vec![3, 2, 1, 0], driver.editor.files.matching(""));
+  let frame = driver.frame();
+  assert_eq!("Buffer:", frame[9]);
+  assert_eq!(&["README.md", "src/memory.rs", "src/main.rs", "a"], &frame[1..5]);
+  // recency breaks ties between equally good matches
+  driver.keys("m");
+  assert_eq!(vec![3, 2, 1], driver.editor.files.matching("m"));
+  driver.keys("<Down><Down><Down>x<Backspace><Down>\n");
+  assert_eq!("src/memory.rs", driver.editor.path());
+  driver.keys("<Ctrl-p>zz\n<Esc>");
+  assert!(matches!(driver.editor.mode, Mode::Normal));
+  assert_eq!("src/memory.rs", driver.editor.path());
+}
+
+#[test]
+fn test_tree() {
+  let dir = tempfile::tempdir().unwrap();
+  let root = dir.path();
+  for name in ["src", ".git", "b"] {
+    fs::create_dir(root.join(name)).unwrap();
+  }
+  for name in ["src/main.rs", "src/lib.rs", "README.md", ".gitignore"] {
+    fs::write(root.join(name), "x\n").unwrap();
+  }
+  let tree = Tree::new(root).unwrap();
+  let names: Vec<String> = tree.entries.iter().map(|entry| entry.name()).collect();
+  assert_eq!(vec!["b", "src", "README.md"], names);
+
+  let mut driver = Driver::new(&["a"], 6, 60);
+  driver.editor.ses.tree = Some(tree);
+  driver.editor.mode = Mode::Tree;
+  driver.keys("jl");
+  assert!(driver.frame()[1].ends_with("\u{2502} \u{25be} src/"));
+  assert!(driver.frame()[2].ends_with("\u{2502}     lib.rs"));
+  driver.keys("j\n");
+  assert_eq!(root.join("src/lib.rs").to_str().unwrap(), driver.editor.path());
+  assert!(matches!(driver.editor.mode, Mode::Normal));
+
+  // back in the tree, the file shown is selected
+  driver.keys("gf");
+  assert_eq!(2, driver.editor.ses.tree.as_ref().unwrap().selected);
+  driver.keys("hh");
+  assert_eq!(3, driver.editor.ses.tree.as_ref().unwrap().entries.len());
+  driver.keys("anew.rs\n");
+  let new = root.join("src/new.rs");
+  assert!(new.exists());
+  assert_eq!(new.to_str().unwrap(), driver.editor.path());
+
+  // an open file renamed is edited under its new name
+  driver.keys("gfr<Backspace><Backspace><Backspace><Backspace><Backspace><Backspace>old.rs\n");
+  let old = root.join("src/old.rs");
+  assert!(!new.exists() && old.exists());
+  assert_eq!(old.to_str().unwrap(), driver.editor.path());
+  assert_eq!(Some(old.as_path()), driver.editor.ses.tree.as_ref().unwrap().selected().map(|entry| entry.path.as_path()));
+  driver.keys("dn");
+  assert!(old.exists());
+  driver.keys("dy");
+  assert!(!old.exists());
+  assert!(matches!(driver.editor.mode, Mode::Tree));
+  driver.keys(":tree\n");
+  assert!(driver.editor.ses.tree.is_none());
+}
+
+#[test]
+fn test_block() {
+  let mut driver = Driver::new(&["abcd", "efgh", "ij", "klmn"], 6, 40);
+  driver.keys("l<Ctrl-v>jjjlx");
+  assert_eq!(vec!["ad", "eh", "i", "kn"], driver.editor.lines());
+  assert!(matches!(driver.editor.mode, Mode::Normal));
+  driver.keys("ggp");
+  assert_eq!(vec!["bcad", "fgeh", "ji", "lmkn"], driver.editor.lines());
+
+  let mut driver = Driver::new(&["ab", "c", "de"], 6, 40);
+  driver.keys("l<Ctrl-v>jjI-<Esc>");
+  assert_eq!(vec!["a-b", "c", "d-e"], driver.editor.lines());
+  driver.keys("gg<Ctrl-v>jjA+<Esc>");
+  assert_eq!(vec!["a+-b", "c+", "d+-e"], driver.editor.lines());
+}
+
+#[test]
+fn test_table() {
+  assert_eq!(vec![0..1, 2..8, 9..9], table::fields("a,\"b,c\"d,", ','));
+  assert_eq!('\t', table::separator("a.tsv", "x,y"));
+  assert_eq!('\t', table::separator("a", "x\ty"));
+  assert_eq!(',', table::separator("a.csv", "x\ty,z"));
+
+  let mut driver = Driver::new(&["name,age,city", "al,7,x", "beatrice,30,york"], 6, 40);
+  driver.keys(":csv\n");
+  assert_eq!("name    ,age,city\u{ac}", driver.frame()[0]);
+  assert_eq!("al      ,7  ,x\u{ac}", driver.frame()[1]);
+  assert_eq!("beatrice,30 ,york\u{ac}", driver.frame()[2]);
+  assert_eq!(vec!["name,age,city", "al,7,x", "beatrice,30,york"], driver.editor.lines());
+  driver.keys("j");
+  driver.editor.handle_key(Key::Alt('l')).unwrap();
+  assert_eq!((1, 3), (driver.editor.files.current().cur.row, driver.editor.files.current().cur.col));
+  assert_eq!(9, cursor_cell(&driver.editor.files.current().cur, &driver.editor.files.current().buf));
+  driver.editor.handle_key(Key::Alt('l')).unwrap();
+  driver.editor.handle_key(Key::Alt('l')).unwrap();
+  assert_eq!((2, 0), (driver.editor.files.current().cur.row, driver.editor.files.current().cur.col));
+  driver.editor.handle_key(Key::Alt('h')).unwrap();
+  assert_eq!((1, 5), (driver.editor.files.current().cur.row, driver.editor.files.current().cur.col));
+  driver.keys(":csv\n");
+  assert_eq!("name,age,city\u{ac}", driver.frame()[0]);
+}
+
+#[test]
+fn test_notes() {
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("a.rs").to_str().unwrap().to_string();
+  fs::write(&path, "one\ntwo\nthree\n").unwrap();
+  let mut driver = Driver::open(std::slice::from_ref(&path), 8, 40);
+  driver.keys("j:note check this\n");
+  assert_eq!("2\tcheck this\n", fs::read_to_string(notes::path(&path)).unwrap());
+  assert_eq!(" one\u{ac}", driver.frame()[0]);
+  assert_eq!("*two\u{ac}", driver.frame()[1]);
+  driver.keys("gn");
+  assert!(matches!(driver.editor.mode, Mode::Note(1)));
+  assert!(driver.frame()[3].starts_with("\u{2500} Note on line 2 \u{2500}"));
+  assert_eq!("check this", driver.frame()[4]);
+  driver.keys("x");
+  assert!(matches!(driver.editor.mode, Mode::Normal));
+
+  // the note moves with its line, and is saved with the file
+  driver.keys("ggizero\n<Esc>");
+  assert_eq!("2\tcheck this\n", fs::read_to_string(notes::path(&path)).unwrap());
+  driver.keys(":w\n");
+  assert_eq!("3\tcheck this\n", fs::read_to_string(notes::path(&path)).unwrap());
+  let driver = Driver::open(std::slice::from_ref(&path), 8, 40);
+  assert_eq!(Some("check this"), driver.editor.files.current().notes.get(2));
+
+  let mut driver = driver;
+  driver.keys("ggjj:unnote\n");
+  assert!(!notes::path(&path).exists());
+  driver.keys("gn");
+  assert!(matches!(driver.editor.mode, Mode::Normal));
+  assert_eq!("No note on this line", driver.frame()[7]);
+}
+
+#[test]
+fn test_timeline() {
+  assert_eq!(Ok(Step::Count(1)), timeline::parse_step(""));
+  assert_eq!(Ok(Step::Count(3)), timeline::parse_step("3"));
+  assert_eq!(Ok(Step::Time(Duration::from_secs(120))), timeline::parse_step("2m"));
+  assert!(timeline::parse_step("2x").is_err());
+  assert!(timeline::parse_step("99999999999999999999d").is_err());
+  assert!(timeline::parse_step("999999999999999999d").is_err());
+  assert_eq!("1 minute", timeline::format_age(Duration::from_secs(90)));
+
+  let start = SystemTime::now();
+  let lines = |text: &str| -> Lines { text.split(' ').map(String::from).collect() };
+  let mut timeline = Timeline::new(&lines("a"), 0);
+  timeline.record_at(&lines("b"), 1, start + Duration::from_secs(60));
+  timeline.record_at(&lines("c"), 2, start + Duration::from_secs(90));
+  timeline.record_at(&lines("d"), 3, start + Duration::from_secs(100));
+  assert_eq!(lines("b"), timeline.go(Step::Time(Duration::from_secs(30)), true).unwrap().0);
+  assert!(timeline.go(Step::Count(1), false).is_some());
+  assert_eq!(lines("d"), timeline.go(Step::Time(Duration::from_secs(5)), false).unwrap().0);
+  assert_eq!(lines("a"), timeline.go(Step::Time(Duration::from_secs(3600)), true).unwrap().0);
+  assert!(timeline.go(Step::Count(1), true).is_none());
+  assert_eq!(lines("d"), timeline.go(Step::Time(Duration::from_secs(u64::MAX)), false).unwrap().0);
+
+  // the states of a large buffer cost only the lines each one changed
+  let mut big: Lines = (0..600_000).map(|row| row.to_string()).collect();
+  let mut timeline = Timeline::new(&big, 0);
+  big[0].push('x');
+  timeline.record(&big, 1);
+  big[599_999].push('x');
+  timeline.record(&big, 2);
+  assert_eq!("0x", timeline.go(Step::Count(1), true).unwrap().0[0]);
+  assert_eq!("0", timeline.go(Step::Count(1), true).unwrap().0[0]);
+
+  let mut driver = Driver::new(&["one"], 4, 40);
+  driver.keys("xx:earlier\n");
+  assert_eq!(vec!["ne"], driver.editor.lines());
+  assert_eq!("Back to the text of 0 seconds ago", driver.frame()[3]);
+  driver.keys(":earlier 5m\n");
+  assert_eq!(vec!["one"], driver.editor.lines());
+  driver.keys(":later 2\n");
+  assert_eq!(vec!["e"], driver.editor.lines());
+  driver.keys(":earlier\nx");
+  assert_eq!(vec!["e"], driver.editor.lines());
+  driver.keys(":later\n");
+  assert_eq!("Already at the newest change", driver.frame()[3]);
+}
+
+#[test]
+fn test_remembered_positions() {
+  assert_eq!(Some((2, 0)), parse_position("3:1"));
+  assert_eq!(None, parse_position("0:1"));
+  assert_eq!("3:1", format_position((2, 0)));
+  let jumps = JumpList::from((0..150).map(|row| (row, 0)).collect());
+  assert_eq!(100, jumps.positions().len());
+  assert_eq!((50, 0), jumps.positions()[0]);
+
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("file");
+  std::fs::write(&path, "").unwrap();
+  state::update_all(dir.path(), &path, &[("marks", "a:1:1"), ("jumps", "2:1")]).unwrap();
+  state::update_named(dir.path(), "marks", "A", "1:1 /x").unwrap();
+  assert_eq!(Some("2:1"), state::load(dir.path(), &path).get("jumps").map(String::as_str));
+  assert_eq!(Some("1:1 /x"), state::load_named(dir.path(), "marks").get("A").map(String::as_str));
+
+  // a mark named by a capital letter goes to the file it is in
+  let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+  std::fs::write(&a, "1\n2\n3\n").unwrap();
+  std::fs::write(&b, "x\n").unwrap();
+  let args = [a.to_str().unwrap().to_string(), b.to_str().unwrap().to_string()];
+  let mut driver = Driver::open(&args, 6, 40);
+  driver.keys("jjmQ<Tab>'Q");
+  assert_eq!(0, driver.editor.files.current);
+  assert_eq!((2, 0), driver.editor.cursor());
+  driver.keys("<Tab>mQgg<Tab>'Q");
+  assert_eq!(1, driver.editor.files.current);
+  assert_eq!(None, driver.editor.files.files[0].marks.get('Q'));
+}
+
+#[test]
+fn test_remembered_view() {
+  let dir = tempfile::tempdir().unwrap();
+  let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+  let lines: String = (1..=30).map(|i| format!("{}\n", i)).collect();
+  std::fs::write(&a, lines).unwrap();
+  std::fs::write(&b, "x\n").unwrap();
+  let args = [a.to_str().unwrap().to_string(), b.to_str().unwrap().to_string()];
+  let view = |driver: &Driver| {
+    let cur = &driver.editor.files.current().cur;
+    (cur.row, cur.col, cur.top)
+  };
+  let mut driver = Driver::open(&args, 6, 40);
+  driver.keys("Gkkkl");
+  assert_eq!((26, 1, 25), view(&driver));
+  // switching files keeps the view of each
+  driver.keys("<Tab><Tab>");
+  assert_eq!((26, 1, 25), view(&driver));
+  assert_eq!("26\u{ac}", driver.frame()[0]);
+  // and so does quitting and opening it again
+  driver.keys(":q\n");
+  let driver = Driver::open(&args[..1], 6, 40);
+  assert_eq!((26, 1, 25), view(&driver));
+  assert_eq!("26\u{ac}", driver.frame()[0]);
+}
+
+#[test]
+fn test_bookmarks() {
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("a");
+  let lines: String = (1..=10).map(|i| format!("{}\n", i)).collect();
+  std::fs::write(&path, lines).unwrap();
+  let args = [path.to_str().unwrap().to_string()];
+  let bookmarks = |driver: &Driver| driver.editor.files.current().bookmarks.rows();
+  let mut driver = Driver::open(&args, 14, 40);
+  driver.keys("jjMjjMjjMkkM");
+  assert_eq!(vec![2, 6], bookmarks(&driver));
+  assert_eq!(">3\u{ac}", driver.frame()[2]);
+  driver.keys("gggb");
+  assert_eq!((2, 0), driver.editor.cursor());
+  // the last bookmark has none after it
+  driver.keys("gbgbgb");
+  assert_eq!((6, 0), driver.editor.cursor());
+  driver.keys("gB");
+  assert_eq!((2, 0), driver.editor.cursor());
+  // a bookmark stays on its line as lines above it go
+  driver.keys("ggdd");
+  assert_eq!(vec![1, 5], bookmarks(&driver));
+
+  // the list shows the lines around the selected bookmark
+  driver.keys("gm");
+  assert_eq!((1, 0), driver.editor.cursor());
+  let frame = driver.frame();
+  assert_eq!(vec!["    1  2", "    2: 3", "    3  4", "    6: 7"], frame[5..9].to_vec());
+  driver.keys("jM");
+  assert_eq!(vec![1], bookmarks(&driver));
+  driver.keys("M");
+  assert!(matches!(driver.editor.mode, Mode::Normal));
+  driver.keys("M:wq\n");
+
+  // the bookmarks are remembered with the file
+  let driver = Driver::open(&args, 14, 40);
+  assert_eq!(vec![1], bookmarks(&driver));
+}
+
+#[test]
+fn test_idle() {
+  use std::os::unix::fs::PermissionsExt;
+  let dir = tempfile::tempdir().unwrap();
+  let path = dir.path().join("a.txt");
+  std::fs::write(&path, "one\n").unwrap();
+  let args = [path.to_str().unwrap().to_string()];
+  let root = dir.path().join("swap");
+  let mut driver = Driver::open(&args, 6, 40);
+  driver.keys("x");
+  assert!(!driver.editor.idle_if_due());
+  driver.editor.typed -= Duration::from_secs(config::get().idle_time as u64);
+  driver.editor.idle_if_due();
+  assert!(driver.editor.idle);
+
+  // the swap file is written while idle, and recovered after a red that
+  // ended without saving
+  let file = driver.editor.files.current();
+  swap::write(&root, &path, &file.buf).unwrap();
+  assert_eq!(Some(vec![String::from("ne")]), swap::read(&root, &path).unwrap());
+  let swapped = fs::read_dir(&root).unwrap().next().unwrap().unwrap().path();
+  assert_eq!(0o600, fs::metadata(swapped).unwrap().permissions().mode() & 0o777);
+  // nothing is kept of encrypted or compressed files
+  assert!(keeps_side_files(path.to_str().unwrap()));
+  assert!(!keeps_side_files("secret.gpg"));
+  assert!(!keeps_side_files("log.gz"));
+  swap::remove(&root, &path).unwrap();
+  assert_eq!(None, swap::read(&root, &path).unwrap());
+
+  let swapped = swap_root().unwrap();
+  assert_eq!(Some(vec![String::from("ne")]), swap::read(&swapped, &path).unwrap());
+  let mut again = Driver::open(&args, 6, 40);
+  assert!(again.frame()[5].contains(" has unsaved"));
+  again.keys(":recover\n");
+  assert_eq!(vec!["ne"], again.editor.lines());
+  assert!(again.editor.files.current().modified());
+  again.keys(":w\n");
+  assert_eq!(None, swap::read(&swapped, &path).unwrap());
+  again.keys(":recover\n");
+  assert_eq!("Nothing to recover", again.frame()[5]);
+}
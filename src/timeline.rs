@@ -0,0 +1,133 @@
+// The states a buffer went through while it was edited, each kept with the
+// time it was reached, for `:earlier` and `:later` to go back and forth
+// through them by time or by count. A state is taken once an edit is done,
+// when insert mode is left rather than on every key typed. Editing after
+// going back drops the states that were later than the one gone back to.
+
+use std::time::{Duration, SystemTime};
+
+use crate::lines::Lines;
+
+// The most states kept for a buffer, and the most lines in them all, after
+// which the oldest go. Lines shared from one state to the next count once.
+const MAX_STATES: usize = 200;
+const MAX_LINES: usize = 1_000_000;
+
+pub struct Timeline {
+  // the time each state was reached, its lines and how many of them it
+  // doesn't share with the state before, oldest first; the states share the
+  // lines that are the same from one to the next
+  states: Vec<(SystemTime, Lines, usize)>,
+  // the state the buffer is in, unless it was edited since
+  current: usize,
+  // the edit of the buffer the current state is
+  generation: u64,
+}
+
+// How far to go: a number of states, or a length of time
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Step {
+  Count(usize),
+  Time(Duration),
+}
+
+// Reads steps like `3`, `30s`, `2m` or `1h`, with `1` for none.
+pub fn parse_step(text: &str) -> Result<Step, String> {
+  let text = text.trim();
+  if text.is_empty() {
+    return Ok(Step::Count(1));
+  }
+  let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+  let (n, unit) = text.split_at(split);
+  let n: u64 = n.parse().map_err(|_| format!("Not a time: {}", text))?;
+  let secs = match unit {
+    "" => return Ok(Step::Count(n as usize)),
+    "s" => Some(n),
+    "m" => n.checked_mul(60),
+    "h" => n.checked_mul(60 * 60),
+    "d" => n.checked_mul(24 * 60 * 60),
+    _ => None,
+  };
+  secs.map(|secs| Step::Time(Duration::from_secs(secs))).ok_or_else(|| format!("Not a time: {}", text))
+}
+
+impl Timeline {
+  pub fn new(lines: &Lines, generation: u64) -> Self {
+    let kept = lines.unshared(&Lines::new());
+    Timeline{states: vec![(SystemTime::now(), lines.clone(), kept)], current: 0, generation}
+  }
+
+  // Takes the state of the buffer if it was edited since the last one.
+  pub fn record(&mut self, lines: &Lines, generation: u64) {
+    self.record_at(lines, generation, SystemTime::now());
+  }
+
+  pub fn record_at(&mut self, lines: &Lines, generation: u64, time: SystemTime) {
+    if generation == self.generation {
+      return;
+    }
+    self.generation = generation;
+    if *lines == self.states[self.current].1 {
+      return;
+    }
+    let new = lines.unshared(&self.states[self.current].1);
+    self.states.truncate(self.current + 1);
+    self.states.push((time, lines.clone(), new));
+    let mut kept: usize = self.states.iter().map(|state| state.2).sum();
+    while self.states.len() > 1 && (self.states.len() > MAX_STATES || kept > MAX_LINES) {
+      kept -= self.states.remove(0).2;
+      // the oldest state left alone has the lines it shared with the one gone
+      let first = &mut self.states[0];
+      kept -= first.2;
+      first.2 = first.1.unshared(&Lines::new());
+      kept += first.2;
+    }
+    self.current = self.states.len() - 1;
+  }
+
+  // Goes back or forward to another state, returning its lines and when it
+  // was reached, or none if there is no other state that way.
+  pub fn go(&mut self, step: Step, back: bool) -> Option<(Lines, SystemTime)> {
+    let now = self.states[self.current].0;
+    let target = match step {
+      Step::Count(n) if back => self.current.saturating_sub(n),
+      Step::Count(n) => (self.current + n).min(self.states.len() - 1),
+      // the latest state at least that long before, or the oldest one
+      Step::Time(length) if back => {
+        let time = now.checked_sub(length).unwrap_or(SystemTime::UNIX_EPOCH);
+        self.states[..self.current].iter().rposition(|state| state.0 <= time).unwrap_or(0)
+      }
+      // the earliest state at least that long after, or the newest one
+      Step::Time(length) => {
+        let newest = self.states.len() - 1;
+        match now.checked_add(length) {
+          Some(time) => self.states.iter().position(|state| state.0 >= time).unwrap_or(newest),
+          None => newest,
+        }
+      }
+    };
+    if target == self.current {
+      return None;
+    }
+    self.current = target;
+    let (time, ref lines, _) = self.states[target];
+    Some((lines.clone(), time))
+  }
+
+  // Takes the buffer as being in the state gone to, once it has its lines.
+  pub fn restored(&mut self, generation: u64) {
+    self.generation = generation;
+  }
+}
+
+// How long ago something was, to the largest whole unit, like `3 minutes`.
+pub fn format_age(age: Duration) -> String {
+  let secs = age.as_secs();
+  let (n, unit) = match secs {
+    0..=59 => (secs, "second"),
+    60..=3599 => (secs / 60, "minute"),
+    3600..=86399 => (secs / 3600, "hour"),
+    _ => (secs / 86400, "day"),
+  };
+  format!("{} {}{}", n, unit, if n == 1 { "" } else { "s" })
+}
@@ -0,0 +1,40 @@
+// Markers like TODO and FIXME left in the text as reminders. A marker only
+// counts as a whole word, so `TODOS` or `MyTODO` are not matched.
+
+use std::ops::Range;
+
+use crate::lines::Lines;
+
+fn is_word_char(c: char) -> bool {
+  c.is_alphanumeric() || c == '_'
+}
+
+// Character ranges of the markers in a line.
+pub fn find_in_line(line: &str, markers: &[String]) -> Vec<Range<usize>> {
+  let chars: Vec<char> = line.chars().collect();
+  let mut found = Vec::new();
+  let mut i = 0;
+  while i < chars.len() {
+    let at_word_start = i == 0 || !is_word_char(chars[i - 1]);
+    let len = markers.iter().filter(|_| at_word_start).find_map(|marker| {
+      let len = marker.chars().count();
+      let matches = len > 0
+        && chars[i..].iter().take(len).copied().eq(marker.chars())
+        && !chars.get(i + len).is_some_and(|&c| is_word_char(c));
+      if matches { Some(len) } else { None }
+    });
+    match len {
+      Some(len) => {
+        found.push(i..i + len);
+        i += len;
+      }
+      None => i += 1,
+    }
+  }
+  found
+}
+
+// Rows of the lines with markers, in order.
+pub fn find_all(buf: &Lines, markers: &[String]) -> Vec<usize> {
+  (0..buf.len()).filter(|&row| !find_in_line(&buf[row], markers).is_empty()).collect()
+}
@@ -0,0 +1,67 @@
+// Changes to whole runs of lines: sorting them, reversing them, and dropping
+// repeated ones, as `:sort`, `:reverse` and `:uniq` do for a range of lines,
+// and tidying the whitespace of a file before it is saved.
+
+use std::ops::Range;
+
+use crate::lines::Lines;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Order {
+  Text,
+  IgnoreCase,
+  // by the first number in each line, after the lines without one
+  Numeric,
+}
+
+// The first whole number in the line, with its sign
+fn first_number(line: &str) -> Option<i128> {
+  let start = line.find(|c: char| c.is_ascii_digit())?;
+  let end = line[start..].find(|c: char| !c.is_ascii_digit()).map_or(line.len(), |i| start + i);
+  let n: i128 = line[start..end].parse().unwrap_or(i128::MAX);
+  Some(if line[..start].ends_with('-') { -n } else { n })
+}
+
+// Sorts the lines, keeping lines that compare equal in the order they were.
+pub fn sort(lines: &mut [String], order: Order) {
+  match order {
+    Order::Text => lines.sort(),
+    Order::IgnoreCase => lines.sort_by_cached_key(|line| line.to_lowercase()),
+    Order::Numeric => lines.sort_by_cached_key(|line| first_number(line)),
+  }
+}
+
+pub fn reverse(lines: &mut [String]) {
+  lines.reverse();
+}
+
+// Drops each line that is the same as the one before it.
+pub fn uniq(lines: &[String]) -> Vec<String> {
+  let mut unique: Vec<String> = Vec::with_capacity(lines.len());
+  for line in lines {
+    if unique.last() != Some(line) {
+      unique.push(line.clone());
+    }
+  }
+  unique
+}
+
+// The columns of the spaces and tabs at the end of the line
+pub fn trailing_whitespace(line: &str) -> Range<usize> {
+  let len = line.chars().count();
+  let trailing = line.chars().rev().take_while(|&c| c == ' ' || c == '\t').count();
+  len - trailing..len
+}
+
+pub fn strip_trailing_whitespace(lines: &mut Lines) {
+  for line in lines.iter_mut() {
+    line.truncate(line.trim_end_matches([' ', '\t']).len());
+  }
+}
+
+// Drops the blank lines at the end, leaving at least one line.
+pub fn strip_final_blank_lines(lines: &mut Lines) {
+  while lines.len() > 1 && lines.last().is_some_and(|line| line.trim().is_empty()) {
+    lines.pop();
+  }
+}
@@ -0,0 +1,192 @@
+// The files of a project as a tree, for the sidebar of `:tree`. Each
+// directory lists its directories first and then its files, both by name,
+// and one that is expanded lists what it holds below itself. Hidden names,
+// which start with `.`, are left out.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub struct Entry {
+  pub path: PathBuf,
+  // how many directories down from the root it is
+  pub depth: usize,
+  pub dir: bool,
+}
+
+impl Entry {
+  pub fn name(&self) -> String {
+    self.path.file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned())
+  }
+}
+
+pub struct Tree {
+  // empty for the current directory, whose entries are named relative to it
+  root: PathBuf,
+  expanded: BTreeSet<PathBuf>,
+  pub entries: Vec<Entry>,
+  pub selected: usize,
+  // the first entry shown
+  pub top: usize,
+}
+
+fn read_dir(dir: &Path) -> io::Result<Vec<(PathBuf, bool)>> {
+  let search = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+  let mut found = Vec::new();
+  for entry in fs::read_dir(search)? {
+    let entry = entry?;
+    let name = entry.file_name().to_string_lossy().into_owned();
+    if !name.starts_with('.') {
+      found.push((!entry.path().is_dir(), name));
+    }
+  }
+  found.sort();
+  Ok(found.into_iter().map(|(file, name)| (dir.join(name), !file)).collect())
+}
+
+impl Tree {
+  pub fn new(root: &Path) -> io::Result<Self> {
+    let mut tree = Tree{
+      root: root.to_path_buf(),
+      expanded: BTreeSet::new(),
+      entries: Vec::new(),
+      selected: 0,
+      top: 0,
+    };
+    tree.refresh()?;
+    Ok(tree)
+  }
+
+  // Reads the directories again, keeping the same entry selected if it is
+  // still there.
+  pub fn refresh(&mut self) -> io::Result<()> {
+    let selected = self.selected().map(|entry| entry.path.clone());
+    self.expanded.retain(|dir| dir.is_dir());
+    let mut entries = Vec::new();
+    self.list(&self.root, 0, &mut entries)?;
+    self.entries = entries;
+    self.selected = selected.and_then(|path| self.position(&path))
+      .unwrap_or(self.selected)
+      .min(self.entries.len().saturating_sub(1));
+    Ok(())
+  }
+
+  fn list(&self, dir: &Path, depth: usize, entries: &mut Vec<Entry>) -> io::Result<()> {
+    for (path, dir) in read_dir(dir)? {
+      let expanded = dir && self.expanded.contains(&path);
+      entries.push(Entry{path: path.clone(), depth, dir});
+      // a directory that can't be read is shown empty
+      if expanded {
+        let _ = self.list(&path, depth + 1, entries);
+      }
+    }
+    Ok(())
+  }
+
+  fn position(&self, path: &Path) -> Option<usize> {
+    self.entries.iter().position(|entry| entry.path == path)
+  }
+
+  pub fn selected(&self) -> Option<&Entry> {
+    self.entries.get(self.selected)
+  }
+
+  pub fn is_expanded(&self, entry: &Entry) -> bool {
+    self.expanded.contains(&entry.path)
+  }
+
+  // Expands the selected directory, or collapses it.
+  pub fn toggle(&mut self) -> io::Result<()> {
+    if let Some(entry) = self.selected().filter(|entry| entry.dir) {
+      let path = entry.path.clone();
+      if !self.expanded.remove(&path) {
+        self.expanded.insert(path);
+      }
+    }
+    self.refresh()
+  }
+
+  // Collapses the selected directory, or selects the one the entry is in,
+  // returning whether there was either.
+  pub fn collapse(&mut self) -> io::Result<bool> {
+    let path = match self.selected() {
+      Some(entry) => entry.path.clone(),
+      None => return Ok(false),
+    };
+    if self.expanded.remove(&path) {
+      self.refresh()?;
+      return Ok(true);
+    }
+    match path.parent().and_then(|dir| self.position(dir)) {
+      Some(i) => {
+        self.selected = i;
+        Ok(true)
+      }
+      None => Ok(false),
+    }
+  }
+
+  // Expands the directories a file is in and selects it, returning whether
+  // it is in the tree.
+  pub fn reveal(&mut self, path: &Path) -> io::Result<bool> {
+    let mut dir = path.parent();
+    while let Some(parent) = dir.filter(|dir| dir.starts_with(&self.root) && *dir != self.root) {
+      self.expanded.insert(parent.to_path_buf());
+      dir = parent.parent();
+    }
+    self.refresh()?;
+    match self.position(path) {
+      Some(i) => {
+        self.selected = i;
+        Ok(true)
+      }
+      None => Ok(false),
+    }
+  }
+
+  // Where a new file goes: in the selected directory, or beside the
+  // selected file
+  pub fn target_dir(&self) -> PathBuf {
+    match self.selected() {
+      Some(entry) if entry.dir => entry.path.clone(),
+      Some(entry) => entry.path.parent().map_or_else(PathBuf::new, Path::to_path_buf),
+      None => self.root.clone(),
+    }
+  }
+
+  // Keeps the selected entry among the `rows` shown.
+  pub fn scroll(&mut self, rows: usize) {
+    if self.selected < self.top {
+      self.top = self.selected;
+    } else if rows > 0 && self.selected >= self.top + rows {
+      self.top = self.selected + 1 - rows;
+    }
+  }
+}
+
+// Creates an empty file, or a directory for a name ending in `/`, along with
+// the directories it goes in.
+pub fn create(name: &str) -> io::Result<()> {
+  let path = Path::new(name);
+  if name.ends_with('/') {
+    return fs::create_dir_all(path);
+  }
+  if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+    fs::create_dir_all(dir)?;
+  }
+  fs::OpenOptions::new().write(true).create_new(true).open(path).map(|_| ())
+}
+
+// Renames a file or directory, without replacing another one.
+pub fn rename(from: &Path, to: &Path) -> io::Result<()> {
+  if to.exists() {
+    return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists", to.display())));
+  }
+  fs::rename(from, to)
+}
+
+// Deletes a file, or a directory and everything in it.
+pub fn remove(path: &Path) -> io::Result<()> {
+  if path.is_dir() { fs::remove_dir_all(path) } else { fs::remove_file(path) }
+}
@@ -0,0 +1,157 @@
+// The text a program writes to a terminal, kept as lines the way a simple
+// terminal shows them, for the shell of `:term`. Only what a shell and the
+// programs it runs write with `TERM=dumb` is understood: text, line breaks,
+// carriage returns, backspaces and tabs, and moving the cursor or erasing with
+// the common escape sequences. Colors and any other sequences are dropped.
+
+// Lines kept above the visible rows, for output that has scrolled out of view
+const SCROLLBACK: usize = 1000;
+
+pub struct Vt {
+  // the lines written so far, the last `rows` of which are on the screen
+  pub lines: Vec<Vec<char>>,
+  rows: usize,
+  // the cursor, as a row of `lines` and a column
+  pub row: usize,
+  pub col: usize,
+  // an escape sequence seen in part, and bytes of a character in part
+  seq: Option<Vec<u8>>,
+  partial: Vec<u8>,
+}
+
+impl Vt {
+  pub fn new(rows: usize) -> Self {
+    Vt{lines: vec![Vec::new()], rows: rows.max(1), row: 0, col: 0, seq: None, partial: Vec::new()}
+  }
+
+  pub fn resize(&mut self, rows: usize) {
+    self.rows = rows.max(1);
+  }
+
+  // The first row of `lines` on the screen
+  pub fn top(&self) -> usize {
+    self.lines.len().saturating_sub(self.rows)
+  }
+
+  pub fn feed(&mut self, bytes: &[u8]) {
+    for &b in bytes {
+      if let Some(mut seq) = self.seq.take() {
+        seq.push(b);
+        if !self.escape(&seq) {
+          self.seq = Some(seq);
+        }
+        continue;
+      }
+      match b {
+        0x1b => self.seq = Some(Vec::new()),
+        b'\n' => self.line_feed(),
+        b'\r' => self.col = 0,
+        0x08 => self.col = self.col.saturating_sub(1),
+        b'\t' => self.col = (self.col / 8 + 1) * 8,
+        0..=0x1f | 0x7f => (),
+        _ => {
+          self.partial.push(b);
+          match std::str::from_utf8(&self.partial) {
+            Ok(text) => {
+              let c = text.chars().next().unwrap_or(char::REPLACEMENT_CHARACTER);
+              self.partial.clear();
+              self.put(c);
+            }
+            Err(err) if err.error_len().is_some() || self.partial.len() >= 4 => {
+              self.partial.clear();
+              self.put(char::REPLACEMENT_CHARACTER);
+            }
+            Err(_) => (),
+          }
+        }
+      }
+    }
+  }
+
+  fn put(&mut self, c: char) {
+    let line = &mut self.lines[self.row];
+    if line.len() < self.col {
+      line.resize(self.col, ' ');
+    }
+    match line.get_mut(self.col) {
+      Some(old) => *old = c,
+      None => line.push(c),
+    }
+    self.col += 1;
+  }
+
+  fn line_feed(&mut self) {
+    self.row += 1;
+    if self.row == self.lines.len() {
+      self.lines.push(Vec::new());
+    }
+    if self.lines.len() > SCROLLBACK + self.rows {
+      let n = self.lines.len() - SCROLLBACK - self.rows;
+      self.lines.drain(..n);
+      self.row -= n;
+    }
+  }
+
+  // Acts on the escape sequence after ESC seen so far, returning whether it
+  // is complete.
+  fn escape(&mut self, seq: &[u8]) -> bool {
+    match seq {
+      [b'['] | [b']'] => false,
+      [b'[', .., last] if !(0x40..=0x7e).contains(last) => false,
+      [b'[', params @ .., last] => {
+        self.control(params, *last);
+        true
+      }
+      // an operating system command, like setting the title, ends with BEL or
+      // ESC \
+      [b']', .., 0x07] | [b']', .., 0x1b, b'\\'] => true,
+      [b']', ..] => false,
+      // a character set, chosen with two bytes
+      [b'(' | b')'] => false,
+      _ => true,
+    }
+  }
+
+  fn control(&mut self, params: &[u8], command: u8) {
+    let params = String::from_utf8_lossy(params);
+    let mut numbers = params.trim_start_matches('?').split(';').map(|n| n.parse::<usize>().ok());
+    let mut next = |default: usize| numbers.next().flatten().filter(|&n| n > 0).unwrap_or(default);
+    let top = self.top();
+    match command {
+      b'A' => self.row = self.row.saturating_sub(next(1)).max(top),
+      b'B' => self.row = (self.row + next(1)).min(self.lines.len() - 1),
+      b'C' => self.col += next(1),
+      b'D' => self.col = self.col.saturating_sub(next(1)),
+      b'G' => self.col = next(1) - 1,
+      b'H' | b'f' => {
+        let (row, col) = (next(1), next(1));
+        self.row = top + row - 1;
+        while self.row >= self.lines.len() {
+          self.lines.push(Vec::new());
+        }
+        self.col = col - 1;
+      }
+      b'K' => {
+        let line = &mut self.lines[self.row];
+        match next(0) {
+          0 => line.truncate(self.col),
+          1 => line.iter_mut().take(self.col + 1).for_each(|c| *c = ' '),
+          _ => line.clear(),
+        }
+      }
+      b'J' => match next(0) {
+        0 => {
+          self.lines[self.row].truncate(self.col);
+          self.lines.truncate(self.row + 1);
+        }
+        _ => {
+          self.lines.truncate(top);
+          self.lines.push(Vec::new());
+          self.row = top;
+          self.col = 0;
+        }
+      },
+      _ => (),
+    }
+  }
+}